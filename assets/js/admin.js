@@ -126,6 +126,18 @@ RustMedia.API = {
         });
     },
 
+    // Upload batches
+    async createUploadBatch(files) {
+        return this.request('/upload/batches', {
+            method: 'POST',
+            body: JSON.stringify({ files }),
+        });
+    },
+
+    batchEvents(batchId) {
+        return new EventSource(`${this.baseUrl}/upload/batches/${batchId}/events`);
+    },
+
     // Stats
     async getStats() {
         return this.request('/stats');
@@ -6,6 +6,20 @@ RustMedia.Upload = {
     config: window.RUSTMEDIA_CONFIG || {},
     queue: [],
     uploading: false,
+    batches: {},
+
+    // Progress shown for server-side stages (sending takes the bar to 80%)
+    stageProgress: {
+        scanning: 85,
+        processing: 90,
+        thumbnails: 95,
+    },
+
+    stageLabels: {
+        scanning: 'Scanning...',
+        processing: 'Processing...',
+        thumbnails: 'Generating thumbnails...',
+    },
 
     init() {
         this.dropzone = document.getElementById('dropzone');
@@ -55,25 +69,81 @@ RustMedia.Upload = {
         });
     },
 
-    addFiles(files) {
+    async addFiles(files) {
+        const items = [];
+
         for (const file of files) {
             if (!this.validateFile(file)) continue;
 
             const queueItem = {
                 id: this.generateId(),
                 file,
-                status: 'pending',
+                status: 'queued',
                 progress: 0,
                 error: null,
             };
 
-            this.queue.push(queueItem);
+            items.push(queueItem);
             this.renderQueueItem(queueItem);
         }
 
+        if (!items.length) return;
+
+        await this.startBatch(items);
+
+        for (const item of items) {
+            item.status = 'pending';
+            this.queue.push(item);
+        }
+
         this.processQueue();
     },
 
+    async startBatch(items) {
+        try {
+            const batch = await RustMedia.API.createUploadBatch(
+                items.map(item => ({ filename: item.file.name, size: item.file.size }))
+            );
+
+            items.forEach((item, index) => {
+                item.batchId = batch.id;
+                item.batchIndex = index;
+            });
+
+            const source = RustMedia.API.batchEvents(batch.id);
+            this.batches[batch.id] = { source, items };
+
+            source.addEventListener('progress', (e) => {
+                this.applyBatchEvent(batch.id, JSON.parse(e.data).file);
+            });
+            source.addEventListener('complete', () => this.closeBatch(batch.id));
+            source.addEventListener('error', () => this.closeBatch(batch.id));
+        } catch (error) {
+            // Uploads still work without server-side progress
+            console.warn('Upload progress unavailable:', error);
+        }
+    },
+
+    applyBatchEvent(batchId, file) {
+        const item = this.batches[batchId]?.items[file.index];
+        if (!item || item.status === 'complete' || item.status === 'error') return;
+
+        if (this.stageProgress[file.state]) {
+            item.status = 'processing';
+            item.stage = file.state;
+            item.progress = Math.max(item.progress, this.stageProgress[file.state]);
+            this.updateQueueItem(item);
+        }
+    },
+
+    closeBatch(batchId) {
+        const batch = this.batches[batchId];
+        if (!batch) return;
+
+        batch.source.close();
+        delete this.batches[batchId];
+    },
+
     validateFile(file) {
         // Check size
         if (file.size > this.config.maxFileSize) {
@@ -122,10 +192,10 @@ RustMedia.Upload = {
 
         switch (item.status) {
             case 'uploading':
-                status.textContent = `Uploading ${item.progress}%`;
+                status.textContent = `Uploading ${item.sent || 0}%`;
                 break;
             case 'processing':
-                status.textContent = 'Processing...';
+                status.textContent = this.stageLabels[item.stage] || 'Processing...';
                 break;
             case 'complete':
                 status.textContent = 'Complete';
@@ -184,13 +254,18 @@ RustMedia.Upload = {
         if (folderId) formData.append('folder_id', folderId);
         formData.append('optimize', optimize);
         formData.append('generate_thumbnails', thumbnails);
+        if (item.batchId) {
+            formData.append('batch_id', item.batchId);
+            formData.append('batch_index', item.batchIndex);
+        }
 
         const xhr = new XMLHttpRequest();
 
         return new Promise((resolve, reject) => {
             xhr.upload.addEventListener('progress', (e) => {
-                if (e.lengthComputable) {
-                    item.progress = Math.round((e.loaded / e.total) * 100);
+                if (e.lengthComputable && item.status === 'uploading') {
+                    item.sent = Math.round((e.loaded / e.total) * 100);
+                    item.progress = Math.round(item.sent * 0.8);
                     this.updateQueueItem(item);
                 }
             });
@@ -225,9 +300,12 @@ RustMedia.Upload = {
             chunk_size: chunkSize,
             total_chunks: totalChunks,
             folder_id: folderId,
+            batch_id: item.batchId,
+            batch_index: item.batchIndex,
         });
 
         const uploadId = initResponse.upload_id;
+        item.uploadId = uploadId;
 
         // Upload chunks
         for (let i = 0; i < totalChunks; i++) {
@@ -237,7 +315,8 @@ RustMedia.Upload = {
 
             await RustMedia.API.uploadChunk(uploadId, i, chunk);
 
-            item.progress = Math.round(((i + 1) / totalChunks) * 90);
+            item.sent = Math.round(((i + 1) / totalChunks) * 100);
+            item.progress = Math.round(item.sent * 0.8);
             this.updateQueueItem(item);
         }
 
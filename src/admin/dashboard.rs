@@ -2,7 +2,8 @@
 
 use std::sync::Arc;
 use serde::Serialize;
-use crate::services::{MediaService, FolderService, media::MediaStats};
+use crate::services::{MediaService, FolderService, RateLimiter, media::MediaStats};
+use crate::services::rate_limit::RateLimitStatus;
 
 /// Dashboard view data
 #[derive(Debug, Serialize)]
@@ -12,6 +13,9 @@ pub struct DashboardData {
     pub storage_usage: StorageUsage,
     pub media_by_type: Vec<MediaTypeCount>,
     pub top_folders: Vec<TopFolder>,
+    /// Upload rate limit usage, most constrained users first (`None` when
+    /// rate limiting is off)
+    pub upload_limits: Option<Vec<RateLimitStatus>>,
 }
 
 #[derive(Debug, Serialize)]
@@ -55,6 +59,7 @@ pub struct DashboardView {
     media_service: Arc<MediaService>,
     folder_service: Arc<FolderService>,
     storage_limit: Option<u64>,
+    rate_limiter: Option<Arc<RateLimiter>>,
 }
 
 impl DashboardView {
@@ -66,6 +71,7 @@ impl DashboardView {
             media_service,
             folder_service,
             storage_limit: None,
+            rate_limiter: None,
         }
     }
 
@@ -74,6 +80,11 @@ impl DashboardView {
         self.storage_limit = Some(limit);
     }
 
+    /// Show upload rate limit usage
+    pub fn set_rate_limiter(&mut self, rate_limiter: Arc<RateLimiter>) {
+        self.rate_limiter = Some(rate_limiter);
+    }
+
     /// Get dashboard data
     pub async fn get_data(&self) -> DashboardData {
         let stats = self.media_service.get_stats().await;
@@ -105,38 +116,38 @@ impl DashboardView {
         let media_by_type = vec![
             MediaTypeCount {
                 media_type: "Images".to_string(),
-                count: stats.images,
-                size: stats.images * 500_000, // Estimate
-                size_formatted: Self::format_size(stats.images * 500_000),
-                percent: if total_size > 0.0 { (stats.images as f64 * 500_000.0 / total_size) * 100.0 } else { 0.0 },
+                count: stats.image_count,
+                size: stats.image_count * 500_000, // Estimate
+                size_formatted: Self::format_size(stats.image_count * 500_000),
+                percent: if total_size > 0.0 { (stats.image_count as f64 * 500_000.0 / total_size) * 100.0 } else { 0.0 },
             },
             MediaTypeCount {
                 media_type: "Videos".to_string(),
-                count: stats.videos,
-                size: stats.videos * 10_000_000, // Estimate
-                size_formatted: Self::format_size(stats.videos * 10_000_000),
-                percent: if total_size > 0.0 { (stats.videos as f64 * 10_000_000.0 / total_size) * 100.0 } else { 0.0 },
+                count: stats.video_count,
+                size: stats.video_count * 10_000_000, // Estimate
+                size_formatted: Self::format_size(stats.video_count * 10_000_000),
+                percent: if total_size > 0.0 { (stats.video_count as f64 * 10_000_000.0 / total_size) * 100.0 } else { 0.0 },
             },
             MediaTypeCount {
                 media_type: "Audio".to_string(),
-                count: stats.audio,
-                size: stats.audio * 5_000_000, // Estimate
-                size_formatted: Self::format_size(stats.audio * 5_000_000),
-                percent: if total_size > 0.0 { (stats.audio as f64 * 5_000_000.0 / total_size) * 100.0 } else { 0.0 },
+                count: stats.audio_count,
+                size: stats.audio_count * 5_000_000, // Estimate
+                size_formatted: Self::format_size(stats.audio_count * 5_000_000),
+                percent: if total_size > 0.0 { (stats.audio_count as f64 * 5_000_000.0 / total_size) * 100.0 } else { 0.0 },
             },
             MediaTypeCount {
                 media_type: "Documents".to_string(),
-                count: stats.documents,
-                size: stats.documents * 200_000, // Estimate
-                size_formatted: Self::format_size(stats.documents * 200_000),
-                percent: if total_size > 0.0 { (stats.documents as f64 * 200_000.0 / total_size) * 100.0 } else { 0.0 },
+                count: stats.document_count,
+                size: stats.document_count * 200_000, // Estimate
+                size_formatted: Self::format_size(stats.document_count * 200_000),
+                percent: if total_size > 0.0 { (stats.document_count as f64 * 200_000.0 / total_size) * 100.0 } else { 0.0 },
             },
             MediaTypeCount {
                 media_type: "Other".to_string(),
-                count: stats.other,
-                size: stats.other * 100_000, // Estimate
-                size_formatted: Self::format_size(stats.other * 100_000),
-                percent: if total_size > 0.0 { (stats.other as f64 * 100_000.0 / total_size) * 100.0 } else { 0.0 },
+                count: stats.other_count,
+                size: stats.other_count * 100_000, // Estimate
+                size_formatted: Self::format_size(stats.other_count * 100_000),
+                percent: if total_size > 0.0 { (stats.other_count as f64 * 100_000.0 / total_size) * 100.0 } else { 0.0 },
             },
         ];
 
@@ -147,18 +158,27 @@ impl DashboardView {
         let top_folders: Vec<TopFolder> = sorted_folders.into_iter().take(5).map(|f| {
             TopFolder {
                 id: f.id.to_string(),
-                name: f.name,
+                name: f.name.clone(),
                 item_count: f.item_count,
                 total_size: f.formatted_size(),
             }
         }).collect();
 
+        // Rate limits
+        let upload_limits = match &self.rate_limiter {
+            Some(limiter) if limiter.is_enabled().await => {
+                Some(limiter.status().await.into_iter().take(10).collect())
+            }
+            _ => None,
+        };
+
         DashboardData {
             stats,
             recent_uploads,
             storage_usage,
             media_by_type,
             top_folders,
+            upload_limits,
         }
     }
 
@@ -231,6 +251,8 @@ impl DashboardView {
                         {}
                     </ul>
                 </div>
+
+                {}
             </div>
         </main>
     </div>
@@ -238,13 +260,14 @@ impl DashboardView {
 </body>
 </html>
 "#,
-            data.stats.total_count,
-            data.stats.images,
-            data.stats.videos,
+            data.stats.total_items,
+            data.stats.image_count,
+            data.stats.video_count,
             data.storage_usage.used_formatted,
             self.render_recent_uploads(&data.recent_uploads),
             self.render_storage_chart(&data.media_by_type),
             self.render_top_folders(&data.top_folders),
+            self.render_upload_limits(data.upload_limits.as_deref()),
         )
     }
 
@@ -301,6 +324,51 @@ impl DashboardView {
         }).collect::<Vec<_>>().join("\n")
     }
 
+    fn render_upload_limits(&self, limits: Option<&[RateLimitStatus]>) -> String {
+        let Some(limits) = limits else {
+            return String::new();
+        };
+
+        let rows = if limits.is_empty() {
+            "<tr><td colspan=\"4\" class=\"empty\">No uploads yet</td></tr>".to_string()
+        } else {
+            limits.iter().map(|l| {
+                let user = l.user_id.map(|id| id.to_string()).unwrap_or_else(|| "Anonymous".to_string());
+                let role = l.role.as_deref().unwrap_or("default");
+                let files = match l.limits.files_per_minute {
+                    0 => "unlimited".to_string(),
+                    max => format!("{} / {} per minute", l.files_available, max),
+                };
+                let bytes = match l.limits.bytes_per_hour {
+                    0 => "unlimited".to_string(),
+                    max => format!("{} / {} per hour", Self::format_size(l.bytes_available), Self::format_size(max)),
+                };
+                format!(r#"
+                    <tr>
+                        <td>{} <span class="role">{}</span></td>
+                        <td>{}</td>
+                        <td>{}</td>
+                        <td>{}</td>
+                    </tr>
+                "#, user, role, files, bytes, l.active_sessions)
+            }).collect::<Vec<_>>().join("\n")
+        };
+
+        format!(r#"
+                <div class="panel upload-limits">
+                    <h2>Upload Limits</h2>
+                    <table class="limits-table">
+                        <thead>
+                            <tr><th>User</th><th>Files left</th><th>Bytes left</th><th>Chunked sessions</th></tr>
+                        </thead>
+                        <tbody>
+                            {}
+                        </tbody>
+                    </table>
+                </div>
+        "#, rows)
+    }
+
     fn get_type_icon(&self, media_type: &str) -> &'static str {
         match media_type {
             "Image" => "🖼️",
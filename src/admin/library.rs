@@ -145,6 +145,7 @@ impl LibraryView {
                 size: m.size,
                 size_formatted: m.formatted_size(),
                 url: m.url,
+                version: m.version,
                 dimensions: m.dimensions.map(|d| crate::handlers::media::DimensionsResponse {
                     width: d.width,
                     height: d.height,
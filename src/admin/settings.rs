@@ -40,7 +40,10 @@ pub struct SettingsForm {
 /// Settings page data
 #[derive(Debug, Serialize)]
 pub struct SettingsPageData {
+    /// Effective settings (secrets are never serialized)
     pub settings: MediaSettings,
+    /// Whether S3 credentials were resolved by the secret provider
+    pub s3_credentials_configured: bool,
     pub storage_backends: Vec<StorageBackendOption>,
     pub image_sizes: Vec<ImageSizeConfig>,
 }
@@ -102,6 +105,8 @@ impl SettingsView {
 
         SettingsPageData {
             settings: settings.clone(),
+            s3_credentials_configured: !settings.s3_access_key.is_empty()
+                && !settings.s3_secret_key.is_empty(),
             storage_backends,
             image_sizes,
         }
@@ -0,0 +1,652 @@
+//! Layered Configuration
+//!
+//! Builds the effective [`MediaSettings`] from several layers, each one
+//! overriding the previous:
+//!
+//! 1. Built-in defaults
+//! 2. A TOML or JSON config file
+//! 3. `RUSTMEDIA_*` environment variables
+//! 4. Runtime overrides
+//!
+//! Secrets (S3 credentials) never come from these layers. They are resolved
+//! through a [`SecretProvider`] and are never serialized back out, nor read
+//! from saved settings.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::settings::MediaSettings;
+
+/// Default environment variable prefix
+pub const ENV_PREFIX: &str = "RUSTMEDIA_";
+
+/// Settings keys that are treated as secrets
+pub const SECRET_KEYS: &[&str] = &["s3_access_key", "s3_secret_key"];
+
+/// Configuration error
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse {0}: {1}")]
+    Parse(String, String),
+    #[error("Unsupported config format: {0}")]
+    UnsupportedFormat(String),
+    #[error("Unknown setting: {0}")]
+    UnknownKey(String),
+    #[error("Invalid value for {0}: {1}")]
+    InvalidValue(String, String),
+    #[error("Secret {0} must be provided by a secret provider, not {1}")]
+    SecretNotAllowed(String, String),
+    #[error("Secret provider error: {0}")]
+    Secret(String),
+    #[error("Invalid settings: {0}")]
+    Validation(String),
+}
+
+/// A secret value
+///
+/// Deliberately implements neither `Serialize` nor `Deserialize`, and
+/// `Debug` output is redacted.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    /// Get the secret value
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Check if the secret is empty
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            write!(f, "Secret(<unset>)")
+        } else {
+            write!(f, "Secret(<redacted>)")
+        }
+    }
+}
+
+/// Source of secret values
+pub trait SecretProvider: Send + Sync {
+    /// Provider name shown in the config report
+    fn name(&self) -> &str;
+
+    /// Resolve a secret by settings key (e.g. `s3_secret_key`)
+    fn resolve(&self, key: &str) -> Result<Option<Secret>, ConfigError>;
+}
+
+/// Reads secrets from environment variables (`RUSTMEDIA_S3_SECRET_KEY`)
+pub struct EnvSecretProvider {
+    prefix: String,
+}
+
+impl EnvSecretProvider {
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self { prefix: prefix.into() }
+    }
+}
+
+impl Default for EnvSecretProvider {
+    fn default() -> Self {
+        Self::new(ENV_PREFIX)
+    }
+}
+
+impl SecretProvider for EnvSecretProvider {
+    fn name(&self) -> &str {
+        "env"
+    }
+
+    fn resolve(&self, key: &str) -> Result<Option<Secret>, ConfigError> {
+        let var = env_var_name(&self.prefix, key);
+        match std::env::var(&var) {
+            Ok(value) if !value.is_empty() => Ok(Some(Secret(value))),
+            Ok(_) | Err(std::env::VarError::NotPresent) => Ok(None),
+            Err(e) => Err(ConfigError::Secret(format!("{}: {}", var, e))),
+        }
+    }
+}
+
+/// Reads secrets from one file per key in a directory
+/// (e.g. Docker or Kubernetes mounted secrets)
+pub struct FileSecretProvider {
+    dir: PathBuf,
+}
+
+impl FileSecretProvider {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl SecretProvider for FileSecretProvider {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn resolve(&self, key: &str) -> Result<Option<Secret>, ConfigError> {
+        let path = self.dir.join(key);
+        match std::fs::read_to_string(&path) {
+            Ok(value) => {
+                let value = value.trim();
+                Ok((!value.is_empty()).then(|| Secret::new(value)))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(ConfigError::Secret(format!("{}: {}", path.display(), e))),
+        }
+    }
+}
+
+/// In-memory secrets, useful for embedding and tests
+#[derive(Default)]
+pub struct MemorySecretProvider {
+    secrets: HashMap<String, Secret>,
+}
+
+impl MemorySecretProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a secret
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.secrets.insert(key.into(), Secret::new(value));
+    }
+}
+
+impl SecretProvider for MemorySecretProvider {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn resolve(&self, key: &str) -> Result<Option<Secret>, ConfigError> {
+        Ok(self.secrets.get(key).cloned())
+    }
+}
+
+/// Configuration layer a value came from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "layer", content = "source", rename_all = "snake_case")]
+pub enum ConfigLayer {
+    /// Built-in default
+    Default,
+    /// Config file (path)
+    File(String),
+    /// Environment variable (name)
+    Environment(String),
+    /// Runtime override
+    Override,
+    /// Secret provider (name)
+    Secret(String),
+}
+
+impl fmt::Display for ConfigLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => write!(f, "default"),
+            Self::File(path) => write!(f, "file:{}", path),
+            Self::Environment(var) => write!(f, "env:{}", var),
+            Self::Override => write!(f, "override"),
+            Self::Secret(provider) => write!(f, "secret:{}", provider),
+        }
+    }
+}
+
+/// Effective value of a single setting
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigEntry {
+    /// Layer the value came from
+    pub layer: ConfigLayer,
+    /// Display value (redacted for secrets)
+    pub value: String,
+}
+
+/// Report of where each effective setting came from
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConfigReport {
+    entries: BTreeMap<String, ConfigEntry>,
+}
+
+impl ConfigReport {
+    /// Get the layer a setting came from
+    pub fn source_of(&self, key: &str) -> Option<&ConfigLayer> {
+        self.entries.get(key).map(|e| &e.layer)
+    }
+
+    /// Get a single entry
+    pub fn get(&self, key: &str) -> Option<&ConfigEntry> {
+        self.entries.get(key)
+    }
+
+    /// Iterate over all entries, sorted by key
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &ConfigEntry)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// Keys whose value does not come from the defaults
+    pub fn overridden(&self) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|(_, e)| e.layer != ConfigLayer::Default)
+            .map(|(k, _)| k.as_str())
+            .collect()
+    }
+
+    fn record(&mut self, key: &str, layer: ConfigLayer, value: &Value) {
+        self.entries.insert(key.to_string(), ConfigEntry {
+            layer,
+            value: value.to_string(),
+        });
+    }
+
+    fn record_secret(&mut self, key: &str, layer: ConfigLayer, is_set: bool) {
+        self.entries.insert(key.to_string(), ConfigEntry {
+            layer,
+            value: if is_set { "********" } else { "<unset>" }.to_string(),
+        });
+    }
+}
+
+impl fmt::Display for ConfigReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, entry) in &self.entries {
+            writeln!(f, "{} = {} ({})", key, entry.value, entry.layer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Settings together with the report of how they were built
+#[derive(Debug, Clone)]
+pub struct LoadedSettings {
+    pub settings: MediaSettings,
+    pub report: ConfigReport,
+}
+
+/// Layered settings loader
+pub struct SettingsLoader {
+    /// Config file path
+    file: Option<PathBuf>,
+    /// Environment variable prefix
+    env_prefix: String,
+    /// Explicit environment (None = process environment)
+    env: Option<HashMap<String, String>>,
+    /// Runtime overrides, applied in order
+    overrides: Vec<(String, Value)>,
+    /// Secret provider (None = environment)
+    secrets: Option<Box<dyn SecretProvider>>,
+}
+
+impl SettingsLoader {
+    /// Create a loader with defaults and environment layers
+    pub fn new() -> Self {
+        Self {
+            file: None,
+            env_prefix: ENV_PREFIX.to_string(),
+            env: None,
+            overrides: Vec::new(),
+            secrets: None,
+        }
+    }
+
+    /// Load a TOML or JSON config file
+    pub fn with_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file = Some(path.into());
+        self
+    }
+
+    /// Use a different environment variable prefix
+    pub fn with_env_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.env_prefix = prefix.into();
+        self
+    }
+
+    /// Read environment variables from a map instead of the process
+    pub fn with_env_vars(mut self, vars: HashMap<String, String>) -> Self {
+        self.env = Some(vars);
+        self
+    }
+
+    /// Add a runtime override
+    pub fn with_override(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.overrides.push((key.into(), value.into()));
+        self
+    }
+
+    /// Resolve secrets through a custom provider
+    pub fn with_secret_provider(mut self, provider: impl SecretProvider + 'static) -> Self {
+        self.secrets = Some(Box::new(provider));
+        self
+    }
+
+    /// Build the effective settings
+    pub fn load(&self) -> Result<LoadedSettings, ConfigError> {
+        let mut merged = match serde_json::to_value(MediaSettings::default()) {
+            Ok(Value::Object(map)) => map,
+            Ok(_) => return Err(ConfigError::Parse("defaults".to_string(), "not an object".to_string())),
+            Err(e) => return Err(ConfigError::Parse("defaults".to_string(), e.to_string())),
+        };
+
+        let mut report = ConfigReport::default();
+        for (key, value) in &merged {
+            report.record(key, ConfigLayer::Default, value);
+        }
+
+        // File layer
+        if let Some(ref path) = self.file {
+            let layer = ConfigLayer::File(path.display().to_string());
+            for (key, value) in read_config_file(path)? {
+                apply(&mut merged, &mut report, &key, value, &layer)?;
+            }
+        }
+
+        // Environment layer
+        let keys: Vec<String> = merged.keys().cloned().collect();
+        for key in keys {
+            let var = env_var_name(&self.env_prefix, &key);
+            if let Some(raw) = self.env_var(&var) {
+                let value = parse_env_value(&key, &raw, &merged[&key])?;
+                apply(&mut merged, &mut report, &key, value, &ConfigLayer::Environment(var))?;
+            }
+        }
+
+        // Runtime overrides
+        for (key, value) in &self.overrides {
+            apply(&mut merged, &mut report, key, value.clone(), &ConfigLayer::Override)?;
+        }
+
+        let mut settings: MediaSettings = serde_json::from_value(Value::Object(merged))
+            .map_err(|e| ConfigError::Parse("settings".to_string(), e.to_string()))?;
+
+        // Secrets
+        let default_provider;
+        let provider: &dyn SecretProvider = match self.secrets {
+            Some(ref p) => p.as_ref(),
+            None => {
+                default_provider = EnvSecretProvider::new(self.env_prefix.clone());
+                &default_provider
+            }
+        };
+
+        for key in SECRET_KEYS {
+            match provider.resolve(key)? {
+                Some(secret) => {
+                    report.record_secret(key, ConfigLayer::Secret(provider.name().to_string()), true);
+                    assign_secret(&mut settings, key, secret);
+                }
+                None => report.record_secret(key, ConfigLayer::Default, false),
+            }
+        }
+
+        settings.validate().map_err(|errors| ConfigError::Validation(errors.join("; ")))?;
+
+        Ok(LoadedSettings { settings, report })
+    }
+
+    fn env_var(&self, name: &str) -> Option<String> {
+        match self.env {
+            Some(ref vars) => vars.get(name).cloned(),
+            None => std::env::var(name).ok(),
+        }
+    }
+}
+
+impl Default for SettingsLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Environment variable name for a settings key
+fn env_var_name(prefix: &str, key: &str) -> String {
+    format!("{}{}", prefix, key.to_uppercase())
+}
+
+/// Read a config file into a map of settings
+fn read_config_file(path: &Path) -> Result<Map<String, Value>, ConfigError> {
+    let content = std::fs::read_to_string(path)?;
+    let ext = path.extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    let parse_error = |e: String| ConfigError::Parse(path.display().to_string(), e);
+
+    let value: Value = match ext.as_str() {
+        "toml" => toml::from_str(&content).map_err(|e| parse_error(e.to_string()))?,
+        "json" => serde_json::from_str(&content).map_err(|e| parse_error(e.to_string()))?,
+        _ => return Err(ConfigError::UnsupportedFormat(path.display().to_string())),
+    };
+
+    match value {
+        Value::Object(map) => Ok(map),
+        _ => Err(parse_error("expected a table of settings".to_string())),
+    }
+}
+
+/// Apply a value from a layer onto the merged settings
+fn apply(
+    merged: &mut Map<String, Value>,
+    report: &mut ConfigReport,
+    key: &str,
+    value: Value,
+    layer: &ConfigLayer,
+) -> Result<(), ConfigError> {
+    if SECRET_KEYS.contains(&key) {
+        return Err(ConfigError::SecretNotAllowed(key.to_string(), layer.to_string()));
+    }
+
+    let current = merged.get(key)
+        .ok_or_else(|| ConfigError::UnknownKey(key.to_string()))?;
+
+    if !same_kind(current, &value) {
+        return Err(ConfigError::InvalidValue(
+            key.to_string(),
+            format!("expected {}, got {}", kind_name(current), kind_name(&value)),
+        ));
+    }
+
+    report.record(key, layer.clone(), &value);
+    merged.insert(key.to_string(), value);
+    Ok(())
+}
+
+/// Parse an environment variable using the type of the current value
+fn parse_env_value(key: &str, raw: &str, current: &Value) -> Result<Value, ConfigError> {
+    let invalid = |message: &str| ConfigError::InvalidValue(key.to_string(), message.to_string());
+    let raw = raw.trim();
+
+    match current {
+        Value::Bool(_) => match raw.to_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(Value::Bool(true)),
+            "0" | "false" | "no" | "off" => Ok(Value::Bool(false)),
+            _ => Err(invalid("expected a boolean")),
+        },
+        Value::Number(_) => {
+            if let Ok(n) = raw.parse::<u64>() {
+                Ok(Value::from(n))
+            } else if let Ok(n) = raw.parse::<i64>() {
+                Ok(Value::from(n))
+            } else {
+                raw.parse::<f64>()
+                    .ok()
+                    .and_then(serde_json::Number::from_f64)
+                    .map(Value::Number)
+                    .ok_or_else(|| invalid("expected a number"))
+            }
+        }
+        Value::String(_) => Ok(Value::String(raw.to_string())),
+        Value::Array(_) if !raw.starts_with('[') => Ok(Value::Array(
+            raw.split(',')
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .map(|s| Value::String(s.to_string()))
+                .collect(),
+        )),
+        // Optional settings: JSON when it parses (`0.98`, `null`), else a
+        // plain string (`WebP`)
+        Value::Null => Ok(serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))),
+        _ => serde_json::from_str(raw).map_err(|e| invalid(&e.to_string())),
+    }
+}
+
+fn same_kind(a: &Value, b: &Value) -> bool {
+    std::mem::discriminant(a) == std::mem::discriminant(b) || a.is_null() || b.is_null()
+}
+
+fn kind_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "table",
+    }
+}
+
+fn assign_secret(settings: &mut MediaSettings, key: &str, secret: Secret) {
+    match key {
+        "s3_access_key" => settings.s3_access_key = secret,
+        "s3_secret_key" => settings.s3_secret_key = secret,
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use crate::models::ImageFormat;
+
+    fn env(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_defaults_only() {
+        let loaded = SettingsLoader::new()
+            .with_env_vars(HashMap::new())
+            .with_secret_provider(MemorySecretProvider::new())
+            .load()
+            .unwrap();
+
+        assert_eq!(loaded.settings.jpeg_quality, 85);
+        assert_eq!(loaded.report.source_of("jpeg_quality"), Some(&ConfigLayer::Default));
+        assert!(loaded.report.overridden().is_empty());
+    }
+
+    #[test]
+    fn test_layer_precedence() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rustmedia.toml");
+        std::fs::write(&path, "jpeg_quality = 70\nwebp_quality = 60\nstorage_path = \"/srv/media\"\n").unwrap();
+
+        let loaded = SettingsLoader::new()
+            .with_file(&path)
+            .with_env_vars(env(&[
+                ("RUSTMEDIA_WEBP_QUALITY", "75"),
+                ("RUSTMEDIA_AUTO_OPTIMIZE", "off"),
+                ("RUSTMEDIA_ALLOWED_EXTENSIONS", "jpg, png"),
+            ]))
+            .with_override("storage_path", "/tmp/media")
+            .with_secret_provider(MemorySecretProvider::new())
+            .load()
+            .unwrap();
+
+        let settings = &loaded.settings;
+        assert_eq!(settings.jpeg_quality, 70);
+        assert_eq!(settings.webp_quality, 75);
+        assert!(!settings.auto_optimize);
+        assert_eq!(settings.allowed_extensions, vec!["jpg", "png"]);
+        assert_eq!(settings.storage_path, "/tmp/media");
+
+        let report = &loaded.report;
+        assert!(matches!(report.source_of("jpeg_quality"), Some(ConfigLayer::File(_))));
+        assert_eq!(
+            report.source_of("webp_quality"),
+            Some(&ConfigLayer::Environment("RUSTMEDIA_WEBP_QUALITY".to_string()))
+        );
+        assert_eq!(report.source_of("storage_path"), Some(&ConfigLayer::Override));
+        assert_eq!(report.source_of("png_compression"), Some(&ConfigLayer::Default));
+    }
+
+    #[test]
+    fn test_secrets_are_never_serialized() {
+        let mut secrets = MemorySecretProvider::new();
+        secrets.insert("s3_access_key", "AKIAEXAMPLE");
+        secrets.insert("s3_secret_key", "super-secret-value");
+
+        let loaded = SettingsLoader::new()
+            .with_env_vars(HashMap::new())
+            .with_override("storage_backend", "s3")
+            .with_override("s3_bucket", "media")
+            .with_secret_provider(secrets)
+            .load()
+            .unwrap();
+
+        assert_eq!(loaded.settings.s3_secret_key.expose(), "super-secret-value");
+        assert_eq!(
+            loaded.report.source_of("s3_secret_key"),
+            Some(&ConfigLayer::Secret("memory".to_string()))
+        );
+
+        let json = serde_json::to_string(&loaded.settings).unwrap();
+        assert!(!json.contains("super-secret-value"));
+        assert!(!json.contains("AKIAEXAMPLE"));
+        assert!(!format!("{:?}", loaded.settings).contains("super-secret-value"));
+        assert!(!loaded.report.to_string().contains("super-secret-value"));
+    }
+
+    #[test]
+    fn test_secret_in_file_rejected() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rustmedia.json");
+        std::fs::write(&path, r#"{"s3_secret_key": "oops"}"#).unwrap();
+
+        let result = SettingsLoader::new()
+            .with_file(&path)
+            .with_env_vars(HashMap::new())
+            .load();
+
+        assert!(matches!(result, Err(ConfigError::SecretNotAllowed(..))));
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let result = SettingsLoader::new()
+            .with_env_vars(env(&[("RUSTMEDIA_JPEG_QUALITY", "high")]))
+            .load();
+        assert!(matches!(result, Err(ConfigError::InvalidValue(..))));
+
+        let result = SettingsLoader::new()
+            .with_env_vars(HashMap::new())
+            .with_override("no_such_setting", true)
+            .load();
+        assert!(matches!(result, Err(ConfigError::UnknownKey(_))));
+    }
+
+    #[test]
+    fn test_optional_values_from_env() {
+        let loaded = SettingsLoader::new()
+            .with_env_vars(env(&[("RUSTMEDIA_THUMBNAIL_FORMAT", "WebP"), ("RUSTMEDIA_TARGET_SSIM", "0.98")]))
+            .load()
+            .unwrap();
+        assert_eq!(loaded.settings.thumbnail_format, Some(ImageFormat::WebP));
+        assert_eq!(loaded.settings.target_ssim, Some(0.98));
+    }
+}
@@ -4,7 +4,10 @@ use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::models::{MediaItem, MediaFilter, MediaListResponse, MediaType};
+use crate::models::{
+    MediaItem, MediaFilter, MediaListResponse,
+    MediaRevision, FieldChange, ImageTransformRequest,
+};
 use crate::services::{MediaService, media::MediaStats};
 
 #[derive(Debug, Serialize)]
@@ -19,6 +22,8 @@ pub struct MediaItemResponse {
     pub size: u64,
     pub size_formatted: String,
     pub url: String,
+    /// File version, for cache busting
+    pub version: u32,
     pub dimensions: Option<DimensionsResponse>,
     pub thumbnails: Vec<ThumbnailResponse>,
     pub uploaded_at: String,
@@ -73,7 +78,12 @@ impl MediaHandler {
     }
 
     /// Update media item
-    pub async fn update(&self, id: &str, request: UpdateMediaRequest) -> Result<MediaItemResponse, String> {
+    pub async fn update(
+        &self,
+        id: &str,
+        request: UpdateMediaRequest,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItemResponse, String> {
         let uuid = Uuid::parse_str(id).map_err(|e| e.to_string())?;
 
         let media = self.media_service.update(
@@ -82,11 +92,53 @@ impl MediaHandler {
             request.description,
             request.alt_text,
             request.tags,
+            user_id,
         ).await.map_err(|e| e.to_string())?;
 
         Ok(Self::to_response(&media))
     }
 
+    /// Edit an image (crop, resize, rotate, filters)
+    pub async fn edit_image(
+        &self,
+        id: &str,
+        request: ImageTransformRequest,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItemResponse, String> {
+        let uuid = Uuid::parse_str(id).map_err(|e| e.to_string())?;
+
+        let media = self.media_service.edit(uuid, &request, user_id).await.map_err(|e| e.to_string())?;
+        Ok(Self::to_response(&media))
+    }
+
+    /// List revisions of a media item
+    pub async fn revisions(&self, id: &str) -> Result<Vec<MediaRevision>, String> {
+        let uuid = Uuid::parse_str(id).map_err(|e| e.to_string())?;
+        Ok(self.media_service.list_revisions(uuid).await)
+    }
+
+    /// Metadata changes between two revisions
+    pub async fn diff_revisions(&self, id: &str, from: u32, to: u32) -> Result<Vec<FieldChange>, String> {
+        let uuid = Uuid::parse_str(id).map_err(|e| e.to_string())?;
+        self.media_service.diff_revisions(uuid, from, to).await.map_err(|e| e.to_string())
+    }
+
+    /// Restore a revision
+    pub async fn restore_revision(
+        &self,
+        id: &str,
+        number: u32,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItemResponse, String> {
+        let uuid = Uuid::parse_str(id).map_err(|e| e.to_string())?;
+
+        let media = self.media_service.restore_revision(uuid, number, user_id)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(Self::to_response(&media))
+    }
+
     /// Delete media item
     pub async fn delete(&self, id: &str, permanent: bool) -> Result<(), String> {
         let uuid = Uuid::parse_str(id).map_err(|e| e.to_string())?;
@@ -135,6 +187,7 @@ impl MediaHandler {
             size: media.size,
             size_formatted: media.formatted_size(),
             url: media.url.clone(),
+            version: media.version,
             dimensions: media.dimensions.map(|d| DimensionsResponse {
                 width: d.width,
                 height: d.height,
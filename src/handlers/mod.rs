@@ -3,7 +3,11 @@
 pub mod media;
 pub mod folder;
 pub mod upload;
+pub mod multipart;
+pub mod sse;
+pub mod tus;
 
 pub use media::MediaHandler;
 pub use folder::FolderHandler;
 pub use upload::UploadHandler;
+pub use tus::TusHandler;
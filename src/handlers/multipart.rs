@@ -0,0 +1,388 @@
+//! Multipart Uploads
+//!
+//! Streaming `multipart/form-data` parser for upload forms. File parts are
+//! written to temporary storage as they arrive; text fields are mapped onto
+//! [`UploadRequest`] whatever their position in the body.
+
+use bytes::Bytes;
+use futures_util::Stream;
+use serde::Serialize;
+
+use crate::models::DuplicateStrategy;
+use crate::services::upload::{StagedFile, UploadError, UploadService};
+use super::upload::{UploadRequest, UploadResponse};
+
+/// Multipart parsing error (the whole request is rejected)
+#[derive(Debug, thiserror::Error)]
+pub enum MultipartError {
+    #[error("Invalid multipart content type: {0}")]
+    ContentType(String),
+    #[error("Malformed multipart body: {0}")]
+    Malformed(String),
+    #[error("Request body exceeds {0} bytes")]
+    TooLarge(u64),
+    #[error("Too many files (max: {0})")]
+    TooManyFiles(usize),
+    #[error("Field {0} exceeds {1} bytes")]
+    FieldTooLarge(String, u64),
+    #[error("Invalid value for field {0}: {1}")]
+    InvalidField(String, String),
+    #[error("Upload error: {0}")]
+    Upload(#[from] UploadError),
+}
+
+impl From<multer::Error> for MultipartError {
+    fn from(e: multer::Error) -> Self {
+        match e {
+            multer::Error::StreamSizeExceeded { limit } => Self::TooLarge(limit),
+            multer::Error::NoMultipart | multer::Error::NoBoundary | multer::Error::DecodeContentType(_) => {
+                Self::ContentType(e.to_string())
+            }
+            e => Self::Malformed(e.to_string()),
+        }
+    }
+}
+
+/// Multipart size and count limits
+#[derive(Debug, Clone)]
+pub struct MultipartLimits {
+    /// Largest file part (also capped by the upload service's maximum file size)
+    pub max_part_size: u64,
+    /// Largest request body
+    pub max_total_size: u64,
+    /// Most file parts per request
+    pub max_files: usize,
+    /// Largest text field
+    pub max_field_size: u64,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        Self {
+            max_part_size: 100 * 1024 * 1024, // 100MB
+            max_total_size: 500 * 1024 * 1024, // 500MB
+            max_files: 20,
+            max_field_size: 64 * 1024, // 64KB
+        }
+    }
+}
+
+/// File part received in a form
+pub struct FilePart {
+    /// Form field name
+    pub field: String,
+    /// Client filename
+    pub filename: String,
+    /// Staged contents, or why the part was refused
+    pub file: Result<StagedFile, UploadError>,
+}
+
+/// Parsed upload form
+pub struct MultipartForm {
+    /// Upload options from the text fields
+    pub request: UploadRequest,
+    /// File parts in arrival order
+    pub files: Vec<FilePart>,
+}
+
+/// Outcome of one file in a multipart upload
+#[derive(Debug, Serialize)]
+pub struct MultipartFileResult {
+    /// Form field name
+    pub field: String,
+    /// Client filename
+    pub filename: String,
+    /// Stored media, on success
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media: Option<UploadResponse>,
+    /// Failure reason
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Multipart upload response
+#[derive(Debug, Serialize)]
+pub struct MultipartUploadResponse {
+    pub files: Vec<MultipartFileResult>,
+    pub uploaded: usize,
+    pub failed: usize,
+}
+
+/// Parse a `multipart/form-data` body, staging file parts in storage
+///
+/// A file part over `max_part_size` is drained and recorded as failed;
+/// every other limit violation rejects the request and discards the files
+/// staged so far.
+pub async fn parse<S, O, E>(
+    content_type: &str,
+    body: S,
+    limits: &MultipartLimits,
+    upload_service: &UploadService,
+) -> Result<MultipartForm, MultipartError>
+where
+    S: Stream<Item = Result<O, E>> + Send + 'static,
+    O: Into<Bytes> + 'static,
+    E: Into<Box<dyn std::error::Error + Send + Sync>> + 'static,
+{
+    let mut form = MultipartForm { request: UploadRequest::default(), files: Vec::new() };
+
+    let result = read_form(content_type, body, limits, upload_service, &mut form).await;
+    if let Err(e) = result {
+        for part in form.files {
+            if let Ok(file) = part.file {
+                upload_service.discard_staged(file).await;
+            }
+        }
+        return Err(e);
+    }
+
+    Ok(form)
+}
+
+async fn read_form<S, O, E>(
+    content_type: &str,
+    body: S,
+    limits: &MultipartLimits,
+    upload_service: &UploadService,
+    form: &mut MultipartForm,
+) -> Result<(), MultipartError>
+where
+    S: Stream<Item = Result<O, E>> + Send + 'static,
+    O: Into<Bytes> + 'static,
+    E: Into<Box<dyn std::error::Error + Send + Sync>> + 'static,
+{
+    let boundary = multer::parse_boundary(content_type)?;
+    let constraints = multer::Constraints::new()
+        .size_limit(multer::SizeLimit::new().whole_stream(limits.max_total_size));
+    let mut multipart = multer::Multipart::with_constraints(body, boundary, constraints);
+
+    while let Some(mut field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or_default().to_string();
+
+        let Some(filename) = field.file_name().map(str::to_string) else {
+            let mut value = Vec::new();
+            while let Some(chunk) = field.chunk().await? {
+                if (value.len() + chunk.len()) as u64 > limits.max_field_size {
+                    return Err(MultipartError::FieldTooLarge(name, limits.max_field_size));
+                }
+                value.extend_from_slice(&chunk);
+            }
+            let value = String::from_utf8(value)
+                .map_err(|_| MultipartError::InvalidField(name.clone(), "not UTF-8".to_string()))?;
+            apply_field(&mut form.request, &name, value)?;
+            continue;
+        };
+
+        // Browsers send an empty part for a file input left blank
+        if filename.is_empty() {
+            while field.chunk().await?.is_some() {}
+            continue;
+        }
+
+        if form.files.len() >= limits.max_files {
+            return Err(MultipartError::TooManyFiles(limits.max_files));
+        }
+
+        let mut staged = upload_service.stage(limits.max_part_size);
+        let mut refused = None;
+        while let Some(chunk) = field.chunk().await? {
+            if refused.is_some() {
+                continue;
+            }
+            if let Err(e) = upload_service.write_staged(&mut staged, &chunk).await {
+                refused = Some(e);
+            }
+        }
+
+        let file = match refused {
+            Some(e) => {
+                upload_service.discard_staged(staged).await;
+                Err(e)
+            }
+            None => Ok(staged),
+        };
+        form.files.push(FilePart { field: name, filename, file });
+    }
+
+    Ok(())
+}
+
+/// Map a text field onto the upload request (unknown fields are ignored)
+fn apply_field(request: &mut UploadRequest, name: &str, value: String) -> Result<(), MultipartError> {
+    let invalid = |reason: &str| MultipartError::InvalidField(name.to_string(), reason.to_string());
+    let text = |value: String| {
+        let value = value.trim().to_string();
+        (!value.is_empty()).then_some(value)
+    };
+
+    match name {
+        "folder_id" => request.folder_id = text(value),
+        "title" => request.title = text(value),
+        "description" => request.description = text(value),
+        "alt_text" => request.alt_text = text(value),
+        "tags" | "tags[]" => {
+            let tags = request.tags.get_or_insert_with(Vec::new);
+            tags.extend(value.split(',').map(str::trim).filter(|t| !t.is_empty()).map(str::to_string));
+        }
+        "optimize" => request.optimize = Some(parse_bool(&value).ok_or_else(|| invalid("expected a boolean"))?),
+        "generate_thumbnails" => {
+            request.generate_thumbnails = Some(parse_bool(&value).ok_or_else(|| invalid("expected a boolean"))?);
+        }
+        "allow_degraded" => {
+            request.allow_degraded = Some(parse_bool(&value).ok_or_else(|| invalid("expected a boolean"))?);
+        }
+        "on_duplicate" => {
+            request.on_duplicate = Some(DuplicateStrategy::from_name(&value).ok_or_else(|| invalid("unknown strategy"))?);
+        }
+        "idempotency_key" => request.idempotency_key = text(value),
+        "batch_id" => request.batch_id = text(value),
+        "batch_index" => {
+            request.batch_index = Some(value.trim().parse().map_err(|_| invalid("expected a file index"))?);
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "1" | "on" | "yes" => Some(true),
+        "false" | "0" | "off" | "no" | "" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::handlers::UploadHandler;
+    use crate::services::testing::TestServices;
+
+    const BOUNDARY: &str = "X-BOUNDARY";
+
+    fn handler(dir: &std::path::Path) -> UploadHandler {
+        let services = TestServices::new(dir);
+        UploadHandler::new(Arc::new(services.upload_service()), services.media)
+    }
+
+    fn body(parts: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, filename, data) in parts {
+            body.extend_from_slice(format!("--{}\r\n", BOUNDARY).as_bytes());
+            match filename {
+                Some(filename) => body.extend_from_slice(format!(
+                    "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: text/plain\r\n\r\n",
+                    name, filename
+                ).as_bytes()),
+                None => body.extend_from_slice(format!(
+                    "Content-Disposition: form-data; name=\"{}\"\r\n\r\n", name
+                ).as_bytes()),
+            }
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+        body
+    }
+
+    /// Body delivered in small blocks, as a network stream would
+    fn stream(body: Vec<u8>) -> impl Stream<Item = Result<Vec<u8>, std::io::Error>> + Send + 'static {
+        let blocks: Vec<_> = body.chunks(7).map(|c| Ok(c.to_vec())).collect();
+        futures_util::stream::iter(blocks)
+    }
+
+    #[tokio::test]
+    async fn test_upload_multipart() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = handler(dir.path());
+        handler.set_multipart_limits(MultipartLimits { max_part_size: 64, ..MultipartLimits::default() });
+
+        let large = vec![b'x'; 100];
+        let body = body(&[
+            ("file", Some("a.txt"), b"first file"),
+            ("file", Some("b.txt"), b"second file"),
+            ("file", Some("big.txt"), &large),
+            ("file", Some(""), b""),
+            // Fields after the files still apply to them
+            ("title", None, b"Notes"),
+            ("tags", None, b"a, b"),
+        ]);
+        let content_type = format!("multipart/form-data; boundary={}", BOUNDARY);
+
+        let response = handler.upload_multipart(&content_type, stream(body), None).await.unwrap();
+        assert_eq!((response.uploaded, response.failed), (2, 1));
+        assert_eq!(response.files[0].filename, "a.txt");
+        assert_eq!(response.files[1].media.as_ref().unwrap().size, 11);
+        assert!(response.files[2].error.as_ref().unwrap().contains("too large"));
+
+        // Staged files are gone
+        assert!(std::fs::read_dir(dir.path().join("temp/staged")).unwrap().next().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_multipart_idempotency_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let handler = handler(dir.path());
+        let content_type = format!("multipart/form-data; boundary={}", BOUNDARY);
+        let form = || body(&[
+            ("idempotency_key", None, b"form-1"),
+            ("file", Some("a.txt"), b"first file"),
+            ("file", Some("b.txt"), b"second file"),
+        ]);
+
+        let first = handler.upload_multipart(&content_type, stream(form()), None).await.unwrap();
+        let retry = handler.upload_multipart(&content_type, stream(form()), None).await.unwrap();
+        assert_eq!(retry.uploaded, 2);
+        for (a, b) in first.files.iter().zip(&retry.files) {
+            assert_eq!(a.media.as_ref().unwrap().id, b.media.as_ref().unwrap().id);
+        }
+        assert_ne!(first.files[0].media.as_ref().unwrap().id, first.files[1].media.as_ref().unwrap().id);
+        assert!(std::fs::read_dir(dir.path().join("temp/staged")).unwrap().next().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_multipart_request_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = handler(dir.path());
+        let content_type = format!("multipart/form-data; boundary={}", BOUNDARY);
+
+        let err = handler.upload_multipart("application/json", stream(body(&[])), None).await.unwrap_err();
+        assert!(err.contains("content type"));
+
+        handler.set_multipart_limits(MultipartLimits { max_files: 1, ..MultipartLimits::default() });
+        let two = body(&[("file", Some("a.txt"), b"one"), ("file", Some("b.txt"), b"two")]);
+        let err = handler.upload_multipart(&content_type, stream(two), None).await.unwrap_err();
+        assert!(err.contains("Too many files"));
+        assert!(std::fs::read_dir(dir.path().join("temp/staged")).unwrap().next().is_none());
+
+        handler.set_multipart_limits(MultipartLimits { max_total_size: 100, ..MultipartLimits::default() });
+        let big = body(&[("file", Some("a.txt"), &[b'x'; 200])]);
+        let err = handler.upload_multipart(&content_type, stream(big), None).await.unwrap_err();
+        assert!(err.contains("exceeds 100 bytes"));
+    }
+
+    #[test]
+    fn test_apply_field() {
+        let mut request = UploadRequest::default();
+        apply_field(&mut request, "title", " Logo ".to_string()).unwrap();
+        apply_field(&mut request, "tags", "brand, header".to_string()).unwrap();
+        apply_field(&mut request, "tags[]", "print".to_string()).unwrap();
+        apply_field(&mut request, "optimize", "off".to_string()).unwrap();
+        apply_field(&mut request, "on_duplicate", "return-existing".to_string()).unwrap();
+        apply_field(&mut request, "idempotency_key", "k1".to_string()).unwrap();
+        apply_field(&mut request, "unknown", "ignored".to_string()).unwrap();
+
+        assert_eq!(request.title.as_deref(), Some("Logo"));
+        assert_eq!(request.tags, Some(vec!["brand".to_string(), "header".to_string(), "print".to_string()]));
+        assert_eq!(request.optimize, Some(false));
+        assert_eq!(request.on_duplicate, Some(DuplicateStrategy::ReturnExisting));
+        assert_eq!(request.idempotency_key.as_deref(), Some("k1"));
+
+        assert!(matches!(
+            apply_field(&mut request, "generate_thumbnails", "maybe".to_string()),
+            Err(MultipartError::InvalidField(..))
+        ));
+    }
+}
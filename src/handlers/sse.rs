@@ -0,0 +1,131 @@
+//! Server-Sent Events
+//!
+//! Streams upload batch progress as a `text/event-stream` body. A client
+//! gets a `snapshot` of the batch first, then a `progress` event per file
+//! state change, and a final `complete` event once every file is done or
+//! failed.
+
+use std::time::Duration;
+use futures_util::stream::{self, Stream};
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+
+use crate::services::batch::BatchEvent;
+use crate::services::UploadBatches;
+
+/// Content type of event streams
+pub const CONTENT_TYPE: &str = "text/event-stream";
+
+/// Idle time after which a comment is sent to keep proxies from closing
+/// the connection
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Format one event
+pub fn event(name: &str, data: &impl Serialize) -> String {
+    // serde_json escapes newlines, so the payload fits on one `data:` line
+    let data = serde_json::to_string(data).unwrap_or_else(|_| "null".to_string());
+    format!("event: {}\ndata: {}\n\n", name, data)
+}
+
+/// Progress events of one batch
+///
+/// The stream ends after the `complete` event, or right away when the
+/// batch is unknown.
+pub fn batch_events(batches: UploadBatches, batch_id: Uuid) -> impl Stream<Item = String> {
+    let state = BatchStream {
+        events: batches.subscribe(),
+        batches,
+        batch_id,
+        started: false,
+        finished: false,
+    };
+
+    stream::unfold(state, |mut state| async move {
+        let chunk = state.next().await?;
+        Some((chunk, state))
+    })
+}
+
+struct BatchStream {
+    batches: UploadBatches,
+    batch_id: Uuid,
+    events: broadcast::Receiver<BatchEvent>,
+    started: bool,
+    finished: bool,
+}
+
+impl BatchStream {
+    async fn next(&mut self) -> Option<String> {
+        if self.finished {
+            return None;
+        }
+        if !self.started {
+            self.started = true;
+            return self.snapshot().await;
+        }
+
+        loop {
+            match tokio::time::timeout(KEEP_ALIVE, self.events.recv()).await {
+                Err(_) => return Some(": keep-alive\n\n".to_string()),
+                Ok(Ok(change)) if change.batch_id == self.batch_id => {
+                    let mut chunk = event("progress", &change);
+                    if change.finished {
+                        self.finished = true;
+                        if let Some(batch) = self.batches.get(self.batch_id).await {
+                            chunk.push_str(&event("complete", &batch));
+                        }
+                    }
+                    return Some(chunk);
+                }
+                Ok(Ok(_)) => continue,
+                // Missed changes are covered by a fresh snapshot
+                Ok(Err(RecvError::Lagged(_))) => return self.snapshot().await,
+                Ok(Err(RecvError::Closed)) => return None,
+            }
+        }
+    }
+
+    async fn snapshot(&mut self) -> Option<String> {
+        let batch = self.batches.get(self.batch_id).await?;
+        let mut chunk = event("snapshot", &batch);
+        if batch.is_finished() {
+            self.finished = true;
+            chunk.push_str(&event("complete", &batch));
+        }
+        Some(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use crate::models::{BatchFileRef, FileState};
+
+    #[tokio::test]
+    async fn test_batch_events() {
+        let batches = UploadBatches::new();
+        let batch = batches.create(vec![("a.txt".into(), 3)], None).await.unwrap();
+        let file = batches.file(BatchFileRef { batch_id: batch.id, index: 0 }).await.unwrap();
+
+        let mut events = Box::pin(batch_events(batches.clone(), batch.id));
+        let snapshot = events.next().await.unwrap();
+        assert!(snapshot.starts_with("event: snapshot\ndata: {"));
+        assert!(snapshot.ends_with("\n\n"));
+
+        file.set_state(FileState::Scanning).await;
+        file.fail("rejected").await;
+
+        let progress = events.next().await.unwrap();
+        assert!(progress.contains("\"state\":\"scanning\""));
+        let last = events.next().await.unwrap();
+        assert!(last.starts_with("event: progress\n"));
+        assert!(last.contains("event: complete\n"));
+        assert!(events.next().await.is_none());
+
+        // Unknown batches end right away
+        let mut events = Box::pin(batch_events(batches, Uuid::new_v4()));
+        assert!(events.next().await.is_none());
+    }
+}
@@ -0,0 +1,305 @@
+//! tus Protocol Handler
+//!
+//! Maps `http` requests onto [`TusService`] so any framework can mount the
+//! endpoint by converting its request/response types.
+
+use std::sync::Arc;
+use http::{header, HeaderMap, HeaderValue, Method, Request, Response, StatusCode};
+use uuid::Uuid;
+
+use crate::models::{ChecksumAlgorithm, TusUpload};
+use crate::services::tus::{
+    parse_checksum, parse_metadata, TusError, TusService, TUS_EXTENSIONS, TUS_VERSION,
+};
+use crate::services::media::MediaError;
+use crate::services::scanner::ScanError;
+use crate::services::upload::UploadError;
+
+/// Status used by the checksum extension for mismatches
+const CHECKSUM_MISMATCH: u16 = 460;
+
+/// tus handler
+pub struct TusHandler {
+    tus_service: Arc<TusService>,
+    /// Endpoint path the handler is mounted on (e.g. `/api/media/tus`)
+    endpoint: String,
+}
+
+impl TusHandler {
+    pub fn new(tus_service: Arc<TusService>, endpoint: impl Into<String>) -> Self {
+        Self {
+            tus_service,
+            endpoint: endpoint.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Handle a tus request
+    pub async fn handle(&self, request: Request<Vec<u8>>, user_id: Option<Uuid>) -> Response<Vec<u8>> {
+        let result = if request.method() == Method::OPTIONS {
+            Ok(self.options())
+        } else if let Some(response) = self.check_version(request.headers()) {
+            Ok(response)
+        } else {
+            let method = request.headers()
+                .get("x-http-method-override")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|m| Method::from_bytes(m.as_bytes()).ok())
+                .unwrap_or_else(|| request.method().clone());
+
+            match (method, self.upload_id(request.uri().path())) {
+                (Method::POST, None) => self.create(&request, user_id).await,
+                (Method::HEAD, Some(Ok(id))) => self.head(id, user_id).await,
+                (Method::PATCH, Some(Ok(id))) => self.patch(id, &request, user_id).await,
+                (Method::DELETE, Some(Ok(id))) => self.delete(id, user_id).await,
+                (_, Some(Err(()))) => Ok(Self::status(StatusCode::NOT_FOUND)),
+                _ => Ok(Self::status(StatusCode::METHOD_NOT_ALLOWED)),
+            }
+        };
+
+        let mut response = result.unwrap_or_else(|e| Self::error_response(&e));
+        response.headers_mut().insert("tus-resumable", HeaderValue::from_static(TUS_VERSION));
+        response
+    }
+
+    /// OPTIONS: advertise server capabilities
+    fn options(&self) -> Response<Vec<u8>> {
+        let algorithms = ChecksumAlgorithm::all()
+            .iter()
+            .map(|a| a.name())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let mut response = Self::status(StatusCode::NO_CONTENT);
+        let headers = response.headers_mut();
+        headers.insert("tus-version", HeaderValue::from_static(TUS_VERSION));
+        headers.insert("tus-extension", HeaderValue::from_static(TUS_EXTENSIONS));
+        headers.insert("tus-max-size", HeaderValue::from(self.tus_service.settings().max_size));
+        if let Ok(value) = HeaderValue::from_str(&algorithms) {
+            headers.insert("tus-checksum-algorithm", value);
+        }
+        response
+    }
+
+    /// POST: create an upload, optionally with the first bytes
+    async fn create(&self, request: &Request<Vec<u8>>, user_id: Option<Uuid>) -> Result<Response<Vec<u8>>, TusError> {
+        let headers = request.headers();
+
+        let length = Self::header_u64(headers, "upload-length")?
+            .ok_or_else(|| TusError::InvalidRequest("Missing Upload-Length".to_string()))?;
+
+        let metadata = match Self::header_str(headers, "upload-metadata") {
+            Some(value) => parse_metadata(value)?,
+            None => Default::default(),
+        };
+
+        let initial_data = if request.body().is_empty() {
+            None
+        } else {
+            Self::check_content_type(headers)?;
+            Some(request.body().as_slice())
+        };
+
+        let upload = self.tus_service.create(length, metadata, user_id, initial_data).await?;
+
+        let mut response = Self::status(StatusCode::CREATED);
+        let location = format!("{}/{}", self.endpoint, upload.id);
+        if let Ok(value) = HeaderValue::from_str(&location) {
+            response.headers_mut().insert(header::LOCATION, value);
+        }
+        Self::upload_headers(response.headers_mut(), &upload);
+        Ok(response)
+    }
+
+    /// HEAD: report the current offset
+    async fn head(&self, id: Uuid, user_id: Option<Uuid>) -> Result<Response<Vec<u8>>, TusError> {
+        self.tus_service.check_owner(id, user_id).await?;
+        let upload = self.tus_service.get(id).await?;
+
+        let mut response = Self::status(StatusCode::OK);
+        let headers = response.headers_mut();
+        headers.insert("upload-length", HeaderValue::from(upload.length));
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        Self::upload_headers(headers, &upload);
+        Ok(response)
+    }
+
+    /// PATCH: append bytes at Upload-Offset
+    async fn patch(
+        &self,
+        id: Uuid,
+        request: &Request<Vec<u8>>,
+        user_id: Option<Uuid>,
+    ) -> Result<Response<Vec<u8>>, TusError> {
+        self.tus_service.check_owner(id, user_id).await?;
+        let headers = request.headers();
+
+        Self::check_content_type(headers)?;
+
+        let offset = Self::header_u64(headers, "upload-offset")?
+            .ok_or_else(|| TusError::InvalidRequest("Missing Upload-Offset".to_string()))?;
+
+        let checksum = Self::header_str(headers, "upload-checksum")
+            .map(parse_checksum)
+            .transpose()?;
+
+        let upload = self.tus_service.append(id, offset, request.body(), checksum).await?;
+
+        let mut response = Self::status(StatusCode::NO_CONTENT);
+        Self::upload_headers(response.headers_mut(), &upload);
+        Ok(response)
+    }
+
+    /// DELETE: terminate an upload
+    async fn delete(&self, id: Uuid, user_id: Option<Uuid>) -> Result<Response<Vec<u8>>, TusError> {
+        self.tus_service.check_owner(id, user_id).await?;
+        self.tus_service.terminate(id).await?;
+        Ok(Self::status(StatusCode::NO_CONTENT))
+    }
+
+    /// Extract the upload ID from `{endpoint}/{id}`
+    fn upload_id(&self, path: &str) -> Option<Result<Uuid, ()>> {
+        let rest = path.trim_end_matches('/').strip_prefix(&self.endpoint)?;
+        let id = rest.strip_prefix('/')?;
+        Some(Uuid::parse_str(id).map_err(|_| ()))
+    }
+
+    /// Returns the rejection response for an unsupported Tus-Resumable version
+    fn check_version(&self, headers: &HeaderMap) -> Option<Response<Vec<u8>>> {
+        match Self::header_str(headers, "tus-resumable") {
+            Some(TUS_VERSION) => None,
+            _ => {
+                let mut response = Self::status(StatusCode::PRECONDITION_FAILED);
+                response.headers_mut().insert("tus-version", HeaderValue::from_static(TUS_VERSION));
+                Some(response)
+            }
+        }
+    }
+
+    fn check_content_type(headers: &HeaderMap) -> Result<(), TusError> {
+        match Self::header_str(headers, "content-type") {
+            Some("application/offset+octet-stream") => Ok(()),
+            _ => Err(TusError::InvalidRequest(
+                "Content-Type must be application/offset+octet-stream".to_string(),
+            )),
+        }
+    }
+
+    fn upload_headers(headers: &mut HeaderMap, upload: &TusUpload) {
+        headers.insert("upload-offset", HeaderValue::from(upload.offset));
+
+        if upload.media_id.is_none() {
+            let expires = upload.expires_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+            if let Ok(value) = HeaderValue::from_str(&expires) {
+                headers.insert("upload-expires", value);
+            }
+        }
+
+        if let Some(media_id) = upload.media_id {
+            if let Ok(value) = HeaderValue::from_str(&media_id.to_string()) {
+                headers.insert("x-media-id", value);
+            }
+        }
+    }
+
+    fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+        headers.get(name).and_then(|v| v.to_str().ok()).map(|v| v.trim())
+    }
+
+    fn header_u64(headers: &HeaderMap, name: &str) -> Result<Option<u64>, TusError> {
+        Self::header_str(headers, name)
+            .map(|v| v.parse::<u64>().map_err(|_| TusError::InvalidRequest(format!("Invalid {}", name))))
+            .transpose()
+    }
+
+    fn status(status: StatusCode) -> Response<Vec<u8>> {
+        let mut response = Response::new(Vec::new());
+        *response.status_mut() = status;
+        response
+    }
+
+    fn error_response(error: &TusError) -> Response<Vec<u8>> {
+        let status = match error {
+            TusError::NotFound(_) => StatusCode::NOT_FOUND,
+            TusError::Expired(_) => StatusCode::GONE,
+            TusError::OffsetMismatch(..) => StatusCode::CONFLICT,
+            TusError::ExceedsLength(..) | TusError::TooLarge(..) => StatusCode::PAYLOAD_TOO_LARGE,
+            TusError::ChecksumMismatch(_) => {
+                StatusCode::from_u16(CHECKSUM_MISMATCH).unwrap_or(StatusCode::BAD_REQUEST)
+            }
+            TusError::UnsupportedChecksum(_) | TusError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            TusError::Locked => StatusCode::LOCKED,
+            TusError::Upload(UploadError::FileTooLarge(..)) => StatusCode::PAYLOAD_TOO_LARGE,
+            TusError::Upload(UploadError::TypeNotAllowed(_)) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            TusError::Upload(
+                UploadError::InvalidFile(_) | UploadError::ContentRejected(_) | UploadError::Infected(_),
+            ) => StatusCode::UNPROCESSABLE_ENTITY,
+            TusError::Upload(UploadError::ScanFailed(ScanError::TooLarge)) => StatusCode::PAYLOAD_TOO_LARGE,
+            TusError::Upload(UploadError::ScanFailed(_)) => StatusCode::SERVICE_UNAVAILABLE,
+            TusError::Upload(UploadError::RateLimited(_)) => StatusCode::TOO_MANY_REQUESTS,
+            TusError::Upload(UploadError::Media(MediaError::Duplicate { .. })) => StatusCode::CONFLICT,
+            TusError::Storage(_) | TusError::Upload(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        let mut response = Response::new(error.to_string().into_bytes());
+        *response.status_mut() = status;
+        response.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+        if let TusError::Upload(UploadError::RateLimited(limited)) = error {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(limited.retry_after));
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::testing::TestServices;
+
+    fn request(method: Method, uri: &str) -> http::request::Builder {
+        Request::builder().method(method).uri(uri).header("tus-resumable", TUS_VERSION)
+    }
+
+    #[tokio::test]
+    async fn test_uploads_owned_by_creator() {
+        let dir = tempfile::tempdir().unwrap();
+        let services = TestServices::new(dir.path());
+        let tus = Arc::new(TusService::new(Arc::new(services.upload_service()), services.storage));
+        let handler = TusHandler::new(tus, "/api/media/tus");
+        let owner = Some(Uuid::new_v4());
+
+        let create = request(Method::POST, "/api/media/tus")
+            .header("upload-length", "5")
+            .body(Vec::new())
+            .unwrap();
+        let response = handler.handle(create, owner).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let location = response.headers()[header::LOCATION].to_str().unwrap().to_string();
+
+        let patch = || {
+            request(Method::PATCH, &location)
+                .header(header::CONTENT_TYPE, "application/offset+octet-stream")
+                .header("upload-offset", "0")
+                .body(b"hel".to_vec())
+                .unwrap()
+        };
+        let head = || request(Method::HEAD, &location).body(Vec::new()).unwrap();
+        let delete = || request(Method::DELETE, &location).body(Vec::new()).unwrap();
+
+        for other in [Some(Uuid::new_v4()), None] {
+            assert_eq!(handler.handle(head(), other).await.status(), StatusCode::NOT_FOUND);
+            assert_eq!(handler.handle(patch(), other).await.status(), StatusCode::NOT_FOUND);
+            assert_eq!(handler.handle(delete(), other).await.status(), StatusCode::NOT_FOUND);
+        }
+
+        let response = handler.handle(head(), owner).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["upload-offset"], "0");
+
+        let response = handler.handle(patch(), owner).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()["upload-offset"], "3");
+
+        assert_eq!(handler.handle(delete(), owner).await.status(), StatusCode::NO_CONTENT);
+        assert_eq!(handler.handle(head(), owner).await.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -4,18 +4,30 @@ use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::models::{MediaItem, UploadOptions, ChunkedUpload, ChunkInfo};
-use crate::services::{UploadService, MediaService, upload::UploadError};
+use crate::models::{
+    MediaItem, UploadOptions, DuplicateStrategy, DegradedStep, Checksum, BatchFileRef, UploadBatch,
+};
+use crate::services::{UploadService, MediaService, upload::UploadError, batch::FileProgress};
+use super::multipart::{self, FilePart, MultipartFileResult, MultipartLimits, MultipartUploadResponse};
+use super::sse;
 
 #[derive(Debug, Serialize)]
 pub struct UploadResponse {
     pub id: String,
     pub filename: String,
     pub url: String,
+    /// File version, for cache busting
+    pub version: u32,
     pub mime_type: String,
     pub size: u64,
     pub size_formatted: String,
     pub thumbnails: Vec<ThumbnailInfo>,
+    /// Existing item the upload matched, when it was a duplicate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<String>,
+    /// Optional steps that failed (only with `allow_degraded`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub degraded: Vec<DegradedStep>,
 }
 
 #[derive(Debug, Serialize)]
@@ -26,7 +38,7 @@ pub struct ThumbnailInfo {
     pub height: u32,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 pub struct UploadRequest {
     pub folder_id: Option<String>,
     pub title: Option<String>,
@@ -35,6 +47,16 @@ pub struct UploadRequest {
     pub tags: Option<Vec<String>>,
     pub optimize: Option<bool>,
     pub generate_thumbnails: Option<bool>,
+    /// Duplicate handling (`reject`, `return_existing`, `link`, `replace`)
+    pub on_duplicate: Option<DuplicateStrategy>,
+    /// Key making retries of this upload replay the first result
+    pub idempotency_key: Option<String>,
+    /// Keep the upload when thumbnails or dimensions fail
+    pub allow_degraded: Option<bool>,
+    /// Batch to report progress to
+    pub batch_id: Option<String>,
+    /// Position of the (first) file in the batch
+    pub batch_index: Option<usize>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -44,7 +66,13 @@ pub struct ChunkUploadInitRequest {
     pub chunk_size: usize,
     pub total_chunks: usize,
     pub mime_type: Option<String>,
+    /// Whole-file checksum (`sha256:<hex>` or `md5:<hex>`)
+    pub checksum: Option<String>,
     pub folder_id: Option<String>,
+    /// Batch to report progress to
+    pub batch_id: Option<String>,
+    /// Position of the file in the batch
+    pub batch_index: Option<usize>,
 }
 
 #[derive(Debug, Serialize)]
@@ -58,6 +86,8 @@ pub struct ChunkUploadInitResponse {
 pub struct ChunkUploadRequest {
     pub upload_id: String,
     pub chunk_index: usize,
+    /// Chunk checksum (`sha256:<hex>` or `md5:<hex>`)
+    pub checksum: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -77,17 +107,31 @@ pub struct ChunkUploadCompleteResponse {
     pub size: u64,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct BatchCreateRequest {
+    pub files: Vec<BatchFileRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchFileRequest {
+    pub filename: String,
+    pub size: u64,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct UrlUploadRequest {
     pub url: String,
     pub folder_id: Option<String>,
     pub filename: Option<String>,
+    /// Key making retries of this import replay the first result
+    pub idempotency_key: Option<String>,
 }
 
 /// Upload handler
 pub struct UploadHandler {
     upload_service: Arc<UploadService>,
     media_service: Arc<MediaService>,
+    multipart_limits: MultipartLimits,
 }
 
 impl UploadHandler {
@@ -95,9 +139,15 @@ impl UploadHandler {
         Self {
             upload_service,
             media_service,
+            multipart_limits: MultipartLimits::default(),
         }
     }
 
+    /// Configure limits for multipart uploads
+    pub fn set_multipart_limits(&mut self, limits: MultipartLimits) {
+        self.multipart_limits = limits;
+    }
+
     /// Handle single file upload
     pub async fn upload(
         &self,
@@ -105,6 +155,77 @@ impl UploadHandler {
         filename: &str,
         request: UploadRequest,
         user_id: Option<Uuid>,
+    ) -> Result<UploadResponse, String> {
+        let folder_id = request.folder_id
+            .map(|f| Uuid::parse_str(&f))
+            .transpose()
+            .map_err(|e| e.to_string())?;
+        let progress = self.batch_file(request.batch_id.as_deref(), request.batch_index, user_id).await?;
+
+        let options = UploadOptions {
+            folder_id,
+            title: request.title,
+            description: request.description,
+            alt_text: request.alt_text,
+            tags: request.tags.unwrap_or_default(),
+            optimize: request.optimize.unwrap_or(true),
+            generate_thumbnails: request.generate_thumbnails.unwrap_or(true),
+            on_duplicate: request.on_duplicate,
+            allow_degraded: request.allow_degraded.unwrap_or(false),
+        };
+
+        let size = data.len() as u64;
+        let key = request.idempotency_key;
+        let upload = async move {
+            match key.as_deref() {
+                Some(key) => self.upload_service.upload_idempotent(key, data, filename, options, user_id).await,
+                None => self.upload_service.upload(data, filename, options, user_id).await,
+            }
+        };
+
+        let media = match progress {
+            Some(progress) => {
+                progress.received(size).await;
+                progress.run(upload).await
+            }
+            None => upload.await,
+        }.map_err(|e| e.to_string())?;
+
+        Ok(Self::to_response(&media))
+    }
+
+    /// Handle a JSON upload whose `data` is a data URL or base64
+    pub async fn upload_data(
+        &self,
+        request: crate::models::UploadRequest,
+        user_id: Option<Uuid>,
+    ) -> Result<UploadResponse, String> {
+        let data = request.data
+            .ok_or_else(|| "Missing data".to_string())?;
+
+        let options = UploadOptions {
+            folder_id: request.folder_id,
+            title: request.title,
+            description: request.description,
+            alt_text: request.alt_text,
+            tags: request.tags.unwrap_or_default(),
+            ..UploadOptions::default()
+        };
+
+        let media = self.upload_service.upload_encoded(&data, &request.filename, options, user_id)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(Self::to_response(&media))
+    }
+
+    /// Handle a raw body holding a data URL or base64
+    pub async fn upload_encoded_body(
+        &self,
+        body: &str,
+        filename: &str,
+        request: UploadRequest,
+        user_id: Option<Uuid>,
     ) -> Result<UploadResponse, String> {
         let folder_id = request.folder_id
             .map(|f| Uuid::parse_str(&f))
@@ -119,9 +240,11 @@ impl UploadHandler {
             tags: request.tags.unwrap_or_default(),
             optimize: request.optimize.unwrap_or(true),
             generate_thumbnails: request.generate_thumbnails.unwrap_or(true),
+            on_duplicate: request.on_duplicate,
+            allow_degraded: request.allow_degraded.unwrap_or(false),
         };
 
-        let media = self.upload_service.upload(data, filename, options, user_id)
+        let media = self.upload_service.upload_encoded(body, filename, options, user_id)
             .await
             .map_err(|e| e.to_string())?;
 
@@ -129,6 +252,9 @@ impl UploadHandler {
     }
 
     /// Handle multiple file uploads
+    ///
+    /// The files are tracked as a batch while the request runs; use
+    /// [`Self::start_batch`] to get the batch ID before processing starts.
     pub async fn upload_multiple(
         &self,
         files: Vec<(Vec<u8>, String)>,
@@ -138,28 +264,171 @@ impl UploadHandler {
         let folder_uuid = folder_id
             .and_then(|f| Uuid::parse_str(&f).ok());
 
-        let mut results = Vec::new();
-
-        for (data, filename) in files {
-            let options = UploadOptions {
-                folder_id: folder_uuid,
-                title: None,
-                description: None,
-                alt_text: None,
-                tags: vec![],
-                optimize: true,
-                generate_thumbnails: true,
+        let Ok(batch) = self.upload_service.batches().create(Self::batch_entries(&files), user_id).await else {
+            return Vec::new();
+        };
+
+        let options = UploadOptions { folder_id: folder_uuid, ..UploadOptions::default() };
+        upload_batch(&self.upload_service, batch.id, files, options, user_id)
+            .await
+            .into_iter()
+            .map(|r| r.map(|m| Self::to_response(&m)).map_err(|e| e.to_string()))
+            .collect()
+    }
+
+    /// Start uploading files in the background
+    ///
+    /// Returns the batch right away; follow it with [`Self::batch_events`].
+    pub async fn start_batch(
+        &self,
+        files: Vec<(Vec<u8>, String)>,
+        folder_id: Option<String>,
+        user_id: Option<Uuid>,
+    ) -> Result<UploadBatch, String> {
+        let folder_id = folder_id
+            .map(|f| Uuid::parse_str(&f))
+            .transpose()
+            .map_err(|e| e.to_string())?;
+
+        let batch = self.upload_service.batches()
+            .create(Self::batch_entries(&files), user_id)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let upload_service = Arc::clone(&self.upload_service);
+        let options = UploadOptions { folder_id, ..UploadOptions::default() };
+        let batch_id = batch.id;
+        tokio::spawn(async move {
+            upload_batch(&upload_service, batch_id, files, options, user_id).await;
+        });
+
+        Ok(batch)
+    }
+
+    /// Announce files the client is about to upload
+    ///
+    /// Uploads sent with the returned batch ID and each file's index report
+    /// their progress to the batch.
+    pub async fn create_batch(&self, request: BatchCreateRequest, user_id: Option<Uuid>) -> Result<UploadBatch, String> {
+        let files = request.files.into_iter().map(|f| (f.filename, f.size)).collect();
+        self.upload_service.batches().create(files, user_id).await.map_err(|e| e.to_string())
+    }
+
+    /// Get the status of a batch the user started
+    pub async fn get_batch(&self, batch_id: &str, user_id: Option<Uuid>) -> Result<UploadBatch, String> {
+        let uuid = Uuid::parse_str(batch_id).map_err(|e| e.to_string())?;
+        self.upload_service.batches()
+            .get_owned(uuid, user_id)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Progress of a batch the user started as a Server-Sent Events body
+    ///
+    /// Serve the stream with the [`sse::CONTENT_TYPE`] content type.
+    pub async fn batch_events(
+        &self,
+        batch_id: &str,
+        user_id: Option<Uuid>,
+    ) -> Result<impl futures_util::Stream<Item = String>, String> {
+        let batch = self.get_batch(batch_id, user_id).await?;
+        Ok(sse::batch_events(self.upload_service.batches().clone(), batch.id))
+    }
+
+    /// Handle a `multipart/form-data` request body
+    ///
+    /// `content_type` is the request's Content-Type header (it carries the
+    /// boundary) and `body` the request body as a stream of byte blocks.
+    /// Text fields (`folder_id`, `title`, `tags`, ...) apply to every file
+    /// in the form. With an `idempotency_key`, each file is keyed by its
+    /// position in the form. Malformed bodies and exceeded request limits
+    /// fail the whole request; otherwise each file gets its own result.
+    pub async fn upload_multipart<S, O, E>(
+        &self,
+        content_type: &str,
+        body: S,
+        user_id: Option<Uuid>,
+    ) -> Result<MultipartUploadResponse, String>
+    where
+        S: futures_util::Stream<Item = Result<O, E>> + Send + 'static,
+        O: Into<bytes::Bytes> + 'static,
+        E: Into<Box<dyn std::error::Error + Send + Sync>> + 'static,
+    {
+        let form = multipart::parse(content_type, body, &self.multipart_limits, &self.upload_service)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let ids = form.request.folder_id.as_deref().map(Uuid::parse_str).transpose()
+            .and_then(|folder_id| Ok((folder_id, form.request.batch_id.as_deref().map(Uuid::parse_str).transpose()?)));
+        let (folder_id, batch_id) = match ids {
+            Ok(ids) => ids,
+            Err(e) => {
+                for part in form.files {
+                    if let Ok(file) = part.file {
+                        self.upload_service.discard_staged(file).await;
+                    }
+                }
+                return Err(e.to_string());
+            }
+        };
+
+        let request = form.request;
+        let batch_index = request.batch_index.unwrap_or(0);
+        let options = UploadOptions {
+            folder_id,
+            title: request.title,
+            description: request.description,
+            alt_text: request.alt_text,
+            tags: request.tags.unwrap_or_default(),
+            optimize: request.optimize.unwrap_or(true),
+            generate_thumbnails: request.generate_thumbnails.unwrap_or(true),
+            on_duplicate: request.on_duplicate,
+            allow_degraded: request.allow_degraded.unwrap_or(false),
+        };
+
+        let mut files = Vec::with_capacity(form.files.len());
+        for (i, FilePart { field, filename, file }) in form.files.into_iter().enumerate() {
+            let progress = match batch_id {
+                Some(batch_id) => {
+                    let file = BatchFileRef { batch_id, index: batch_index + i };
+                    self.upload_service.batches().file_owned(file, user_id).await.ok()
+                }
+                None => None,
             };
 
-            let result = self.upload_service.upload(data, &filename, options, user_id)
-                .await
-                .map(|m| Self::to_response(&m))
-                .map_err(|e| e.to_string());
+            let size = file.as_ref().map(|f| f.size()).unwrap_or(0);
+            let key = request.idempotency_key.as_ref().map(|key| format!("{}:{}", key, i));
+            let upload = async {
+                match (file, key) {
+                    (Ok(file), Some(key)) => {
+                        self.upload_service.upload_staged_idempotent(&key, file, &filename, options.clone(), user_id).await
+                    }
+                    (Ok(file), None) => self.upload_service.upload_staged(file, &filename, options.clone(), user_id).await,
+                    (Err(e), _) => Err(e),
+                }
+            };
+
+            let result = match progress {
+                Some(progress) => {
+                    progress.received(size).await;
+                    progress.run(upload).await
+                }
+                None => upload.await,
+            };
 
-            results.push(result);
+            let (media, error) = match result {
+                Ok(media) => (Some(Self::to_response(&media)), None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            files.push(MultipartFileResult { field, filename, media, error });
         }
 
-        results
+        let uploaded = files.iter().filter(|f| f.media.is_some()).count();
+        Ok(MultipartUploadResponse {
+            failed: files.len() - uploaded,
+            uploaded,
+            files,
+        })
     }
 
     /// Initialize chunked upload
@@ -173,16 +442,30 @@ impl UploadHandler {
             .transpose()
             .map_err(|e| e.to_string())?;
 
+        let checksum = request.checksum
+            .as_deref()
+            .map(Self::parse_checksum)
+            .transpose()?;
+
+        let progress = self.batch_file(request.batch_id.as_deref(), request.batch_index, user_id).await?;
+
         let upload = self.upload_service.init_chunked_upload(
             &request.filename,
             request.total_size,
             request.chunk_size,
             request.total_chunks,
             request.mime_type,
+            checksum,
             folder_id,
             user_id,
         ).await.map_err(|e| e.to_string())?;
 
+        if let Some(progress) = progress {
+            self.upload_service.attach_batch(upload.id, progress.file())
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+
         Ok(ChunkUploadInitResponse {
             upload_id: upload.id.to_string(),
             chunk_size: upload.chunk_size,
@@ -196,16 +479,25 @@ impl UploadHandler {
         upload_id: &str,
         chunk_index: usize,
         data: Vec<u8>,
+        checksum: Option<&str>,
     ) -> Result<ChunkUploadResponse, String> {
         let uuid = Uuid::parse_str(upload_id).map_err(|e| e.to_string())?;
+        let checksum = checksum.map(Self::parse_checksum).transpose()?;
 
-        let upload = self.upload_service.upload_chunk(uuid, chunk_index, data)
+        let upload = self.upload_service.upload_chunk(uuid, chunk_index, data, checksum)
             .await
             .map_err(|e| e.to_string())?;
 
         let chunks_received = upload.chunks.iter().filter(|c| c.received).count();
         let progress = (chunks_received as f64 / upload.total_chunks as f64) * 100.0;
 
+        if let Some(file) = upload.batch {
+            if let Ok(batch_file) = self.upload_service.batches().file(file).await {
+                let received = upload.chunks.iter().filter(|c| c.received).map(|c| c.size as u64).sum();
+                batch_file.received(received).await;
+            }
+        }
+
         Ok(ChunkUploadResponse {
             upload_id: upload.id.to_string(),
             chunk_index,
@@ -216,15 +508,34 @@ impl UploadHandler {
     }
 
     /// Complete chunked upload
+    ///
+    /// With an idempotency key, retries after a successful completion get
+    /// the same response.
     pub async fn complete_chunked_upload(
         &self,
         upload_id: &str,
+        idempotency_key: Option<&str>,
+        user_id: Option<Uuid>,
     ) -> Result<ChunkUploadCompleteResponse, String> {
         let uuid = Uuid::parse_str(upload_id).map_err(|e| e.to_string())?;
 
-        let media = self.upload_service.complete_chunked_upload(uuid)
-            .await
-            .map_err(|e| e.to_string())?;
+        let batch_file = self.upload_service.get_chunked_upload(uuid).await.and_then(|u| u.batch);
+        let progress = match batch_file {
+            Some(file) => self.upload_service.batches().file(file).await.ok(),
+            None => None,
+        };
+
+        let complete = async {
+            match idempotency_key {
+                Some(key) => self.upload_service.complete_chunked_upload_idempotent(key, uuid, user_id).await,
+                None => self.upload_service.complete_chunked_upload(uuid).await,
+            }
+        };
+
+        let media = match progress {
+            Some(progress) => progress.run(complete).await,
+            None => complete.await,
+        }.map_err(|e| e.to_string())?;
 
         Ok(ChunkUploadCompleteResponse {
             id: media.id.to_string(),
@@ -278,12 +589,32 @@ impl UploadHandler {
             .transpose()
             .map_err(|e| e.to_string())?;
 
-        let media = self.upload_service.upload_from_url(
-            &request.url,
-            request.filename.as_deref(),
-            folder_id,
-            user_id,
-        ).await.map_err(|e| e.to_string())?;
+        let media = match request.idempotency_key.as_deref() {
+            Some(key) => self.upload_service.upload_from_url_idempotent(
+                key,
+                &request.url,
+                request.filename.as_deref(),
+                folder_id,
+                user_id,
+            ).await,
+            None => self.upload_service.upload_from_url(
+                &request.url,
+                request.filename.as_deref(),
+                folder_id,
+                user_id,
+            ).await,
+        }.map_err(|e| e.to_string())?;
+
+        Ok(Self::to_response(&media))
+    }
+
+    /// Replace a media item's file, keeping its ID and URL
+    pub async fn replace(&self, id: &str, data: Vec<u8>, user_id: Option<Uuid>) -> Result<UploadResponse, String> {
+        let id = Uuid::parse_str(id).map_err(|e| e.to_string())?;
+
+        let media = self.upload_service.replace(id, data, user_id)
+            .await
+            .map_err(|e| e.to_string())?;
 
         Ok(Self::to_response(&media))
     }
@@ -304,11 +635,33 @@ impl UploadHandler {
         self.upload_service.get_max_file_size()
     }
 
+    /// Progress handle of the batch file a request names, if any
+    async fn batch_file(
+        &self,
+        batch_id: Option<&str>,
+        index: Option<usize>,
+        user_id: Option<Uuid>,
+    ) -> Result<Option<FileProgress>, String> {
+        let Some(batch_id) = batch_id else { return Ok(None) };
+        let batch_id = Uuid::parse_str(batch_id).map_err(|e| e.to_string())?;
+        let file = BatchFileRef { batch_id, index: index.unwrap_or(0) };
+        self.upload_service.batches().file_owned(file, user_id).await.map(Some).map_err(|e| e.to_string())
+    }
+
+    fn batch_entries(files: &[(Vec<u8>, String)]) -> Vec<(String, u64)> {
+        files.iter().map(|(data, filename)| (filename.clone(), data.len() as u64)).collect()
+    }
+
+    fn parse_checksum(value: &str) -> Result<Checksum, String> {
+        Checksum::parse(value).ok_or_else(|| format!("Invalid checksum: {}", value))
+    }
+
     fn to_response(media: &MediaItem) -> UploadResponse {
         UploadResponse {
             id: media.id.to_string(),
             filename: media.filename.clone(),
             url: media.url.clone(),
+            version: media.version,
             mime_type: media.mime_type.clone(),
             size: media.size,
             size_formatted: media.formatted_size(),
@@ -318,10 +671,39 @@ impl UploadHandler {
                 width: t.width,
                 height: t.height,
             }).collect(),
+            duplicate_of: media.duplicate_of.map(|id| id.to_string()),
+            degraded: media.degraded.clone(),
         }
     }
 }
 
+/// Upload files one after another, reporting to a batch
+async fn upload_batch(
+    upload_service: &UploadService,
+    batch_id: Uuid,
+    files: Vec<(Vec<u8>, String)>,
+    options: UploadOptions,
+    user_id: Option<Uuid>,
+) -> Vec<Result<MediaItem, UploadError>> {
+    let mut results = Vec::with_capacity(files.len());
+
+    for (index, (data, filename)) in files.into_iter().enumerate() {
+        let size = data.len() as u64;
+        let upload = upload_service.upload(data, &filename, options.clone(), user_id);
+
+        let result = match upload_service.batches().file(BatchFileRef { batch_id, index }).await {
+            Ok(progress) => {
+                progress.received(size).await;
+                progress.run(upload).await
+            }
+            Err(_) => upload.await,
+        };
+        results.push(result);
+    }
+
+    results
+}
+
 #[derive(Debug, Serialize)]
 pub struct ChunkedUploadStatus {
     pub upload_id: String,
@@ -0,0 +1,411 @@
+//! Lifecycle Hooks
+//!
+//! Typed hooks that let other plugins observe, veto or adjust media and
+//! folder operations.
+//!
+//! Hooks run in ascending [`MediaHook::priority`] order, ties in
+//! registration order. A "before" hook can veto the operation by returning
+//! [`HookError::Veto`]. Any other error, or a timeout, is logged and
+//! isolated: changes the failing hook made are rolled back and the
+//! remaining hooks still run.
+
+use std::sync::Arc;
+use std::time::Duration;
+use async_trait::async_trait;
+use bytes::Bytes;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::models::{MediaFolder, MediaItem, OptimizationResult, UploadOptions};
+
+/// Hook names as advertised in `plugin_info()`
+pub const MEDIA_UPLOAD: &str = "media.upload";
+pub const MEDIA_DELETE: &str = "media.delete";
+pub const MEDIA_OPTIMIZE: &str = "media.optimize";
+pub const FOLDER_CREATE: &str = "folder.create";
+pub const FOLDER_DELETE: &str = "folder.delete";
+
+/// Default priority (lower runs first)
+pub const DEFAULT_PRIORITY: i32 = 10;
+
+/// Hook error
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum HookError {
+    /// Stop the operation (only honoured by "before" hooks)
+    #[error("Vetoed: {0}")]
+    Veto(String),
+    /// The hook itself failed; the operation continues
+    #[error("Failed: {0}")]
+    Failed(String),
+}
+
+/// Operation vetoed by a hook
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("Rejected by hook {hook}: {reason}")]
+pub struct HookRejection {
+    /// Hook name
+    pub hook: String,
+    /// Reason given by the hook
+    pub reason: String,
+}
+
+/// Upload about to be stored
+#[derive(Debug, Clone)]
+pub struct PendingUpload {
+    /// Filename
+    pub filename: String,
+    /// Detected MIME type
+    pub mime_type: String,
+    /// Size in bytes
+    pub size: u64,
+    /// File contents, replaced to rewrite the file; `None` for files
+    /// streamed to storage, which can be vetoed but not rewritten
+    pub data: Option<Bytes>,
+    /// Upload options (folder, title, tags, ...)
+    pub options: UploadOptions,
+    /// Uploading user
+    pub user_id: Option<Uuid>,
+}
+
+/// Folder about to be created
+#[derive(Debug, Clone)]
+pub struct PendingFolder {
+    /// Folder name
+    pub name: String,
+    /// Parent folder
+    pub parent_id: Option<Uuid>,
+    /// Creating user
+    pub user_id: Option<Uuid>,
+}
+
+/// Lifecycle hook
+///
+/// Every method has a no-op default, so implementors only override the
+/// events they care about.
+#[async_trait]
+pub trait MediaHook: Send + Sync {
+    /// Hook name (used in logs and rejections)
+    fn name(&self) -> &str;
+
+    /// Ordering priority, lower runs first
+    fn priority(&self) -> i32 {
+        DEFAULT_PRIORITY
+    }
+
+    /// Before a file is stored; may rewrite the file or its metadata
+    async fn before_upload(&self, _upload: &mut PendingUpload) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    /// After a media item was created
+    async fn after_upload(&self, _media: &MediaItem) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    /// Before a media item is deleted
+    async fn before_delete(&self, _media: &MediaItem, _permanent: bool) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    /// After a media item was deleted
+    async fn after_delete(&self, _media: &MediaItem, _permanent: bool) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    /// After an uploaded image was optimized and stored
+    async fn after_optimize(&self, _media: &MediaItem, _result: &OptimizationResult) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    /// Before a folder is created; may rename or move it
+    async fn before_folder_create(&self, _folder: &mut PendingFolder) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    /// After a folder was created
+    async fn after_folder_create(&self, _folder: &MediaFolder) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    /// Before a folder is deleted
+    async fn before_folder_delete(&self, _folder: &MediaFolder) -> Result<(), HookError> {
+        Ok(())
+    }
+
+    /// After a folder was deleted
+    async fn after_folder_delete(&self, _folder: &MediaFolder) -> Result<(), HookError> {
+        Ok(())
+    }
+}
+
+/// Registered hook with its registration sequence
+struct Registered {
+    hook: Arc<dyn MediaHook>,
+    sequence: u64,
+}
+
+/// Hook registry
+pub struct HookRegistry {
+    hooks: RwLock<Vec<Registered>>,
+    next_sequence: std::sync::atomic::AtomicU64,
+    timeout: Duration,
+}
+
+impl Default for HookRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HookRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self {
+            hooks: RwLock::new(Vec::new()),
+            next_sequence: std::sync::atomic::AtomicU64::new(0),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Set the time limit for a single hook call
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Register a hook
+    pub async fn register(&self, hook: Arc<dyn MediaHook>) {
+        let sequence = self.next_sequence.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let mut hooks = self.hooks.write().await;
+        hooks.push(Registered { hook, sequence });
+        hooks.sort_by_key(|r| (r.hook.priority(), r.sequence));
+    }
+
+    /// Remove hooks by name. Returns the number removed.
+    pub async fn unregister(&self, name: &str) -> usize {
+        let mut hooks = self.hooks.write().await;
+        let before = hooks.len();
+        hooks.retain(|r| r.hook.name() != name);
+        before - hooks.len()
+    }
+
+    /// Registered hook names in execution order
+    pub async fn names(&self) -> Vec<String> {
+        self.hooks.read().await.iter().map(|r| r.hook.name().to_string()).collect()
+    }
+
+    /// Check if no hooks are registered
+    pub async fn is_empty(&self) -> bool {
+        self.hooks.read().await.is_empty()
+    }
+
+    /// Snapshot of hooks so none are held locked while running
+    async fn ordered(&self) -> Vec<Arc<dyn MediaHook>> {
+        self.hooks.read().await.iter().map(|r| Arc::clone(&r.hook)).collect()
+    }
+
+    /// Run `before_upload` hooks
+    pub async fn before_upload(&self, upload: &mut PendingUpload) -> Result<(), HookRejection> {
+        for hook in self.ordered().await {
+            let snapshot = upload.clone();
+            let result = self.call(hook.as_ref(), "before_upload", hook.before_upload(upload)).await;
+            self.settle_before(hook.as_ref(), result, || *upload = snapshot)?;
+        }
+        Ok(())
+    }
+
+    /// Run `after_upload` hooks
+    pub async fn after_upload(&self, media: &MediaItem) {
+        for hook in self.ordered().await {
+            let result = self.call(hook.as_ref(), "after_upload", hook.after_upload(media)).await;
+            Self::log_failure(hook.as_ref(), "after_upload", result);
+        }
+    }
+
+    /// Run `before_delete` hooks
+    pub async fn before_delete(&self, media: &MediaItem, permanent: bool) -> Result<(), HookRejection> {
+        for hook in self.ordered().await {
+            let result = self.call(hook.as_ref(), "before_delete", hook.before_delete(media, permanent)).await;
+            self.settle_before(hook.as_ref(), result, || {})?;
+        }
+        Ok(())
+    }
+
+    /// Run `after_delete` hooks
+    pub async fn after_delete(&self, media: &MediaItem, permanent: bool) {
+        for hook in self.ordered().await {
+            let result = self.call(hook.as_ref(), "after_delete", hook.after_delete(media, permanent)).await;
+            Self::log_failure(hook.as_ref(), "after_delete", result);
+        }
+    }
+
+    /// Run `after_optimize` hooks
+    pub async fn after_optimize(&self, media: &MediaItem, optimization: &OptimizationResult) {
+        for hook in self.ordered().await {
+            let result = self.call(hook.as_ref(), "after_optimize", hook.after_optimize(media, optimization)).await;
+            Self::log_failure(hook.as_ref(), "after_optimize", result);
+        }
+    }
+
+    /// Run `before_folder_create` hooks
+    pub async fn before_folder_create(&self, folder: &mut PendingFolder) -> Result<(), HookRejection> {
+        for hook in self.ordered().await {
+            let snapshot = folder.clone();
+            let result = self.call(hook.as_ref(), "before_folder_create", hook.before_folder_create(folder)).await;
+            self.settle_before(hook.as_ref(), result, || *folder = snapshot)?;
+        }
+        Ok(())
+    }
+
+    /// Run `after_folder_create` hooks
+    pub async fn after_folder_create(&self, folder: &MediaFolder) {
+        for hook in self.ordered().await {
+            let result = self.call(hook.as_ref(), "after_folder_create", hook.after_folder_create(folder)).await;
+            Self::log_failure(hook.as_ref(), "after_folder_create", result);
+        }
+    }
+
+    /// Run `before_folder_delete` hooks
+    pub async fn before_folder_delete(&self, folder: &MediaFolder) -> Result<(), HookRejection> {
+        for hook in self.ordered().await {
+            let result = self.call(hook.as_ref(), "before_folder_delete", hook.before_folder_delete(folder)).await;
+            self.settle_before(hook.as_ref(), result, || {})?;
+        }
+        Ok(())
+    }
+
+    /// Run `after_folder_delete` hooks
+    pub async fn after_folder_delete(&self, folder: &MediaFolder) {
+        for hook in self.ordered().await {
+            let result = self.call(hook.as_ref(), "after_folder_delete", hook.after_folder_delete(folder)).await;
+            Self::log_failure(hook.as_ref(), "after_folder_delete", result);
+        }
+    }
+
+    /// Run one hook call under the timeout
+    async fn call<F>(&self, hook: &dyn MediaHook, event: &str, future: F) -> Result<(), HookError>
+    where
+        F: std::future::Future<Output = Result<(), HookError>>,
+    {
+        match tokio::time::timeout(self.timeout, future).await {
+            Ok(result) => result,
+            Err(_) => Err(HookError::Failed(format!(
+                "{} timed out in {} after {:?}",
+                hook.name(), event, self.timeout
+            ))),
+        }
+    }
+
+    /// Turn a veto into a rejection; roll back and log any other failure
+    fn settle_before(
+        &self,
+        hook: &dyn MediaHook,
+        result: Result<(), HookError>,
+        rollback: impl FnOnce(),
+    ) -> Result<(), HookRejection> {
+        match result {
+            Ok(()) => Ok(()),
+            Err(HookError::Veto(reason)) => Err(HookRejection {
+                hook: hook.name().to_string(),
+                reason,
+            }),
+            Err(HookError::Failed(error)) => {
+                rollback();
+                tracing::warn!("Hook {} failed: {}", hook.name(), error);
+                Ok(())
+            }
+        }
+    }
+
+    fn log_failure(hook: &dyn MediaHook, event: &str, result: Result<(), HookError>) {
+        if let Err(e) = result {
+            tracing::warn!("Hook {} failed in {}: {}", hook.name(), event, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct Recorder {
+        name: &'static str,
+        priority: i32,
+        log: Arc<Mutex<Vec<String>>>,
+        action: Option<HookError>,
+    }
+
+    #[async_trait]
+    impl MediaHook for Recorder {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn priority(&self) -> i32 {
+            self.priority
+        }
+
+        async fn before_upload(&self, upload: &mut PendingUpload) -> Result<(), HookError> {
+            self.log.lock().unwrap().push(self.name.to_string());
+            upload.filename = format!("{}-{}", self.name, upload.filename);
+            match &self.action {
+                Some(e) => Err(e.clone()),
+                None => Ok(()),
+            }
+        }
+    }
+
+    fn pending() -> PendingUpload {
+        PendingUpload {
+            filename: "a.txt".to_string(),
+            mime_type: "text/plain".to_string(),
+            size: 1,
+            data: Some(Bytes::from_static(b"a")),
+            options: UploadOptions::default(),
+            user_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ordering_and_isolation() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let registry = HookRegistry::new();
+
+        for (name, priority, action) in [
+            ("late", 20, None),
+            ("broken", 10, Some(HookError::Failed("boom".to_string()))),
+            ("first", 5, None),
+            ("second", 10, None),
+        ] {
+            registry.register(Arc::new(Recorder { name, priority, log: Arc::clone(&log), action })).await;
+        }
+
+        let mut upload = pending();
+        registry.before_upload(&mut upload).await.unwrap();
+
+        assert_eq!(*log.lock().unwrap(), vec!["first", "broken", "second", "late"]);
+        // The failing hook's rename was rolled back
+        assert_eq!(upload.filename, "late-second-first-a.txt");
+    }
+
+    #[tokio::test]
+    async fn test_veto() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let registry = HookRegistry::new();
+        registry.register(Arc::new(Recorder {
+            name: "guard",
+            priority: DEFAULT_PRIORITY,
+            log: Arc::clone(&log),
+            action: Some(HookError::Veto("no text files".to_string())),
+        })).await;
+        registry.register(Arc::new(Recorder { name: "after", priority: 50, log: Arc::clone(&log), action: None })).await;
+
+        let err = registry.before_upload(&mut pending()).await.unwrap_err();
+        assert_eq!(err.hook, "guard");
+        assert_eq!(err.reason, "no text files");
+        assert_eq!(*log.lock().unwrap(), vec!["guard"]);
+
+        assert_eq!(registry.unregister("guard").await, 1);
+        assert!(registry.before_upload(&mut pending()).await.is_ok());
+    }
+}
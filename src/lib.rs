@@ -36,8 +36,11 @@
 //! - **EXIF Extraction**: Read and optionally strip image metadata
 //! - **Deduplication**: Prevent duplicate uploads using content hashing
 //! - **Chunked Uploads**: Support for large file uploads
+//! - **Resumable Uploads**: tus 1.0 protocol endpoint
 //! - **URL Uploads**: Download and store files from URLs
 //! - **Watermarks**: Apply watermarks to uploaded images
+//! - **Lifecycle Hooks**: Let other plugins veto, adjust or observe uploads,
+//!   deletes and folder changes
 //!
 //! ## Configuration
 //!
@@ -51,12 +54,29 @@
 //!
 //! let plugin = RustMediaPlugin::with_settings(settings);
 //! ```
+//!
+//! Settings can also be layered: defaults, then a TOML/JSON file, then
+//! `RUSTMEDIA_*` environment variables, then runtime overrides. Secrets such
+//! as S3 credentials are resolved through a [`SecretProvider`].
+//!
+//! ```rust,ignore
+//! use rustmedia::{RustMediaPlugin, SettingsLoader};
+//!
+//! let loader = SettingsLoader::new()
+//!     .with_file("rustmedia.toml")
+//!     .with_override("auto_optimize", false);
+//!
+//! let plugin = RustMediaPlugin::from_loader(&loader)?;
+//! println!("{}", plugin.config_report().unwrap());
+//! ```
 
 pub mod models;
 pub mod services;
 pub mod handlers;
 pub mod admin;
 pub mod settings;
+pub mod config;
+pub mod hooks;
 pub mod plugin;
 
 // Re-exports
@@ -65,18 +85,21 @@ pub use models::{
     ImageSize, ResizeMode, ImageFormat, ImageTransformRequest,
     Thumbnail, ImageDimensions, FolderTreeNode, FolderBreadcrumb,
     UploadOptions, ChunkedUpload, ChunkInfo, OptimizationResult,
+    ChecksumAlgorithm, TusUpload,
 };
 
 pub use services::{
     MediaService, FolderService, ImageService,
-    StorageService, OptimizerService, UploadService,
+    StorageService, OptimizerService, UploadService, TusService,
 };
 
 pub use handlers::{
-    MediaHandler, FolderHandler, UploadHandler,
+    MediaHandler, FolderHandler, UploadHandler, TusHandler,
 };
 
 pub use settings::MediaSettings;
+pub use config::{SettingsLoader, ConfigReport, ConfigLayer, SecretProvider, Secret};
+pub use hooks::{MediaHook, HookRegistry, HookError, HookRejection, PendingUpload, PendingFolder};
 pub use plugin::{RustMediaPlugin, PluginInfo, plugin_info};
 
 /// Library version
@@ -216,11 +239,98 @@ mod tests {
         assert!(matches!(document, MediaType::Document));
     }
 
+    #[tokio::test]
+    async fn test_update_keeps_secrets() {
+        let plugin = RustMediaPlugin::with_settings(MediaSettings {
+            s3_secret_key: Secret::new("resolved"),
+            ..MediaSettings::default()
+        });
+
+        // As sent back by the admin page
+        let json = serde_json::to_string(&plugin.get_settings().await).unwrap();
+        let mut edited: MediaSettings = serde_json::from_str(&json).unwrap();
+        edited.jpeg_quality = 70;
+        plugin.update_settings(edited).await.unwrap();
+
+        let settings = plugin.get_settings().await;
+        assert_eq!(settings.jpeg_quality, 70);
+        assert_eq!(settings.s3_secret_key.expose(), "resolved");
+    }
+
+    #[tokio::test]
+    async fn test_update_reaches_services() {
+        let plugin = RustMediaPlugin::new();
+        assert!(!plugin.rate_limiter().is_enabled().await);
+
+        plugin.update_settings(MediaSettings {
+            rate_limit_uploads: true,
+            ..MediaSettings::default()
+        }).await.unwrap();
+        assert!(plugin.rate_limiter().is_enabled().await);
+
+        plugin.update_settings(MediaSettings::default()).await.unwrap();
+        assert!(!plugin.rate_limiter().is_enabled().await);
+    }
+
+    #[tokio::test]
+    async fn test_update_applies_upload_and_image_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = MediaSettings {
+            storage_path: dir.path().to_string_lossy().into_owned(),
+            ..MediaSettings::default()
+        };
+        let plugin = RustMediaPlugin::with_settings(settings.clone());
+        plugin.upload_file(b"first".to_vec(), "a.txt").await.unwrap();
+
+        plugin.update_settings(MediaSettings {
+            max_file_size: 4,
+            jpeg_quality: 60,
+            avif_speed: 9,
+            ..settings.clone()
+        }).await.unwrap();
+        assert!(plugin.upload_file(b"second".to_vec(), "b.txt").await.is_err());
+        let image = plugin.image_service().settings();
+        assert_eq!((image.default_quality, image.avif_speed), (60, 9));
+
+        // Duplicates are accepted once deduplication is off
+        plugin.update_settings(MediaSettings { deduplicate: false, ..settings.clone() }).await.unwrap();
+        plugin.upload_file(b"first".to_vec(), "c.txt").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_update_rejects_invalid_settings() {
+        let plugin = RustMediaPlugin::new();
+
+        let errors = plugin.update_settings(MediaSettings {
+            jpeg_quality: 0,
+            ..MediaSettings::default()
+        }).await.unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(plugin.get_settings().await.jpeg_quality, 85);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_scanner_fails_closed() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = MediaSettings {
+            storage_path: dir.path().to_string_lossy().into_owned(),
+            scan_uploads: true,
+            clamd_address: "not a scanner".to_string(),
+            ..MediaSettings::default()
+        };
+        let plugin = RustMediaPlugin::with_settings(settings.clone());
+        assert!(plugin.upload_file(b"data".to_vec(), "a.txt").await.is_err());
+
+        // Settings updates are validated instead
+        assert!(plugin.update_settings(settings).await.is_err());
+    }
+
     #[test]
     fn test_plugin_info() {
         let info = plugin_info();
         assert_eq!(info.name, "RustMedia");
         assert!(!info.routes.is_empty());
+        assert!(info.routes.contains(&"/api/media/upload/batches/{id}/events"));
         assert!(!info.hooks.is_empty());
     }
 
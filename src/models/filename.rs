@@ -0,0 +1,205 @@
+//! Filename Policy
+//!
+//! Turns user-supplied filenames into safe, readable storage names and
+//! picks alternatives when a name is taken.
+
+use serde::{Deserialize, Serialize};
+
+/// Names Windows reserves regardless of extension
+const WINDOWS_RESERVED: &[&str] = &[
+    "con", "prn", "aux", "nul",
+    "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8", "com9",
+    "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// Longest extension kept
+const MAX_EXTENSION_LENGTH: usize = 16;
+
+/// How to name a file whose name is already taken
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionStrategy {
+    /// `photo-1.jpg`, `photo-2.jpg`, ...
+    #[default]
+    NumericSuffix,
+    /// `photo-3f2a9c1b.jpg` from the content hash
+    HashSuffix,
+    /// `photo-8c41e0d7.jpg` from random bits
+    Random,
+}
+
+/// Filename policy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilenamePolicy {
+    /// Transliterate Unicode to ASCII ("Café" becomes "Cafe")
+    pub transliterate: bool,
+    /// Lowercase names
+    pub lowercase: bool,
+    /// Maximum length in bytes, extension included
+    pub max_length: usize,
+    /// Naming of colliding files
+    pub collision: CollisionStrategy,
+}
+
+impl Default for FilenamePolicy {
+    fn default() -> Self {
+        Self {
+            transliterate: true,
+            lowercase: true,
+            max_length: 255,
+            collision: CollisionStrategy::NumericSuffix,
+        }
+    }
+}
+
+impl FilenamePolicy {
+    /// Make a filename safe for storage and URLs
+    pub fn sanitize(&self, filename: &str) -> String {
+        // Drop any directory part, whichever separator the client used
+        let name = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+
+        let (stem, extension) = match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.trim_matches('.').is_empty() => (stem, ext),
+            _ => (name, ""),
+        };
+
+        let mut stem = self.clean(stem, true);
+        let mut extension: String = self.clean(extension, false);
+        extension.truncate(MAX_EXTENSION_LENGTH);
+
+        if stem.is_empty() {
+            stem = "file".to_string();
+        }
+        if WINDOWS_RESERVED.contains(&stem.to_lowercase().as_str()) {
+            stem.push('_');
+        }
+
+        self.join(&stem, "", &extension)
+    }
+
+    /// Name to try for the given attempt (0 is the sanitized name itself)
+    ///
+    /// `hash` is the content hash; without it the hash strategy falls back
+    /// to random suffixes.
+    pub fn candidate(&self, sanitized: &str, attempt: u32, hash: Option<&str>) -> String {
+        if attempt == 0 {
+            return sanitized.to_string();
+        }
+
+        let (stem, extension) = sanitized.rsplit_once('.').unwrap_or((sanitized, ""));
+
+        let suffix = match (self.collision, hash) {
+            (CollisionStrategy::NumericSuffix, _) => attempt.to_string(),
+            (CollisionStrategy::HashSuffix, Some(hash)) if attempt == 1 => short(hash),
+            (CollisionStrategy::HashSuffix, Some(hash)) => format!("{}-{}", short(hash), attempt),
+            (CollisionStrategy::HashSuffix, None) | (CollisionStrategy::Random, _) => {
+                short(&uuid::Uuid::new_v4().simple().to_string())
+            }
+        };
+
+        self.join(stem, &format!("-{}", suffix), extension)
+    }
+
+    /// Transliterate, replace unsafe characters and collapse separators
+    fn clean(&self, part: &str, allow_separators: bool) -> String {
+        let part = if self.transliterate {
+            deunicode::deunicode_with_tofu(part, "-")
+        } else {
+            part.to_string()
+        };
+        let part = if self.lowercase { part.to_lowercase() } else { part };
+
+        let mut out = String::with_capacity(part.len());
+        for c in part.chars() {
+            let c = match c {
+                c if c.is_ascii_alphanumeric() => c,
+                '_' | '.' | '-' if allow_separators => c,
+                _ if allow_separators => '-',
+                _ => continue,
+            };
+            // Collapse runs of separators into the first one
+            if !c.is_ascii_alphanumeric() && out.ends_with(['-', '.', '_']) {
+                continue;
+            }
+            out.push(c);
+        }
+
+        out.trim_matches(['-', '.', '_']).to_string()
+    }
+
+    /// Join stem, suffix and extension, shortening the stem to fit
+    fn join(&self, stem: &str, suffix: &str, extension: &str) -> String {
+        let tail = if extension.is_empty() {
+            suffix.len()
+        } else {
+            suffix.len() + 1 + extension.len()
+        };
+        let budget = self.max_length.saturating_sub(tail).max(1);
+
+        let stem = if stem.len() > budget {
+            // Stems are ASCII once cleaned, but stay on a char boundary regardless
+            let mut end = budget;
+            while !stem.is_char_boundary(end) {
+                end -= 1;
+            }
+            stem[..end].trim_end_matches(['-', '.', '_'])
+        } else {
+            stem
+        };
+
+        if extension.is_empty() {
+            format!("{}{}", stem, suffix)
+        } else {
+            format!("{}{}.{}", stem, suffix, extension)
+        }
+    }
+}
+
+fn short(hex: &str) -> String {
+    hex.chars().take(8).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        let policy = FilenamePolicy::default();
+        assert_eq!(policy.sanitize("Café Müller.JPG"), "cafe-muller.jpg");
+        assert_eq!(policy.sanitize("a  --  b__c.png"), "a-b_c.png");
+        assert_eq!(policy.sanitize("../../etc/passwd"), "passwd");
+        assert_eq!(policy.sanitize("C:\\Users\\me\\report.final.pdf"), "report.final.pdf");
+        assert_eq!(policy.sanitize("東京.png"), "dong-jing.png");
+        assert_eq!(policy.sanitize(".htaccess"), "htaccess");
+        assert_eq!(policy.sanitize("@@@.txt"), "file.txt");
+        assert_eq!(policy.sanitize("CON.txt"), "con_.txt");
+        assert_eq!(policy.sanitize("lpt1"), "lpt1_");
+
+        let keep_case = FilenamePolicy { lowercase: false, ..FilenamePolicy::default() };
+        assert_eq!(keep_case.sanitize("Café Müller.JPG"), "Cafe-Muller.JPG");
+    }
+
+    #[test]
+    fn test_length_limit_keeps_extension() {
+        let policy = FilenamePolicy { max_length: 12, ..FilenamePolicy::default() };
+        assert_eq!(policy.sanitize("a-very-long-name.jpeg"), "a-very.jpeg");
+        assert_eq!(policy.candidate("a-very.jpeg", 12, None), "a-ve-12.jpeg");
+    }
+
+    #[test]
+    fn test_collision_strategies() {
+        let numeric = FilenamePolicy::default();
+        assert_eq!(numeric.candidate("photo.jpg", 0, None), "photo.jpg");
+        assert_eq!(numeric.candidate("photo.jpg", 2, None), "photo-2.jpg");
+
+        let hashed = FilenamePolicy { collision: CollisionStrategy::HashSuffix, ..FilenamePolicy::default() };
+        assert_eq!(hashed.candidate("photo.jpg", 1, Some("3f2a9c1b77")), "photo-3f2a9c1b.jpg");
+        assert_eq!(hashed.candidate("photo.jpg", 2, Some("3f2a9c1b77")), "photo-3f2a9c1b-2.jpg");
+
+        let random = FilenamePolicy { collision: CollisionStrategy::Random, ..FilenamePolicy::default() };
+        let a = random.candidate("notes", 1, None);
+        assert!(a.starts_with("notes-") && a.len() == 14);
+        assert_ne!(a, random.candidate("notes", 1, None));
+    }
+}
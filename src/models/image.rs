@@ -132,7 +132,7 @@ pub fn default_image_sizes() -> Vec<ImageSize> {
 }
 
 /// Image transformation request
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct ImageTransformRequest {
     /// Resize width (0 = auto)
     pub width: Option<u32>,
@@ -292,12 +292,19 @@ pub struct OptimizationResult {
     pub dimensions: Option<(u32, u32)>,
     /// Format used
     pub format: ImageFormat,
+    /// Encoder quality, for lossy output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u8>,
+    /// SSIM against the source, when quality was searched for a target
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssim: Option<f64>,
 }
 
 impl OptimizationResult {
     pub fn new(original_size: u64, optimized_size: u64) -> Self {
         let savings_percent = if original_size > 0 {
-            ((original_size - optimized_size) as f64 / original_size as f64) * 100.0
+            // Negative when the output grew
+            (1.0 - optimized_size as f64 / original_size as f64) * 100.0
         } else {
             0.0
         };
@@ -308,6 +315,8 @@ impl OptimizationResult {
             savings_percent,
             dimensions: None,
             format: ImageFormat::default(),
+            quality: None,
+            ssim: None,
         }
     }
 }
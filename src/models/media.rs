@@ -7,6 +7,8 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use uuid::Uuid;
 
+use super::DegradedStep;
+
 /// Media item
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MediaItem {
@@ -60,6 +62,15 @@ pub struct MediaItem {
     pub content_hash: String,
     /// Is soft deleted
     pub deleted: bool,
+    /// File version, bumped when the file is replaced
+    #[serde(default)]
+    pub version: u32,
+    /// Item whose content this upload duplicated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<Uuid>,
+    /// Optional processing steps that failed during upload
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub degraded: Vec<DegradedStep>,
 }
 
 impl MediaItem {
@@ -111,9 +122,17 @@ impl MediaItem {
             custom: HashMap::new(),
             content_hash: String::new(),
             deleted: false,
+            version: 1,
+            duplicate_of: None,
+            degraded: Vec::new(),
         }
     }
 
+    /// URL with the file version appended, for cache busting
+    pub fn versioned_url(&self) -> String {
+        format!("{}?v={}", self.url, self.version)
+    }
+
     /// Check if item is an image
     pub fn is_image(&self) -> bool {
         matches!(self.media_type, MediaType::Image)
@@ -325,7 +344,7 @@ pub struct GpsLocation {
 /// Upload request
 #[derive(Debug, Clone, Deserialize)]
 pub struct UploadRequest {
-    /// File data (base64 encoded)
+    /// File data (base64 or a `data:` URL)
     pub data: Option<String>,
     /// Original filename
     pub filename: String,
@@ -392,24 +411,7 @@ pub struct MediaListResponse {
 
 /// Sanitize filename for URL safety
 pub fn sanitize_filename(filename: &str) -> String {
-    let re = regex::Regex::new(r"[^a-zA-Z0-9._-]").unwrap();
-    let name = std::path::Path::new(filename)
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("file");
-
-    let ext = std::path::Path::new(filename)
-        .extension()
-        .and_then(|s| s.to_str())
-        .unwrap_or("");
-
-    let sanitized = re.replace_all(name, "-").to_lowercase();
-
-    if ext.is_empty() {
-        sanitized.to_string()
-    } else {
-        format!("{}.{}", sanitized, ext.to_lowercase())
-    }
+    super::FilenamePolicy::default().sanitize(filename)
 }
 
 /// Format bytes to human-readable string
@@ -445,7 +447,8 @@ mod tests {
     #[test]
     fn test_sanitize_filename() {
         assert_eq!(sanitize_filename("My File Name.jpg"), "my-file-name.jpg");
-        assert_eq!(sanitize_filename("test@#$%.png"), "test----.png");
+        assert_eq!(sanitize_filename("test@#$%.png"), "test.png");
+        assert_eq!(sanitize_filename("Café Müller.jpg"), "cafe-muller.jpg");
         assert_eq!(sanitize_filename("normal.pdf"), "normal.pdf");
     }
 
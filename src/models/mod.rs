@@ -5,7 +5,13 @@
 pub mod media;
 pub mod folder;
 pub mod image;
+pub mod upload;
+pub mod filename;
+pub mod revision;
 
 pub use media::*;
 pub use folder::*;
 pub use image::*;
+pub use upload::*;
+pub use filename::*;
+pub use revision::*;
@@ -0,0 +1,189 @@
+//! Revision Models
+//!
+//! History of metadata changes and file replacements for media items.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use uuid::Uuid;
+
+use super::MediaItem;
+
+/// Media revision (the state of an item after a change)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaRevision {
+    /// Revision number, starting at 1 for the original upload
+    pub number: u32,
+    /// Media item ID
+    pub media_id: Uuid,
+    /// What changed
+    pub change: RevisionChange,
+    /// User who made the change
+    pub author: Option<Uuid>,
+    /// When the change was made
+    pub created_at: DateTime<Utc>,
+    /// Metadata at this revision
+    pub metadata: RevisionMetadata,
+    /// File at this revision
+    pub file: RevisionFile,
+}
+
+/// Kind of change recorded by a revision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RevisionChange {
+    /// State at upload time
+    Original,
+    /// Metadata edited
+    Metadata,
+    /// File replaced
+    File,
+    /// Image edited (crop, rotate, ...)
+    Edit,
+    /// Earlier revision restored
+    Restore { from: u32 },
+}
+
+/// Editable metadata captured by a revision
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevisionMetadata {
+    pub filename: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub alt_text: Option<String>,
+    pub tags: Vec<String>,
+    pub custom: HashMap<String, String>,
+}
+
+impl RevisionMetadata {
+    /// Capture the metadata of an item
+    pub fn of(media: &MediaItem) -> Self {
+        Self {
+            filename: media.filename.clone(),
+            title: media.title.clone(),
+            description: media.description.clone(),
+            alt_text: media.alt_text.clone(),
+            tags: media.tags.clone(),
+            custom: media.custom.clone(),
+        }
+    }
+
+    /// Copy the editable fields onto an item (the filename follows the file)
+    pub fn apply(&self, media: &mut MediaItem) {
+        media.title = self.title.clone();
+        media.description = self.description.clone();
+        media.alt_text = self.alt_text.clone();
+        media.tags = self.tags.clone();
+        media.custom = self.custom.clone();
+    }
+
+    /// Fields that differ from `other`, with custom fields as `custom.<key>`
+    pub fn diff(&self, other: &Self) -> Vec<FieldChange> {
+        let mut changes = Vec::new();
+
+        let fields = [
+            ("filename", Some(self.filename.clone()), Some(other.filename.clone())),
+            ("title", self.title.clone(), other.title.clone()),
+            ("description", self.description.clone(), other.description.clone()),
+            ("alt_text", self.alt_text.clone(), other.alt_text.clone()),
+            ("tags", join_tags(&self.tags), join_tags(&other.tags)),
+        ];
+        for (field, before, after) in fields {
+            if before != after {
+                changes.push(FieldChange { field: field.to_string(), before, after });
+            }
+        }
+
+        let keys: BTreeSet<&String> = self.custom.keys().chain(other.custom.keys()).collect();
+        for key in keys {
+            let before = self.custom.get(key).cloned();
+            let after = other.custom.get(key).cloned();
+            if before != after {
+                changes.push(FieldChange { field: format!("custom.{}", key), before, after });
+            }
+        }
+
+        changes
+    }
+}
+
+fn join_tags(tags: &[String]) -> Option<String> {
+    (!tags.is_empty()).then(|| tags.join(", "))
+}
+
+/// File captured by a revision
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevisionFile {
+    /// Item version the file belonged to
+    pub version: u32,
+    /// MIME type
+    pub mime_type: String,
+    /// File size in bytes
+    pub size: u64,
+    /// Content hash
+    pub content_hash: String,
+    /// Where the binary is kept (`None` once pruned)
+    pub path: Option<String>,
+}
+
+impl RevisionFile {
+    /// Capture the current file of an item
+    pub fn of(media: &MediaItem) -> Self {
+        Self {
+            version: media.version,
+            mime_type: media.mime_type.clone(),
+            size: media.size,
+            content_hash: media.content_hash.clone(),
+            path: Some(media.path.clone()),
+        }
+    }
+}
+
+/// Single field difference between two revisions
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// How long replaced binaries are kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevisionPolicy {
+    /// Old binaries kept per item, newest first
+    pub keep_files: usize,
+    /// Old binaries older than this are removed regardless (0 = no limit)
+    pub max_file_age_days: u32,
+}
+
+impl Default for RevisionPolicy {
+    fn default() -> Self {
+        Self {
+            keep_files: 10,
+            max_file_age_days: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_diff() {
+        let mut media = MediaItem::new("logo.png", "image/png", 10, "logo.png");
+        let before = RevisionMetadata::of(&media);
+
+        media.title = Some("Logo".to_string());
+        media.tags = vec!["brand".to_string(), "header".to_string()];
+        media.custom.insert("credit".to_string(), "ACME".to_string());
+        let after = RevisionMetadata::of(&media);
+
+        assert_eq!(before.diff(&after), vec![
+            FieldChange { field: "title".to_string(), before: None, after: Some("Logo".to_string()) },
+            FieldChange { field: "tags".to_string(), before: None, after: Some("brand, header".to_string()) },
+            FieldChange { field: "custom.credit".to_string(), before: None, after: Some("ACME".to_string()) },
+        ]);
+        assert!(after.diff(&after).is_empty());
+    }
+}
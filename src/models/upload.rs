@@ -0,0 +1,458 @@
+//! Upload Models
+//!
+//! Upload options, resumable upload state and batch progress.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Options applied to an upload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadOptions {
+    /// Target folder ID
+    pub folder_id: Option<Uuid>,
+    /// Title
+    pub title: Option<String>,
+    /// Description
+    pub description: Option<String>,
+    /// Alt text
+    pub alt_text: Option<String>,
+    /// Tags
+    pub tags: Vec<String>,
+    /// Optimize images
+    pub optimize: bool,
+    /// Generate thumbnails
+    pub generate_thumbnails: bool,
+    /// Duplicate handling for this upload (service default when unset)
+    #[serde(default)]
+    pub on_duplicate: Option<DuplicateStrategy>,
+    /// Keep the upload when optional steps (dimensions, thumbnails) fail,
+    /// recording them on the item instead of rolling back
+    #[serde(default)]
+    pub allow_degraded: bool,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        Self {
+            folder_id: None,
+            title: None,
+            description: None,
+            alt_text: None,
+            tags: Vec::new(),
+            optimize: true,
+            generate_thumbnails: true,
+            on_duplicate: None,
+            allow_degraded: false,
+        }
+    }
+}
+
+/// Step of the upload pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadStep {
+    /// Write the original file
+    StoreOriginal,
+    /// Read image dimensions
+    Dimensions,
+    /// Generate and store thumbnails
+    Thumbnails,
+}
+
+impl std::fmt::Display for UploadStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::StoreOriginal => "store original",
+            Self::Dimensions => "dimensions",
+            Self::Thumbnails => "thumbnails",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Optional step that failed on an item kept as degraded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DegradedStep {
+    pub step: UploadStep,
+    pub error: String,
+}
+
+/// What to do when uploaded content is already in the library
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateStrategy {
+    /// Fail with the existing item's id
+    #[default]
+    Reject,
+    /// Return the existing item unchanged
+    ReturnExisting,
+    /// Create a new item sharing the existing stored file
+    Link,
+    /// Overwrite the existing item's metadata with the upload's
+    Replace,
+}
+
+impl DuplicateStrategy {
+    /// Parse a strategy name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().replace('-', "_").as_str() {
+            "reject" => Some(Self::Reject),
+            "return_existing" => Some(Self::ReturnExisting),
+            "link" => Some(Self::Link),
+            "replace" => Some(Self::Replace),
+            _ => None,
+        }
+    }
+}
+
+/// Chunked upload in progress
+#[derive(Debug, Clone, Serialize)]
+pub struct ChunkedUpload {
+    /// Upload ID
+    pub id: Uuid,
+    /// Original filename
+    pub filename: String,
+    /// Total file size in bytes
+    pub total_size: u64,
+    /// Chunk size in bytes
+    pub chunk_size: usize,
+    /// Number of chunks
+    pub total_chunks: usize,
+    /// Chunk states
+    pub chunks: Vec<ChunkInfo>,
+    /// Declared MIME type
+    pub mime_type: Option<String>,
+    /// Expected checksum of the assembled file
+    pub checksum: Option<Checksum>,
+    /// Target folder ID
+    pub folder_id: Option<Uuid>,
+    /// Uploader user ID
+    pub user_id: Option<Uuid>,
+    /// Temporary storage path for chunks
+    pub temp_path: String,
+    /// Started timestamp
+    pub started_at: DateTime<Utc>,
+    /// Expiry timestamp
+    pub expires_at: DateTime<Utc>,
+    /// Batch file this upload reports progress to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch: Option<BatchFileRef>,
+}
+
+/// Single chunk of a chunked upload
+#[derive(Debug, Clone, Serialize)]
+pub struct ChunkInfo {
+    /// Chunk index
+    pub index: usize,
+    /// Start byte offset
+    pub start: usize,
+    /// End byte offset (exclusive)
+    pub end: usize,
+    /// Chunk size in bytes
+    pub size: usize,
+    /// Whether the chunk has been received
+    pub received: bool,
+    /// Checksum of the received data
+    pub checksum: Option<Checksum>,
+}
+
+/// Checksum algorithm for upload verification
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    /// Parse algorithm name (case-insensitive)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "md5" => Some(Self::Md5),
+            "sha1" => Some(Self::Sha1),
+            "sha256" | "sha-256" => Some(Self::Sha256),
+            _ => None,
+        }
+    }
+
+    /// Algorithm name
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Md5 => "md5",
+            Self::Sha1 => "sha1",
+            Self::Sha256 => "sha256",
+        }
+    }
+
+    /// All supported algorithms
+    pub fn all() -> &'static [ChecksumAlgorithm] {
+        &[Self::Md5, Self::Sha1, Self::Sha256]
+    }
+
+    /// Compute digest of data
+    pub fn digest(&self, data: &[u8]) -> Vec<u8> {
+        use sha2::Digest;
+
+        match self {
+            Self::Md5 => md5::compute(data).0.to_vec(),
+            Self::Sha1 => sha1::Sha1::digest(data).to_vec(),
+            Self::Sha256 => sha2::Sha256::digest(data).to_vec(),
+        }
+    }
+}
+
+/// Incremental checksum computation for streamed data
+#[derive(Clone)]
+pub enum ChecksumHasher {
+    Md5(md5::Context),
+    Sha1(sha1::Sha1),
+    Sha256(sha2::Sha256),
+}
+
+impl ChecksumHasher {
+    /// Create a hasher for an algorithm
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        use sha2::Digest;
+
+        match algorithm {
+            ChecksumAlgorithm::Md5 => Self::Md5(md5::Context::new()),
+            ChecksumAlgorithm::Sha1 => Self::Sha1(sha1::Sha1::new()),
+            ChecksumAlgorithm::Sha256 => Self::Sha256(sha2::Sha256::new()),
+        }
+    }
+
+    /// Feed data
+    pub fn update(&mut self, data: &[u8]) {
+        use sha2::Digest;
+
+        match self {
+            Self::Md5(ctx) => ctx.consume(data),
+            Self::Sha1(hasher) => hasher.update(data),
+            Self::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Finish and return the checksum
+    pub fn finish(self) -> Checksum {
+        use sha2::Digest;
+
+        let (algorithm, digest) = match self {
+            Self::Md5(ctx) => (ChecksumAlgorithm::Md5, ctx.compute().0.to_vec()),
+            Self::Sha1(hasher) => (ChecksumAlgorithm::Sha1, hasher.finalize().to_vec()),
+            Self::Sha256(hasher) => (ChecksumAlgorithm::Sha256, hasher.finalize().to_vec()),
+        };
+
+        Checksum { algorithm, value: hex::encode(digest) }
+    }
+}
+
+/// Hex-encoded checksum (`sha256:9f86d0...`)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Checksum {
+    /// Algorithm
+    pub algorithm: ChecksumAlgorithm,
+    /// Lowercase hex digest
+    pub value: String,
+}
+
+impl Checksum {
+    /// Compute checksum of data
+    pub fn compute(algorithm: ChecksumAlgorithm, data: &[u8]) -> Self {
+        Self {
+            algorithm,
+            value: hex::encode(algorithm.digest(data)),
+        }
+    }
+
+    /// Parse `algorithm:hex`
+    pub fn parse(value: &str) -> Option<Self> {
+        let (name, digest) = value.trim().split_once(':')?;
+        let algorithm = ChecksumAlgorithm::from_name(name.trim())?;
+        let digest = digest.trim().to_lowercase();
+
+        let expected_len = match algorithm {
+            ChecksumAlgorithm::Md5 => 32,
+            ChecksumAlgorithm::Sha1 => 40,
+            ChecksumAlgorithm::Sha256 => 64,
+        };
+        if digest.len() != expected_len || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        Some(Self { algorithm, value: digest })
+    }
+
+    /// Check data against this checksum
+    pub fn matches(&self, data: &[u8]) -> bool {
+        hex::encode(self.algorithm.digest(data)) == self.value
+    }
+}
+
+impl std::fmt::Display for Checksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.algorithm.name(), self.value)
+    }
+}
+
+/// tus resumable upload
+#[derive(Debug, Clone, Serialize)]
+pub struct TusUpload {
+    /// Upload ID
+    pub id: Uuid,
+    /// Total length in bytes (Upload-Length)
+    pub length: u64,
+    /// Bytes received so far (Upload-Offset)
+    pub offset: u64,
+    /// Decoded Upload-Metadata
+    pub metadata: HashMap<String, String>,
+    /// Uploader user ID
+    pub user_id: Option<Uuid>,
+    /// Temporary storage path
+    pub temp_path: String,
+    /// Created timestamp
+    pub created_at: DateTime<Utc>,
+    /// Expiry timestamp (Upload-Expires)
+    pub expires_at: DateTime<Utc>,
+    /// Resulting media item once the upload is complete
+    pub media_id: Option<Uuid>,
+}
+
+impl TusUpload {
+    /// Check if all bytes have been received
+    pub fn is_complete(&self) -> bool {
+        self.offset >= self.length
+    }
+
+    /// Check if the upload has expired
+    pub fn is_expired(&self) -> bool {
+        Utc::now() > self.expires_at
+    }
+
+    /// Filename from metadata (`filename` or `name`)
+    pub fn filename(&self) -> Option<&str> {
+        self.metadata
+            .get("filename")
+            .or_else(|| self.metadata.get("name"))
+            .map(|s| s.as_str())
+    }
+}
+
+/// State of a file in an upload batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileState {
+    /// Waiting to be sent
+    Queued,
+    /// Bytes arriving
+    Receiving,
+    /// Validation and malware scan
+    Scanning,
+    /// Optimization and storage
+    Processing,
+    /// Thumbnail generation
+    Thumbnails,
+    /// Stored
+    Done,
+    /// Rejected or failed
+    Failed,
+}
+
+impl FileState {
+    /// Whether the file will not change state again
+    pub fn is_final(self) -> bool {
+        matches!(self, Self::Done | Self::Failed)
+    }
+}
+
+/// File in an upload batch
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchFile {
+    /// Position in the batch
+    pub index: usize,
+    /// Original filename
+    pub filename: String,
+    /// Size in bytes (as announced by the client)
+    pub size: u64,
+    /// Bytes received so far
+    pub received: u64,
+    /// Current state
+    pub state: FileState,
+    /// Resulting media item once done
+    pub media_id: Option<Uuid>,
+    /// Failure reason
+    pub error: Option<String>,
+}
+
+/// Files uploaded together
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadBatch {
+    /// Batch ID
+    pub id: Uuid,
+    /// Uploader user ID
+    pub user_id: Option<Uuid>,
+    /// Files in upload order
+    pub files: Vec<BatchFile>,
+    /// Created timestamp
+    pub created_at: DateTime<Utc>,
+    /// Last state change
+    pub updated_at: DateTime<Utc>,
+}
+
+impl UploadBatch {
+    /// Check if every file is done or failed
+    pub fn is_finished(&self) -> bool {
+        self.files.iter().all(|f| f.state.is_final())
+    }
+}
+
+/// Reference to a file in an upload batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchFileRef {
+    pub batch_id: Uuid,
+    pub index: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum_algorithm() {
+        assert_eq!(ChecksumAlgorithm::from_name("SHA1"), Some(ChecksumAlgorithm::Sha1));
+        assert_eq!(ChecksumAlgorithm::from_name("crc32"), None);
+        assert_eq!(
+            hex::encode(ChecksumAlgorithm::Md5.digest(b"hello")),
+            "5d41402abc4b2a76b9719d911017c592"
+        );
+        assert_eq!(
+            hex::encode(ChecksumAlgorithm::Sha1.digest(b"hello")),
+            "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d"
+        );
+    }
+
+    #[test]
+    fn test_checksum_parse() {
+        let checksum = Checksum::parse("MD5:5D41402ABC4B2A76B9719D911017C592").unwrap();
+        assert_eq!(checksum.algorithm, ChecksumAlgorithm::Md5);
+        assert!(checksum.matches(b"hello"));
+        assert!(!checksum.matches(b"hello!"));
+        assert_eq!(checksum.to_string(), "md5:5d41402abc4b2a76b9719d911017c592");
+
+        assert!(Checksum::parse("sha256:abcd").is_none());
+        assert!(Checksum::parse("crc32:00000000").is_none());
+        assert!(Checksum::parse("5d41402abc4b2a76b9719d911017c592").is_none());
+    }
+
+    #[test]
+    fn test_checksum_hasher() {
+        for &algorithm in ChecksumAlgorithm::all() {
+            let mut hasher = ChecksumHasher::new(algorithm);
+            hasher.update(b"hel");
+            hasher.update(b"lo");
+            assert_eq!(hasher.finish(), Checksum::compute(algorithm, b"hello"));
+        }
+    }
+}
@@ -3,18 +3,30 @@
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+use crate::config::{ConfigError, ConfigReport, SettingsLoader};
+use crate::hooks::{self, HookRegistry, MediaHook};
 use crate::settings::MediaSettings;
 use crate::services::{
     StorageService, ImageService, MediaService,
-    FolderService, OptimizerService, UploadService,
+    FolderService, OptimizerService, UploadService, TusService, RateLimiter,
 };
-use crate::handlers::{MediaHandler, FolderHandler, UploadHandler};
+use crate::services::scanner::{
+    ClamdScanner, ScanFailurePolicy, ScanSettings, UnavailableScanner, VirusScanner,
+};
+use crate::services::import::{
+    DirectoryImporter, DirectoryImportOptions, DirectoryImportReport, ImportError, ImportProgress,
+};
+use crate::handlers::{MediaHandler, FolderHandler, UploadHandler, TusHandler};
 use crate::admin::{DashboardView, LibraryView, UploadView, FoldersView, SettingsView};
 
 /// RustMedia Plugin
 pub struct RustMediaPlugin {
     /// Plugin settings
     settings: Arc<RwLock<MediaSettings>>,
+    /// Where each setting came from (when loaded through layers)
+    config_report: Option<ConfigReport>,
+    /// Lifecycle hooks shared by all services
+    hooks: Arc<HookRegistry>,
 
     /// Services
     storage_service: Arc<StorageService>,
@@ -23,11 +35,14 @@ pub struct RustMediaPlugin {
     folder_service: Arc<FolderService>,
     optimizer_service: Arc<OptimizerService>,
     upload_service: Arc<UploadService>,
+    tus_service: Arc<TusService>,
+    rate_limiter: Arc<RateLimiter>,
 
     /// Handlers
     media_handler: Arc<MediaHandler>,
     folder_handler: Arc<FolderHandler>,
     upload_handler: Arc<UploadHandler>,
+    tus_handler: Arc<TusHandler>,
 
     /// Admin views
     dashboard_view: DashboardView,
@@ -40,26 +55,66 @@ pub struct RustMediaPlugin {
 impl RustMediaPlugin {
     /// Create a new RustMedia plugin instance
     pub fn new() -> Self {
-        let settings = Arc::new(RwLock::new(MediaSettings::default()));
+        Self::with_settings(MediaSettings::default())
+    }
 
+    /// Create with custom settings
+    pub fn with_settings(settings: MediaSettings) -> Self {
         // Create services
-        let storage_service = Arc::new(StorageService::new("uploads/media"));
-        let image_service = Arc::new(ImageService::new());
-        let folder_service = Arc::new(FolderService::new());
-        let optimizer_service = Arc::new(OptimizerService::new(
+        let mut storage_service = StorageService::new(
+            settings.storage_path.clone().into(),
+            settings.get_base_url(),
+        );
+        storage_service.set_max_size(settings.max_file_size);
+        storage_service.set_filename_policy(settings.filename_policy());
+        let storage_service = Arc::new(storage_service);
+
+        let mut image_service = ImageService::new(Arc::clone(&storage_service));
+        image_service.configure(settings.image_settings());
+        let image_service = Arc::new(image_service);
+
+        let hooks = Arc::new(HookRegistry::new());
+        let mut folder_service = FolderService::new();
+        folder_service.set_hooks(Arc::clone(&hooks));
+        let folder_service = Arc::new(folder_service);
+        let mut optimizer_service = OptimizerService::new(
             Arc::clone(&image_service),
             Arc::clone(&storage_service),
-        ));
-        let media_service = Arc::new(MediaService::new(
+        );
+        optimizer_service.configure(settings.optimization_settings());
+        let optimizer_service = Arc::new(optimizer_service);
+        let mut media_service = MediaService::new(
             Arc::clone(&storage_service),
             Arc::clone(&image_service),
-            Arc::clone(&folder_service),
-        ));
-        let upload_service = Arc::new(UploadService::new(
+        );
+        media_service.set_hooks(Arc::clone(&hooks));
+        media_service.set_deduplicate(settings.deduplicate);
+        media_service.set_duplicate_strategy(settings.duplicate_strategy);
+        media_service.set_revision_policy(settings.revision_policy());
+        let media_service = Arc::new(media_service);
+        let mut upload_service = UploadService::new(
             Arc::clone(&storage_service),
             Arc::clone(&image_service),
             Arc::clone(&media_service),
             Arc::clone(&optimizer_service),
+        );
+        upload_service.configure(settings.upload_settings());
+        upload_service.set_folder_service(Arc::clone(&folder_service));
+        // Always present, so limits can be switched on at runtime
+        let rate_limiter = Arc::new(if settings.rate_limit_uploads {
+            RateLimiter::new(settings.upload_rate_limits.clone())
+        } else {
+            RateLimiter::disabled()
+        });
+        upload_service.set_rate_limiter(Arc::clone(&rate_limiter));
+        if let (Some(scanner), scan_settings) = Self::scanner(&settings) {
+            upload_service.set_scanner(scanner, scan_settings);
+        }
+        let settings = Arc::new(RwLock::new(settings));
+        let upload_service = Arc::new(upload_service);
+        let tus_service = Arc::new(TusService::new(
+            Arc::clone(&upload_service),
+            Arc::clone(&storage_service),
         ));
 
         // Create handlers
@@ -69,12 +124,14 @@ impl RustMediaPlugin {
             Arc::clone(&upload_service),
             Arc::clone(&media_service),
         ));
+        let tus_handler = Arc::new(TusHandler::new(Arc::clone(&tus_service), "/api/media/tus"));
 
         // Create admin views
-        let dashboard_view = DashboardView::new(
+        let mut dashboard_view = DashboardView::new(
             Arc::clone(&media_service),
             Arc::clone(&folder_service),
         );
+        dashboard_view.set_rate_limiter(Arc::clone(&rate_limiter));
         let library_view = LibraryView::new(
             Arc::clone(&media_service),
             Arc::clone(&folder_service),
@@ -89,15 +146,20 @@ impl RustMediaPlugin {
 
         Self {
             settings,
+            config_report: None,
+            hooks,
             storage_service,
             image_service,
             media_service,
             folder_service,
             optimizer_service,
             upload_service,
+            tus_service,
+            rate_limiter,
             media_handler,
             folder_handler,
             upload_handler,
+            tus_handler,
             dashboard_view,
             library_view,
             upload_view,
@@ -106,11 +168,12 @@ impl RustMediaPlugin {
         }
     }
 
-    /// Create with custom settings
-    pub fn with_settings(settings: MediaSettings) -> Self {
-        let mut plugin = Self::new();
-        plugin.settings = Arc::new(RwLock::new(settings));
-        plugin
+    /// Create from layered configuration (defaults, file, environment, overrides)
+    pub fn from_loader(loader: &SettingsLoader) -> Result<Self, ConfigError> {
+        let loaded = loader.load()?;
+        let mut plugin = Self::with_settings(loaded.settings);
+        plugin.config_report = Some(loaded.report);
+        Ok(plugin)
     }
 
     /// Initialize the plugin
@@ -145,6 +208,16 @@ impl RustMediaPlugin {
         "Complete media management for RustPress"
     }
 
+    /// Lifecycle hook registry
+    pub fn hooks(&self) -> &Arc<HookRegistry> {
+        &self.hooks
+    }
+
+    /// Register a lifecycle hook
+    pub async fn register_hook(&self, hook: Arc<dyn MediaHook>) {
+        self.hooks.register(hook).await;
+    }
+
     // Service accessors
     pub fn storage_service(&self) -> &Arc<StorageService> {
         &self.storage_service
@@ -170,6 +243,15 @@ impl RustMediaPlugin {
         &self.upload_service
     }
 
+    pub fn tus_service(&self) -> &Arc<TusService> {
+        &self.tus_service
+    }
+
+    /// Upload rate limiter, for assigning user roles
+    pub fn rate_limiter(&self) -> &Arc<RateLimiter> {
+        &self.rate_limiter
+    }
+
     // Handler accessors
     pub fn media_handler(&self) -> &Arc<MediaHandler> {
         &self.media_handler
@@ -183,6 +265,10 @@ impl RustMediaPlugin {
         &self.upload_handler
     }
 
+    pub fn tus_handler(&self) -> &Arc<TusHandler> {
+        &self.tus_handler
+    }
+
     // Admin view accessors
     pub fn dashboard_view(&self) -> &DashboardView {
         &self.dashboard_view
@@ -209,10 +295,65 @@ impl RustMediaPlugin {
         self.settings.read().await.clone()
     }
 
+    /// Get the report of where each setting came from
+    pub fn config_report(&self) -> Option<&ConfigReport> {
+        self.config_report.as_ref()
+    }
+
     /// Update settings
-    pub async fn update_settings(&self, settings: MediaSettings) {
+    ///
+    /// Secrets are never serialized, so settings that went through the
+    /// admin page arrive without them: empty secrets keep the current ones.
+    /// Invalid settings are rejected and the current ones stay in effect.
+    pub async fn update_settings(&self, mut settings: MediaSettings) -> Result<(), Vec<String>> {
         let mut current = self.settings.write().await;
+        if settings.s3_access_key.is_empty() {
+            settings.s3_access_key = current.s3_access_key.clone();
+        }
+        if settings.s3_secret_key.is_empty() {
+            settings.s3_secret_key = current.s3_secret_key.clone();
+        }
+        settings.validate()?;
+
+        self.storage_service.update_max_size(settings.max_file_size).await;
+        self.storage_service.update_filename_policy(settings.filename_policy()).await;
+        self.image_service.update_settings(settings.image_settings());
+        self.optimizer_service.update_settings(settings.optimization_settings()).await;
+        self.media_service.update_duplicates(settings.deduplicate, settings.duplicate_strategy).await;
+        self.media_service.update_revision_policy(settings.revision_policy()).await;
+        self.upload_service.update_settings(settings.upload_settings());
+        self.rate_limiter.update_settings(
+            settings.rate_limit_uploads.then(|| settings.upload_rate_limits.clone()),
+        ).await;
+        let (scanner, scan_settings) = Self::scanner(&settings);
+        self.upload_service.update_scanner(scanner, scan_settings).await;
+
         *current = settings;
+        Ok(())
+    }
+
+    /// Build the upload scanner the settings ask for
+    fn scanner(settings: &MediaSettings) -> (Option<Arc<dyn VirusScanner>>, ScanSettings) {
+        let scan_settings = ScanSettings {
+            on_failure: if settings.scan_fail_open {
+                ScanFailurePolicy::FailOpen
+            } else {
+                ScanFailurePolicy::FailClosed
+            },
+            quarantine: settings.quarantine_infected,
+            ..ScanSettings::default()
+        };
+        if !settings.scan_uploads {
+            return (None, scan_settings);
+        }
+        match ClamdScanner::from_address(&settings.clamd_address) {
+            Ok(scanner) => (Some(Arc::new(scanner)), scan_settings),
+            // Left to the failure policy rather than skipping scans
+            Err(e) => {
+                tracing::error!("Upload scanner unavailable: {}", e);
+                (Some(Arc::new(UnavailableScanner::new(e.to_string()))), scan_settings)
+            }
+        }
     }
 
     // Convenience methods
@@ -224,7 +365,7 @@ impl RustMediaPlugin {
         filename: &str,
     ) -> Result<crate::models::MediaItem, String> {
         let options = crate::models::UploadOptions::default();
-        self.media_service.upload(data, filename, options, None)
+        self.upload_service.upload(data, filename, options, None)
             .await
             .map_err(|e| e.to_string())
     }
@@ -280,18 +421,14 @@ impl RustMediaPlugin {
 
         let mut thumbnails = Vec::new();
         for size in sizes {
-            if let Ok(thumb_data) = self.image_service.resize(
-                data,
-                size.width,
-                size.height,
-                size.mode,
-            ) {
+            if let Ok(thumb_data) = self.image_service.resize(data, size) {
                 thumbnails.push(crate::models::Thumbnail {
                     size_name: size.name.clone(),
                     url: String::new(), // Would be set when saved
                     path: String::new(),
                     width: size.width,
                     height: size.height,
+                    size: thumb_data.len() as u64,
                 });
             }
         }
@@ -299,9 +436,30 @@ impl RustMediaPlugin {
         Ok(thumbnails)
     }
 
+    /// Import a directory already on the server
+    ///
+    /// Pass the same checkpoint file to resume an interrupted import.
+    pub async fn import_directory<F>(
+        &self,
+        options: DirectoryImportOptions,
+        on_progress: F,
+    ) -> Result<DirectoryImportReport, ImportError>
+    where
+        F: FnMut(&ImportProgress),
+    {
+        let importer = DirectoryImporter::new(
+            Arc::clone(&self.storage_service),
+            Arc::clone(&self.media_service),
+            Arc::clone(&self.folder_service),
+            Arc::clone(&self.upload_service),
+        );
+
+        importer.import(options, on_progress).await
+    }
+
     /// Cleanup expired chunked uploads
     pub async fn cleanup_expired_uploads(&self) -> usize {
-        self.upload_service.cleanup_expired().await
+        self.upload_service.cleanup_expired().await + self.tus_service.cleanup_expired().await
     }
 
     /// Get allowed file types for upload
@@ -319,8 +477,10 @@ impl RustMediaPlugin {
     /// Run storage cleanup
     pub async fn cleanup_storage(&self) -> Result<CleanupResult, String> {
         // Would implement orphan file cleanup, etc.
+        let revisions_pruned = self.media_service.prune_revisions().await;
+
         Ok(CleanupResult {
-            files_removed: 0,
+            files_removed: revisions_pruned,
             bytes_freed: 0,
             errors: vec![],
         })
@@ -376,11 +536,11 @@ pub fn plugin_info() -> PluginInfo {
         license: "MIT",
         dependencies: vec![],
         hooks: vec![
-            "media.upload",
-            "media.delete",
-            "media.optimize",
-            "folder.create",
-            "folder.delete",
+            hooks::MEDIA_UPLOAD,
+            hooks::MEDIA_DELETE,
+            hooks::MEDIA_OPTIMIZE,
+            hooks::FOLDER_CREATE,
+            hooks::FOLDER_DELETE,
         ],
         routes: vec![
             "/admin/media",
@@ -391,6 +551,9 @@ pub fn plugin_info() -> PluginInfo {
             "/api/media",
             "/api/media/folders",
             "/api/media/upload",
+            "/api/media/upload/batches",
+            "/api/media/upload/batches/{id}/events",
+            "/api/media/tus",
         ],
     }
 }
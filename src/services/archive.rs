@@ -0,0 +1,522 @@
+//! Archive Service
+//!
+//! ZIP and tar archive expansion with zip-bomb and path traversal guards.
+
+use std::io::{self, Cursor, Read};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Archive error
+#[derive(Debug, thiserror::Error)]
+pub enum ArchiveError {
+    #[error("Unsupported archive: {0}")]
+    Unsupported(String),
+    #[error("Corrupt archive: {0}")]
+    Corrupt(String),
+    #[error("Too many entries: more than {0}")]
+    TooManyEntries(usize),
+    #[error("Expanded size exceeds {0} bytes")]
+    TooLarge(u64),
+    #[error("Compression ratio of {0} exceeds limit of {1}")]
+    RatioExceeded(String, f64),
+    #[error("Import stopped")]
+    Stopped,
+}
+
+/// Archive format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// Detect format from content, falling back to the filename
+    pub fn detect(data: &[u8], filename: &str) -> Option<Self> {
+        if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+            return Some(Self::Zip);
+        }
+        if data.starts_with(&[0x1f, 0x8b]) {
+            return Some(Self::TarGz);
+        }
+        if data.len() > 262 && &data[257..262] == b"ustar" {
+            return Some(Self::Tar);
+        }
+
+        let name = filename.to_lowercase();
+        if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+/// Zip-bomb limits
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArchiveLimits {
+    /// Maximum number of entries (files and directories)
+    pub max_entries: usize,
+    /// Maximum total expanded size in bytes
+    pub max_total_size: u64,
+    /// Maximum expanded size of a single entry (`None` uses the upload
+    /// size limit)
+    pub max_entry_size: Option<u64>,
+    /// Maximum expanded/compressed ratio, per entry and for the whole archive
+    pub max_ratio: f64,
+}
+
+impl Default for ArchiveLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: 5_000,
+            max_total_size: 1024 * 1024 * 1024, // 1GB
+            max_entry_size: None,
+            max_ratio: 100.0,
+        }
+    }
+}
+
+/// Entry read from an archive
+#[derive(Debug, Clone)]
+pub enum ArchiveEntry {
+    /// Directory (sanitized path components)
+    Directory { path: String, dirs: Vec<String> },
+    /// File (sanitized directory components and filename), its contents
+    /// passed alongside
+    File { path: String, dirs: Vec<String>, name: String },
+    /// Entry that was not extracted
+    Skipped { path: String, reason: String },
+}
+
+/// Outcome of importing one archive entry
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum EntryStatus {
+    Imported { media_id: Uuid },
+    Skipped { reason: String },
+    Failed { error: String },
+}
+
+/// Per-entry import result
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryResult {
+    /// Path inside the archive
+    pub path: String,
+    /// Outcome
+    #[serde(flatten)]
+    pub status: EntryStatus,
+}
+
+/// Archive import report
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ArchiveImportReport {
+    /// Entry results in archive order
+    pub entries: Vec<EntryResult>,
+    /// Folders created for the archive's directories
+    pub folders_created: Vec<Uuid>,
+    /// Limit violation or corruption that stopped the import early
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ArchiveImportReport {
+    /// Number of imported files
+    pub fn imported(&self) -> usize {
+        self.count(|s| matches!(s, EntryStatus::Imported { .. }))
+    }
+
+    /// Number of skipped entries
+    pub fn skipped(&self) -> usize {
+        self.count(|s| matches!(s, EntryStatus::Skipped { .. }))
+    }
+
+    /// Number of failed entries
+    pub fn failed(&self) -> usize {
+        self.count(|s| matches!(s, EntryStatus::Failed { .. }))
+    }
+
+    fn count(&self, f: impl Fn(&EntryStatus) -> bool) -> usize {
+        self.entries.iter().filter(|e| f(&e.status)).count()
+    }
+
+    pub(crate) fn push(&mut self, path: String, status: EntryStatus) {
+        self.entries.push(EntryResult { path, status });
+    }
+}
+
+/// Visit the entries of an archive in order, enforcing limits
+///
+/// File contents are streamed to `visit` through a reader that fails once
+/// a limit is hit, so no entry is held in memory; other entries get an
+/// empty reader. Runs synchronously; call from `spawn_blocking` for large
+/// archives.
+pub fn read_archive<F>(
+    data: &[u8],
+    format: ArchiveFormat,
+    limits: &ArchiveLimits,
+    mut visit: F,
+) -> Result<(), ArchiveError>
+where
+    F: FnMut(ArchiveEntry, &mut dyn Read) -> Result<(), ArchiveError>,
+{
+    let mut budget = Budget::new(limits, data.len() as u64);
+
+    match format {
+        ArchiveFormat::Zip => read_zip(data, &mut budget, &mut visit),
+        ArchiveFormat::Tar => read_tar(Cursor::new(data), &mut budget, &mut visit),
+        ArchiveFormat::TarGz => {
+            read_tar(flate2::read::GzDecoder::new(Cursor::new(data)), &mut budget, &mut visit)
+        }
+    }
+}
+
+/// Running totals checked against the limits
+struct Budget<'a> {
+    limits: &'a ArchiveLimits,
+    archive_size: u64,
+    entries: usize,
+    total: u64,
+}
+
+impl<'a> Budget<'a> {
+    fn new(limits: &'a ArchiveLimits, archive_size: u64) -> Self {
+        Self { limits, archive_size, entries: 0, total: 0 }
+    }
+
+    fn add_entry(&mut self) -> Result<(), ArchiveError> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            return Err(ArchiveError::TooManyEntries(self.limits.max_entries));
+        }
+        Ok(())
+    }
+
+    /// Pass a file entry to `visit`, never expanding more than the
+    /// remaining budget allows
+    fn visit_file<F>(
+        &mut self,
+        entry: ArchiveEntry,
+        reader: impl Read,
+        compressed_size: Option<u64>,
+        visit: &mut F,
+    ) -> Result<(), ArchiveError>
+    where
+        F: FnMut(ArchiveEntry, &mut dyn Read) -> Result<(), ArchiveError>,
+    {
+        let mut limited = Limited { reader, budget: self, compressed_size, size: 0, error: None };
+        let result = visit(entry, &mut limited);
+
+        // The visitor only sees an I/O error; report the limit instead
+        match limited.error {
+            Some(e) => Err(e),
+            None => result,
+        }
+    }
+
+    /// Count `bytes` more of an entry that has expanded to `size` so far
+    fn consume(&mut self, size: u64, bytes: u64, compressed_size: Option<u64>) -> Result<(), ArchiveError> {
+        self.total += bytes;
+        if self.total > self.limits.max_total_size {
+            return Err(ArchiveError::TooLarge(self.limits.max_total_size));
+        }
+        if let Some(max) = self.limits.max_entry_size.filter(|&max| size > max) {
+            return Err(ArchiveError::TooLarge(max));
+        }
+
+        if let Some(compressed) = compressed_size {
+            self.check_ratio(size, compressed)?;
+        }
+        self.check_ratio(self.total, self.archive_size)
+    }
+
+    fn check_ratio(&self, expanded: u64, compressed: u64) -> Result<(), ArchiveError> {
+        // Tiny entries compress arbitrarily well without being dangerous
+        if expanded < 1024 * 1024 {
+            return Ok(());
+        }
+
+        let ratio = expanded as f64 / compressed.max(1) as f64;
+        if ratio > self.limits.max_ratio {
+            return Err(ArchiveError::RatioExceeded(format!("{:.0}", ratio), self.limits.max_ratio));
+        }
+        Ok(())
+    }
+}
+
+/// Entry reader that counts what it expands against the budget
+struct Limited<'a, 'b, R> {
+    reader: R,
+    budget: &'a mut Budget<'b>,
+    compressed_size: Option<u64>,
+    size: u64,
+    /// Limit that stopped reading
+    error: Option<ArchiveError>,
+}
+
+impl<R: Read> Read for Limited<'_, '_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.error.is_some() {
+            return Err(io::Error::other("archive limit exceeded"));
+        }
+
+        let read = self.reader.read(buf)?;
+        self.size += read as u64;
+        if let Err(e) = self.budget.consume(self.size, read as u64, self.compressed_size) {
+            let message = e.to_string();
+            self.error = Some(e);
+            return Err(io::Error::other(message));
+        }
+        Ok(read)
+    }
+}
+
+fn read_zip<F>(data: &[u8], budget: &mut Budget, visit: &mut F) -> Result<(), ArchiveError>
+where
+    F: FnMut(ArchiveEntry, &mut dyn Read) -> Result<(), ArchiveError>,
+{
+    let mut archive = zip::ZipArchive::new(Cursor::new(data))
+        .map_err(|e| ArchiveError::Corrupt(e.to_string()))?;
+
+    // The central directory count is known up front
+    if archive.len() > budget.limits.max_entries {
+        return Err(ArchiveError::TooManyEntries(budget.limits.max_entries));
+    }
+
+    for i in 0..archive.len() {
+        budget.add_entry()?;
+
+        let file = archive.by_index(i).map_err(|e| ArchiveError::Corrupt(e.to_string()))?;
+        let raw = file.name().to_string();
+
+        if file.is_symlink() {
+            visit(skipped(raw, "Symbolic links are not imported"), &mut io::empty())?;
+            continue;
+        }
+
+        let is_dir = file.is_dir();
+        let compressed = file.compressed_size();
+
+        let entry = match sanitize_path(&raw) {
+            Err(reason) => skipped(raw, reason),
+            Ok(parts) if is_dir => ArchiveEntry::Directory { path: raw, dirs: parts },
+            Ok(mut parts) => match skip_reason(&parts) {
+                Some(reason) => skipped(raw, reason),
+                None => {
+                    let name = parts.pop().unwrap_or_default();
+                    let entry = ArchiveEntry::File { path: raw, dirs: parts, name };
+                    budget.visit_file(entry, file, Some(compressed), visit)?;
+                    continue;
+                }
+            },
+        };
+        visit(entry, &mut io::empty())?;
+    }
+
+    Ok(())
+}
+
+fn read_tar<F>(reader: impl Read, budget: &mut Budget, visit: &mut F) -> Result<(), ArchiveError>
+where
+    F: FnMut(ArchiveEntry, &mut dyn Read) -> Result<(), ArchiveError>,
+{
+    let mut archive = tar::Archive::new(reader);
+
+    let iter = archive.entries().map_err(|e| ArchiveError::Corrupt(e.to_string()))?;
+    for entry in iter {
+        budget.add_entry()?;
+
+        let entry = entry.map_err(|e| ArchiveError::Corrupt(e.to_string()))?;
+        let raw = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+        let kind = entry.header().entry_type();
+
+        let parsed = if kind.is_dir() {
+            match sanitize_path(&raw) {
+                Ok(parts) => ArchiveEntry::Directory { path: raw, dirs: parts },
+                Err(reason) => skipped(raw, reason),
+            }
+        } else if kind.is_file() {
+            match sanitize_path(&raw) {
+                Err(reason) => skipped(raw, reason),
+                Ok(mut parts) => match skip_reason(&parts) {
+                    Some(reason) => skipped(raw, reason),
+                    None => {
+                        let name = parts.pop().unwrap_or_default();
+                        let file = ArchiveEntry::File { path: raw, dirs: parts, name };
+                        budget.visit_file(file, entry, None, visit)?;
+                        continue;
+                    }
+                },
+            }
+        } else if kind.is_symlink() || kind.is_hard_link() {
+            skipped(raw, "Links are not imported")
+        } else if kind.is_pax_global_extensions() || kind.is_pax_local_extensions() || kind.is_gnu_longname() {
+            continue;
+        } else {
+            skipped(raw, "Unsupported entry type")
+        };
+        visit(parsed, &mut io::empty())?;
+    }
+
+    Ok(())
+}
+
+fn skipped(path: String, reason: &str) -> ArchiveEntry {
+    ArchiveEntry::Skipped { path, reason: reason.to_string() }
+}
+
+/// Split an entry path into safe components
+///
+/// Absolute paths, drive prefixes and `..` are rejected rather than
+/// normalized, so an entry can never land outside its archive root.
+pub fn sanitize_path(raw: &str) -> Result<Vec<String>, &'static str> {
+    if raw.starts_with('/') || raw.starts_with('\\') {
+        return Err("Absolute paths are not allowed");
+    }
+
+    let mut parts = Vec::new();
+    for part in raw.split(['/', '\\']) {
+        match part {
+            "" | "." => continue,
+            ".." => return Err("Path traversal is not allowed"),
+            p if p.contains(':') || p.contains('\0') => return Err("Invalid path component"),
+            p => parts.push(p.to_string()),
+        }
+    }
+
+    if parts.is_empty() {
+        return Err("Empty path");
+    }
+
+    Ok(parts)
+}
+
+/// OS metadata that should not become media items
+fn skip_reason(parts: &[String]) -> Option<&'static str> {
+    if parts.iter().any(|p| p == "__MACOSX") {
+        return Some("macOS resource fork");
+    }
+    match parts.last().map(|s| s.as_str()) {
+        Some(".DS_Store") | Some("Thumbs.db") | Some("desktop.ini") => Some("System file"),
+        Some(name) if name.starts_with("._") => Some("macOS resource fork"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn zip_of(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        for (name, data) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn read_all(
+        data: &[u8],
+        format: ArchiveFormat,
+        limits: &ArchiveLimits,
+    ) -> Result<Vec<(ArchiveEntry, Vec<u8>)>, ArchiveError> {
+        let mut entries = Vec::new();
+        read_archive(data, format, limits, |entry, reader| {
+            let mut contents = Vec::new();
+            reader.read_to_end(&mut contents).map_err(|e| ArchiveError::Corrupt(e.to_string()))?;
+            entries.push((entry, contents));
+            Ok(())
+        })?;
+        Ok(entries)
+    }
+
+    #[test]
+    fn test_sanitize_path() {
+        assert_eq!(sanitize_path("a/./b\\c.txt").unwrap(), vec!["a", "b", "c.txt"]);
+        assert!(sanitize_path("../etc/passwd").is_err());
+        assert!(sanitize_path("a/../../b").is_err());
+        assert!(sanitize_path("/etc/passwd").is_err());
+        assert!(sanitize_path("C:/Windows/win.ini").is_err());
+    }
+
+    #[test]
+    fn test_read_zip() {
+        let data = zip_of(&[
+            ("photos/2024/a.txt", b"hello"),
+            ("../evil.txt", b"nope"),
+            ("__MACOSX/._a.txt", b""),
+        ]);
+        assert_eq!(ArchiveFormat::detect(&data, "upload.bin"), Some(ArchiveFormat::Zip));
+
+        let entries = read_all(&data, ArchiveFormat::Zip, &ArchiveLimits::default()).unwrap();
+        assert_eq!(entries.len(), 3);
+        match &entries[0] {
+            (ArchiveEntry::File { dirs, name, .. }, data) => {
+                assert_eq!(dirs, &vec!["photos".to_string(), "2024".to_string()]);
+                assert_eq!(name, "a.txt");
+                assert_eq!(data, b"hello");
+            }
+            other => panic!("unexpected entry: {:?}", other),
+        }
+        assert!(matches!(entries[1].0, ArchiveEntry::Skipped { .. }));
+        assert!(matches!(entries[2].0, ArchiveEntry::Skipped { .. }));
+    }
+
+    #[test]
+    fn test_zip_bomb_limits() {
+        let zeros = vec![0u8; 4 * 1024 * 1024];
+        let data = zip_of(&[("zeros.txt", &zeros)]);
+
+        let err = read_all(&data, ArchiveFormat::Zip, &ArchiveLimits::default()).unwrap_err();
+        assert!(matches!(err, ArchiveError::RatioExceeded(..)));
+
+        let limits = ArchiveLimits { max_ratio: f64::MAX, max_total_size: 1024 * 1024, ..Default::default() };
+        let err = read_all(&data, ArchiveFormat::Zip, &limits).unwrap_err();
+        assert!(matches!(err, ArchiveError::TooLarge(_)));
+
+        let limits = ArchiveLimits { max_ratio: f64::MAX, max_entry_size: Some(1024), ..Default::default() };
+        let err = read_all(&data, ArchiveFormat::Zip, &limits).unwrap_err();
+        assert!(matches!(err, ArchiveError::TooLarge(1024)));
+
+        let many: Vec<(String, &[u8])> = (0..5).map(|i| (format!("{}.txt", i), &b"x"[..])).collect();
+        let refs: Vec<(&str, &[u8])> = many.iter().map(|(n, d)| (n.as_str(), *d)).collect();
+        let limits = ArchiveLimits { max_entries: 4, ..Default::default() };
+        let err = read_all(&zip_of(&refs), ArchiveFormat::Zip, &limits).unwrap_err();
+        assert!(matches!(err, ArchiveError::TooManyEntries(4)));
+    }
+
+    #[test]
+    fn test_read_tar_gz() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, "docs/readme.txt", &b"hello"[..]).unwrap();
+
+        let mut link = tar::Header::new_gnu();
+        link.set_entry_type(tar::EntryType::Symlink);
+        link.set_size(0);
+        builder.append_link(&mut link, "docs/passwd", "/etc/passwd").unwrap();
+
+        let tar = builder.into_inner().unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&tar).unwrap();
+        let data = encoder.finish().unwrap();
+
+        assert_eq!(ArchiveFormat::detect(&data, "x"), Some(ArchiveFormat::TarGz));
+        let entries = read_all(&data, ArchiveFormat::TarGz, &ArchiveLimits::default()).unwrap();
+        assert!(matches!(&entries[0], (ArchiveEntry::File { name, .. }, data) if name == "readme.txt" && data == b"hello"));
+        assert!(matches!(entries[1].0, ArchiveEntry::Skipped { .. }));
+    }
+}
@@ -0,0 +1,214 @@
+//! AVIF
+//!
+//! Encoding through the pure-Rust rav1e encoder (behind the `avif`
+//! feature), and image size from the container header, which needs no AV1
+//! decoder.
+
+use image::DynamicImage;
+
+use super::image::ImageError;
+
+/// Whether the data is an AVIF file (`ftyp` box with an AVIF brand)
+pub fn is_avif(data: &[u8]) -> bool {
+    let Some((kind, ftyp)) = boxes(data).next() else { return false };
+    if &kind != b"ftyp" || ftyp.len() < 8 {
+        return false;
+    }
+
+    // Major brand, then compatible brands after the minor version
+    std::iter::once(&ftyp[..4])
+        .chain(ftyp[8..].chunks_exact(4))
+        .any(|brand| brand == b"avif" || brand == b"avis")
+}
+
+/// Width and height of the primary image, from its `ispe` property
+pub fn dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if !is_avif(data) {
+        return None;
+    }
+
+    // `meta` is a full box: skip version and flags
+    let meta = find(data, b"meta")?.get(4..)?;
+    let iprp = find(meta, b"iprp")?;
+    let properties: Vec<([u8; 4], &[u8])> = boxes(find(iprp, b"ipco")?).collect();
+
+    let ispe = |(kind, body): &([u8; 4], &[u8])| -> Option<(u32, u32)> {
+        if kind != b"ispe" {
+            return None;
+        }
+        let (width, height) = (read_u32(body, 4)?, read_u32(body, 8)?);
+        (width > 0 && height > 0).then_some((width, height))
+    };
+
+    let primary = find(meta, b"pitm").and_then(|pitm| match pitm.first()? {
+        0 => read_u16(pitm, 4).map(u32::from),
+        _ => read_u32(pitm, 4),
+    });
+    if let (Some(primary), Some(ipma)) = (primary, find(iprp, b"ipma")) {
+        let found = associations(ipma, primary)
+            .into_iter()
+            .filter_map(|index| properties.get(index.checked_sub(1)?))
+            .find_map(ispe);
+        if found.is_some() {
+            return found;
+        }
+    }
+
+    // Without usable associations, the first size will do
+    properties.iter().find_map(ispe)
+}
+
+/// Encode as AVIF
+///
+/// `speed` ranges from 1 (smallest files) to 10 (fastest).
+#[cfg(feature = "avif")]
+pub fn encode(img: &DynamicImage, quality: u8, speed: u8) -> Result<Vec<u8>, ImageError> {
+    use image::codecs::avif::AvifEncoder;
+
+    // The encoder takes 8-bit RGB(A)
+    let img = if img.color().has_alpha() {
+        DynamicImage::ImageRgba8(img.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(img.to_rgb8())
+    };
+
+    let mut buffer = Vec::new();
+    let encoder = AvifEncoder::new_with_speed_quality(&mut buffer, speed.clamp(1, 10), quality.clamp(1, 100));
+    img.write_with_encoder(encoder)?;
+    Ok(buffer)
+}
+
+/// Encode as AVIF (unavailable: built without the `avif` feature)
+#[cfg(not(feature = "avif"))]
+pub fn encode(_img: &DynamicImage, _quality: u8, _speed: u8) -> Result<Vec<u8>, ImageError> {
+    Err(ImageError::UnsupportedFormat("AVIF (built without the `avif` feature)".to_string()))
+}
+
+/// Property indices (1-based) associated with `item` in an `ipma` box
+fn associations(ipma: &[u8], item: u32) -> Vec<usize> {
+    let Some(&version) = ipma.first() else { return Vec::new() };
+    let large_index = ipma.get(3).is_some_and(|flags| flags & 1 == 1);
+    let Some(count) = read_u32(ipma, 4) else { return Vec::new() };
+
+    let mut pos = 8;
+    for _ in 0..count {
+        let id = if version == 0 {
+            read_u16(ipma, pos).map(u32::from)
+        } else {
+            read_u32(ipma, pos)
+        };
+        let Some(id) = id else { break };
+        pos += if version == 0 { 2 } else { 4 };
+
+        let Some(&associated) = ipma.get(pos) else { break };
+        pos += 1;
+
+        let width = if large_index { 2 } else { 1 };
+        let Some(entries) = ipma.get(pos..pos + associated as usize * width) else { break };
+        pos += entries.len();
+
+        if id == item {
+            // The top bit of each entry marks the property as essential
+            return entries.chunks_exact(width).map(|entry| match entry {
+                [high, low] => (u16::from_be_bytes([*high, *low]) & 0x7fff) as usize,
+                [index] => (index & 0x7f) as usize,
+                _ => 0,
+            }).collect();
+        }
+    }
+
+    Vec::new()
+}
+
+/// Payload of the first child box of type `kind`
+fn find<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(data).find(|(k, _)| k == kind).map(|(_, body)| body)
+}
+
+/// ISO BMFF boxes in `data`, as (type, payload)
+///
+/// Iteration stops at the first box whose size does not fit.
+fn boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = 0usize;
+    std::iter::from_fn(move || {
+        let size = read_u32(data, pos)? as usize;
+        let kind: [u8; 4] = data.get(pos + 4..pos + 8)?.try_into().ok()?;
+
+        let (header, size) = match size {
+            0 => (8, data.len() - pos),
+            1 => (16, usize::try_from(read_u64(data, pos + 8)?).ok()?),
+            size => (8, size),
+        };
+        if size < header {
+            return None;
+        }
+
+        let body = data.get(pos + header..pos.checked_add(size)?)?;
+        pos += size;
+        Some((kind, body))
+    })
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn read_u64(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(pos..pos + 8)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bx(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn ispe(width: u32, height: u32) -> Vec<u8> {
+        let mut body = vec![0; 4];
+        body.extend_from_slice(&width.to_be_bytes());
+        body.extend_from_slice(&height.to_be_bytes());
+        bx(b"ispe", &body)
+    }
+
+    /// Container with a thumbnail-sized `ispe` first and the primary
+    /// item's (item 2) second
+    fn container() -> Vec<u8> {
+        let ipco = bx(b"ipco", &[ispe(64, 48), ispe(640, 480)].concat());
+        // version 0, flags 0, one entry: item 2 -> property 2 (essential)
+        let ipma = bx(b"ipma", &[0, 0, 0, 0, 0, 0, 0, 1, 0, 2, 1, 0x82]);
+        let pitm = bx(b"pitm", &[0, 0, 0, 0, 0, 2]);
+        let meta = bx(b"meta", &[vec![0; 4], pitm, bx(b"iprp", &[ipco, ipma].concat())].concat());
+
+        [bx(b"ftyp", b"mif1\0\0\0\0mif1avif"), meta].concat()
+    }
+
+    #[test]
+    fn test_dimensions() {
+        let data = container();
+        assert!(is_avif(&data));
+        assert_eq!(dimensions(&data), Some((640, 480)));
+
+        // Truncated or foreign data is rejected, not misread
+        assert_eq!(dimensions(&data[..data.len() - 6]), None);
+        assert!(!is_avif(&bx(b"ftyp", b"isom\0\0\0\0mp41")));
+        assert!(!is_avif(b"\x89PNG\r\n\x1a\n"));
+        assert_eq!(dimensions(&[0, 0, 0, 4, b'f', b't', b'y', b'p']), None);
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn test_encode_round_trip() {
+        let img = DynamicImage::new_rgb8(40, 30);
+        let data = encode(&img, 60, 10).unwrap();
+        assert_eq!(dimensions(&data), Some((40, 30)));
+    }
+}
@@ -0,0 +1,301 @@
+//! Upload Batches
+//!
+//! Files uploaded together share a batch ID. Each file's state is tracked
+//! through the pipeline and every change is published on a broadcast
+//! channel, which handlers stream to the browser as Server-Sent Events.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use tokio::sync::{broadcast, RwLock};
+use uuid::Uuid;
+
+use crate::models::{BatchFile, BatchFileRef, FileState, MediaItem, UploadBatch};
+
+/// Events buffered for each subscriber before it lags
+const EVENT_CAPACITY: usize = 256;
+
+tokio::task_local! {
+    /// Batch file the current upload reports to
+    static CURRENT: FileProgress;
+}
+
+/// Batch error
+#[derive(Debug, thiserror::Error)]
+pub enum BatchError {
+    #[error("Batch not found: {0}")]
+    NotFound(Uuid),
+    #[error("Batch {0} has no file {1}")]
+    InvalidFile(Uuid, usize),
+    #[error("Batch has no files")]
+    Empty,
+}
+
+/// State change of one file
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchEvent {
+    pub batch_id: Uuid,
+    /// File after the change
+    pub file: BatchFile,
+    /// Whether every file in the batch is now done or failed
+    pub finished: bool,
+}
+
+/// Upload batches and their progress events
+#[derive(Clone)]
+pub struct UploadBatches {
+    batches: Arc<RwLock<HashMap<Uuid, UploadBatch>>>,
+    events: broadcast::Sender<BatchEvent>,
+}
+
+impl Default for UploadBatches {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UploadBatches {
+    /// Create an empty batch registry
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        Self {
+            batches: Arc::new(RwLock::new(HashMap::new())),
+            events,
+        }
+    }
+
+    /// Start a batch of `(filename, size)` files, all queued
+    pub async fn create(&self, files: Vec<(String, u64)>, user_id: Option<Uuid>) -> Result<UploadBatch, BatchError> {
+        if files.is_empty() {
+            return Err(BatchError::Empty);
+        }
+
+        let now = Utc::now();
+        let batch = UploadBatch {
+            id: Uuid::now_v7(),
+            user_id,
+            files: files.into_iter().enumerate().map(|(index, (filename, size))| BatchFile {
+                index,
+                filename,
+                size,
+                received: 0,
+                state: FileState::Queued,
+                media_id: None,
+                error: None,
+            }).collect(),
+            created_at: now,
+            updated_at: now,
+        };
+
+        self.batches.write().await.insert(batch.id, batch.clone());
+        Ok(batch)
+    }
+
+    /// Get a batch
+    pub async fn get(&self, id: Uuid) -> Option<UploadBatch> {
+        self.batches.read().await.get(&id).cloned()
+    }
+
+    /// Get a batch started by `user_id`
+    ///
+    /// Other users' batches are reported as not found.
+    pub async fn get_owned(&self, id: Uuid, user_id: Option<Uuid>) -> Result<UploadBatch, BatchError> {
+        self.get(id).await
+            .filter(|batch| batch.user_id == user_id)
+            .ok_or(BatchError::NotFound(id))
+    }
+
+    /// Receive the events of every batch
+    ///
+    /// Subscribe before reading a batch's current state so no change is
+    /// missed in between.
+    pub fn subscribe(&self) -> broadcast::Receiver<BatchEvent> {
+        self.events.subscribe()
+    }
+
+    /// Progress handle of one file
+    pub async fn file(&self, file: BatchFileRef) -> Result<FileProgress, BatchError> {
+        let batches = self.batches.read().await;
+        let batch = batches.get(&file.batch_id).ok_or(BatchError::NotFound(file.batch_id))?;
+        if file.index >= batch.files.len() {
+            return Err(BatchError::InvalidFile(file.batch_id, file.index));
+        }
+
+        Ok(FileProgress { batches: self.clone(), file })
+    }
+
+    /// Track a file of a batch started by `user_id`
+    pub async fn file_owned(&self, file: BatchFileRef, user_id: Option<Uuid>) -> Result<FileProgress, BatchError> {
+        self.get_owned(file.batch_id, user_id).await?;
+        self.file(file).await
+    }
+
+    /// Change a file and publish the result
+    ///
+    /// Files that are done or failed are left alone.
+    async fn update(&self, file: BatchFileRef, change: impl FnOnce(&mut BatchFile)) {
+        let event = {
+            let mut batches = self.batches.write().await;
+            let Some(batch) = batches.get_mut(&file.batch_id) else { return };
+            let Some(entry) = batch.files.get_mut(file.index) else { return };
+            if entry.state.is_final() {
+                return;
+            }
+
+            change(entry);
+            let entry = entry.clone();
+            batch.updated_at = Utc::now();
+
+            BatchEvent { batch_id: batch.id, file: entry, finished: batch.is_finished() }
+        };
+
+        // Nobody listening is fine
+        let _ = self.events.send(event);
+    }
+
+    /// Forget batches without changes for `max_age`
+    pub async fn cleanup(&self, max_age: Duration) -> usize {
+        let cutoff = Utc::now() - max_age;
+        let mut batches = self.batches.write().await;
+        let before = batches.len();
+        batches.retain(|_, b| b.updated_at > cutoff);
+        before - batches.len()
+    }
+}
+
+/// Reports the progress of one file in a batch
+#[derive(Clone)]
+pub struct FileProgress {
+    batches: UploadBatches,
+    file: BatchFileRef,
+}
+
+impl FileProgress {
+    /// File this handle reports to
+    pub fn file(&self) -> BatchFileRef {
+        self.file
+    }
+
+    /// Move the file to `state`
+    pub async fn set_state(&self, state: FileState) {
+        self.batches.update(self.file, |f| f.state = state).await;
+    }
+
+    /// Record `bytes` received so far
+    pub async fn received(&self, bytes: u64) {
+        self.batches.update(self.file, |f| {
+            f.state = FileState::Receiving;
+            f.received = bytes;
+        }).await;
+    }
+
+    /// Mark the file stored as `media`
+    pub async fn done(&self, media: &MediaItem) {
+        self.batches.update(self.file, |f| {
+            f.state = FileState::Done;
+            f.received = f.received.max(f.size);
+            f.media_id = Some(media.id);
+        }).await;
+    }
+
+    /// Mark the file failed
+    pub async fn fail(&self, error: impl ToString) {
+        let error = error.to_string();
+        self.batches.update(self.file, |f| {
+            f.state = FileState::Failed;
+            f.error = Some(error);
+        }).await;
+    }
+
+    /// Run the upload of this file, reporting its steps and outcome
+    pub async fn run<F, E>(self, upload: F) -> Result<MediaItem, E>
+    where
+        F: Future<Output = Result<MediaItem, E>>,
+        E: std::fmt::Display,
+    {
+        let result = CURRENT.scope(self.clone(), upload).await;
+        match &result {
+            Ok(media) => self.done(media).await,
+            Err(e) => self.fail(e).await,
+        }
+        result
+    }
+}
+
+/// Report a pipeline step for the batch file being uploaded, if any
+pub(crate) async fn report(state: FileState) {
+    if let Ok(progress) = CURRENT.try_with(FileProgress::clone) {
+        progress.set_state(state).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_batch_progress() {
+        let batches = UploadBatches::new();
+        let batch = batches.create(vec![("a.png".into(), 10), ("b.png".into(), 20)], None).await.unwrap();
+        let mut events = batches.subscribe();
+
+        let first = batches.file(BatchFileRef { batch_id: batch.id, index: 0 }).await.unwrap();
+        let second = batches.file(BatchFileRef { batch_id: batch.id, index: 1 }).await.unwrap();
+        assert!(matches!(
+            batches.file(BatchFileRef { batch_id: batch.id, index: 2 }).await,
+            Err(BatchError::InvalidFile(_, 2))
+        ));
+
+        first.received(10).await;
+        let media = MediaItem::new("a.png", "image/png", 10, "a.png");
+        let result: Result<_, String> = first.run(async {
+            report(FileState::Scanning).await;
+            report(FileState::Processing).await;
+            Ok(media.clone())
+        }).await;
+        assert!(result.is_ok());
+
+        let states: Vec<FileState> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|e| e.file.state)
+            .collect();
+        assert_eq!(states, [FileState::Receiving, FileState::Scanning, FileState::Processing, FileState::Done]);
+
+        // Outside of `run` nothing is reported
+        report(FileState::Thumbnails).await;
+        assert!(events.try_recv().is_err());
+
+        let result = second.clone().run(async { Err::<MediaItem, _>("infected") }).await;
+        assert!(result.is_err());
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.file.error.as_deref(), Some("infected"));
+        assert!(event.finished);
+
+        // Final states stick
+        second.set_state(FileState::Processing).await;
+        let batch = batches.get(batch.id).await.unwrap();
+        assert_eq!(batch.files[0].media_id, Some(media.id));
+        assert_eq!(batch.files[1].state, FileState::Failed);
+        assert!(batch.is_finished());
+
+        assert_eq!(batches.cleanup(Duration::hours(1)).await, 0);
+        assert_eq!(batches.cleanup(Duration::zero()).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_batches_owned_by_user() {
+        let batches = UploadBatches::new();
+        let owner = Some(Uuid::new_v4());
+        let batch = batches.create(vec![("a.png".into(), 10)], owner).await.unwrap();
+        let file = BatchFileRef { batch_id: batch.id, index: 0 };
+
+        assert!(batches.get_owned(batch.id, owner).await.is_ok());
+        assert!(batches.file_owned(file, owner).await.is_ok());
+
+        for other in [Some(Uuid::new_v4()), None] {
+            assert!(matches!(batches.get_owned(batch.id, other).await, Err(BatchError::NotFound(_))));
+            assert!(matches!(batches.file_owned(file, other).await, Err(BatchError::NotFound(_))));
+        }
+    }
+}
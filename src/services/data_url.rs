@@ -0,0 +1,174 @@
+//! Encoded Uploads
+//!
+//! Decodes `data:` URLs and raw base64 bodies, as posted by editors for
+//! pasted images, without ever holding more than the size limit.
+
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+
+/// Base64 characters decoded per step (a multiple of 4)
+const DECODE_BLOCK: usize = 16 * 1024;
+
+/// Encoded upload error
+#[derive(Debug, thiserror::Error)]
+pub enum DataUrlError {
+    #[error("Malformed data URL: {0}")]
+    Malformed(String),
+    #[error("Invalid base64: {0}")]
+    Base64(String),
+    #[error("Decoded data exceeds {0} bytes")]
+    TooLarge(u64),
+    #[error("No data")]
+    Empty,
+}
+
+/// Decoded upload
+#[derive(Debug, Clone)]
+pub struct DecodedData {
+    /// File contents
+    pub data: Vec<u8>,
+    /// MIME type declared by the data URL
+    pub declared_type: Option<String>,
+}
+
+/// Decode a `data:` URL or a raw base64 string
+///
+/// Whitespace is ignored, and both the standard and URL-safe alphabets are
+/// accepted with or without padding. Decoding stops as soon as the output
+/// would exceed `max_size`.
+pub fn decode(input: &str, max_size: u64) -> Result<DecodedData, DataUrlError> {
+    let input = input.trim();
+
+    let Some(rest) = strip_prefix_ignore_case(input, "data:") else {
+        let data = decode_base64(input, max_size)?;
+        return Ok(DecodedData { data, declared_type: None });
+    };
+
+    let (header, payload) = rest.split_once(',')
+        .ok_or_else(|| DataUrlError::Malformed("missing ','".to_string()))?;
+
+    let mut params = header.split(';').map(str::trim);
+    let declared_type = params.next()
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_ascii_lowercase());
+    if declared_type.as_deref().is_some_and(|t| !t.contains('/')) {
+        return Err(DataUrlError::Malformed(format!("invalid media type {}", header)));
+    }
+    let is_base64 = params.any(|p| p.eq_ignore_ascii_case("base64"));
+
+    let data = if is_base64 {
+        decode_base64(payload, max_size)?
+    } else {
+        percent_decode(payload, max_size)?
+    };
+
+    Ok(DecodedData { data, declared_type })
+}
+
+fn strip_prefix_ignore_case<'a>(input: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = input.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix).then(|| &input[prefix.len()..])
+}
+
+/// Decode base64 block by block, bounded by `max_size`
+fn decode_base64(encoded: &str, max_size: u64) -> Result<Vec<u8>, DataUrlError> {
+    // Cheap early rejection, generous enough to allow for line breaks
+    let estimate = (encoded.len() as u64 / 4) * 3;
+    if estimate > max_size + max_size / 2 + 3 {
+        return Err(DataUrlError::TooLarge(max_size));
+    }
+
+    let url_safe = encoded.bytes().any(|b| b == b'-' || b == b'_');
+    let config = GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+    let engine = if url_safe {
+        GeneralPurpose::new(&alphabet::URL_SAFE, config)
+    } else {
+        GeneralPurpose::new(&alphabet::STANDARD, config)
+    };
+
+    let mut out = Vec::with_capacity(estimate.min(max_size) as usize);
+    let mut block = Vec::with_capacity(DECODE_BLOCK);
+
+    let mut chars = encoded.bytes().filter(|b| !b.is_ascii_whitespace()).peekable();
+    while chars.peek().is_some() {
+        block.clear();
+        block.extend(chars.by_ref().take(DECODE_BLOCK));
+
+        engine.decode_vec(&block, &mut out)
+            .map_err(|e| DataUrlError::Base64(e.to_string()))?;
+        if out.len() as u64 > max_size {
+            return Err(DataUrlError::TooLarge(max_size));
+        }
+    }
+
+    if out.is_empty() {
+        return Err(DataUrlError::Empty);
+    }
+
+    Ok(out)
+}
+
+/// Decode a percent-encoded (non-base64) data URL payload
+fn percent_decode(payload: &str, max_size: u64) -> Result<Vec<u8>, DataUrlError> {
+    let bytes = payload.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len().min(max_size as usize));
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = payload.get(i + 1..i + 3)
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+                .ok_or_else(|| DataUrlError::Malformed(format!("bad escape at {}", i)))?;
+            out.push(hex);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+        if out.len() as u64 > max_size {
+            return Err(DataUrlError::TooLarge(max_size));
+        }
+    }
+
+    if out.is_empty() {
+        return Err(DataUrlError::Empty);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_data_url() {
+        let decoded = decode("data:Image/PNG;base64,aGVsbG8gd29y\nbGQ=", 1024).unwrap();
+        assert_eq!(decoded.data, b"hello world");
+        assert_eq!(decoded.declared_type.as_deref(), Some("image/png"));
+
+        let decoded = decode("data:image/svg+xml;charset=utf-8,%3Csvg%3E%3C/svg%3E", 1024).unwrap();
+        assert_eq!(decoded.data, b"<svg></svg>");
+        assert_eq!(decoded.declared_type.as_deref(), Some("image/svg+xml"));
+
+        assert!(matches!(decode("data:image/png;base64", 1024), Err(DataUrlError::Malformed(_))));
+        assert!(matches!(decode("data:png;base64,aGk=", 1024), Err(DataUrlError::Malformed(_))));
+        assert!(matches!(decode("data:image/png;base64,", 1024), Err(DataUrlError::Empty)));
+    }
+
+    #[test]
+    fn test_decode_raw_base64() {
+        // URL-safe alphabet without padding
+        assert_eq!(decode("-_-_", 1024).unwrap().data, vec![0xfb, 0xff, 0xbf]);
+        assert_eq!(decode("aGk", 1024).unwrap().data, b"hi");
+        assert!(matches!(decode("not base64!", 1024), Err(DataUrlError::Base64(_))));
+
+        // Large input decoded across several blocks
+        let data: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+        let encoded = base64::engine::general_purpose::STANDARD.encode(&data);
+        assert_eq!(decode(&encoded, 40_000).unwrap().data, data);
+        assert!(matches!(decode(&encoded, 39_999), Err(DataUrlError::TooLarge(39_999))));
+        assert!(matches!(decode(&encoded, 1_000), Err(DataUrlError::TooLarge(1_000))));
+    }
+}
@@ -0,0 +1,363 @@
+//! EXIF
+//!
+//! Camera metadata from JPEG, TIFF, PNG (`eXIf` chunk) and WebP (`EXIF`
+//! chunk) files, read with kamadak-exif. Fields that are missing, of the
+//! wrong type or out of range are left out; a block that does not parse at
+//! all is an error the caller can ignore.
+
+use std::io::Cursor;
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use ::exif::{Exif, In, Reader, Tag, Value};
+
+use crate::models::{ExifData, GpsLocation, MediaMetadata};
+
+/// EXIF error
+#[derive(Debug, thiserror::Error)]
+pub enum ExifError {
+    #[error("No EXIF data")]
+    NotFound,
+    #[error("Malformed EXIF data: {0}")]
+    Malformed(String),
+}
+
+/// Metadata read from an EXIF block
+#[derive(Debug, Clone, Default)]
+pub struct ExifMetadata {
+    /// Camera and capture settings
+    pub exif: ExifData,
+    /// Where the photo was taken
+    pub location: Option<GpsLocation>,
+    /// Photographer
+    pub artist: Option<String>,
+    /// Copyright notice
+    pub copyright: Option<String>,
+}
+
+impl ExifMetadata {
+    /// Store on an item's metadata
+    ///
+    /// The location is always replaced, so a new file never keeps the old
+    /// one's coordinates.
+    pub fn apply(self, metadata: &mut MediaMetadata) {
+        if self.exif.date_taken.is_some() {
+            metadata.created_date = self.exif.date_taken;
+        }
+        if self.artist.is_some() {
+            metadata.artist = self.artist;
+        }
+        if self.copyright.is_some() {
+            metadata.copyright = self.copyright;
+        }
+        metadata.location = self.location;
+        metadata.exif = Some(self.exif);
+    }
+}
+
+/// Read the EXIF block of an image
+pub fn read(data: &[u8]) -> Result<ExifMetadata, ExifError> {
+    let exif = Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .map_err(|e| match e {
+            ::exif::Error::NotFound(_) => ExifError::NotFound,
+            e => ExifError::Malformed(e.to_string()),
+        })?;
+
+    Ok(ExifMetadata {
+        exif: ExifData {
+            camera_make: text(&exif, Tag::Make),
+            camera_model: text(&exif, Tag::Model),
+            exposure_time: rational(&exif, Tag::ExposureTime, 0).and_then(exposure),
+            f_number: positive(&exif, Tag::FNumber),
+            iso: uint(&exif, Tag::PhotographicSensitivity)
+                .or_else(|| uint(&exif, Tag::ISOSpeed))
+                .filter(|&iso| iso > 0),
+            focal_length: positive(&exif, Tag::FocalLength),
+            // Bit 0: the flash fired
+            flash: uint(&exif, Tag::Flash).map(|flash| flash & 1 == 1),
+            orientation: uint(&exif, Tag::Orientation).filter(|o| (1..=8).contains(o)),
+            date_taken: date_taken(&exif),
+            software: text(&exif, Tag::Software),
+        },
+        location: location(&exif),
+        artist: text(&exif, Tag::Artist),
+        copyright: text(&exif, Tag::Copyright),
+    })
+}
+
+/// ASCII field, trimmed; parts (photographer and editor copyright) are
+/// joined with "; "
+fn text(exif: &Exif, tag: Tag) -> Option<String> {
+    let Value::Ascii(parts) = &exif.get_field(tag, In::PRIMARY)?.value else { return None };
+
+    // Cameras often write UTF-8 despite the ASCII type
+    let parts: Vec<String> = parts.iter()
+        .map(|part| String::from_utf8_lossy(part).trim_matches(|c: char| c == '\0' || c.is_whitespace()).to_string())
+        .filter(|part| !part.is_empty())
+        .collect();
+
+    (!parts.is_empty()).then(|| parts.join("; "))
+}
+
+fn uint(exif: &Exif, tag: Tag) -> Option<u32> {
+    exif.get_field(tag, In::PRIMARY)?.value.get_uint(0)
+}
+
+/// Rational at `index` as (numerator, denominator), if the denominator is
+/// not zero
+fn rational(exif: &Exif, tag: Tag, index: usize) -> Option<(u32, u32)> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(values) => values.get(index)
+            .filter(|r| r.denom != 0)
+            .map(|r| (r.num, r.denom)),
+        _ => None,
+    }
+}
+
+fn float(exif: &Exif, tag: Tag, index: usize) -> Option<f64> {
+    rational(exif, tag, index).map(|(num, denom)| f64::from(num) / f64::from(denom))
+}
+
+fn positive(exif: &Exif, tag: Tag) -> Option<f64> {
+    float(exif, tag, 0).filter(|&v| v > 0.0)
+}
+
+/// Exposure as photographers write it: `1/250` below a second, else
+/// seconds (`2`, `2.5`)
+fn exposure((num, denom): (u32, u32)) -> Option<String> {
+    if num == 0 {
+        return None;
+    }
+    if num < denom {
+        let denom = f64::from(denom) / f64::from(num);
+        return Some(format!("1/{}", round(denom, 1)));
+    }
+    Some(round(f64::from(num) / f64::from(denom), 1).to_string())
+}
+
+/// Round to `places` decimals
+fn round(value: f64, places: i32) -> f64 {
+    let factor = 10f64.powi(places);
+    (value * factor).round() / factor
+}
+
+/// Original capture time, then digitized, then last modified
+///
+/// The matching `OffsetTime*` field gives the time zone; without one the
+/// time is taken as UTC.
+fn date_taken(exif: &Exif) -> Option<DateTime<Utc>> {
+    [
+        (Tag::DateTimeOriginal, Tag::OffsetTimeOriginal),
+        (Tag::DateTimeDigitized, Tag::OffsetTimeDigitized),
+        (Tag::DateTime, Tag::OffsetTime),
+    ]
+    .into_iter()
+    .find_map(|(date, offset)| {
+        let Value::Ascii(parts) = &exif.get_field(date, In::PRIMARY)?.value else { return None };
+        let mut parsed = ::exif::DateTime::from_ascii(parts.first()?).ok()?;
+        if let Some(Value::Ascii(offset)) = exif.get_field(offset, In::PRIMARY).map(|f| &f.value) {
+            if let Some(offset) = offset.first() {
+                // A blank or broken offset still leaves the date
+                let _ = parsed.parse_offset(offset);
+            }
+        }
+
+        let local = NaiveDate::from_ymd_opt(i32::from(parsed.year), u32::from(parsed.month), u32::from(parsed.day))?
+            .and_hms_opt(u32::from(parsed.hour), u32::from(parsed.minute), u32::from(parsed.second))?;
+        let offset = FixedOffset::east_opt(i32::from(parsed.offset.unwrap_or(0)) * 60)?;
+        Some(local.and_local_timezone(offset).single()?.with_timezone(&Utc))
+    })
+}
+
+/// GPS position in decimal degrees, with altitude in metres (negative
+/// below sea level)
+fn location(exif: &Exif) -> Option<GpsLocation> {
+    let latitude = coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S')?;
+    let longitude = coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W')?;
+    if latitude.abs() > 90.0 || longitude.abs() > 180.0 {
+        return None;
+    }
+
+    let altitude = float(exif, Tag::GPSAltitude, 0).map(|altitude| {
+        match uint(exif, Tag::GPSAltitudeRef) {
+            Some(1) => -altitude,
+            _ => altitude,
+        }
+    });
+
+    Some(GpsLocation { latitude, longitude, altitude })
+}
+
+/// Degrees, minutes and seconds as signed decimal degrees
+fn coordinate(exif: &Exif, tag: Tag, reference: Tag, negative: u8) -> Option<f64> {
+    let degrees = float(exif, tag, 0)?;
+    // Minutes and seconds may be missing or folded into the degrees
+    let minutes = float(exif, tag, 1).unwrap_or(0.0);
+    let seconds = float(exif, tag, 2).unwrap_or(0.0);
+    let value = degrees + minutes / 60.0 + seconds / 3600.0;
+
+    let sign = match &exif.get_field(reference, In::PRIMARY)?.value {
+        Value::Ascii(parts) if parts.first()?.first()?.eq_ignore_ascii_case(&negative) => -1.0,
+        Value::Ascii(_) => 1.0,
+        _ => return None,
+    };
+
+    Some(sign * value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::exif::experimental::Writer;
+    use ::exif::{Field, Rational};
+
+    fn ascii(tag: Tag, value: &str) -> Field {
+        Field { tag, ifd_num: In::PRIMARY, value: Value::Ascii(vec![value.as_bytes().to_vec()]) }
+    }
+
+    fn rationals(tag: Tag, values: &[(u32, u32)]) -> Field {
+        let values = values.iter().map(|&(num, denom)| Rational { num, denom }).collect();
+        Field { tag, ifd_num: In::PRIMARY, value: Value::Rational(values) }
+    }
+
+    fn short(tag: Tag, value: u16) -> Field {
+        Field { tag, ifd_num: In::PRIMARY, value: Value::Short(vec![value]) }
+    }
+
+    /// TIFF-structured EXIF block of a photo taken in Tokyo
+    fn block() -> Vec<u8> {
+        let fields = [
+            ascii(Tag::Make, "Canon"),
+            ascii(Tag::Model, "EOS R5 "),
+            ascii(Tag::Software, "Firmware 1.8"),
+            ascii(Tag::Artist, "Jane Doe"),
+            ascii(Tag::Copyright, "(c) Jane Doe"),
+            short(Tag::Orientation, 6),
+            rationals(Tag::ExposureTime, &[(10, 2500)]),
+            rationals(Tag::FNumber, &[(28, 10)]),
+            short(Tag::PhotographicSensitivity, 400),
+            rationals(Tag::FocalLength, &[(50, 1)]),
+            short(Tag::Flash, 0x19),
+            ascii(Tag::DateTimeOriginal, "2024:05:01 18:30:00"),
+            ascii(Tag::OffsetTimeOriginal, "+09:00"),
+            ascii(Tag::GPSLatitudeRef, "N"),
+            rationals(Tag::GPSLatitude, &[(35, 1), (40, 1), (3024, 100)]),
+            ascii(Tag::GPSLongitudeRef, "E"),
+            rationals(Tag::GPSLongitude, &[(139, 1), (45, 1), (0, 1)]),
+            Field { tag: Tag::GPSAltitudeRef, ifd_num: In::PRIMARY, value: Value::Byte(vec![1]) },
+            rationals(Tag::GPSAltitude, &[(125, 10)]),
+        ];
+
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut buffer = Cursor::new(Vec::new());
+        writer.write(&mut buffer, false).unwrap();
+        buffer.into_inner()
+    }
+
+    fn jpeg(tiff: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        data.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        data.extend_from_slice(b"Exif\0\0");
+        data.extend_from_slice(tiff);
+        data.extend_from_slice(&[0xFF, 0xD9]);
+        data
+    }
+
+    fn png(tiff: &[u8]) -> Vec<u8> {
+        let chunk = |kind: &[u8], body: &[u8]| {
+            let mut chunk = (body.len() as u32).to_be_bytes().to_vec();
+            chunk.extend_from_slice(kind);
+            chunk.extend_from_slice(body);
+            // The reader does not check CRCs
+            chunk.extend_from_slice(&[0; 4]);
+            chunk
+        };
+        let ihdr = [0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0];
+        [b"\x89PNG\r\n\x1a\n".to_vec(), chunk(b"IHDR", &ihdr), chunk(b"eXIf", tiff), chunk(b"IEND", &[])].concat()
+    }
+
+    fn webp(tiff: &[u8]) -> Vec<u8> {
+        let mut chunk = b"EXIF".to_vec();
+        chunk.extend_from_slice(&(tiff.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(tiff);
+        if tiff.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&((chunk.len() + 4) as u32).to_le_bytes());
+        data.extend_from_slice(b"WEBP");
+        data.extend_from_slice(&chunk);
+        data
+    }
+
+    #[test]
+    fn test_read_containers() {
+        let tiff = block();
+        for data in [tiff.clone(), jpeg(&tiff), png(&tiff), webp(&tiff)] {
+            let metadata = read(&data).unwrap();
+            let exif = &metadata.exif;
+            assert_eq!(exif.camera_make.as_deref(), Some("Canon"));
+            assert_eq!(exif.camera_model.as_deref(), Some("EOS R5"));
+            assert_eq!(exif.software.as_deref(), Some("Firmware 1.8"));
+            assert_eq!(exif.exposure_time.as_deref(), Some("1/250"));
+            assert_eq!(exif.f_number, Some(2.8));
+            assert_eq!(exif.iso, Some(400));
+            assert_eq!(exif.focal_length, Some(50.0));
+            assert_eq!(exif.flash, Some(true));
+            assert_eq!(exif.orientation, Some(6));
+            // 18:30 in Tokyo
+            assert_eq!(exif.date_taken.unwrap().to_rfc3339(), "2024-05-01T09:30:00+00:00");
+
+            let location = metadata.location.unwrap();
+            assert!((location.latitude - 35.6751).abs() < 1e-4);
+            assert!((location.longitude - 139.75).abs() < 1e-9);
+            assert_eq!(location.altitude, Some(-12.5));
+            assert_eq!(metadata.artist.as_deref(), Some("Jane Doe"));
+            assert_eq!(metadata.copyright.as_deref(), Some("(c) Jane Doe"));
+        }
+
+        let mut media = MediaMetadata::default();
+        read(&tiff).unwrap().apply(&mut media);
+        assert_eq!(media.artist.as_deref(), Some("Jane Doe"));
+        assert!(media.created_date.is_some() && media.location.is_some() && media.exif.is_some());
+    }
+
+    #[test]
+    fn test_malformed() {
+        // Truncated, garbage and missing blocks fail without panicking
+        let tiff = block();
+        for len in [0, 4, 8, 20, tiff.len() / 2] {
+            let _ = read(&jpeg(&tiff[..len]));
+            let _ = read(&tiff[..len]);
+        }
+        assert!(read(b"not an image").is_err());
+        assert!(matches!(read(&[0xFF, 0xD8, 0xFF, 0xD9]), Err(ExifError::NotFound)));
+
+        // Out-of-range and wrongly typed values are dropped
+        let fields = [
+            short(Tag::Orientation, 42),
+            rationals(Tag::FNumber, &[(28, 0)]),
+            ascii(Tag::DateTimeOriginal, "2024:13:45 99:00:00"),
+            ascii(Tag::GPSLatitudeRef, "N"),
+            rationals(Tag::GPSLatitude, &[(135, 1)]),
+            ascii(Tag::GPSLongitudeRef, "E"),
+            rationals(Tag::GPSLongitude, &[(10, 1)]),
+            short(Tag::Make, 7),
+        ];
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut buffer = Cursor::new(Vec::new());
+        writer.write(&mut buffer, true).unwrap();
+
+        let metadata = read(&buffer.into_inner()).unwrap();
+        assert_eq!(metadata.exif.orientation, None);
+        assert_eq!(metadata.exif.f_number, None);
+        assert_eq!(metadata.exif.date_taken, None);
+        assert_eq!(metadata.exif.camera_make, None);
+        assert!(metadata.location.is_none());
+    }
+}
@@ -8,6 +8,7 @@ use tokio::sync::RwLock;
 use chrono::Utc;
 use uuid::Uuid;
 
+use crate::hooks::{HookRegistry, HookRejection, PendingFolder};
 use crate::models::{MediaFolder, FolderTreeNode, FolderBreadcrumb, slugify};
 
 /// Folder service error
@@ -21,12 +22,16 @@ pub enum FolderError {
     Invalid(String),
     #[error("Cannot delete non-empty folder")]
     NotEmpty,
+    #[error(transparent)]
+    Rejected(#[from] HookRejection),
 }
 
 /// Folder service
 pub struct FolderService {
     /// Folders (in-memory, would be database in production)
     folders: Arc<RwLock<HashMap<Uuid, MediaFolder>>>,
+    /// Lifecycle hooks
+    hooks: Arc<HookRegistry>,
 }
 
 impl FolderService {
@@ -34,9 +39,15 @@ impl FolderService {
     pub fn new() -> Self {
         Self {
             folders: Arc::new(RwLock::new(HashMap::new())),
+            hooks: Arc::new(HookRegistry::new()),
         }
     }
 
+    /// Set the lifecycle hook registry
+    pub fn set_hooks(&mut self, hooks: Arc<HookRegistry>) {
+        self.hooks = hooks;
+    }
+
     /// Create a new folder
     pub async fn create(
         &self,
@@ -44,6 +55,15 @@ impl FolderService {
         parent_id: Option<Uuid>,
         user_id: Option<Uuid>,
     ) -> Result<MediaFolder, FolderError> {
+        let mut pending = PendingFolder {
+            name: name.to_string(),
+            parent_id,
+            user_id,
+        };
+        self.hooks.before_folder_create(&mut pending).await?;
+        let PendingFolder { name, parent_id, user_id } = pending;
+        let name = name.as_str();
+
         let folders = self.folders.read().await;
 
         // Check parent exists
@@ -71,7 +91,10 @@ impl FolderService {
 
         // Build path
         if let Some(pid) = parent_id {
-            let ancestors = self.get_ancestors(pid).await;
+            let mut ancestors = self.get_ancestors(pid).await;
+            if let Some(parent) = self.get(pid).await {
+                ancestors.push(parent);
+            }
             let ancestor_refs: Vec<&MediaFolder> = ancestors.iter().collect();
             folder.build_path(&ancestor_refs);
         } else {
@@ -82,10 +105,37 @@ impl FolderService {
         let id = folder.id;
         let mut folders = self.folders.write().await;
         folders.insert(id, folder.clone());
+        drop(folders);
+
+        self.hooks.after_folder_create(&folder).await;
 
         Ok(folder)
     }
 
+    /// Get the child folder with this name, creating it if needed
+    ///
+    /// Returns the folder and whether it was created.
+    pub async fn ensure_child(
+        &self,
+        name: &str,
+        parent_id: Option<Uuid>,
+        user_id: Option<Uuid>,
+    ) -> Result<(MediaFolder, bool), FolderError> {
+        match self.create(name, parent_id, user_id).await {
+            Ok(folder) => Ok((folder, true)),
+            Err(FolderError::AlreadyExists(_)) => {
+                let slug = slugify(name);
+                let folders = self.folders.read().await;
+                folders.values()
+                    .find(|f| f.parent_id == parent_id && f.slug == slug)
+                    .cloned()
+                    .map(|f| (f, false))
+                    .ok_or_else(|| FolderError::NotFound(name.to_string()))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
     /// Get folder by ID
     pub async fn get(&self, id: Uuid) -> Option<MediaFolder> {
         let folders = self.folders.read().await;
@@ -148,19 +198,28 @@ impl FolderService {
             return Err(FolderError::NotEmpty);
         }
 
+        let folder = folder.clone();
         drop(folders);
 
+        self.hooks.before_folder_delete(&folder).await?;
+
         // Delete children recursively if force
         if force {
             let children = self.get_children(id).await;
             for child in children {
-                let _ = Box::pin(self.delete(child.id, true)).await;
+                // A vetoed child keeps its parent alive
+                if let Err(e @ FolderError::Rejected(_)) = Box::pin(self.delete(child.id, true)).await {
+                    return Err(e);
+                }
             }
         }
 
         // Delete folder
         let mut folders = self.folders.write().await;
         folders.remove(&id);
+        drop(folders);
+
+        self.hooks.after_folder_delete(&folder).await;
 
         Ok(())
     }
@@ -0,0 +1,254 @@
+//! Idempotency Keys
+//!
+//! Remembers the outcome of upload requests by client-chosen key, so a
+//! retried request replays the first result instead of running again.
+//! Keys are scoped to the user sending them.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use chrono::{DateTime, Duration, Utc};
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::models::MediaItem;
+
+/// Keys remembered by default before the oldest make room
+const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Idempotency error
+#[derive(Debug, thiserror::Error)]
+pub enum IdempotencyError {
+    #[error("A request with idempotency key {0} is still in progress")]
+    InProgress(String),
+    #[error("Idempotency key {0} was already used for a different request")]
+    KeyReused(String),
+}
+
+/// Key as sent by a user (`None` for anonymous requests)
+type ScopedKey = (Option<Uuid>, String);
+
+/// Remembered request
+#[derive(Debug, Clone)]
+struct Entry {
+    /// Hash of the request payload
+    fingerprint: String,
+    /// Result, once the request succeeded
+    media: Option<MediaItem>,
+    /// When the entry is forgotten
+    expires_at: DateTime<Utc>,
+}
+
+/// What to do with a request carrying an idempotency key
+pub enum Claim {
+    /// First attempt: run it and settle the guard with the outcome
+    Run(ClaimGuard),
+    /// Retry of a successful request
+    Replay(Box<MediaItem>),
+}
+
+/// Claimed key of a running request
+///
+/// Dropping the guard unsettled (e.g. when the request is cancelled)
+/// releases the key so the client can retry.
+pub struct ClaimGuard {
+    store: IdempotencyStore,
+    key: Option<ScopedKey>,
+}
+
+impl ClaimGuard {
+    /// Remember the result for `window`
+    pub async fn complete(mut self, media: &MediaItem, window: Duration) {
+        if let Some(key) = self.key.take() {
+            self.store.complete(&key, media, window).await;
+        }
+    }
+
+    /// Release the key after a failure
+    pub async fn release(mut self) {
+        if let Some(key) = self.key.take() {
+            self.store.release(&key).await;
+        }
+    }
+}
+
+impl Drop for ClaimGuard {
+    fn drop(&mut self) {
+        let Some(key) = self.key.take() else { return };
+        let store = self.store.clone();
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move { store.release(&key).await });
+        }
+    }
+}
+
+/// Results of requests by idempotency key
+///
+/// Only successes are remembered; a failed request releases its key so it
+/// can be retried. Expired entries are dropped on every claim, and the
+/// store never holds more than its capacity.
+#[derive(Clone)]
+pub struct IdempotencyStore {
+    entries: Arc<RwLock<HashMap<ScopedKey, Entry>>>,
+    /// Most keys remembered at once
+    capacity: usize,
+}
+
+impl Default for IdempotencyStore {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl IdempotencyStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty store remembering at most `capacity` keys
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self { entries: Arc::new(RwLock::new(HashMap::new())), capacity: capacity.max(1) }
+    }
+
+    /// Claim a user's key for a request
+    ///
+    /// `window` bounds how long an unfinished claim blocks retries.
+    pub async fn claim(
+        &self,
+        user_id: Option<Uuid>,
+        key: &str,
+        fingerprint: &str,
+        window: Duration,
+    ) -> Result<Claim, IdempotencyError> {
+        let now = Utc::now();
+        let mut entries = self.entries.write().await;
+        entries.retain(|_, e| e.expires_at > now);
+
+        let scoped = (user_id, key.to_string());
+        if let Some(entry) = entries.get(&scoped) {
+            if entry.fingerprint != fingerprint {
+                return Err(IdempotencyError::KeyReused(key.to_string()));
+            }
+            return match &entry.media {
+                Some(media) => Ok(Claim::Replay(Box::new(media.clone()))),
+                None => Err(IdempotencyError::InProgress(key.to_string())),
+            };
+        }
+
+        // Make room, forgetting finished requests before running ones
+        while entries.len() >= self.capacity {
+            let Some(oldest) = entries.iter()
+                .min_by_key(|(_, e)| (e.media.is_none(), e.expires_at))
+                .map(|(k, _)| k.clone())
+            else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+
+        entries.insert(scoped.clone(), Entry {
+            fingerprint: fingerprint.to_string(),
+            media: None,
+            expires_at: now + window,
+        });
+        Ok(Claim::Run(ClaimGuard { store: self.clone(), key: Some(scoped) }))
+    }
+
+    async fn complete(&self, key: &ScopedKey, media: &MediaItem, window: Duration) {
+        if let Some(entry) = self.entries.write().await.get_mut(key) {
+            entry.media = Some(media.clone());
+            entry.expires_at = Utc::now() + window;
+        }
+    }
+
+    async fn release(&self, key: &ScopedKey) {
+        let mut entries = self.entries.write().await;
+        if entries.get(key).is_some_and(|e| e.media.is_none()) {
+            entries.remove(key);
+        }
+    }
+
+    /// Hash request parts into a fingerprint
+    pub fn fingerprint(parts: &[&[u8]]) -> String {
+        let mut hasher = Sha256::new();
+        for part in parts {
+            // Length prefixes keep ("ab", "c") apart from ("a", "bc")
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+        hex::encode(hasher.finalize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_claim_lifecycle() {
+        let store = IdempotencyStore::new();
+        let window = Duration::minutes(5);
+        let media = MediaItem::new("a.txt", "text/plain", 1, "a.txt");
+
+        let Ok(Claim::Run(guard)) = store.claim(None, "k", "f1", window).await else { panic!("expected a claim") };
+        assert!(matches!(store.claim(None, "k", "f1", window).await, Err(IdempotencyError::InProgress(_))));
+        assert!(matches!(store.claim(None, "k", "f2", window).await, Err(IdempotencyError::KeyReused(_))));
+
+        // Failures free the key
+        guard.release().await;
+        let Ok(Claim::Run(guard)) = store.claim(None, "k", "f1", window).await else { panic!("expected a claim") };
+
+        guard.complete(&media, window).await;
+        match store.claim(None, "k", "f1", window).await {
+            Ok(Claim::Replay(replayed)) => assert_eq!(replayed.id, media.id),
+            _ => panic!("expected a replay"),
+        }
+
+        // Expired entries are forgotten
+        store.complete(&(None, "k".to_string()), &media, Duration::zero()).await;
+        let Ok(Claim::Run(guard)) = store.claim(None, "k", "f2", window).await else { panic!("expected a claim") };
+
+        // So are abandoned claims
+        drop(guard);
+        tokio::task::yield_now().await;
+        assert!(matches!(store.claim(None, "k", "f3", window).await, Ok(Claim::Run(_))));
+    }
+
+    #[tokio::test]
+    async fn test_keys_scoped_by_user() {
+        let store = IdempotencyStore::new();
+        let window = Duration::minutes(5);
+        let media = MediaItem::new("a.txt", "text/plain", 1, "a.txt");
+
+        let user = Some(Uuid::new_v4());
+        let Ok(Claim::Run(guard)) = store.claim(user, "k", "f1", window).await else { panic!("expected a claim") };
+        guard.complete(&media, window).await;
+
+        // Another user's identical key neither replays nor conflicts
+        assert!(matches!(store.claim(Some(Uuid::new_v4()), "k", "f1", window).await, Ok(Claim::Run(_))));
+        assert!(matches!(store.claim(None, "k", "f2", window).await, Ok(Claim::Run(_))));
+    }
+
+    #[tokio::test]
+    async fn test_capacity() {
+        let store = IdempotencyStore::with_capacity(2);
+        let window = Duration::minutes(5);
+        let media = MediaItem::new("a.txt", "text/plain", 1, "a.txt");
+
+        let Ok(Claim::Run(guard)) = store.claim(None, "a", "f", window).await else { panic!("expected a claim") };
+        guard.complete(&media, window).await;
+        let Ok(Claim::Run(_running)) = store.claim(None, "b", "f", window).await else { panic!("expected a claim") };
+
+        // The finished request makes room, the running one is kept
+        assert!(matches!(store.claim(None, "c", "f", window).await, Ok(Claim::Run(_))));
+        assert_eq!(store.entries.read().await.len(), 2);
+        assert!(matches!(store.claim(None, "b", "f", window).await, Err(IdempotencyError::InProgress(_))));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(IdempotencyStore::fingerprint(&[b"ab", b"c"]), IdempotencyStore::fingerprint(&[b"ab", b"c"]));
+        assert_ne!(IdempotencyStore::fingerprint(&[b"ab", b"c"]), IdempotencyStore::fingerprint(&[b"a", b"bc"]));
+    }
+}
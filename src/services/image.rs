@@ -3,6 +3,7 @@
 //! Image processing and manipulation.
 
 use std::path::Path;
+use std::sync::{Arc, PoisonError, RwLock};
 use image::{DynamicImage, ImageFormat as ImgFormat, imageops::FilterType};
 
 use crate::models::{
@@ -11,6 +12,7 @@ use crate::models::{
     Thumbnail, default_image_sizes,
 };
 use super::storage::{StorageService, StorageError};
+use super::avif;
 
 /// Image processing error
 #[derive(Debug, thiserror::Error)]
@@ -27,49 +29,116 @@ pub enum ImageError {
     Image(#[from] image::ImageError),
 }
 
+/// Image processing settings
+#[derive(Debug, Clone)]
+pub struct ImageSettings {
+    /// Configured image sizes
+    pub sizes: Vec<ImageSize>,
+    /// Default quality (1-100)
+    pub default_quality: u8,
+    /// Convert to WebP
+    pub convert_to_webp: bool,
+    /// AVIF encoder speed (1 = smallest files, 10 = fastest)
+    pub avif_speed: u8,
+    /// Thumbnail format (JPEG or WebP per `convert_to_webp` when unset)
+    pub thumbnail_format: Option<ImageFormat>,
+}
+
+impl ImageSettings {
+    /// Bring values into their valid ranges
+    fn clamped(mut self) -> Self {
+        self.default_quality = self.default_quality.clamp(1, 100);
+        self.avif_speed = self.avif_speed.clamp(1, 10);
+        self
+    }
+}
+
+impl Default for ImageSettings {
+    fn default() -> Self {
+        Self {
+            sizes: default_image_sizes(),
+            default_quality: 85,
+            convert_to_webp: false,
+            avif_speed: 6,
+            thumbnail_format: None,
+        }
+    }
+}
+
 /// Image service for processing
 pub struct ImageService {
     /// Storage service
-    storage: std::sync::Arc<StorageService>,
-    /// Configured image sizes
-    sizes: Vec<ImageSize>,
-    /// Default quality
-    default_quality: u8,
-    /// Convert to WebP
-    convert_to_webp: bool,
-    /// Strip metadata
-    strip_metadata: bool,
+    storage: Arc<StorageService>,
+    /// Settings (read by synchronous encoders, so behind a std lock)
+    settings: RwLock<Arc<ImageSettings>>,
 }
 
 impl ImageService {
     /// Create a new image service
-    pub fn new(storage: std::sync::Arc<StorageService>) -> Self {
+    pub fn new(storage: Arc<StorageService>) -> Self {
         Self {
             storage,
-            sizes: default_image_sizes(),
-            default_quality: 85,
-            convert_to_webp: false,
-            strip_metadata: true,
+            settings: RwLock::new(Arc::new(ImageSettings::default())),
         }
     }
 
+    /// Configure settings
+    pub fn configure(&mut self, settings: ImageSettings) {
+        *self.settings_mut() = settings.clamped();
+    }
+
+    /// Replace the settings of a running service
+    pub fn update_settings(&self, settings: ImageSettings) {
+        *self.settings.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(settings.clamped());
+    }
+
+    /// Current settings
+    pub fn settings(&self) -> Arc<ImageSettings> {
+        Arc::clone(&self.settings.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    fn settings_mut(&mut self) -> &mut ImageSettings {
+        Arc::make_mut(self.settings.get_mut().unwrap_or_else(PoisonError::into_inner))
+    }
+
     /// Set image sizes
     pub fn set_sizes(&mut self, sizes: Vec<ImageSize>) {
-        self.sizes = sizes;
+        self.settings_mut().sizes = sizes;
     }
 
     /// Set default quality
     pub fn set_quality(&mut self, quality: u8) {
-        self.default_quality = quality.clamp(1, 100);
+        self.settings_mut().default_quality = quality.clamp(1, 100);
     }
 
     /// Enable WebP conversion
     pub fn enable_webp(&mut self, enabled: bool) {
-        self.convert_to_webp = enabled;
+        self.settings_mut().convert_to_webp = enabled;
+    }
+
+    /// Set AVIF encoder speed (1 = smallest files, 10 = fastest)
+    pub fn set_avif_speed(&mut self, speed: u8) {
+        self.settings_mut().avif_speed = speed.clamp(1, 10);
+    }
+
+    /// Set the thumbnail format
+    pub fn set_thumbnail_format(&mut self, format: Option<ImageFormat>) {
+        self.settings_mut().thumbnail_format = format;
+    }
+
+    /// Whether the contents are in a format this service can decode
+    pub fn can_decode(&self, data: &[u8]) -> bool {
+        image::guess_format(data).is_ok_and(|format| format.reading_enabled())
     }
 
     /// Get image dimensions
+    ///
+    /// AVIF sizes are read from the container, without decoding.
     pub fn get_dimensions(&self, data: &[u8]) -> Result<ImageDimensions, ImageError> {
+        if let Some((width, height)) = avif::dimensions(data) {
+            return Ok(ImageDimensions::new(width, height));
+        }
+
         let img = image::load_from_memory(data)?;
         Ok(ImageDimensions::new(img.width(), img.height()))
     }
@@ -103,7 +172,7 @@ impl ImageService {
         let img = image::load_from_memory(data)?;
         let mut thumbnails = Vec::new();
 
-        for size in &self.sizes {
+        for size in &self.settings().sizes {
             if !size.enabled {
                 continue;
             }
@@ -113,55 +182,77 @@ impl ImageService {
                 continue;
             }
 
-            let (width, height) = size.calculate_dimensions(img.width(), img.height());
-
-            let resized = match size.mode {
-                ResizeMode::Exact => img.resize_exact(width, height, FilterType::Lanczos3),
-                ResizeMode::Fit => img.resize(width, height, FilterType::Lanczos3),
-                ResizeMode::Fill | ResizeMode::Cover => {
-                    img.resize_to_fill(width, height, FilterType::Lanczos3)
+            match self.store_thumbnail(&img, size, original_path).await {
+                Ok(thumbnail) => thumbnails.push(thumbnail),
+                Err(e) => {
+                    // Leave no partial set behind
+                    for thumbnail in &thumbnails {
+                        if let Err(e) = self.storage.delete(&thumbnail.path).await {
+                            tracing::warn!("Failed to remove thumbnail {}: {}", thumbnail.path, e);
+                        }
+                    }
+                    return Err(e);
                 }
-            };
-
-            // Determine output format
-            let format = if self.convert_to_webp {
-                ImageFormat::WebP
-            } else {
-                ImageFormat::Jpeg
-            };
-
-            let thumb_data = self.encode_image(&resized, format, size.quality)?;
-
-            // Generate thumbnail path
-            let thumb_path = self.generate_thumbnail_path(original_path, &size.name, format);
-
-            // Store thumbnail
-            let stored = self.storage.store(
-                &thumb_data,
-                &thumb_path,
-                format.mime_type(),
-            ).await?;
-
-            thumbnails.push(Thumbnail {
-                size_name: size.name.clone(),
-                width: resized.width(),
-                height: resized.height(),
-                path: stored.path,
-                url: stored.url,
-                size: stored.size,
-            });
+            }
         }
 
         Ok(thumbnails)
     }
 
+    /// Resize, encode and store one thumbnail
+    async fn store_thumbnail(
+        &self,
+        img: &DynamicImage,
+        size: &ImageSize,
+        original_path: &str,
+    ) -> Result<Thumbnail, ImageError> {
+        let (width, height) = size.calculate_dimensions(img.width(), img.height());
+
+        let resized = match size.mode {
+            ResizeMode::Exact => img.resize_exact(width, height, FilterType::Lanczos3),
+            ResizeMode::Fit => img.resize(width, height, FilterType::Lanczos3),
+            ResizeMode::Fill | ResizeMode::Cover => {
+                img.resize_to_fill(width, height, FilterType::Lanczos3)
+            }
+        };
+
+        // Determine output format
+        let settings = self.settings();
+        let format = settings.thumbnail_format.unwrap_or(if settings.convert_to_webp {
+            ImageFormat::WebP
+        } else {
+            ImageFormat::Jpeg
+        });
+
+        let thumb_data = self.encode_image(&resized, format, size.quality)?;
+
+        // Generate thumbnail path
+        let thumb_path = self.generate_thumbnail_path(original_path, &size.name, format);
+
+        // Store thumbnail
+        let stored = self.storage.store(
+            &thumb_data,
+            &thumb_path,
+            format.mime_type(),
+        ).await?;
+
+        Ok(Thumbnail {
+            size_name: size.name.clone(),
+            width: resized.width(),
+            height: resized.height(),
+            path: stored.path,
+            url: stored.url,
+            size: stored.size,
+        })
+    }
+
     /// Crop image
     pub fn crop(&self, data: &[u8], params: &CropParams) -> Result<Vec<u8>, ImageError> {
         let img = image::load_from_memory(data)?;
 
         let cropped = img.crop_imm(params.x, params.y, params.width, params.height);
 
-        self.encode_image(&cropped, ImageFormat::Jpeg, self.default_quality)
+        self.encode_image(&cropped, ImageFormat::Jpeg, self.settings().default_quality)
     }
 
     /// Rotate image
@@ -175,35 +266,35 @@ impl ImageService {
             _ => img,
         };
 
-        self.encode_image(&rotated, ImageFormat::Jpeg, self.default_quality)
+        self.encode_image(&rotated, ImageFormat::Jpeg, self.settings().default_quality)
     }
 
     /// Flip image horizontally
     pub fn flip_horizontal(&self, data: &[u8]) -> Result<Vec<u8>, ImageError> {
         let img = image::load_from_memory(data)?;
         let flipped = img.fliph();
-        self.encode_image(&flipped, ImageFormat::Jpeg, self.default_quality)
+        self.encode_image(&flipped, ImageFormat::Jpeg, self.settings().default_quality)
     }
 
     /// Flip image vertically
     pub fn flip_vertical(&self, data: &[u8]) -> Result<Vec<u8>, ImageError> {
         let img = image::load_from_memory(data)?;
         let flipped = img.flipv();
-        self.encode_image(&flipped, ImageFormat::Jpeg, self.default_quality)
+        self.encode_image(&flipped, ImageFormat::Jpeg, self.settings().default_quality)
     }
 
     /// Convert to grayscale
     pub fn grayscale(&self, data: &[u8]) -> Result<Vec<u8>, ImageError> {
         let img = image::load_from_memory(data)?;
         let gray = img.grayscale();
-        self.encode_image(&gray, ImageFormat::Jpeg, self.default_quality)
+        self.encode_image(&gray, ImageFormat::Jpeg, self.settings().default_quality)
     }
 
     /// Apply blur
     pub fn blur(&self, data: &[u8], sigma: f32) -> Result<Vec<u8>, ImageError> {
         let img = image::load_from_memory(data)?;
         let blurred = img.blur(sigma);
-        self.encode_image(&blurred, ImageFormat::Jpeg, self.default_quality)
+        self.encode_image(&blurred, ImageFormat::Jpeg, self.settings().default_quality)
     }
 
     /// Optimize image
@@ -211,7 +302,7 @@ impl ImageService {
         let original_size = data.len() as u64;
         let img = image::load_from_memory(data)?;
 
-        let format = if self.convert_to_webp {
+        let format = if self.settings().convert_to_webp {
             ImageFormat::WebP
         } else {
             ImageFormat::Jpeg
@@ -294,7 +385,7 @@ impl ImageService {
 
         // Encode
         let format = request.format.unwrap_or(ImageFormat::Jpeg);
-        let quality = request.quality.unwrap_or(self.default_quality);
+        let quality = request.quality.unwrap_or(self.settings().default_quality);
 
         self.encode_image(&img, format, quality)
     }
@@ -350,8 +441,8 @@ impl ImageService {
             ImageFormat::Gif => {
                 img.write_to(&mut cursor, ImgFormat::Gif)?;
             }
-            _ => {
-                return Err(ImageError::UnsupportedFormat(format!("{:?}", format)));
+            ImageFormat::Avif => {
+                return avif::encode(img, quality, self.settings().avif_speed);
             }
         }
 
@@ -378,7 +469,7 @@ impl ImageService {
 
     /// Get supported image extensions
     pub fn supported_extensions() -> Vec<&'static str> {
-        vec!["jpg", "jpeg", "png", "gif", "webp", "bmp", "ico", "tiff", "tif"]
+        vec!["jpg", "jpeg", "png", "gif", "webp", "avif", "bmp", "ico", "tiff", "tif"]
     }
 }
 
@@ -393,4 +484,37 @@ mod tests {
         assert!(!ImageService::is_image("text/plain"));
         assert!(!ImageService::is_image("application/pdf"));
     }
+
+    /// Transform a 32x24 PNG to 16px wide AVIF, returning its dimensions
+    fn transform_to_avif() -> Result<(u32, u32), ImageError> {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Arc::new(StorageService::new(dir.path().to_path_buf(), "/media"));
+        let service = ImageService::new(storage);
+
+        let mut png = Vec::new();
+        DynamicImage::new_rgb8(32, 24)
+            .write_to(&mut std::io::Cursor::new(&mut png), ImgFormat::Png)
+            .unwrap();
+        let request = ImageTransformRequest {
+            width: Some(16),
+            format: Some(ImageFormat::Avif),
+            ..Default::default()
+        };
+
+        let avif = service.transform(&png, &request)?;
+        let dims = service.get_dimensions(&avif).unwrap();
+        Ok((dims.width, dims.height))
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn test_transform_to_avif() {
+        assert_eq!(transform_to_avif().unwrap(), (16, 12));
+    }
+
+    #[cfg(not(feature = "avif"))]
+    #[test]
+    fn test_transform_to_avif_unsupported() {
+        assert!(matches!(transform_to_avif(), Err(ImageError::UnsupportedFormat(_))));
+    }
 }
@@ -0,0 +1,547 @@
+//! Directory Import Service
+//!
+//! Bulk import of a directory tree already on the server.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use uuid::Uuid;
+use walkdir::WalkDir;
+
+use crate::models::UploadOptions;
+use super::folder::{FolderError, FolderService};
+use super::media::{MediaError, MediaService};
+use super::storage::StorageService;
+use super::upload::{UploadError, UploadService};
+
+/// Directory import error
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid source: {0}")]
+    InvalidSource(String),
+    #[error("Folder error: {0}")]
+    Folder(#[from] FolderError),
+    #[error("Checkpoint error: {0}")]
+    Checkpoint(String),
+}
+
+/// Directory import options
+#[derive(Debug, Clone)]
+pub struct DirectoryImportOptions {
+    /// Directory to import
+    pub source: PathBuf,
+    /// Recreate the directory structure as folders
+    pub mirror_folders: bool,
+    /// Folder to import into
+    pub folder_id: Option<Uuid>,
+    /// Files processed at the same time
+    pub concurrency: usize,
+    /// File recording completed paths, so an interrupted import can resume
+    pub checkpoint: Option<PathBuf>,
+    /// Follow symbolic links while walking
+    pub follow_links: bool,
+    /// Generate dimensions and thumbnails for images
+    pub process_images: bool,
+    /// Importing user
+    pub user_id: Option<Uuid>,
+}
+
+impl DirectoryImportOptions {
+    /// Options with defaults for a source directory
+    pub fn new(source: impl Into<PathBuf>) -> Self {
+        Self {
+            source: source.into(),
+            mirror_folders: true,
+            folder_id: None,
+            concurrency: 4,
+            checkpoint: None,
+            follow_links: false,
+            process_images: true,
+            user_id: None,
+        }
+    }
+}
+
+/// Import progress
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportProgress {
+    /// Files found in the source
+    pub total_files: u64,
+    /// Bytes found in the source
+    pub total_bytes: u64,
+    /// Files handled so far (any outcome)
+    pub processed_files: u64,
+    /// Bytes handled so far
+    pub processed_bytes: u64,
+    /// Files imported
+    pub imported: u64,
+    /// Files skipped as duplicates
+    pub duplicates: u64,
+    /// Files skipped because the checkpoint lists them
+    pub resumed: u64,
+    /// Files that failed
+    pub failed: u64,
+    /// Time since the import started
+    pub elapsed: Duration,
+    /// Estimated time remaining
+    pub eta: Option<Duration>,
+}
+
+impl ImportProgress {
+    /// Percentage of bytes handled
+    pub fn percent(&self) -> f64 {
+        if self.total_bytes == 0 {
+            return 100.0;
+        }
+        (self.processed_bytes as f64 / self.total_bytes as f64) * 100.0
+    }
+
+    /// Update the ETA from throughput in this run
+    ///
+    /// Files skipped through the checkpoint cost nothing, so they are left
+    /// out of the rate.
+    fn update_eta(&mut self, resumed_bytes: u64) {
+        let done = self.processed_bytes.saturating_sub(resumed_bytes);
+        let remaining = self.total_bytes.saturating_sub(self.processed_bytes);
+        let secs = self.elapsed.as_secs_f64();
+
+        self.eta = if remaining == 0 {
+            Some(Duration::ZERO)
+        } else if done == 0 || secs <= 0.0 {
+            None
+        } else {
+            Some(Duration::from_secs_f64(remaining as f64 / (done as f64 / secs)))
+        };
+    }
+}
+
+/// Directory import report
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DirectoryImportReport {
+    /// Final progress counters
+    pub progress: ImportProgress,
+    /// Folders created while mirroring
+    pub folders_created: Vec<Uuid>,
+    /// Failed files (relative path, error)
+    pub failures: Vec<(String, String)>,
+}
+
+/// Outcome of importing one file
+enum FileOutcome {
+    Imported,
+    Duplicate,
+    Failed(String),
+}
+
+/// Directory importer
+#[derive(Clone)]
+pub struct DirectoryImporter {
+    storage: Arc<StorageService>,
+    media_service: Arc<MediaService>,
+    folder_service: Arc<FolderService>,
+    upload_service: Arc<UploadService>,
+}
+
+impl DirectoryImporter {
+    /// Create a new directory importer
+    pub fn new(
+        storage: Arc<StorageService>,
+        media_service: Arc<MediaService>,
+        folder_service: Arc<FolderService>,
+        upload_service: Arc<UploadService>,
+    ) -> Self {
+        Self {
+            storage,
+            media_service,
+            folder_service,
+            upload_service,
+        }
+    }
+
+    /// Import a directory tree
+    ///
+    /// Files go through the same checks as uploads and are deduplicated by
+    /// content hash; only images small enough to process are loaded into
+    /// memory. `on_progress` is called after every file.
+    pub async fn import<F>(
+        &self,
+        options: DirectoryImportOptions,
+        mut on_progress: F,
+    ) -> Result<DirectoryImportReport, ImportError>
+    where
+        F: FnMut(&ImportProgress),
+    {
+        let source = options.source.canonicalize()
+            .map_err(|e| ImportError::InvalidSource(format!("{}: {}", options.source.display(), e)))?;
+        if !source.is_dir() {
+            return Err(ImportError::InvalidSource(format!("{} is not a directory", source.display())));
+        }
+
+        let started = Instant::now();
+        let mut report = DirectoryImportReport::default();
+
+        let completed = match &options.checkpoint {
+            Some(path) => load_checkpoint(path).await?,
+            None => HashSet::new(),
+        };
+        let mut checkpoint = match &options.checkpoint {
+            Some(path) => Some(
+                tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?
+            ),
+            None => None,
+        };
+
+        // Scan first so progress has totals
+        let files = tokio::task::spawn_blocking({
+            let source = source.clone();
+            let follow_links = options.follow_links;
+            move || scan(&source, follow_links)
+        }).await.map_err(|e| ImportError::InvalidSource(e.to_string()))?;
+
+        report.progress.total_files = files.len() as u64;
+        report.progress.total_bytes = files.iter().map(|(_, _, size)| size).sum();
+
+        let semaphore = Arc::new(Semaphore::new(options.concurrency.max(1)));
+        let mut tasks: JoinSet<(String, u64, FileOutcome)> = JoinSet::new();
+        let mut folders: HashMap<PathBuf, Option<Uuid>> = HashMap::new();
+        let mut resumed_bytes = 0u64;
+
+        for (path, relative, size) in files {
+            if completed.contains(&relative) {
+                report.progress.resumed += 1;
+                report.progress.processed_files += 1;
+                report.progress.processed_bytes += size;
+                resumed_bytes += size;
+                continue;
+            }
+
+            let folder_id = if options.mirror_folders {
+                self.mirror_folder(&relative, &options, &mut folders, &mut report).await?
+            } else {
+                options.folder_id
+            };
+
+            // Wait for a slot, collecting finished files meanwhile
+            let permit = loop {
+                match Arc::clone(&semaphore).try_acquire_owned() {
+                    Ok(permit) => break permit,
+                    Err(_) => {
+                        if let Some(joined) = tasks.join_next().await {
+                            self.record(joined, &mut report, checkpoint.as_mut(), started, resumed_bytes).await?;
+                            on_progress(&report.progress);
+                        }
+                    }
+                }
+            };
+
+            let importer = self.clone();
+            let process_images = options.process_images;
+            let user_id = options.user_id;
+            tasks.spawn(async move {
+                let outcome = importer.import_file(&path, folder_id, process_images, user_id).await;
+                drop(permit);
+                (relative, size, outcome)
+            });
+        }
+
+        while let Some(joined) = tasks.join_next().await {
+            self.record(joined, &mut report, checkpoint.as_mut(), started, resumed_bytes).await?;
+            on_progress(&report.progress);
+        }
+
+        report.progress.elapsed = started.elapsed();
+        report.progress.eta = Some(Duration::ZERO);
+
+        Ok(report)
+    }
+
+    /// Apply a finished file to the report and checkpoint
+    async fn record(
+        &self,
+        joined: Result<(String, u64, FileOutcome), tokio::task::JoinError>,
+        report: &mut DirectoryImportReport,
+        checkpoint: Option<&mut tokio::fs::File>,
+        started: Instant,
+        resumed_bytes: u64,
+    ) -> Result<(), ImportError> {
+        let (relative, size, outcome) = joined
+            .map_err(|e| ImportError::Checkpoint(format!("Import task failed: {}", e)))?;
+
+        let progress = &mut report.progress;
+        progress.processed_files += 1;
+        progress.processed_bytes += size;
+
+        let done = match outcome {
+            FileOutcome::Imported => {
+                progress.imported += 1;
+                true
+            }
+            FileOutcome::Duplicate => {
+                progress.duplicates += 1;
+                true
+            }
+            FileOutcome::Failed(error) => {
+                progress.failed += 1;
+                report.failures.push((relative.clone(), error));
+                false
+            }
+        };
+
+        // Failed files are retried on resume
+        if let (true, Some(file)) = (done, checkpoint) {
+            file.write_all(format!("{}\n", relative).as_bytes()).await
+                .map_err(|e| ImportError::Checkpoint(e.to_string()))?;
+            file.flush().await.map_err(|e| ImportError::Checkpoint(e.to_string()))?;
+        }
+
+        progress.elapsed = started.elapsed();
+        progress.update_eta(resumed_bytes);
+
+        Ok(())
+    }
+
+    /// Resolve the folder for a file's parent directory
+    async fn mirror_folder(
+        &self,
+        relative: &str,
+        options: &DirectoryImportOptions,
+        folders: &mut HashMap<PathBuf, Option<Uuid>>,
+        report: &mut DirectoryImportReport,
+    ) -> Result<Option<Uuid>, ImportError> {
+        let dir = Path::new(relative).parent().map(Path::to_path_buf).unwrap_or_default();
+        if let Some(&id) = folders.get(&dir) {
+            return Ok(id);
+        }
+
+        let mut parent = options.folder_id;
+        let mut current = PathBuf::new();
+
+        for component in dir.components() {
+            current.push(component);
+            if let Some(&id) = folders.get(&current) {
+                parent = id;
+                continue;
+            }
+
+            let name = component.as_os_str().to_string_lossy();
+            let (folder, created) = self.folder_service.ensure_child(&name, parent, options.user_id).await?;
+            if created {
+                report.folders_created.push(folder.id);
+            }
+
+            parent = Some(folder.id);
+            folders.insert(current.clone(), parent);
+        }
+
+        folders.insert(dir, parent);
+        Ok(parent)
+    }
+
+    /// Import one file
+    async fn import_file(
+        &self,
+        path: &Path,
+        folder_id: Option<Uuid>,
+        process_images: bool,
+        user_id: Option<Uuid>,
+    ) -> FileOutcome {
+        match self.try_import_file(path, folder_id, process_images, user_id).await {
+            Ok(outcome) => outcome,
+            Err(error) => FileOutcome::Failed(error),
+        }
+    }
+
+    async fn try_import_file(
+        &self,
+        path: &Path,
+        folder_id: Option<Uuid>,
+        process_images: bool,
+        user_id: Option<Uuid>,
+    ) -> Result<FileOutcome, String> {
+        let filename = path.file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .ok_or_else(|| "Missing filename".to_string())?;
+
+        let size = tokio::fs::metadata(path).await.map_err(|e| e.to_string())?.len();
+        let mime_type = mime_guess::from_path(path).first_or_octet_stream().to_string();
+
+        self.upload_service.validate_file(&filename, size, Some(&mime_type))
+            .map_err(|e| e.to_string())?;
+
+        // Skip known content before copying anything
+        let hash = self.storage.hash_file(path).await.map_err(|e| e.to_string())?;
+        if self.media_service.find_by_hash(&hash).await.is_some() {
+            return Ok(FileOutcome::Duplicate);
+        }
+
+        // Copy into temporary storage and upload from there, so imports
+        // are validated, scanned and sanitized like any other upload
+        self.upload_service.check_rate_limit(user_id, size).await.map_err(|e| e.to_string())?;
+        let temp_path = format!("temp/import/{}", Uuid::now_v7());
+        let full_path = self.storage.full_path(&temp_path);
+        if let Some(parent) = full_path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(|e| e.to_string())?;
+        }
+        tokio::fs::copy(path, &full_path).await.map_err(|e| e.to_string())?;
+
+        let options = UploadOptions {
+            folder_id,
+            // Imported originals are kept as they are
+            optimize: false,
+            generate_thumbnails: process_images,
+            ..Default::default()
+        };
+
+        let result = self.upload_service
+            .upload_stored_without_limit(&temp_path, &filename, options, user_id)
+            .await;
+        if self.storage.exists(&temp_path).await {
+            let _ = self.storage.delete(&temp_path).await;
+        }
+
+        let media = match result {
+            // Same content imported concurrently
+            Ok(media) if media.duplicate_of.is_some() => return Ok(FileOutcome::Duplicate),
+            Err(UploadError::Media(MediaError::Duplicate { .. })) => return Ok(FileOutcome::Duplicate),
+            Ok(media) => media,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        // Files too large to process in memory were stored as they are
+        if process_images && media.is_image() && media.dimensions.is_none() {
+            if let Err(e) = self.media_service.process_image(media.id).await {
+                tracing::warn!("Failed to process {}: {}", filename, e);
+            }
+        }
+
+        Ok(FileOutcome::Imported)
+    }
+}
+
+/// Collect files as (absolute path, relative path, size), sorted for a stable order
+fn scan(source: &Path, follow_links: bool) -> Vec<(PathBuf, String, u64)> {
+    let mut files: Vec<(PathBuf, String, u64)> = WalkDir::new(source)
+        .follow_links(follow_links)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| {
+            let relative = e.path().strip_prefix(source).ok()?.to_string_lossy().replace('\\', "/");
+            let size = e.metadata().ok()?.len();
+            Some((e.into_path(), relative, size))
+        })
+        .collect();
+
+    files.sort_by(|a, b| a.1.cmp(&b.1));
+    files
+}
+
+/// Read completed relative paths from a checkpoint file
+async fn load_checkpoint(path: &Path) -> Result<HashSet<String>, ImportError> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(content) => Ok(content.lines().filter(|l| !l.is_empty()).map(String::from).collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(ImportError::Checkpoint(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use crate::services::testing::TestServices;
+
+    fn importer(dir: &Path) -> DirectoryImporter {
+        let services = TestServices::new(dir);
+        let upload = Arc::new(services.upload_service());
+        DirectoryImporter::new(services.storage, services.media, Arc::new(FolderService::new()), upload)
+    }
+
+    #[tokio::test]
+    async fn test_import_directory() {
+        let source = tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join("docs/old")).unwrap();
+        std::fs::write(source.path().join("a.txt"), "alpha").unwrap();
+        std::fs::write(source.path().join("docs/b.txt"), "beta").unwrap();
+        std::fs::write(source.path().join("docs/old/copy.txt"), "alpha").unwrap();
+        std::fs::write(source.path().join("docs/tool.exe"), "MZ").unwrap();
+
+        let uploads = tempdir().unwrap();
+        let importer = importer(uploads.path());
+
+        let mut options = DirectoryImportOptions::new(source.path());
+        options.concurrency = 1;
+
+        let mut updates = 0;
+        let report = importer.import(options, |_| updates += 1).await.unwrap();
+
+        assert_eq!(updates, 4);
+        assert_eq!(report.progress.imported, 2);
+        assert_eq!(report.progress.duplicates, 1);
+        assert_eq!(report.progress.failed, 1);
+        assert_eq!(report.folders_created.len(), 2);
+        assert_eq!(report.failures[0].0, "docs/tool.exe");
+        assert!(importer.folder_service.get_by_path("docs/old").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_imports_checked_like_uploads() {
+        let source = tempdir().unwrap();
+        std::fs::write(
+            source.path().join("logo.svg"),
+            r#"<svg xmlns="http://www.w3.org/2000/svg" onload="alert(1)"><script>alert(2)</script><rect width="1" height="1"/></svg>"#,
+        ).unwrap();
+        // Named like an image, but not one
+        std::fs::write(source.path().join("fake.png"), "not an image").unwrap();
+
+        let uploads = tempdir().unwrap();
+        let importer = importer(uploads.path());
+
+        let report = importer.import(DirectoryImportOptions::new(source.path()), |_| {}).await.unwrap();
+        assert_eq!(report.progress.imported, 1);
+        assert_eq!(report.progress.failed, 1);
+        assert_eq!(report.failures[0].0, "fake.png");
+
+        let media = importer.media_service.list(Default::default()).await.items.remove(0);
+        let stored = std::fs::read_to_string(uploads.path().join(&media.path)).unwrap();
+        assert!(!stored.contains("alert"));
+        assert!(stored.contains("<rect"));
+        assert_eq!(media.metadata.custom.get("svg_sanitized").unwrap(), "<script>, svg@onload");
+
+        // Nothing is left behind in temporary storage
+        assert!(std::fs::read_dir(uploads.path().join("temp/import")).unwrap().next().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_checkpoint() {
+        let source = tempdir().unwrap();
+        for i in 0..6 {
+            std::fs::write(source.path().join(format!("{}.txt", i)), format!("file {}", i)).unwrap();
+        }
+
+        let uploads = tempdir().unwrap();
+        let importer = importer(uploads.path());
+        let checkpoint = uploads.path().join("import.checkpoint");
+        std::fs::write(&checkpoint, "0.txt\n1.txt\n").unwrap();
+
+        let mut options = DirectoryImportOptions::new(source.path());
+        options.checkpoint = Some(checkpoint.clone());
+
+        let report = importer.import(options.clone(), |_| {}).await.unwrap();
+        assert_eq!(report.progress.resumed, 2);
+        assert_eq!(report.progress.imported, 4);
+        assert_eq!(report.progress.eta, Some(Duration::ZERO));
+
+        // Everything is recorded now
+        let report = importer.import(options, |_| {}).await.unwrap();
+        assert_eq!(report.progress.resumed, 6);
+        assert_eq!(report.progress.imported, 0);
+    }
+}
@@ -5,16 +5,27 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
 use sha2::{Sha256, Digest};
+use bytes::Bytes;
 
 use crate::models::{
-    MediaItem, MediaType, MediaFilter, MediaListResponse,
-    ImageDimensions, MediaMetadata, Thumbnail,
+    MediaItem, MediaType, MediaFilter, MediaListResponse, UploadOptions, DuplicateStrategy,
+    ImageFormat, ImageTransformRequest, MediaRevision, RevisionChange, RevisionFile,
+    RevisionMetadata, RevisionPolicy, FieldChange, DegradedStep, UploadStep, FileState,
 };
-use super::storage::{StorageService, StorageError};
+use crate::hooks::{HookRegistry, HookRejection, PendingUpload};
+use super::storage::{StorageService, StorageError, StoredFile};
 use super::image::{ImageService, ImageError};
+use super::svg::{self, SvgError};
+use super::validator::{self, ContentRejection, ContentValidator};
+use super::pipeline::{at_step, Rollback};
+use super::batch;
+use super::exif;
+
+/// Storage directory for binaries kept by revisions
+const ARCHIVE_DIR: &str = "revisions/";
 
 /// Media service error
 #[derive(Debug, thiserror::Error)]
@@ -27,8 +38,16 @@ pub enum MediaError {
     NotFound(String),
     #[error("Invalid media: {0}")]
     Invalid(String),
-    #[error("Duplicate file: {0}")]
-    Duplicate(String),
+    #[error("Duplicate file: {filename} (existing media {existing_id})")]
+    Duplicate { filename: String, existing_id: Uuid },
+    #[error(transparent)]
+    Rejected(#[from] HookRejection),
+    #[error("Content rejected: {0}")]
+    ContentRejected(#[from] ContentRejection),
+    #[error("SVG error: {0}")]
+    Svg(#[from] SvgError),
+    #[error("Upload failed at {step}: {source}")]
+    Step { step: UploadStep, source: Box<MediaError> },
 }
 
 /// Media service
@@ -41,10 +60,22 @@ pub struct MediaService {
     items: Arc<RwLock<HashMap<Uuid, MediaItem>>>,
     /// Content hash index for deduplication
     hash_index: Arc<RwLock<HashMap<String, Uuid>>>,
+    /// Old paths of replaced files, mapped to their current path
+    redirects: Arc<RwLock<HashMap<String, String>>>,
+    /// Validator for replacement files
+    validator: ContentValidator,
+    /// Revision history per item, oldest first
+    revisions: Arc<RwLock<HashMap<Uuid, Vec<MediaRevision>>>>,
+    /// Retention of replaced binaries
+    revision_policy: RwLock<RevisionPolicy>,
     /// Enable deduplication
-    deduplicate: bool,
+    deduplicate: RwLock<bool>,
+    /// Handling of duplicates when the upload does not choose
+    duplicate_strategy: RwLock<DuplicateStrategy>,
     /// Auto-generate thumbnails
     auto_thumbnails: bool,
+    /// Lifecycle hooks
+    hooks: Arc<HookRegistry>,
 }
 
 impl MediaService {
@@ -55,18 +86,123 @@ impl MediaService {
             image_service,
             items: Arc::new(RwLock::new(HashMap::new())),
             hash_index: Arc::new(RwLock::new(HashMap::new())),
-            deduplicate: true,
+            redirects: Arc::new(RwLock::new(HashMap::new())),
+            validator: ContentValidator::new(),
+            revisions: Arc::new(RwLock::new(HashMap::new())),
+            revision_policy: RwLock::new(RevisionPolicy::default()),
+            deduplicate: RwLock::new(true),
+            duplicate_strategy: RwLock::new(DuplicateStrategy::default()),
             auto_thumbnails: true,
+            hooks: Arc::new(HookRegistry::new()),
         }
     }
 
+    /// Set the lifecycle hook registry
+    pub fn set_hooks(&mut self, hooks: Arc<HookRegistry>) {
+        self.hooks = hooks;
+    }
+
+    /// Enable or disable deduplication by content hash
+    pub fn set_deduplicate(&mut self, deduplicate: bool) {
+        *self.deduplicate.get_mut() = deduplicate;
+    }
+
+    /// Set the default duplicate strategy
+    pub fn set_duplicate_strategy(&mut self, strategy: DuplicateStrategy) {
+        *self.duplicate_strategy.get_mut() = strategy;
+    }
+
+    /// Set the retention policy for replaced binaries
+    pub fn set_revision_policy(&mut self, policy: RevisionPolicy) {
+        *self.revision_policy.get_mut() = policy;
+    }
+
+    /// Replace the duplicate handling of a running service
+    pub async fn update_duplicates(&self, deduplicate: bool, strategy: DuplicateStrategy) {
+        *self.deduplicate.write().await = deduplicate;
+        *self.duplicate_strategy.write().await = strategy;
+    }
+
+    /// Replace the retention policy of a running service
+    ///
+    /// Existing histories are pruned on their next revision, or by
+    /// [`Self::prune_revisions`].
+    pub async fn update_revision_policy(&self, policy: RevisionPolicy) {
+        *self.revision_policy.write().await = policy;
+    }
+
+    /// Lifecycle hook registry
+    pub fn hooks(&self) -> &Arc<HookRegistry> {
+        &self.hooks
+    }
+
     /// Upload a new media item
+    ///
+    /// `before_upload` hooks may veto the upload or rewrite the file and its
+    /// metadata before anything is stored.
     pub async fn upload(
         &self,
         data: &[u8],
         filename: &str,
         mime_type: &str,
-        folder_id: Option<Uuid>,
+        options: &UploadOptions,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItem, MediaError> {
+        if self.hooks.is_empty().await {
+            return self.store_upload(data, filename, mime_type, options, user_id).await;
+        }
+
+        let mut pending = PendingUpload {
+            filename: filename.to_string(),
+            mime_type: mime_type.to_string(),
+            size: data.len() as u64,
+            data: Some(Bytes::copy_from_slice(data)),
+            options: options.clone(),
+            user_id,
+        };
+        self.hooks.before_upload(&mut pending).await?;
+
+        self.upload_approved(
+            pending.data.as_deref().unwrap_or(data),
+            &pending.filename,
+            &pending.mime_type,
+            &pending.options,
+            pending.user_id,
+        ).await
+    }
+
+    /// Store an upload its `before_upload` hooks already approved
+    ///
+    /// For callers that run the hooks themselves, to check what they changed.
+    pub(crate) async fn upload_approved(
+        &self,
+        data: &[u8],
+        filename: &str,
+        mime_type: &str,
+        options: &UploadOptions,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItem, MediaError> {
+        let media = self.store_upload(data, filename, mime_type, options, user_id).await?;
+
+        if Self::is_new(&media) {
+            self.hooks.after_upload(&media).await;
+        }
+
+        Ok(media)
+    }
+
+    /// Store and index an upload
+    ///
+    /// Runs as a sequence of steps. When one fails, the files written so
+    /// far are removed and the error names the step; with
+    /// `options.allow_degraded`, failed dimension and thumbnail steps are
+    /// recorded on the item instead.
+    async fn store_upload(
+        &self,
+        data: &[u8],
+        filename: &str,
+        mime_type: &str,
+        options: &UploadOptions,
         user_id: Option<Uuid>,
     ) -> Result<MediaItem, MediaError> {
         // Calculate content hash
@@ -75,61 +211,752 @@ impl MediaService {
         let content_hash = hex::encode(hasher.finalize());
 
         // Check for duplicates
-        if self.deduplicate {
-            let hash_index = self.hash_index.read().await;
-            if let Some(&existing_id) = hash_index.get(&content_hash) {
-                let items = self.items.read().await;
-                if let Some(existing) = items.get(&existing_id) {
-                    return Err(MediaError::Duplicate(existing.filename.clone()));
-                }
-            }
+        if let Some(media) = self.resolve_duplicate(&content_hash, filename, options, user_id).await? {
+            return Ok(media);
         }
 
         // Store the file
-        let stored = self.storage.store(data, filename, mime_type).await?;
+        let stored = at_step(UploadStep::StoreOriginal, self.storage.store(data, filename, mime_type).await)?;
+        let mut rollback = Rollback::new(Arc::clone(&self.storage));
+        rollback.record(&stored.path);
 
         // Create media item
         let mut media = MediaItem::new(filename, mime_type, stored.size, &stored.path);
         media.url = stored.url;
-        media.folder_id = folder_id;
+        media.folder_id = options.folder_id;
         media.uploaded_by = user_id;
-        media.content_hash = content_hash.clone();
+        media.content_hash = content_hash;
+        media.title = options.title.clone();
+        media.description = options.description.clone();
+        media.alt_text = options.alt_text.clone();
+        media.tags = options.tags.clone();
+
+        let result = async {
+            self.process_upload(&mut media, data, options, &mut rollback).await?;
+            self.index(media).await
+        }.await;
+
+        match result {
+            Ok(media) => {
+                rollback.commit();
+                Ok(media)
+            }
+            Err(e) => {
+                rollback.undo().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Image steps of an upload: dimensions, thumbnails and EXIF
+    ///
+    /// Formats the image service cannot decode (SVG, TIFF, ...) are stored
+    /// as they are, keeping any size their header gives (AVIF).
+    async fn process_upload(
+        &self,
+        media: &mut MediaItem,
+        data: &[u8],
+        options: &UploadOptions,
+        rollback: &mut Rollback,
+    ) -> Result<(), MediaError> {
+        if !media.is_image() {
+            return Ok(());
+        }
+
+        // Read straight from the file, so TIFFs get EXIF too
+        if let Ok(metadata) = exif::read(data) {
+            metadata.apply(&mut media.metadata);
+        }
+
+        if !self.image_service.can_decode(data) {
+            media.dimensions = self.image_service.get_dimensions(data).ok();
+            return Ok(());
+        }
+
+        match self.image_service.get_dimensions(data) {
+            Ok(dims) => media.dimensions = Some(dims),
+            Err(e) => Self::optional_step(media, UploadStep::Dimensions, e.into(), options)?,
+        }
+
+        if self.auto_thumbnails && options.generate_thumbnails {
+            batch::report(FileState::Thumbnails).await;
+            match self.image_service.generate_thumbnails(data, &media.path).await {
+                Ok(thumbnails) => {
+                    for thumbnail in &thumbnails {
+                        rollback.record(&thumbnail.path);
+                    }
+                    media.thumbnails = thumbnails;
+                }
+                Err(e) => Self::optional_step(media, UploadStep::Thumbnails, e.into(), options)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fail the upload at `step`, or mark the item degraded when allowed
+    fn optional_step(
+        media: &mut MediaItem,
+        step: UploadStep,
+        error: MediaError,
+        options: &UploadOptions,
+    ) -> Result<(), MediaError> {
+        if !options.allow_degraded {
+            return Err(MediaError::Step { step, source: Box::new(error) });
+        }
+
+        tracing::warn!("Keeping {} without {}: {}", media.filename, step, error);
+        media.degraded.push(DegradedStep { step, error: error.to_string() });
+        Ok(())
+    }
+
+    /// Register a file assembled in storage without loading it into memory
+    ///
+    /// The file at `temp_path` is moved into place. No image processing is
+    /// done; callers route images through [`MediaService::upload`].
+    pub async fn upload_from_storage(
+        &self,
+        temp_path: &str,
+        filename: &str,
+        mime_type: &str,
+        content_hash: String,
+        options: &UploadOptions,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItem, MediaError> {
+        let mut pending = Self::pending(filename, mime_type, 0, options, user_id);
+        pending.size = self.storage.size(temp_path).await.unwrap_or_default();
+        self.hooks.before_upload(&mut pending).await?;
+
+        self.upload_from_storage_approved(
+            temp_path,
+            &pending.filename,
+            &pending.mime_type,
+            content_hash,
+            &pending.options,
+            pending.user_id,
+        ).await
+    }
+
+    /// Register a file assembled in storage its `before_upload` hooks
+    /// already approved
+    pub(crate) async fn upload_from_storage_approved(
+        &self,
+        temp_path: &str,
+        filename: &str,
+        mime_type: &str,
+        content_hash: String,
+        options: &UploadOptions,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItem, MediaError> {
+        // Check for duplicates; the assembled file is not needed then
+        if let Some(media) = self.resolve_duplicate(&content_hash, filename, options, user_id).await? {
+            let _ = self.storage.delete(temp_path).await;
+            return Ok(media);
+        }
+
+        let stored = at_step(
+            UploadStep::StoreOriginal,
+            self.storage.store_file(temp_path, filename, mime_type, content_hash).await,
+        )?;
+        let path = stored.path.clone();
+
+        let media = match self.insert(stored, filename, mime_type, options, user_id).await {
+            Ok(media) => media,
+            Err(e) => {
+                let mut rollback = Rollback::new(Arc::clone(&self.storage));
+                rollback.record(path);
+                rollback.undo().await;
+                return Err(e);
+            }
+        };
+        self.hooks.after_upload(&media).await;
+
+        Ok(media)
+    }
+
+    /// Create a media item for a file already in its final storage location
+    ///
+    /// Fails with [`MediaError::Duplicate`] (leaving the file in place) when
+    /// deduplication is on and the content hash is known. Hooks see the
+    /// upload without its contents, so they can veto it but not rewrite it.
+    pub async fn register(
+        &self,
+        stored: StoredFile,
+        filename: &str,
+        mime_type: &str,
+        options: &UploadOptions,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItem, MediaError> {
+        let mut pending = Self::pending(filename, mime_type, stored.size, options, user_id);
+        self.hooks.before_upload(&mut pending).await?;
+
+        let media = self.insert(stored, &pending.filename, &pending.mime_type, &pending.options, pending.user_id).await?;
+        self.hooks.after_upload(&media).await;
+
+        Ok(media)
+    }
+
+    /// Hook context for an upload whose contents stay in storage
+    fn pending(
+        filename: &str,
+        mime_type: &str,
+        size: u64,
+        options: &UploadOptions,
+        user_id: Option<Uuid>,
+    ) -> PendingUpload {
+        PendingUpload {
+            filename: filename.to_string(),
+            mime_type: mime_type.to_string(),
+            size,
+            data: None,
+            options: options.clone(),
+            user_id,
+        }
+    }
+
+    /// Index a stored file as a media item
+    async fn insert(
+        &self,
+        stored: StoredFile,
+        filename: &str,
+        mime_type: &str,
+        options: &UploadOptions,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItem, MediaError> {
+        let mut media = MediaItem::new(filename, mime_type, stored.size, &stored.path);
+        media.url = stored.url;
+        media.folder_id = options.folder_id;
+        media.uploaded_by = user_id;
+        media.content_hash = stored.hash;
+        media.title = options.title.clone();
+        media.description = options.description.clone();
+        media.alt_text = options.alt_text.clone();
+        media.tags = options.tags.clone();
+
+        self.index(media).await
+    }
+
+    /// Add an item to the index (the last upload step)
+    ///
+    /// The hash is checked and claimed under one lock so concurrent uploads
+    /// of the same content cannot both succeed.
+    async fn index(&self, media: MediaItem) -> Result<MediaItem, MediaError> {
+        let deduplicate = *self.deduplicate.read().await;
+        let mut items = self.items.write().await;
+        if deduplicate {
+            let mut hash_index = self.hash_index.write().await;
+            if let Some(existing) = hash_index.get(&media.content_hash).and_then(|id| items.get(id)) {
+                return Err(MediaError::Duplicate {
+                    filename: existing.filename.clone(),
+                    existing_id: existing.id,
+                });
+            }
+            hash_index.insert(media.content_hash.clone(), media.id);
+        }
+
+        items.insert(media.id, media.clone());
+
+        Ok(media)
+    }
+
+    /// Apply the duplicate strategy when `content_hash` is already known
+    ///
+    /// Returns the item to hand back instead of storing the upload, with
+    /// `duplicate_of` set to the matched item.
+    async fn resolve_duplicate(
+        &self,
+        content_hash: &str,
+        filename: &str,
+        options: &UploadOptions,
+        user_id: Option<Uuid>,
+    ) -> Result<Option<MediaItem>, MediaError> {
+        if !*self.deduplicate.read().await {
+            return Ok(None);
+        }
+        let strategy = *self.duplicate_strategy.read().await;
+
+        let mut items = self.items.write().await;
+        let existing_id = self.hash_index.read().await.get(content_hash).copied();
+        let Some(existing) = existing_id.and_then(|id| items.get_mut(&id)) else {
+            return Ok(None);
+        };
+
+        let media = match options.on_duplicate.unwrap_or(strategy) {
+            DuplicateStrategy::Reject => {
+                return Err(MediaError::Duplicate {
+                    filename: existing.filename.clone(),
+                    existing_id: existing.id,
+                });
+            }
+            DuplicateStrategy::ReturnExisting => {
+                let mut media = existing.clone();
+                media.duplicate_of = Some(media.id);
+                media
+            }
+            DuplicateStrategy::Replace => {
+                Self::apply_options(existing, options);
+                existing.updated_at = Utc::now();
+
+                let mut media = existing.clone();
+                media.duplicate_of = Some(media.id);
+                media
+            }
+            DuplicateStrategy::Link => {
+                let now = Utc::now();
+                let mut media = existing.clone();
+                media.id = Uuid::now_v7();
+                media.filename = filename.to_string();
+                media.slug = crate::models::sanitize_filename(filename);
+                media.title = None;
+                media.description = None;
+                media.alt_text = None;
+                media.tags = Vec::new();
+                media.folder_id = None;
+                Self::apply_options(&mut media, options);
+                media.uploaded_by = user_id;
+                media.uploaded_at = now;
+                media.updated_at = now;
+                media.usage_count = 0;
+                media.deleted = false;
+                media.duplicate_of = Some(existing.id);
+
+                items.insert(media.id, media.clone());
+                media
+            }
+        };
+
+        Ok(Some(media))
+    }
+
+    /// Copy the metadata set in upload options onto an item
+    fn apply_options(media: &mut MediaItem, options: &UploadOptions) {
+        if options.folder_id.is_some() {
+            media.folder_id = options.folder_id;
+        }
+        if let Some(title) = &options.title {
+            media.title = Some(title.clone());
+        }
+        if let Some(description) = &options.description {
+            media.description = Some(description.clone());
+        }
+        if let Some(alt_text) = &options.alt_text {
+            media.alt_text = Some(alt_text.clone());
+        }
+        if !options.tags.is_empty() {
+            media.tags = options.tags.clone();
+        }
+    }
+
+    /// Whether an upload created a new item rather than resolving to an existing one
+    fn is_new(media: &MediaItem) -> bool {
+        media.duplicate_of != Some(media.id)
+    }
+
+    /// Find a media item by content hash
+    pub async fn find_by_hash(&self, hash: &str) -> Option<MediaItem> {
+        let id = *self.hash_index.read().await.get(hash)?;
+        self.get(id).await
+    }
+
+    /// Read an image back from storage to set dimensions, thumbnails and EXIF
+    pub async fn process_image(&self, id: Uuid) -> Result<MediaItem, MediaError> {
+        let media = self.get(id).await
+            .ok_or_else(|| MediaError::NotFound(id.to_string()))?;
+
+        if !media.is_image() {
+            return Err(MediaError::Invalid(format!("Not an image: {}", media.mime_type)));
+        }
+
+        let data = self.storage.read(&media.path).await?;
+
+        let dimensions = self.image_service.get_dimensions(&data).ok();
+        let thumbnails = if self.auto_thumbnails {
+            match self.image_service.generate_thumbnails(&data, &media.path).await {
+                Ok(thumbnails) => thumbnails,
+                Err(e) => {
+                    tracing::warn!("Failed to generate thumbnails: {}", e);
+                    Vec::new()
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        let exif = exif::read(&data).ok();
+
+        let mut items = self.items.write().await;
+        let media = items.get_mut(&id)
+            .ok_or_else(|| MediaError::NotFound(id.to_string()))?;
+
+        media.dimensions = dimensions;
+        media.thumbnails = thumbnails;
+        if let Some(exif) = exif {
+            exif.apply(&mut media.metadata);
+        }
+        media.updated_at = Utc::now();
+
+        Ok(media.clone())
+    }
+
+    /// Replace an item's file, keeping its ID and, where possible, its URL
+    ///
+    /// A file of the same type overwrites the stored one in place. Content
+    /// of another type is stored under a new extension and the old path
+    /// redirects to it (see [`MediaService::redirect_for`]). Thumbnails,
+    /// dimensions and EXIF are regenerated and `version` is bumped. The old
+    /// binary is kept with the previous revision.
+    pub async fn replace(&self, id: Uuid, data: &[u8], author: Option<Uuid>) -> Result<MediaItem, MediaError> {
+        self.replace_file(id, data, RevisionChange::File, author, None).await
+    }
+
+    /// Crop, resize, rotate or filter an image
+    ///
+    /// The edited image replaces the stored file as with
+    /// [`MediaService::replace`], keeping the image's format unless the
+    /// request asks for another.
+    pub async fn edit(
+        &self,
+        id: Uuid,
+        request: &ImageTransformRequest,
+        author: Option<Uuid>,
+    ) -> Result<MediaItem, MediaError> {
+        let media = self.get(id).await
+            .ok_or_else(|| MediaError::NotFound(id.to_string()))?;
+
+        if !media.is_image() {
+            return Err(MediaError::Invalid(format!("Not an image: {}", media.mime_type)));
+        }
 
-        // Process based on type
-        if media.is_image() {
-            // Get dimensions
-            if let Ok(dims) = self.image_service.get_dimensions(data) {
-                media.dimensions = Some(dims);
+        let mut request = request.clone();
+        if request.format.is_none() {
+            request.format = ImageFormat::from_extension(&media.extension);
+        }
+
+        let data = self.storage.read(&media.path).await?;
+        let edited = self.image_service.transform(&data, &request)?;
+
+        self.replace_file(id, &edited, RevisionChange::Edit, author, None).await
+    }
+
+    /// Swap an item's file and record the change as a revision
+    ///
+    /// `metadata` is applied in the same step (used when restoring).
+    async fn replace_file(
+        &self,
+        id: Uuid,
+        data: &[u8],
+        change: RevisionChange,
+        author: Option<Uuid>,
+        metadata: Option<&RevisionMetadata>,
+    ) -> Result<MediaItem, MediaError> {
+        let media = self.get(id).await
+            .ok_or_else(|| MediaError::NotFound(id.to_string()))?;
+
+        let (filename, mime_type) = match self.validator.validate(data, &media.filename) {
+            Ok(_) => (media.filename.clone(), media.mime_type.clone()),
+            Err(ContentRejection::ExtensionMismatch { extension, detected }) => {
+                let Some(new_extension) = validator::extension_for(&detected) else {
+                    return Err(ContentRejection::ExtensionMismatch { extension, detected }.into());
+                };
+                let stem = std::path::Path::new(&media.filename)
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or("file");
+                let filename = format!("{}.{}", stem, new_extension);
+                self.validator.validate(data, &filename)?;
+                (filename, detected)
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let sanitized = if mime_type == "image/svg+xml" {
+            Some(svg::sanitize(data)?)
+        } else {
+            None
+        };
+        let data = sanitized.as_ref().map_or(data, |s| s.data.as_slice());
+
+        // Linked items share the stored file and must keep the old contents
+        let shared = self.items.read().await
+            .values()
+            .any(|m| m.id != id && m.path == media.path);
+
+        // Keep the old binary for the revision history
+        let archived = self.archive_file(&media).await?;
+
+        let stored = if filename == media.filename && !shared {
+            self.storage.replace(&media.path, data, &mime_type).await
+        } else {
+            self.storage.store(data, &filename, &mime_type).await
+        };
+        let stored = match stored {
+            Ok(stored) => stored,
+            Err(e) => {
+                let _ = self.storage.delete(&archived).await;
+                return Err(e.into());
+            }
+        };
+        if stored.path != media.path && !shared {
+            let _ = self.storage.delete(&media.path).await;
+            self.add_redirect(&media.path, &stored.path).await;
+        }
+
+        // Old renditions
+        if !shared {
+            for thumb in &media.thumbnails {
+                let _ = self.storage.delete(&thumb.path).await;
             }
+        }
 
-            // Generate thumbnails
+        let mut dimensions = None;
+        let mut thumbnails = Vec::new();
+        let mut exif = None;
+        if MediaType::from_mime(&mime_type) == MediaType::Image && mime_type != "image/svg+xml" {
+            dimensions = self.image_service.get_dimensions(data).ok();
             if self.auto_thumbnails {
                 match self.image_service.generate_thumbnails(data, &stored.path).await {
-                    Ok(thumbnails) => media.thumbnails = thumbnails,
+                    Ok(generated) => thumbnails = generated,
                     Err(e) => tracing::warn!("Failed to generate thumbnails: {}", e),
                 }
             }
+            exif = exif::read(data).ok();
+        }
+
+        let deduplicate = *self.deduplicate.read().await;
+        let mut items = self.items.write().await;
+        let item = items.get_mut(&id)
+            .ok_or_else(|| MediaError::NotFound(id.to_string()))?;
 
-            // Extract EXIF
-            if let Ok(exif) = self.extract_exif(data) {
-                media.metadata.exif = Some(exif);
+        if deduplicate {
+            let mut hash_index = self.hash_index.write().await;
+            if hash_index.get(&item.content_hash) == Some(&id) {
+                hash_index.remove(&item.content_hash);
             }
+            hash_index.entry(stored.hash.clone()).or_insert(id);
         }
 
-        // Store in index
-        let id = media.id;
-        {
-            let mut items = self.items.write().await;
-            items.insert(id, media.clone());
+        item.extension = std::path::Path::new(&filename)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+        item.slug = crate::models::sanitize_filename(&filename);
+        item.filename = filename;
+        item.media_type = MediaType::from_mime(&mime_type);
+        item.mime_type = mime_type;
+        item.size = stored.size;
+        item.path = stored.path;
+        item.url = stored.url;
+        item.content_hash = stored.hash;
+        item.dimensions = dimensions;
+        item.thumbnails = thumbnails;
+        item.metadata.exif = None;
+        item.metadata.location = None;
+        if let Some(exif) = exif {
+            exif.apply(&mut item.metadata);
+        }
+        match sanitized.map(|s| s.report).filter(|r| !r.is_clean()) {
+            Some(report) => item.metadata.custom.insert("svg_sanitized".to_string(), report.summary()),
+            None => item.metadata.custom.remove("svg_sanitized"),
+        };
+        if let Some(metadata) = metadata {
+            metadata.apply(item);
         }
+        item.version += 1;
+        item.updated_at = Utc::now();
 
-        // Update hash index
-        if self.deduplicate {
-            let mut hash_index = self.hash_index.write().await;
-            hash_index.insert(content_hash, id);
+        let updated = item.clone();
+        drop(items);
+
+        self.record_revision(&media, &updated, change, author, Some(archived)).await;
+
+        Ok(updated)
+    }
+
+    /// Copy an item's current file into the revision archive
+    async fn archive_file(&self, media: &MediaItem) -> Result<String, MediaError> {
+        let name = std::path::Path::new(&media.path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("file");
+        let archived = format!("{}{}/{}-{}", ARCHIVE_DIR, media.id, media.version, name);
+
+        self.storage.copy_file(&media.path, &archived).await?;
+
+        Ok(archived)
+    }
+
+    /// Record the state of `after` as a new revision
+    ///
+    /// The first change also records the original state. `archived` is where
+    /// the previous file now lives when the file changed.
+    async fn record_revision(
+        &self,
+        before: &MediaItem,
+        after: &MediaItem,
+        change: RevisionChange,
+        author: Option<Uuid>,
+        archived: Option<String>,
+    ) {
+        let policy = self.revision_policy.read().await.clone();
+        let mut revisions = self.revisions.write().await;
+        let history = revisions.entry(after.id).or_default();
+
+        if history.is_empty() {
+            history.push(MediaRevision {
+                number: 1,
+                media_id: before.id,
+                change: RevisionChange::Original,
+                author: before.uploaded_by,
+                created_at: before.uploaded_at,
+                metadata: RevisionMetadata::of(before),
+                file: RevisionFile::of(before),
+            });
         }
 
-        Ok(media)
+        // The previous file is no longer at the item's path
+        if let Some(archived) = archived {
+            for revision in history.iter_mut().filter(|r| r.file.version == before.version) {
+                revision.file.path = Some(archived.clone());
+            }
+        }
+
+        let number = history.last().map_or(1, |r| r.number + 1);
+        history.push(MediaRevision {
+            number,
+            media_id: after.id,
+            change,
+            author,
+            created_at: after.updated_at,
+            metadata: RevisionMetadata::of(after),
+            file: RevisionFile::of(after),
+        });
+
+        let pruned = Self::prune_history(history, &policy);
+        drop(revisions);
+
+        for path in pruned {
+            let _ = self.storage.delete(&path).await;
+        }
+    }
+
+    /// Drop archived binaries the policy no longer keeps; returns their paths
+    fn prune_history(history: &mut [MediaRevision], policy: &RevisionPolicy) -> Vec<String> {
+        let cutoff = (policy.max_file_age_days > 0)
+            .then(|| Utc::now() - chrono::Duration::days(policy.max_file_age_days as i64));
+
+        // Archived binaries, newest first; several revisions can share one
+        let mut archived: Vec<(&str, DateTime<Utc>)> = Vec::new();
+        for revision in history.iter().rev() {
+            if let Some(path) = revision.file.path.as_deref().filter(|p| p.starts_with(ARCHIVE_DIR)) {
+                if !archived.iter().any(|(p, _)| *p == path) {
+                    archived.push((path, revision.created_at));
+                }
+            }
+        }
+
+        let pruned: Vec<String> = archived.iter()
+            .enumerate()
+            .filter(|(i, (_, at))| *i >= policy.keep_files || cutoff.is_some_and(|cutoff| *at < cutoff))
+            .map(|(_, (path, _))| path.to_string())
+            .collect();
+
+        for revision in history.iter_mut() {
+            if revision.file.path.as_ref().is_some_and(|p| pruned.contains(p)) {
+                revision.file.path = None;
+            }
+        }
+
+        pruned
+    }
+
+    /// Apply the retention policy to every item's history
+    ///
+    /// Returns the number of binaries removed.
+    pub async fn prune_revisions(&self) -> usize {
+        let policy = self.revision_policy.read().await.clone();
+        let pruned: Vec<String> = {
+            let mut revisions = self.revisions.write().await;
+            revisions.values_mut()
+                .flat_map(|history| Self::prune_history(history, &policy))
+                .collect()
+        };
+
+        for path in &pruned {
+            let _ = self.storage.delete(path).await;
+        }
+
+        pruned.len()
+    }
+
+    /// Revisions of an item, oldest first
+    ///
+    /// Items that were never changed have no revisions.
+    pub async fn list_revisions(&self, id: Uuid) -> Vec<MediaRevision> {
+        let revisions = self.revisions.read().await;
+        revisions.get(&id).cloned().unwrap_or_default()
+    }
+
+    /// Metadata changes between two revisions of an item
+    pub async fn diff_revisions(&self, id: Uuid, from: u32, to: u32) -> Result<Vec<FieldChange>, MediaError> {
+        let from = self.revision(id, from).await?;
+        let to = self.revision(id, to).await?;
+        Ok(from.metadata.diff(&to.metadata))
+    }
+
+    /// Restore an item's metadata and file from an earlier revision
+    ///
+    /// Recorded as a new revision, so the restore itself can be undone.
+    pub async fn restore_revision(&self, id: Uuid, number: u32, author: Option<Uuid>) -> Result<MediaItem, MediaError> {
+        let revision = self.revision(id, number).await?;
+        let media = self.get(id).await
+            .ok_or_else(|| MediaError::NotFound(id.to_string()))?;
+        let change = RevisionChange::Restore { from: number };
+
+        if revision.file.content_hash != media.content_hash {
+            let path = revision.file.path.as_deref()
+                .ok_or_else(|| MediaError::Invalid(format!("File of revision {} was pruned", number)))?;
+            let data = self.storage.read(path).await?;
+            return self.replace_file(id, &data, change, author, Some(&revision.metadata)).await;
+        }
+
+        let updated = {
+            let mut items = self.items.write().await;
+            let item = items.get_mut(&id)
+                .ok_or_else(|| MediaError::NotFound(id.to_string()))?;
+            revision.metadata.apply(item);
+            item.updated_at = Utc::now();
+            item.clone()
+        };
+
+        self.record_revision(&media, &updated, change, author, None).await;
+
+        Ok(updated)
+    }
+
+    async fn revision(&self, id: Uuid, number: u32) -> Result<MediaRevision, MediaError> {
+        let revisions = self.revisions.read().await;
+        revisions.get(&id)
+            .and_then(|history| history.iter().find(|r| r.number == number))
+            .cloned()
+            .ok_or_else(|| MediaError::NotFound(format!("revision {} of {}", number, id)))
+    }
+
+    /// Current URL for the old path of a replaced file
+    pub async fn redirect_for(&self, path: &str) -> Option<String> {
+        let redirects = self.redirects.read().await;
+        redirects.get(path).map(|target| self.storage.url_for(target))
+    }
+
+    /// Point `from` (and anything already redirecting to it) at `to`
+    async fn add_redirect(&self, from: &str, to: &str) {
+        let mut redirects = self.redirects.write().await;
+        for target in redirects.values_mut() {
+            if target == from {
+                *target = to.to_string();
+            }
+        }
+        redirects.remove(to);
+        redirects.insert(from.to_string(), to.to_string());
     }
 
     /// Get media item by ID
@@ -152,25 +979,88 @@ impl MediaService {
         description: Option<String>,
         alt_text: Option<String>,
         tags: Option<Vec<String>>,
+        author: Option<Uuid>,
     ) -> Result<MediaItem, MediaError> {
+        self.edit_metadata(id, author, |media| {
+            if let Some(t) = title {
+                media.title = Some(t);
+            }
+            if let Some(d) = description {
+                media.description = Some(d);
+            }
+            if let Some(a) = alt_text {
+                media.alt_text = Some(a);
+            }
+            if let Some(t) = tags {
+                media.tags = t;
+            }
+        }).await
+    }
+
+    /// Set or remove (`None`) a custom field
+    pub async fn set_custom_field(
+        &self,
+        id: Uuid,
+        key: &str,
+        value: Option<String>,
+        author: Option<Uuid>,
+    ) -> Result<MediaItem, MediaError> {
+        self.edit_metadata(id, author, |media| {
+            match value {
+                Some(value) => media.custom.insert(key.to_string(), value),
+                None => media.custom.remove(key),
+            };
+        }).await
+    }
+
+    /// Apply a metadata change, recording a revision when anything changed
+    async fn edit_metadata<F>(&self, id: Uuid, author: Option<Uuid>, change: F) -> Result<MediaItem, MediaError>
+    where
+        F: FnOnce(&mut MediaItem),
+    {
+        let (before, after) = {
+            let mut items = self.items.write().await;
+            let media = items.get_mut(&id)
+                .ok_or_else(|| MediaError::NotFound(id.to_string()))?;
+
+            let before = media.clone();
+            change(media);
+            if RevisionMetadata::of(media) == RevisionMetadata::of(&before) {
+                return Ok(before);
+            }
+            media.updated_at = Utc::now();
+
+            (before, media.clone())
+        };
+
+        self.record_revision(&before, &after, RevisionChange::Metadata, author, None).await;
+
+        Ok(after)
+    }
+
+    /// Set a custom metadata value
+    pub async fn set_custom_metadata(&self, id: Uuid, key: &str, value: String) -> Result<MediaItem, MediaError> {
         let mut items = self.items.write().await;
 
         let media = items.get_mut(&id)
             .ok_or_else(|| MediaError::NotFound(id.to_string()))?;
 
-        if let Some(t) = title {
-            media.title = Some(t);
-        }
-        if let Some(d) = description {
-            media.description = Some(d);
-        }
-        if let Some(a) = alt_text {
-            media.alt_text = Some(a);
-        }
-        if let Some(t) = tags {
-            media.tags = t;
-        }
+        media.metadata.custom.insert(key.to_string(), value);
+        media.updated_at = Utc::now();
+
+        Ok(media.clone())
+    }
 
+    /// Store EXIF metadata read from elsewhere than the stored file
+    ///
+    /// Used when optimization stripped the EXIF block from what was stored.
+    pub async fn set_exif(&self, id: Uuid, metadata: exif::ExifMetadata) -> Result<MediaItem, MediaError> {
+        let mut items = self.items.write().await;
+
+        let media = items.get_mut(&id)
+            .ok_or_else(|| MediaError::NotFound(id.to_string()))?;
+
+        metadata.apply(&mut media.metadata);
         media.updated_at = Utc::now();
 
         Ok(media.clone())
@@ -178,26 +1068,65 @@ impl MediaService {
 
     /// Delete media item
     pub async fn delete(&self, id: Uuid, permanent: bool) -> Result<(), MediaError> {
+        let snapshot = self.get(id).await
+            .ok_or_else(|| MediaError::NotFound(id.to_string()))?;
+        self.hooks.before_delete(&snapshot, permanent).await?;
+
+        self.delete_item(id, permanent).await?;
+
+        self.hooks.after_delete(&snapshot, permanent).await;
+
+        Ok(())
+    }
+
+    async fn delete_item(&self, id: Uuid, permanent: bool) -> Result<(), MediaError> {
         let mut items = self.items.write().await;
 
         let media = items.get_mut(&id)
             .ok_or_else(|| MediaError::NotFound(id.to_string()))?;
 
         if permanent {
-            // Delete file from storage
-            self.storage.delete(&media.path).await?;
+            // Linked items share the stored file; keep it while any remain
+            let path = media.path.clone();
+            let hash = media.content_hash.clone();
+            let sharer = items.values()
+                .find(|m| m.id != id && m.path == path)
+                .map(|m| m.id);
 
-            // Delete thumbnails
-            for thumb in &media.thumbnails {
-                let _ = self.storage.delete(&thumb.path).await;
-            }
-
-            // Remove from hash index
             let mut hash_index = self.hash_index.write().await;
-            hash_index.remove(&media.content_hash);
+            match sharer {
+                Some(sharer) => {
+                    if hash_index.get(&hash) == Some(&id) {
+                        hash_index.insert(hash, sharer);
+                    }
+                }
+                None => {
+                    // Delete file from storage
+                    self.storage.delete(&path).await?;
+
+                    // Delete thumbnails
+                    for thumb in &items[&id].thumbnails {
+                        let _ = self.storage.delete(&thumb.path).await;
+                    }
+
+                    // Remove from hash index
+                    if hash_index.get(&hash) == Some(&id) {
+                        hash_index.remove(&hash);
+                    }
+                }
+            }
 
             // Remove from items
             items.remove(&id);
+
+            // Drop the history and its archived binaries
+            if let Some(history) = self.revisions.write().await.remove(&id) {
+                for path in history.iter().filter_map(|r| r.file.path.as_deref()) {
+                    if path.starts_with(ARCHIVE_DIR) {
+                        let _ = self.storage.delete(path).await;
+                    }
+                }
+            }
         } else {
             // Soft delete
             media.deleted = true;
@@ -398,12 +1327,6 @@ impl MediaService {
         Ok(())
     }
 
-    /// Extract EXIF data from image
-    fn extract_exif(&self, _data: &[u8]) -> Result<crate::models::ExifData, ImageError> {
-        // Simplified - would use exif crate for real implementation
-        Ok(crate::models::ExifData::default())
-    }
-
     /// Get recent uploads
     pub async fn get_recent(&self, limit: usize) -> Vec<MediaItem> {
         let items = self.items.read().await;
@@ -435,3 +1358,197 @@ impl MediaStats {
         crate::models::format_bytes(self.total_size)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use crate::services::testing::TestServices;
+
+    fn service(dir: &std::path::Path) -> MediaService {
+        let services = TestServices::new(dir);
+        MediaService::new(services.storage, services.image)
+    }
+
+    fn options(strategy: DuplicateStrategy) -> UploadOptions {
+        UploadOptions { on_duplicate: Some(strategy), ..UploadOptions::default() }
+    }
+
+    fn stored_files(dir: &std::path::Path) -> usize {
+        walkdir::WalkDir::new(dir).into_iter().filter_map(Result::ok).filter(|e| e.file_type().is_file()).count()
+    }
+
+    #[tokio::test]
+    async fn test_failed_upload_steps_roll_back() {
+        let dir = tempdir().unwrap();
+        let service = service(dir.path());
+
+        // PNG signature with a corrupt body
+        let broken = [&b"\x89PNG\r\n\x1a\n"[..], &[0u8; 64]].concat();
+
+        let err = service.upload(&broken, "broken.png", "image/png", &UploadOptions::default(), None).await.unwrap_err();
+        assert!(matches!(err, MediaError::Step { step: UploadStep::Dimensions, .. }));
+        assert_eq!(stored_files(dir.path()), 0);
+        assert_eq!(service.get_stats().await.total_items, 0);
+
+        let degraded = UploadOptions { allow_degraded: true, ..UploadOptions::default() };
+        let media = service.upload(&broken, "broken.png", "image/png", &degraded, None).await.unwrap();
+        let steps: Vec<UploadStep> = media.degraded.iter().map(|d| d.step).collect();
+        assert_eq!(steps, vec![UploadStep::Dimensions, UploadStep::Thumbnails]);
+        assert!(service.storage.exists(&media.path).await);
+
+        // Formats the image service cannot decode are stored untouched
+        let svg = service.upload(b"<svg/>", "icon.svg", "image/svg+xml", &UploadOptions::default(), None).await.unwrap();
+        assert!(svg.degraded.is_empty() && svg.dimensions.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_strategies() {
+        let dir = tempdir().unwrap();
+        let service = service(dir.path());
+        let original = service.upload(b"same", "a.txt", "text/plain", &UploadOptions::default(), None).await.unwrap();
+
+        let err = service.upload(b"same", "b.txt", "text/plain", &UploadOptions::default(), None).await.unwrap_err();
+        assert!(matches!(err, MediaError::Duplicate { existing_id, .. } if existing_id == original.id));
+
+        let existing = service.upload(b"same", "b.txt", "text/plain", &options(DuplicateStrategy::ReturnExisting), None).await.unwrap();
+        assert_eq!(existing.id, original.id);
+        assert_eq!(existing.duplicate_of, Some(original.id));
+
+        let mut replace = options(DuplicateStrategy::Replace);
+        replace.title = Some("Renamed".to_string());
+        let replaced = service.upload(b"same", "b.txt", "text/plain", &replace, None).await.unwrap();
+        assert_eq!(replaced.id, original.id);
+        assert_eq!(service.get(original.id).await.unwrap().title.as_deref(), Some("Renamed"));
+
+        let linked = service.upload(b"same", "b.txt", "text/plain", &options(DuplicateStrategy::Link), None).await.unwrap();
+        assert_ne!(linked.id, original.id);
+        assert_eq!(linked.path, original.path);
+        assert_eq!(linked.filename, "b.txt");
+        assert_eq!(linked.title, None);
+        assert_eq!(linked.duplicate_of, Some(original.id));
+        assert_eq!(service.get_stats().await.total_items, 2);
+    }
+
+    #[tokio::test]
+    async fn test_linked_file_kept_until_last_delete() {
+        let dir = tempdir().unwrap();
+        let service = service(dir.path());
+        let original = service.upload(b"shared", "a.txt", "text/plain", &UploadOptions::default(), None).await.unwrap();
+        let linked = service.upload(b"shared", "b.txt", "text/plain", &options(DuplicateStrategy::Link), None).await.unwrap();
+
+        service.delete(original.id, true).await.unwrap();
+        assert!(dir.path().join(&linked.path).exists());
+        assert_eq!(service.find_by_hash(&linked.content_hash).await.unwrap().id, linked.id);
+
+        service.delete(linked.id, true).await.unwrap();
+        assert!(!dir.path().join(&linked.path).exists());
+        assert!(service.find_by_hash(&linked.content_hash).await.is_none());
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut out = std::io::Cursor::new(Vec::new());
+        image::RgbImage::new(width, height).write_to(&mut out, image::ImageFormat::Png).unwrap();
+        out.into_inner()
+    }
+
+    #[tokio::test]
+    async fn test_replace_in_place() {
+        let dir = tempdir().unwrap();
+        let service = service(dir.path());
+        let options = UploadOptions { generate_thumbnails: false, ..UploadOptions::default() };
+        let original = service.upload(&png(4, 4), "logo.png", "image/png", &options, None).await.unwrap();
+
+        let replaced = service.replace(original.id, &png(8, 6), None).await.unwrap();
+        assert_eq!(replaced.id, original.id);
+        assert_eq!(replaced.url, original.url);
+        assert_eq!(replaced.version, original.version + 1);
+        assert_eq!(replaced.dimensions.map(|d| (d.width, d.height)), Some((8, 6)));
+        assert_ne!(replaced.content_hash, original.content_hash);
+        assert_eq!(service.find_by_hash(&replaced.content_hash).await.unwrap().id, original.id);
+        assert!(service.find_by_hash(&original.content_hash).await.is_none());
+
+        let err = service.replace(original.id, b"MZ\x90\0\x03\0\0\0", None).await.unwrap_err();
+        assert!(matches!(err, MediaError::ContentRejected(_)));
+    }
+
+    #[tokio::test]
+    async fn test_replace_with_other_type_redirects() {
+        let dir = tempdir().unwrap();
+        let service = service(dir.path());
+        let original = service.upload(b"plain notes", "notes.txt", "text/plain", &UploadOptions::default(), None).await.unwrap();
+
+        let replaced = service.replace(original.id, &png(2, 2), None).await.unwrap();
+        assert_eq!(replaced.filename, "notes.png");
+        assert_eq!(replaced.mime_type, "image/png");
+        assert_ne!(replaced.path, original.path);
+        assert!(!dir.path().join(&original.path).exists());
+        assert_eq!(service.redirect_for(&original.path).await, Some(replaced.url.clone()));
+
+        let again = service.replace(original.id, b"plain notes again", None).await.unwrap();
+        assert_eq!(again.filename, "notes.txt");
+        assert_eq!(again.path, original.path);
+        assert_eq!(service.redirect_for(&original.path).await, None);
+        assert_eq!(service.redirect_for(&replaced.path).await, Some(again.url));
+    }
+
+    #[tokio::test]
+    async fn test_revisions_and_restore() {
+        let dir = tempdir().unwrap();
+        let service = service(dir.path());
+        let author = Some(Uuid::now_v7());
+        let original = service.upload(b"first", "notes.txt", "text/plain", &UploadOptions::default(), None).await.unwrap();
+        assert!(service.list_revisions(original.id).await.is_empty());
+
+        service.update(original.id, Some("Notes".to_string()), None, None, None, author).await.unwrap();
+        // No-op edits are not recorded
+        service.update(original.id, Some("Notes".to_string()), None, None, None, author).await.unwrap();
+        service.set_custom_field(original.id, "source", Some("scan".to_string()), author).await.unwrap();
+        service.replace(original.id, b"second", author).await.unwrap();
+
+        let revisions = service.list_revisions(original.id).await;
+        let changes: Vec<RevisionChange> = revisions.iter().map(|r| r.change).collect();
+        assert_eq!(changes, vec![
+            RevisionChange::Original,
+            RevisionChange::Metadata,
+            RevisionChange::Metadata,
+            RevisionChange::File,
+        ]);
+        assert_eq!(revisions[1].author, author);
+        let archived = revisions[2].file.path.clone().unwrap();
+        assert!(archived.starts_with(ARCHIVE_DIR));
+        assert_eq!(revisions[0].file.path.as_ref(), Some(&archived));
+        assert_eq!(service.storage.read(&archived).await.unwrap(), b"first");
+
+        let diff = service.diff_revisions(original.id, 1, 3).await.unwrap();
+        let fields: Vec<&str> = diff.iter().map(|c| c.field.as_str()).collect();
+        assert_eq!(fields, vec!["title", "custom.source"]);
+
+        let restored = service.restore_revision(original.id, 1, author).await.unwrap();
+        assert_eq!(restored.title, None);
+        assert!(restored.custom.is_empty());
+        assert_eq!(service.storage.read(&restored.path).await.unwrap(), b"first");
+        assert_eq!(service.list_revisions(original.id).await.last().unwrap().change, RevisionChange::Restore { from: 1 });
+    }
+
+    #[tokio::test]
+    async fn test_revision_retention() {
+        let dir = tempdir().unwrap();
+        let mut service = service(dir.path());
+        service.set_revision_policy(RevisionPolicy { keep_files: 1, max_file_age_days: 0 });
+        let media = service.upload(b"v1", "notes.txt", "text/plain", &UploadOptions::default(), None).await.unwrap();
+
+        service.replace(media.id, b"v2", None).await.unwrap();
+        service.replace(media.id, b"v3", None).await.unwrap();
+
+        let revisions = service.list_revisions(media.id).await;
+        assert_eq!(revisions.len(), 3);
+        assert!(revisions[0].file.path.is_none());
+        assert!(revisions[1].file.path.is_some());
+        assert!(!dir.path().join(ARCHIVE_DIR).join(media.id.to_string()).join("1-notes.txt").exists());
+
+        let err = service.restore_revision(media.id, 1, None).await.unwrap_err();
+        assert!(matches!(err, MediaError::Invalid(_)));
+        assert_eq!(service.restore_revision(media.id, 2, None).await.unwrap().size, 2);
+    }
+}
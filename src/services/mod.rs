@@ -5,9 +5,26 @@
 pub mod media;
 pub mod folder;
 pub mod image;
+pub mod avif;
+pub mod exif;
+pub mod ssim;
 pub mod storage;
 pub mod optimizer;
 pub mod upload;
+pub mod tus;
+pub mod url_import;
+pub mod archive;
+pub mod import;
+pub mod scanner;
+pub mod validator;
+pub mod svg;
+pub mod data_url;
+pub mod idempotency;
+pub mod rate_limit;
+pub mod pipeline;
+pub mod batch;
+#[cfg(test)]
+pub(crate) mod testing;
 
 pub use media::MediaService;
 pub use folder::FolderService;
@@ -15,3 +32,10 @@ pub use image::ImageService;
 pub use storage::StorageService;
 pub use optimizer::OptimizerService;
 pub use upload::UploadService;
+pub use tus::TusService;
+pub use url_import::UrlImporter;
+pub use import::DirectoryImporter;
+pub use scanner::{VirusScanner, ClamdScanner, UnavailableScanner};
+pub use validator::ContentValidator;
+pub use rate_limit::RateLimiter;
+pub use batch::UploadBatches;
@@ -1,9 +1,26 @@
 //! Optimizer Service
 //!
-//! Media optimization and compression.
+//! Media optimization and compression. Images are re-encoded with the
+//! configured settings, and the smallest candidate wins; the original is
+//! kept whenever no re-encoding beats it.
+//!
+//! With a target SSIM, JPEG quality is no longer fixed: it is searched for
+//! the lowest setting whose output still scores the target against the
+//! source, so simple images get smaller files and detailed ones keep their
+//! detail.
 
+use std::io::Cursor;
 use std::sync::Arc;
+use tokio::sync::RwLock;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType as ResizeFilter;
+use image::metadata::Orientation;
+use image::{ColorType, DynamicImage, ImageDecoder, ImageEncoder, ImageReader};
+
 use crate::models::{ImageFormat, OptimizationResult};
+use super::avif;
+use super::ssim;
 use super::image::ImageService;
 use super::storage::StorageService;
 
@@ -16,6 +33,14 @@ pub enum OptimizerError {
     Storage(#[from] super::storage::StorageError),
     #[error("Unsupported format: {0}")]
     UnsupportedFormat(String),
+    #[error("Encoding error: {0}")]
+    Encoding(String),
+}
+
+impl From<image::ImageError> for OptimizerError {
+    fn from(e: image::ImageError) -> Self {
+        Self::Image(e.into())
+    }
 }
 
 /// Optimization settings
@@ -37,6 +62,19 @@ pub struct OptimizationSettings {
     pub convert_to_webp: bool,
     /// Progressive JPEG
     pub progressive_jpeg: bool,
+    /// AVIF quality (1-100)
+    pub avif_quality: u8,
+    /// AVIF encoder speed (1 = smallest files, 10 = fastest)
+    pub avif_speed: u8,
+    /// SSIM (0-1) JPEG output should reach; searches the quality instead of
+    /// using `jpeg_quality`
+    pub target_ssim: Option<f64>,
+    /// Lowest quality the search may pick
+    pub min_quality: u8,
+    /// Highest quality the search may pick
+    pub max_quality: u8,
+    /// Most encodes the search may try per image
+    pub max_iterations: u8,
 }
 
 impl Default for OptimizationSettings {
@@ -50,10 +88,38 @@ impl Default for OptimizationSettings {
             strip_metadata: true,
             convert_to_webp: false,
             progressive_jpeg: true,
+            avif_quality: 70,
+            avif_speed: 6,
+            target_ssim: None,
+            min_quality: 40,
+            max_quality: 95,
+            max_iterations: 6,
         }
     }
 }
 
+/// Decoded image with the metadata worth carrying over
+struct Source {
+    image: DynamicImage,
+    format: ImageFormat,
+    /// Size of the stored pixels (before orientation is applied)
+    dimensions: (u32, u32),
+    orientation: Orientation,
+    exif: Option<Vec<u8>>,
+    icc: Option<Vec<u8>>,
+}
+
+/// Encoded output competing for the smallest size
+struct Candidate {
+    data: Vec<u8>,
+    format: ImageFormat,
+    dimensions: (u32, u32),
+    /// Encoder quality, for lossy output
+    quality: Option<u8>,
+    /// SSIM against the source, when measured
+    ssim: Option<f64>,
+}
+
 /// Optimizer service
 pub struct OptimizerService {
     /// Image service
@@ -61,7 +127,7 @@ pub struct OptimizerService {
     /// Storage service
     storage: Arc<StorageService>,
     /// Settings
-    settings: OptimizationSettings,
+    settings: RwLock<OptimizationSettings>,
 }
 
 impl OptimizerService {
@@ -73,65 +139,45 @@ impl OptimizerService {
         Self {
             image_service,
             storage,
-            settings: OptimizationSettings::default(),
+            settings: RwLock::new(OptimizationSettings::default()),
         }
     }
 
     /// Set optimization settings
     pub fn configure(&mut self, settings: OptimizationSettings) {
-        self.settings = settings;
+        *self.settings.get_mut() = settings;
+    }
+
+    /// Replace the settings of a running service
+    pub async fn update_settings(&self, settings: OptimizationSettings) {
+        *self.settings.write().await = settings;
     }
 
     /// Optimize an image
+    ///
+    /// The image is converted to `format` when given, and otherwise keeps
+    /// its format (or becomes lossless WebP, with `convert_to_webp`, where
+    /// that is smaller).
     pub async fn optimize_image(
         &self,
         data: &[u8],
         format: Option<ImageFormat>,
     ) -> Result<OptimizedImage, OptimizerError> {
-        let original_size = data.len() as u64;
-
-        // Determine output format
-        let output_format = if self.settings.convert_to_webp {
-            ImageFormat::WebP
-        } else {
-            format.unwrap_or(ImageFormat::Jpeg)
-        };
-
-        // Get quality based on format
-        let quality = match output_format {
-            ImageFormat::Jpeg => self.settings.jpeg_quality,
-            ImageFormat::WebP => self.settings.webp_quality,
-            ImageFormat::Png => self.settings.png_compression,
-            _ => 85,
-        };
-
-        // Optimize
-        let result = self.image_service.optimize(data, quality)?;
-
-        // Encode optimized image
-        let optimized_data = self.image_service.optimize(data, quality)?;
-
-        Ok(OptimizedImage {
-            data: Vec::new(), // Would need to return actual optimized bytes
-            original_size,
-            optimized_size: optimized_data.optimized_size,
-            format: output_format,
-            savings_percent: optimized_data.savings_percent,
-        })
+        self.optimize_blocking(data.to_vec(), None, format).await
     }
 
     /// Optimize image file in place
+    ///
+    /// The file is only rewritten when it got smaller in the same format.
     pub async fn optimize_file(&self, path: &str) -> Result<OptimizationResult, OptimizerError> {
         let data = self.storage.read(path).await?;
-        let result = self.optimize_image(&data, None).await?;
+        let result = self.optimize_blocking(data, None, None).await?;
 
-        Ok(OptimizationResult {
-            original_size: result.original_size,
-            optimized_size: result.optimized_size,
-            savings_percent: result.savings_percent,
-            dimensions: None,
-            format: result.format,
-        })
+        if result.optimized_size < result.original_size {
+            self.storage.write(path, &result.data).await?;
+        }
+
+        Ok(result.result())
     }
 
     /// Batch optimize images
@@ -155,77 +201,34 @@ impl OptimizerService {
         data: &[u8],
         target_format: ImageFormat,
     ) -> Result<Vec<u8>, OptimizerError> {
-        let quality = match target_format {
-            ImageFormat::Jpeg => self.settings.jpeg_quality,
-            ImageFormat::WebP => self.settings.webp_quality,
-            ImageFormat::Png => self.settings.png_compression,
-            _ => 85,
-        };
-
-        let transform = crate::models::ImageTransformRequest {
-            width: None,
-            height: None,
-            mode: None,
-            quality: Some(quality),
-            format: Some(target_format),
-            rotate: None,
-            flip_h: None,
-            flip_v: None,
-            crop: None,
-            watermark: None,
-            filters: None,
-        };
-
-        let result = self.image_service.transform(data, &transform)?;
-        Ok(result)
+        Ok(self.optimize_blocking(data.to_vec(), None, Some(target_format)).await?.data)
     }
 
     /// Resize and optimize
+    ///
+    /// Images larger than the bounds are scaled down to fit, keeping their
+    /// aspect ratio.
     pub async fn resize_and_optimize(
         &self,
         data: &[u8],
         max_width: u32,
         max_height: u32,
     ) -> Result<OptimizedImage, OptimizerError> {
-        let transform = crate::models::ImageTransformRequest {
-            width: Some(max_width),
-            height: Some(max_height),
-            mode: Some(crate::models::ResizeMode::Fit),
-            quality: Some(self.settings.jpeg_quality),
-            format: if self.settings.convert_to_webp {
-                Some(ImageFormat::WebP)
-            } else {
-                None
-            },
-            rotate: None,
-            flip_h: None,
-            flip_v: None,
-            crop: None,
-            watermark: None,
-            filters: None,
-        };
-
-        let original_size = data.len() as u64;
-        let optimized_data = self.image_service.transform(data, &transform)?;
-        let optimized_size = optimized_data.len() as u64;
+        self.optimize_blocking(data.to_vec(), Some((max_width, max_height)), None).await
+    }
 
-        let savings_percent = if original_size > 0 {
-            ((original_size - optimized_size) as f64 / original_size as f64) * 100.0
-        } else {
-            0.0
+    /// Optimize an image, scaling it down to the configured `max_width` and
+    /// `max_height` (either may be unset)
+    pub async fn optimize_within_limits(&self, data: &[u8]) -> Result<OptimizedImage, OptimizerError> {
+        let bounds = {
+            let settings = self.settings.read().await;
+            match (settings.max_width, settings.max_height) {
+                (None, None) => None,
+                (width, height) => Some((width.unwrap_or(u32::MAX), height.unwrap_or(u32::MAX))),
+            }
         };
 
-        Ok(OptimizedImage {
-            data: optimized_data,
-            original_size,
-            optimized_size,
-            format: if self.settings.convert_to_webp {
-                ImageFormat::WebP
-            } else {
-                ImageFormat::Jpeg
-            },
-            savings_percent,
-        })
+        self.optimize_blocking(data.to_vec(), bounds, None).await
     }
 
     /// Get estimated savings for image
@@ -241,6 +244,191 @@ impl OptimizerService {
 
         ((size as f64) * (1.0 - ratio)) as u64
     }
+
+    /// Run [`Self::optimize`] with the current settings on the blocking
+    /// thread pool, as encoding (and the SSIM search) can take a while
+    async fn optimize_blocking(
+        &self,
+        data: Vec<u8>,
+        bounds: Option<(u32, u32)>,
+        target: Option<ImageFormat>,
+    ) -> Result<OptimizedImage, OptimizerError> {
+        let settings = self.settings.read().await.clone();
+        let image_service = Arc::clone(&self.image_service);
+
+        tokio::task::spawn_blocking(move || {
+            Self::optimize(&image_service, &settings, &data, bounds, target)
+        })
+        .await
+        .map_err(|e| OptimizerError::Encoding(e.to_string()))?
+    }
+
+    /// Re-encode `data` and keep the smallest result
+    fn optimize(
+        image_service: &ImageService,
+        settings: &OptimizationSettings,
+        data: &[u8],
+        bounds: Option<(u32, u32)>,
+        target: Option<ImageFormat>,
+    ) -> Result<OptimizedImage, OptimizerError> {
+        if !image_service.can_decode(data) {
+            return Err(OptimizerError::UnsupportedFormat("cannot decode image".to_string()));
+        }
+
+        let source = decode(data)?;
+
+        // Re-encoding a GIF would drop its animation
+        if source.format == ImageFormat::Gif && target.is_none() {
+            return Ok(OptimizedImage::new(data.len() as u64, Candidate {
+                data: data.to_vec(),
+                format: ImageFormat::Gif,
+                dimensions: source.dimensions,
+                quality: None,
+                ssim: None,
+            }));
+        }
+
+        let strip = settings.strip_metadata;
+        let mut image = source.image.clone();
+        let oriented = strip && source.orientation != Orientation::NoTransforms;
+        if oriented {
+            // The orientation tag goes with the rest of the EXIF data
+            image.apply_orientation(source.orientation);
+        }
+
+        let mut resized = false;
+        if let Some((max_width, max_height)) = bounds {
+            if image.width() > max_width || image.height() > max_height {
+                image = image.resize(max_width, max_height, ResizeFilter::Lanczos3);
+                resized = true;
+            }
+        }
+
+        let exif = if strip { None } else { source.exif.as_deref() };
+        let icc = source.icc.as_deref();
+        let format = target.unwrap_or(source.format);
+
+        let mut candidates = Vec::new();
+
+        // The original (listed first, so it wins ties) is only an option
+        // when nothing had to change. With metadata stripped, that takes a
+        // file that strips without re-encoding and needs no rotation.
+        let original = match (strip, oriented) {
+            _ if format != source.format || resized => None,
+            (false, _) => Some(data.to_vec()),
+            (true, false) => strip_metadata(data, format),
+            (true, true) => None,
+        };
+        if let Some(original) = original {
+            candidates.push(Candidate {
+                data: original,
+                format,
+                dimensions: source.dimensions,
+                quality: None,
+                ssim: None,
+            });
+        }
+
+        candidates.push(Self::encode(settings, &image, format, exif, icc)?);
+
+        if settings.convert_to_webp && target.is_none() && format != ImageFormat::WebP {
+            candidates.push(Self::encode(settings, &image, ImageFormat::WebP, exif, icc)?);
+        }
+
+        let best = candidates.into_iter()
+            .min_by_key(|c| c.data.len())
+            .expect("at least one candidate");
+
+        Ok(OptimizedImage::new(data.len() as u64, best))
+    }
+
+    /// Encode an image in `format` with the configured settings
+    fn encode(
+        settings: &OptimizationSettings,
+        image: &DynamicImage,
+        format: ImageFormat,
+        exif: Option<&[u8]>,
+        icc: Option<&[u8]>,
+    ) -> Result<Candidate, OptimizerError> {
+        let mut quality = None;
+        let data = match format {
+            ImageFormat::Jpeg if settings.target_ssim.is_some() => {
+                return Self::search_jpeg(settings, image, exif, icc);
+            }
+            ImageFormat::Jpeg => {
+                quality = Some(settings.jpeg_quality);
+                encode_jpeg(image, settings.jpeg_quality, settings.progressive_jpeg, exif, icc)?
+            }
+            ImageFormat::Png => encode_png(image, settings.png_compression, exif, icc)?,
+            ImageFormat::WebP => encode_webp_lossless(image, exif, icc)?,
+            ImageFormat::Avif => {
+                quality = Some(settings.avif_quality);
+                avif::encode(image, settings.avif_quality, settings.avif_speed)?
+            }
+            ImageFormat::Gif => {
+                let mut buffer = Vec::new();
+                image.write_to(&mut Cursor::new(&mut buffer), image::ImageFormat::Gif)?;
+                buffer
+            }
+        };
+
+        Ok(Candidate { data, format, dimensions: (image.width(), image.height()), quality, ssim: None })
+    }
+
+    /// Encode a JPEG at the lowest quality that reaches the target SSIM
+    ///
+    /// `max_quality` is tried first: if even that misses the target, it is
+    /// the best there is. Otherwise the range below is bisected until the
+    /// iterations run out. AVIF output is not searched, as it cannot be
+    /// decoded back for scoring, and WebP output is lossless.
+    fn search_jpeg(
+        settings: &OptimizationSettings,
+        image: &DynamicImage,
+        exif: Option<&[u8]>,
+        icc: Option<&[u8]>,
+    ) -> Result<Candidate, OptimizerError> {
+        let target = settings.target_ssim.unwrap_or(1.0);
+        let max = settings.max_quality.clamp(1, 100);
+        let min = settings.min_quality.clamp(1, max);
+
+        let attempt = |quality: u8| -> Result<Candidate, OptimizerError> {
+            let data = encode_jpeg(image, quality, settings.progressive_jpeg, exif, icc)?;
+            let decoded = image::load_from_memory_with_format(&data, image::ImageFormat::Jpeg)?;
+            Ok(Candidate {
+                ssim: ssim::ssim(image, &decoded),
+                data,
+                format: ImageFormat::Jpeg,
+                dimensions: (image.width(), image.height()),
+                quality: Some(quality),
+            })
+        };
+        let reaches = |candidate: &Candidate| candidate.ssim.is_some_and(|score| score >= target);
+
+        let mut best = attempt(max)?;
+        if !reaches(&best) {
+            return Ok(best);
+        }
+
+        let (mut low, mut high) = (min, max - 1);
+        for _ in 1..settings.max_iterations.max(1) {
+            if low > high {
+                break;
+            }
+            let quality = low + (high - low) / 2;
+            let candidate = attempt(quality)?;
+            if reaches(&candidate) {
+                best = candidate;
+                match quality.checked_sub(1) {
+                    Some(below) if below >= low => high = below,
+                    _ => break,
+                }
+            } else {
+                low = quality + 1;
+            }
+        }
+
+        Ok(best)
+    }
 }
 
 /// Optimized image result
@@ -256,11 +444,508 @@ pub struct OptimizedImage {
     pub format: ImageFormat,
     /// Savings percentage
     pub savings_percent: f64,
+    /// Output width and height
+    pub dimensions: (u32, u32),
+    /// Encoder quality, for lossy output
+    pub quality: Option<u8>,
+    /// SSIM against the source, when a target SSIM was searched for
+    pub ssim: Option<f64>,
 }
 
 impl OptimizedImage {
+    fn new(original_size: u64, best: Candidate) -> Self {
+        let result = OptimizationResult::new(original_size, best.data.len() as u64);
+        Self {
+            original_size,
+            optimized_size: result.optimized_size,
+            savings_percent: result.savings_percent,
+            data: best.data,
+            format: best.format,
+            dimensions: best.dimensions,
+            quality: best.quality,
+            ssim: best.ssim,
+        }
+    }
+
     /// Get bytes saved
     pub fn bytes_saved(&self) -> u64 {
         self.original_size.saturating_sub(self.optimized_size)
     }
+
+    /// Summary for hooks and reports
+    pub fn result(&self) -> OptimizationResult {
+        let mut result = OptimizationResult::new(self.original_size, self.optimized_size);
+        result.dimensions = Some(self.dimensions);
+        result.format = self.format;
+        result.quality = self.quality;
+        result.ssim = self.ssim;
+        result
+    }
+}
+
+/// Decode an image along with its orientation, EXIF and ICC profile
+fn decode(data: &[u8]) -> Result<Source, OptimizerError> {
+    let reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|e| OptimizerError::UnsupportedFormat(e.to_string()))?;
+    let format = match reader.format() {
+        Some(image::ImageFormat::Jpeg) => ImageFormat::Jpeg,
+        Some(image::ImageFormat::Png) => ImageFormat::Png,
+        Some(image::ImageFormat::WebP) => ImageFormat::WebP,
+        Some(image::ImageFormat::Gif) => ImageFormat::Gif,
+        other => return Err(OptimizerError::UnsupportedFormat(format!("{:?}", other))),
+    };
+
+    let mut decoder = reader.into_decoder()?;
+    // Broken metadata should not cost the image
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let exif = decoder.exif_metadata().ok().flatten();
+    let icc = decoder.icc_profile().ok().flatten();
+    let image = DynamicImage::from_decoder(decoder)?;
+
+    Ok(Source {
+        dimensions: (image.width(), image.height()),
+        image,
+        format,
+        orientation,
+        exif,
+        icc,
+    })
+}
+
+/// Encode a JPEG (progressive if asked) with optimized Huffman tables
+fn encode_jpeg(
+    image: &DynamicImage,
+    quality: u8,
+    progressive: bool,
+    exif: Option<&[u8]>,
+    icc: Option<&[u8]>,
+) -> Result<Vec<u8>, OptimizerError> {
+    use jpeg_encoder::{ColorType as JpegColor, Encoder};
+
+    let (width, height) = match (u16::try_from(image.width()), u16::try_from(image.height())) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => return Err(OptimizerError::Encoding("image too large for JPEG".to_string())),
+    };
+
+    let mut buffer = Vec::new();
+    let mut encoder = Encoder::new(&mut buffer, quality.clamp(1, 100));
+    encoder.set_progressive(progressive);
+    encoder.set_optimized_huffman_tables(true);
+
+    let encoding = |e: jpeg_encoder::EncodingError| OptimizerError::Encoding(e.to_string());
+    if let Some(icc) = icc {
+        encoder.add_icc_profile(icc).map_err(encoding)?;
+    }
+    if let Some(exif) = exif {
+        encoder.add_exif_metadata(exif).map_err(encoding)?;
+    }
+
+    if image.color().has_color() {
+        encoder.encode(image.to_rgb8().as_raw(), width, height, JpegColor::Rgb).map_err(encoding)?;
+    } else {
+        encoder.encode(image.to_luma8().as_raw(), width, height, JpegColor::Luma).map_err(encoding)?;
+    }
+
+    Ok(buffer)
+}
+
+/// Encode a PNG, trying each filter at the deflate levels allowed by
+/// `compression` (0-9) and keeping the smallest
+fn encode_png(
+    image: &DynamicImage,
+    compression: u8,
+    exif: Option<&[u8]>,
+    icc: Option<&[u8]>,
+) -> Result<Vec<u8>, OptimizerError> {
+    let levels: &[CompressionType] = match compression {
+        0..=2 => &[CompressionType::Fast],
+        3..=6 => &[CompressionType::Default],
+        _ => &[CompressionType::Default, CompressionType::Best],
+    };
+    let filters = [
+        FilterType::Adaptive,
+        FilterType::NoFilter,
+        FilterType::Sub,
+        FilterType::Up,
+        FilterType::Avg,
+        FilterType::Paeth,
+    ];
+
+    let image = reduce_color(image);
+    let mut best: Option<Vec<u8>> = None;
+
+    for &level in levels {
+        for filter in filters {
+            let mut buffer = Vec::new();
+            let mut encoder = PngEncoder::new_with_quality(&mut buffer, level, filter);
+            if let Some(icc) = icc {
+                let _ = encoder.set_icc_profile(icc.to_vec());
+            }
+            if let Some(exif) = exif {
+                let _ = encoder.set_exif_metadata(exif.to_vec());
+            }
+            encoder.write_image(image.as_bytes(), image.width(), image.height(), image.color().into())?;
+
+            if best.as_ref().is_none_or(|b| buffer.len() < b.len()) {
+                best = Some(buffer);
+            }
+        }
+    }
+
+    Ok(best.expect("at least one PNG encoding"))
+}
+
+/// Encode a lossless WebP
+fn encode_webp_lossless(
+    image: &DynamicImage,
+    exif: Option<&[u8]>,
+    icc: Option<&[u8]>,
+) -> Result<Vec<u8>, OptimizerError> {
+    let image = match reduce_color(image) {
+        image @ (DynamicImage::ImageLuma8(_) | DynamicImage::ImageRgb8(_)) => DynamicImage::ImageRgb8(image.to_rgb8()),
+        image => DynamicImage::ImageRgba8(image.to_rgba8()),
+    };
+
+    let mut buffer = Vec::new();
+    let mut encoder = WebPEncoder::new_lossless(&mut buffer);
+    if let Some(icc) = icc {
+        let _ = encoder.set_icc_profile(icc.to_vec());
+    }
+    if let Some(exif) = exif {
+        let _ = encoder.set_exif_metadata(exif.to_vec());
+    }
+    encoder.write_image(image.as_bytes(), image.width(), image.height(), image.color().into())?;
+
+    Ok(buffer)
+}
+
+/// Drop channels that carry no information (opaque alpha, equal RGB)
+///
+/// Only 8-bit images are reduced; anything else is converted to RGBA8.
+fn reduce_color(image: &DynamicImage) -> DynamicImage {
+    if !matches!(image.color(), ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8) {
+        return DynamicImage::ImageRgba8(image.to_rgba8());
+    }
+
+    let rgba = image.to_rgba8();
+    let opaque = rgba.pixels().all(|p| p[3] == u8::MAX);
+    let gray = rgba.pixels().all(|p| p[0] == p[1] && p[1] == p[2]);
+
+    match (gray, opaque) {
+        (true, true) => DynamicImage::ImageLuma8(image.to_luma8()),
+        (true, false) => DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+        (false, true) => DynamicImage::ImageRgb8(image.to_rgb8()),
+        (false, false) => DynamicImage::ImageRgba8(rgba),
+    }
+}
+
+/// Remove metadata from an encoded file without re-encoding it
+///
+/// Covers EXIF, XMP, IPTC and comments in JPEGs and EXIF and text chunks in
+/// PNGs. Colour profiles stay. Returns `None` for other formats and for
+/// files that do not parse.
+fn strip_metadata(data: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    match format {
+        ImageFormat::Jpeg => strip_jpeg(data),
+        ImageFormat::Png => strip_png(data),
+        _ => None,
+    }
+}
+
+fn strip_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    const SOS: u8 = 0xDA;
+    const APP1: u8 = 0xE1;
+    const APP12: u8 = 0xEC;
+    const APP13: u8 = 0xED;
+    const COM: u8 = 0xFE;
+
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..2]);
+    let mut pos = 2;
+
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        if marker == SOS {
+            // Entropy-coded data follows; no metadata after this point
+            out.extend_from_slice(&data[pos..]);
+            return Some(out);
+        }
+
+        let length = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+        let segment = data.get(pos..pos + 2 + length)?;
+        if !matches!(marker, APP1 | APP12 | APP13 | COM) {
+            out.extend_from_slice(segment);
+        }
+        pos += segment.len();
+    }
+}
+
+fn strip_png(data: &[u8]) -> Option<Vec<u8>> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    const DROPPED: [&[u8; 4]; 5] = [b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+
+    if !data.starts_with(SIGNATURE) {
+        return None;
+    }
+
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(SIGNATURE);
+    let mut pos = SIGNATURE.len();
+
+    while pos < data.len() {
+        let length = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        // length, type, data, CRC
+        let chunk = data.get(pos..pos.checked_add(12 + length)?)?;
+        let kind = &chunk[4..8];
+        if !DROPPED.iter().any(|d| d.as_slice() == kind) {
+            out.extend_from_slice(chunk);
+        }
+        pos += chunk.len();
+        if kind == b"IEND" {
+            return Some(out);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+    use crate::services::testing::TestServices;
+
+    fn service(settings: OptimizationSettings) -> (tempfile::TempDir, OptimizerService) {
+        let dir = tempfile::tempdir().unwrap();
+        let services = TestServices::new(dir.path());
+        let mut service = OptimizerService::new(services.image, services.storage);
+        service.configure(settings);
+        (dir, service)
+    }
+
+    fn photo(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            Rgb([(x * 7 % 256) as u8, (y * 5 % 256) as u8, ((x * y) % 256) as u8])
+        }))
+    }
+
+    fn encoded(image: &DynamicImage, format: image::ImageFormat) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        image.write_to(&mut Cursor::new(&mut buffer), format).unwrap();
+        buffer
+    }
+
+    /// JPEG from the `image` encoder with an EXIF segment spliced in
+    fn jpeg_with_exif(image: &DynamicImage, quality: u8) -> Vec<u8> {
+        // Big-endian TIFF header with no entries
+        jpeg_with_tiff(image, quality, b"MM\0\x2a\0\0\0\x08\0\0\0\0\0\0")
+    }
+
+    /// JPEG from the `image` encoder with `tiff` spliced in as EXIF
+    fn jpeg_with_tiff(image: &DynamicImage, quality: u8, tiff: &[u8]) -> Vec<u8> {
+        let mut plain = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut plain, quality)
+            .encode_image(image)
+            .unwrap();
+
+        let exif = [b"Exif\0\0".as_slice(), tiff].concat();
+        let mut data = plain[..2].to_vec();
+        data.extend_from_slice(&[0xFF, 0xE1]);
+        data.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+        data.extend_from_slice(&exif);
+        data.extend_from_slice(&plain[2..]);
+        data
+    }
+
+    fn contains(data: &[u8], needle: &[u8]) -> bool {
+        data.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[tokio::test]
+    async fn test_progressive_jpeg_and_resize() {
+        let (_dir, optimizer) = service(OptimizationSettings::default());
+        let data = jpeg_with_exif(&photo(300, 200), 98);
+
+        let result = optimizer.resize_and_optimize(&data, 150, 150).await.unwrap();
+        assert_eq!(result.format, ImageFormat::Jpeg);
+        assert_eq!(result.dimensions, (150, 100));
+        assert_eq!(result.optimized_size, result.data.len() as u64);
+        assert!(result.optimized_size < result.original_size);
+
+        // Progressive (SOF2) and without the EXIF segment
+        assert!(contains(&result.data, &[0xFF, 0xC2]));
+        assert!(!contains(&result.data, b"Exif\0\0"));
+        assert_eq!(image::load_from_memory(&result.data).unwrap().width(), 150);
+
+        let report = result.result();
+        assert_eq!(report.dimensions, Some((150, 100)));
+        assert!(report.savings_percent > 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_configured_limits() {
+        let (_dir, optimizer) = service(OptimizationSettings {
+            max_width: Some(100),
+            max_height: None,
+            ..OptimizationSettings::default()
+        });
+        let data = jpeg_with_exif(&photo(400, 200), 90);
+
+        let result = optimizer.optimize_within_limits(&data).await.unwrap();
+        assert_eq!(result.dimensions, (100, 50));
+
+        // Images within the limits keep their size
+        optimizer
+            .update_settings(OptimizationSettings {
+                max_width: None,
+                max_height: None,
+                ..OptimizationSettings::default()
+            })
+            .await;
+        let result = optimizer.optimize_within_limits(&data).await.unwrap();
+        assert_eq!(result.dimensions, (400, 200));
+    }
+
+    #[tokio::test]
+    async fn test_original_kept_when_smaller() {
+        let (_dir, optimizer) = service(OptimizationSettings { jpeg_quality: 100, ..OptimizationSettings::default() });
+        let data = jpeg_with_exif(&photo(64, 64), 20);
+
+        let result = optimizer.optimize_image(&data, None).await.unwrap();
+        assert!(result.optimized_size <= result.original_size);
+        // The original, losslessly stripped of its EXIF segment
+        assert_eq!(result.data, strip_jpeg(&data).unwrap());
+        assert!(!contains(&result.data, b"Exif\0\0"));
+
+        // Metadata survives when not stripped
+        let (_dir, optimizer) = service(OptimizationSettings {
+            jpeg_quality: 100,
+            strip_metadata: false,
+            ..OptimizationSettings::default()
+        });
+        let result = optimizer.optimize_image(&data, None).await.unwrap();
+        assert_eq!(result.data, data);
+    }
+
+    #[tokio::test]
+    async fn test_oriented_original_never_kept() {
+        // Orientation 6 (rotate 90° clockwise) and a GPS latitude reference
+        let tiff = [
+            &b"MM\0\x2a\0\0\0\x08\0\x02"[..],
+            b"\x01\x12\0\x03\0\0\0\x01\0\x06\0\0",
+            b"\x88\x25\0\x04\0\0\0\x01\0\0\0\x26",
+            b"\0\0\0\0",
+            b"\0\x01\0\x01\0\x02\0\0\0\x02N\0\0\0\0\0\0\0",
+        ].concat();
+        let data = jpeg_with_tiff(&photo(64, 48), 20, &tiff);
+
+        // The original is smaller, but can be neither stripped nor kept
+        let (_dir, optimizer) = service(OptimizationSettings { jpeg_quality: 100, ..OptimizationSettings::default() });
+        let result = optimizer.optimize_image(&data, None).await.unwrap();
+        assert!(result.optimized_size > result.original_size);
+        assert!(!contains(&result.data, b"Exif\0\0"));
+        assert_eq!(result.dimensions, (48, 64));
+    }
+
+    #[tokio::test]
+    async fn test_png_is_lossless() {
+        let (_dir, optimizer) = service(OptimizationSettings { png_compression: 9, ..OptimizationSettings::default() });
+
+        // Opaque RGBA gray gradient, stored with fast compression
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(120, 80, |x, _| {
+            let v = (x * 2) as u8;
+            Rgba([v, v, v, 255])
+        }));
+        let mut data = Vec::new();
+        PngEncoder::new_with_quality(&mut data, CompressionType::Fast, FilterType::NoFilter)
+            .write_image(image.as_bytes(), 120, 80, image.color().into())
+            .unwrap();
+
+        let result = optimizer.optimize_image(&data, None).await.unwrap();
+        assert_eq!(result.format, ImageFormat::Png);
+        assert!(result.optimized_size < result.original_size);
+
+        let decoded = image::load_from_memory(&result.data).unwrap();
+        assert_eq!(decoded.color(), ColorType::L8);
+        assert_eq!(decoded.to_rgba8(), image.to_rgba8());
+    }
+
+    #[tokio::test]
+    async fn test_webp_only_where_smaller() {
+        let settings = OptimizationSettings { convert_to_webp: true, ..OptimizationSettings::default() };
+        let (_dir, optimizer) = service(settings);
+
+        // Flat graphics compress better as lossless WebP than as PNG
+        let flat = DynamicImage::ImageRgb8(RgbImage::from_fn(200, 200, |x, y| {
+            if (x / 50 + y / 50) % 2 == 0 { Rgb([200, 30, 30]) } else { Rgb([30, 30, 200]) }
+        }));
+        let png = encoded(&flat, image::ImageFormat::Png);
+        let result = optimizer.optimize_image(&png, None).await.unwrap();
+        assert_eq!(result.format, ImageFormat::WebP);
+        assert_eq!(image::load_from_memory(&result.data).unwrap().to_rgb8(), flat.to_rgb8());
+
+        // Lossy photos stay JPEG
+        let jpeg = jpeg_with_exif(&photo(120, 90), 80);
+        let result = optimizer.optimize_image(&jpeg, None).await.unwrap();
+        assert_eq!(result.format, ImageFormat::Jpeg);
+
+        // GIFs are left alone unless converted
+        let gif = encoded(&flat, image::ImageFormat::Gif);
+        let result = optimizer.optimize_image(&gif, None).await.unwrap();
+        assert_eq!(result.data, gif);
+        let png = optimizer.convert(&gif, ImageFormat::Png).await.unwrap();
+        assert_eq!(image::guess_format(&png).unwrap(), image::ImageFormat::Png);
+    }
+
+    #[tokio::test]
+    async fn test_ssim_target() {
+        let settings = |target_ssim| OptimizationSettings {
+            target_ssim,
+            min_quality: 10,
+            max_quality: 95,
+            ..OptimizationSettings::default()
+        };
+        let flat = DynamicImage::ImageRgb8(RgbImage::from_fn(96, 96, |x, _| Rgb([(x * 2) as u8, 90, 160])));
+        let png = encoded(&flat, image::ImageFormat::Png);
+
+        // A loose target settles on a lower quality than a strict one
+        let (_dir, optimizer) = service(settings(Some(0.9)));
+        let loose = optimizer.optimize_image(&png, Some(ImageFormat::Jpeg)).await.unwrap();
+        let (_dir, optimizer) = service(settings(Some(0.995)));
+        let strict = optimizer.optimize_image(&png, Some(ImageFormat::Jpeg)).await.unwrap();
+
+        assert!(loose.ssim.unwrap() >= 0.9);
+        assert!(strict.ssim.unwrap() >= 0.995 || strict.quality == Some(95));
+        assert!(loose.quality.unwrap() < strict.quality.unwrap());
+        assert!(loose.optimized_size <= strict.optimized_size);
+
+        let report = loose.result();
+        assert_eq!(report.quality, loose.quality);
+        assert_eq!(report.ssim, loose.ssim);
+
+        // Unreachable targets end at the maximum after one encode
+        let (_dir, optimizer) = service(OptimizationSettings { max_iterations: 1, ..settings(Some(1.0)) });
+        let capped = optimizer.optimize_image(&png, Some(ImageFormat::Jpeg)).await.unwrap();
+        assert_eq!(capped.quality, Some(95));
+
+        // Without a target the fixed quality is used
+        let (_dir, optimizer) = service(settings(None));
+        let fixed = optimizer.optimize_image(&png, Some(ImageFormat::Jpeg)).await.unwrap();
+        assert_eq!((fixed.quality, fixed.ssim), (Some(85), None));
+    }
+
+    #[test]
+    fn test_strip_rejects_malformed() {
+        assert!(strip_jpeg(&[0xFF, 0xD8, 0xFF, 0xE1, 0x00]).is_none());
+        assert!(strip_png(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR").is_none());
+        assert!(strip_metadata(b"GIF89a", ImageFormat::Gif).is_none());
+    }
 }
@@ -0,0 +1,101 @@
+//! Upload Pipeline
+//!
+//! Bookkeeping for multi-step uploads: every file a step writes is
+//! recorded, so a later failure can remove them all again.
+
+use std::sync::Arc;
+
+use crate::models::UploadStep;
+use super::media::MediaError;
+use super::storage::StorageService;
+
+/// Compensating actions for the completed steps of an upload
+///
+/// Dropping it without [`Rollback::commit`] (e.g. when the upload is
+/// cancelled) removes the recorded files in the background.
+pub struct Rollback {
+    storage: Arc<StorageService>,
+    /// Files written so far, oldest first
+    written: Vec<String>,
+}
+
+impl Rollback {
+    /// Start tracking an upload
+    pub fn new(storage: Arc<StorageService>) -> Self {
+        Self { storage, written: Vec::new() }
+    }
+
+    /// Record a file written by a step
+    pub fn record(&mut self, path: impl Into<String>) {
+        self.written.push(path.into());
+    }
+
+    /// Remove every recorded file, newest first
+    ///
+    /// Failures are logged; the upload error is what the caller reports.
+    pub async fn undo(mut self) {
+        remove(&self.storage, std::mem::take(&mut self.written)).await;
+    }
+
+    /// Keep the recorded files
+    pub fn commit(mut self) {
+        self.written.clear();
+    }
+}
+
+impl Drop for Rollback {
+    fn drop(&mut self) {
+        if self.written.is_empty() {
+            return;
+        }
+        let storage = Arc::clone(&self.storage);
+        let written = std::mem::take(&mut self.written);
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move { remove(&storage, written).await });
+        }
+    }
+}
+
+async fn remove(storage: &StorageService, written: Vec<String>) {
+    for path in written.iter().rev() {
+        if let Err(e) = storage.delete(path).await {
+            tracing::error!("Rollback could not remove {}: {}", path, e);
+        }
+    }
+}
+
+/// Attach the failing step to an error
+pub fn at_step<T, E: Into<MediaError>>(step: UploadStep, result: Result<T, E>) -> Result<T, MediaError> {
+    result.map_err(|e| MediaError::Step { step, source: Box::new(e.into()) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_dropped_rollback_undoes() {
+        let dir = tempdir().unwrap();
+        let storage = Arc::new(StorageService::new(dir.path().to_path_buf(), "/uploads"));
+        storage.write("kept.txt", b"a").await.unwrap();
+        storage.write("dropped.txt", b"b").await.unwrap();
+
+        let mut rollback = Rollback::new(Arc::clone(&storage));
+        rollback.record("kept.txt");
+        rollback.commit();
+
+        let mut rollback = Rollback::new(Arc::clone(&storage));
+        rollback.record("dropped.txt");
+        drop(rollback);
+
+        for _ in 0..100 {
+            if !storage.exists("dropped.txt").await {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(!storage.exists("dropped.txt").await);
+        assert!(storage.exists("kept.txt").await);
+    }
+}
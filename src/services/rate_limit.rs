@@ -0,0 +1,428 @@
+//! Upload Rate Limiting
+//!
+//! Token buckets per user for files per minute and bytes per hour, plus a
+//! cap on concurrent chunked upload sessions. Roles can override the
+//! default limits.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+/// Suggested wait when the session cap is reached (sessions end on the
+/// client's schedule, so there is no exact value)
+const SESSION_RETRY_AFTER_SECS: u64 = 60;
+
+/// How often buckets that refilled completely are dropped
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Rate limit rejection
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{limit} limit reached, retry after {retry_after} seconds")]
+pub struct RateLimited {
+    /// Limit that was hit (`files`, `bytes` or `sessions`)
+    pub limit: &'static str,
+    /// Seconds until the request can succeed
+    pub retry_after: u64,
+}
+
+/// Upload limits (0 = unlimited)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimits {
+    /// Files per minute
+    pub files_per_minute: u32,
+    /// Bytes per hour
+    pub bytes_per_hour: u64,
+    /// Chunked upload sessions open at once
+    pub concurrent_sessions: u32,
+}
+
+impl RateLimits {
+    /// No limits at all
+    pub const UNLIMITED: Self = Self { files_per_minute: 0, bytes_per_hour: 0, concurrent_sessions: 0 };
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            files_per_minute: 60,
+            bytes_per_hour: 2 * 1024 * 1024 * 1024, // 2GB
+            concurrent_sessions: 5,
+        }
+    }
+}
+
+/// Rate limit settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RateLimitSettings {
+    /// Limits for users without an overriding role (and anonymous uploads)
+    pub default: RateLimits,
+    /// Limits by role name
+    pub roles: HashMap<String, RateLimits>,
+}
+
+/// Refilling token bucket
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn full(capacity: f64) -> Self {
+        Self { tokens: capacity, updated: Instant::now() }
+    }
+
+    /// Tokens available after refilling up to `capacity`
+    fn refill(&mut self, capacity: f64, per_second: f64, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_second).min(capacity);
+        self.updated = now;
+        self.tokens
+    }
+}
+
+/// Buckets of one user
+#[derive(Debug, Clone)]
+struct UserState {
+    files: TokenBucket,
+    bytes: TokenBucket,
+    /// When both buckets are full again, after which the state can go
+    full_at: Instant,
+}
+
+/// Current limits and usage of one user, for the dashboard
+#[derive(Debug, Clone, Serialize)]
+pub struct RateLimitStatus {
+    /// User (`None` for anonymous uploads)
+    pub user_id: Option<Uuid>,
+    /// Role whose limits apply
+    pub role: Option<String>,
+    /// Limits in effect
+    pub limits: RateLimits,
+    /// Files that can be uploaded right now
+    pub files_available: u32,
+    /// Bytes that can be uploaded right now
+    pub bytes_available: u64,
+    /// Open chunked upload sessions
+    pub active_sessions: u32,
+}
+
+/// Per-user upload rate limiter
+pub struct RateLimiter {
+    /// Limits (`None` when rate limiting is off)
+    settings: RwLock<Option<RateLimitSettings>>,
+    /// Role of each user, as assigned by the host application
+    roles: Arc<RwLock<HashMap<Uuid, String>>>,
+    /// Buckets by user
+    users: Arc<RwLock<HashMap<Option<Uuid>, UserState>>>,
+    /// Last time full buckets were dropped
+    pruned: RwLock<Instant>,
+    /// Open chunked sessions (upload ID to user)
+    sessions: Arc<RwLock<HashMap<Uuid, Option<Uuid>>>>,
+}
+
+impl RateLimiter {
+    /// Create a rate limiter
+    pub fn new(settings: RateLimitSettings) -> Self {
+        Self::with_settings(Some(settings))
+    }
+
+    /// Create a rate limiter that limits nothing until enabled
+    pub fn disabled() -> Self {
+        Self::with_settings(None)
+    }
+
+    fn with_settings(settings: Option<RateLimitSettings>) -> Self {
+        Self {
+            settings: RwLock::new(settings),
+            roles: Arc::new(RwLock::new(HashMap::new())),
+            users: Arc::new(RwLock::new(HashMap::new())),
+            pruned: RwLock::new(Instant::now()),
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Replace the limits (`None` turns rate limiting off)
+    ///
+    /// Buckets start full under the new limits; open sessions still count.
+    pub async fn update_settings(&self, settings: Option<RateLimitSettings>) {
+        *self.settings.write().await = settings;
+        self.users.write().await.clear();
+    }
+
+    /// Check if rate limiting is on
+    pub async fn is_enabled(&self) -> bool {
+        self.settings.read().await.is_some()
+    }
+
+    /// Set the role whose limits apply to a user
+    pub async fn assign_role(&self, user_id: Uuid, role: impl Into<String>) {
+        self.roles.write().await.insert(user_id, role.into());
+    }
+
+    /// Limits in effect for a user, with the overriding role
+    pub async fn limits_for(&self, user_id: Option<Uuid>) -> (Option<String>, RateLimits) {
+        let role = match user_id {
+            Some(id) => self.roles.read().await.get(&id).cloned(),
+            None => None,
+        };
+        let settings = self.settings.read().await;
+        let Some(settings) = settings.as_ref() else {
+            return (role, RateLimits::UNLIMITED);
+        };
+        match role.as_ref().and_then(|r| settings.roles.get(r)) {
+            Some(limits) => (role, *limits),
+            None => (role, settings.default),
+        }
+    }
+
+    /// Charge one file of `size` bytes
+    ///
+    /// Nothing is charged when either bucket is short. A file larger than
+    /// the hourly allowance is accepted once the bucket is full, leaving it
+    /// in debt.
+    pub async fn acquire(&self, user_id: Option<Uuid>, size: u64) -> Result<(), RateLimited> {
+        let (_, limits) = self.limits_for(user_id).await;
+        let now = Instant::now();
+
+        let mut users = self.users.write().await;
+        self.prune(&mut users, now).await;
+        let state = users.entry(user_id).or_insert_with(|| UserState {
+            files: TokenBucket::full(limits.files_per_minute as f64),
+            bytes: TokenBucket::full(limits.bytes_per_hour as f64),
+            full_at: now,
+        });
+
+        let files = Bucket::new(limits.files_per_minute as f64, 60.0);
+        let bytes = Bucket::new(limits.bytes_per_hour as f64, 3600.0);
+
+        files.check(&mut state.files, 1.0, now).map_err(|retry_after| RateLimited { limit: "files", retry_after })?;
+        bytes.check(&mut state.bytes, size as f64, now).map_err(|retry_after| RateLimited { limit: "bytes", retry_after })?;
+
+        if files.limited() {
+            state.files.tokens -= 1.0;
+        }
+        if bytes.limited() {
+            state.bytes.tokens -= size as f64;
+        }
+        state.full_at = now + files.time_to_full(&state.files).max(bytes.time_to_full(&state.bytes));
+
+        Ok(())
+    }
+
+    /// Drop users whose buckets have refilled completely
+    ///
+    /// A full bucket is what a new user starts with, so nothing is lost.
+    /// Runs at most once per [`PRUNE_INTERVAL`].
+    async fn prune(&self, users: &mut HashMap<Option<Uuid>, UserState>, now: Instant) {
+        let mut pruned = self.pruned.write().await;
+        if now.saturating_duration_since(*pruned) < PRUNE_INTERVAL {
+            return;
+        }
+        *pruned = now;
+        users.retain(|_, state| state.full_at > now);
+    }
+
+    /// Open a chunked upload session
+    pub async fn open_session(&self, user_id: Option<Uuid>, upload_id: Uuid) -> Result<(), RateLimited> {
+        let (_, limits) = self.limits_for(user_id).await;
+
+        let mut sessions = self.sessions.write().await;
+        let open = sessions.values().filter(|u| **u == user_id).count() as u32;
+        if limits.concurrent_sessions > 0 && open >= limits.concurrent_sessions {
+            return Err(RateLimited { limit: "sessions", retry_after: SESSION_RETRY_AFTER_SECS });
+        }
+
+        sessions.insert(upload_id, user_id);
+        Ok(())
+    }
+
+    /// Close a chunked upload session (completed, cancelled or expired)
+    pub async fn close_session(&self, upload_id: Uuid) {
+        self.sessions.write().await.remove(&upload_id);
+    }
+
+    /// Usage of every user with recent uploads or open sessions
+    pub async fn status(&self) -> Vec<RateLimitStatus> {
+        let now = Instant::now();
+        let sessions = self.sessions.read().await.clone();
+        let mut users = self.users.write().await;
+
+        let mut ids: Vec<Option<Uuid>> = users.keys().copied().collect();
+        for user_id in sessions.values() {
+            if !ids.contains(user_id) {
+                ids.push(*user_id);
+            }
+        }
+
+        let mut status = Vec::with_capacity(ids.len());
+        for user_id in ids {
+            let (role, limits) = self.limits_for(user_id).await;
+            let (files_available, bytes_available) = match users.get_mut(&user_id) {
+                Some(state) => (
+                    Bucket::new(limits.files_per_minute as f64, 60.0).available(&mut state.files, now),
+                    Bucket::new(limits.bytes_per_hour as f64, 3600.0).available(&mut state.bytes, now),
+                ),
+                None => (limits.files_per_minute as f64, limits.bytes_per_hour as f64),
+            };
+
+            status.push(RateLimitStatus {
+                user_id,
+                role,
+                limits,
+                files_available: files_available as u32,
+                bytes_available: bytes_available as u64,
+                active_sessions: sessions.values().filter(|u| **u == user_id).count() as u32,
+            });
+        }
+
+        status.sort_by(|a, b| a.files_available.cmp(&b.files_available).then(a.user_id.cmp(&b.user_id)));
+        status
+    }
+}
+
+/// Capacity and refill rate of a limit
+struct Bucket {
+    capacity: f64,
+    per_second: f64,
+}
+
+impl Bucket {
+    /// `capacity` tokens refilled over `period` seconds
+    fn new(capacity: f64, period: f64) -> Self {
+        Self { capacity, per_second: capacity / period }
+    }
+
+    fn limited(&self) -> bool {
+        self.capacity > 0.0
+    }
+
+    fn available(&self, bucket: &mut TokenBucket, now: Instant) -> f64 {
+        if !self.limited() {
+            return f64::INFINITY;
+        }
+        bucket.refill(self.capacity, self.per_second, now).max(0.0)
+    }
+
+    /// Time until `bucket` is full again
+    fn time_to_full(&self, bucket: &TokenBucket) -> Duration {
+        if !self.limited() {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(((self.capacity - bucket.tokens) / self.per_second).max(0.0))
+    }
+
+    /// Check that `amount` tokens (at most a full bucket) are available,
+    /// or return the seconds to wait
+    fn check(&self, bucket: &mut TokenBucket, amount: f64, now: Instant) -> Result<(), u64> {
+        if !self.limited() {
+            return Ok(());
+        }
+
+        let needed = amount.min(self.capacity);
+        let tokens = bucket.refill(self.capacity, self.per_second, now);
+        if tokens >= needed {
+            return Ok(());
+        }
+
+        Err(((needed - tokens) / self.per_second).ceil().max(1.0) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> RateLimiter {
+        let mut roles = HashMap::new();
+        roles.insert("editor".to_string(), RateLimits { files_per_minute: 0, bytes_per_hour: 0, concurrent_sessions: 3 });
+        RateLimiter::new(RateLimitSettings {
+            default: RateLimits { files_per_minute: 2, bytes_per_hour: 1000, concurrent_sessions: 1 },
+            roles,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_token_buckets() {
+        let limiter = limiter();
+        let user = Some(Uuid::new_v4());
+
+        limiter.acquire(user, 10).await.unwrap();
+        limiter.acquire(user, 10).await.unwrap();
+        let err = limiter.acquire(user, 10).await.unwrap_err();
+        assert_eq!(err.limit, "files");
+        assert!((29..=30).contains(&err.retry_after));
+
+        // Buckets are per user; an oversized file drains a full bucket
+        let other = Some(Uuid::new_v4());
+        limiter.acquire(other, 5000).await.unwrap();
+        let err = limiter.acquire(other, 1).await.unwrap_err();
+        assert_eq!(err.limit, "bytes");
+        assert!(err.retry_after > 3600);
+
+        let status = limiter.status().await;
+        let mine = status.iter().find(|s| s.user_id == user).unwrap();
+        assert_eq!((mine.files_available, mine.bytes_available), (0, 980));
+    }
+
+    #[tokio::test]
+    async fn test_sessions_and_roles() {
+        let limiter = limiter();
+        let user = Uuid::new_v4();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+
+        limiter.open_session(Some(user), a).await.unwrap();
+        let err = limiter.open_session(Some(user), b).await.unwrap_err();
+        assert_eq!(err.limit, "sessions");
+
+        limiter.close_session(a).await;
+        limiter.open_session(Some(user), b).await.unwrap();
+
+        // Role overrides lift the file and byte limits
+        limiter.assign_role(user, "editor").await;
+        limiter.open_session(Some(user), a).await.unwrap();
+        for _ in 0..10 {
+            limiter.acquire(Some(user), 10_000).await.unwrap();
+        }
+        let status = limiter.status().await;
+        assert_eq!(status[0].role.as_deref(), Some("editor"));
+        assert_eq!(status[0].active_sessions, 2);
+    }
+
+    #[tokio::test]
+    async fn test_full_buckets_dropped() {
+        let limiter = limiter();
+        let (idle, in_debt) = (Some(Uuid::new_v4()), Some(Uuid::new_v4()));
+
+        limiter.acquire(idle, 10).await.unwrap();
+        limiter.acquire(in_debt, 5000).await.unwrap();
+
+        // An hour refills the idle user, not the 4000 bytes of debt
+        let mut users = limiter.users.write().await;
+        limiter.prune(&mut users, Instant::now() + Duration::from_secs(3600)).await;
+        assert!(!users.contains_key(&idle));
+        assert!(users.contains_key(&in_debt));
+    }
+
+    #[tokio::test]
+    async fn test_update_settings() {
+        let limiter = limiter();
+        let user = Some(Uuid::new_v4());
+
+        limiter.update_settings(None).await;
+        assert!(!limiter.is_enabled().await);
+        for _ in 0..5 {
+            limiter.acquire(user, 10).await.unwrap();
+        }
+
+        limiter.update_settings(Some(RateLimitSettings {
+            default: RateLimits { files_per_minute: 1, bytes_per_hour: 0, concurrent_sessions: 0 },
+            roles: HashMap::new(),
+        })).await;
+        limiter.acquire(user, 10).await.unwrap();
+        assert_eq!(limiter.acquire(user, 10).await.unwrap_err().limit, "files");
+    }
+}
@@ -0,0 +1,419 @@
+//! Malware Scanning
+//!
+//! [`VirusScanner`] abstraction and a ClamAV `clamd` client using the
+//! INSTREAM command over TCP or a Unix socket.
+
+use std::path::PathBuf;
+use std::time::Duration;
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Scanner error
+#[derive(Debug, thiserror::Error)]
+pub enum ScanError {
+    #[error("Invalid scanner address: {0}")]
+    InvalidAddress(String),
+    #[error("Scanner unavailable: {0}")]
+    Unavailable(String),
+    #[error("Scan timed out")]
+    Timeout,
+    #[error("Scanner error: {0}")]
+    Protocol(String),
+    /// Never accepted unscanned, whatever the failure policy
+    #[error("File exceeds the scanner's size limit")]
+    TooLarge,
+}
+
+/// Scan result
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanVerdict {
+    /// No threat found
+    Clean,
+    /// Threat found (signature name)
+    Infected(String),
+}
+
+/// What to do when the scanner cannot give a verdict
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScanFailurePolicy {
+    /// Accept the file
+    FailOpen,
+    /// Reject the file
+    #[default]
+    FailClosed,
+}
+
+/// Scan settings for uploads
+#[derive(Debug, Clone)]
+pub struct ScanSettings {
+    /// Behaviour on scanner outages
+    pub on_failure: ScanFailurePolicy,
+    /// Keep infected files instead of discarding them
+    pub quarantine: bool,
+    /// Storage directory for quarantined files
+    pub quarantine_dir: String,
+}
+
+impl Default for ScanSettings {
+    fn default() -> Self {
+        Self {
+            on_failure: ScanFailurePolicy::FailClosed,
+            quarantine: true,
+            quarantine_dir: "quarantine".to_string(),
+        }
+    }
+}
+
+/// Malware scanner
+#[async_trait]
+pub trait VirusScanner: Send + Sync {
+    /// Scanner name (used in logs)
+    fn name(&self) -> &str;
+
+    /// Scan a stream of bytes
+    async fn scan_reader(&self, reader: &mut (dyn AsyncRead + Unpin + Send)) -> Result<ScanVerdict, ScanError>;
+
+    /// Scan in-memory contents
+    async fn scan(&self, data: &[u8]) -> Result<ScanVerdict, ScanError> {
+        let mut reader = data;
+        self.scan_reader(&mut reader).await
+    }
+}
+
+/// clamd address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClamdEndpoint {
+    /// `host:port`
+    Tcp(String),
+    /// Unix socket path
+    Unix(PathBuf),
+}
+
+impl ClamdEndpoint {
+    /// Parse `tcp://host:port`, `host:port`, `unix:///path` or `/path`
+    pub fn parse(address: &str) -> Result<Self, ScanError> {
+        let address = address.trim();
+        let invalid = || ScanError::InvalidAddress(address.to_string());
+
+        if let Some(path) = address.strip_prefix("unix://").or_else(|| address.strip_prefix("unix:")) {
+            if path.is_empty() {
+                return Err(invalid());
+            }
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        if address.starts_with('/') {
+            return Ok(Self::Unix(PathBuf::from(address)));
+        }
+
+        let host_port = address.strip_prefix("tcp://").unwrap_or(address);
+        match host_port.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                Ok(Self::Tcp(host_port.to_string()))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// ClamAV daemon client
+pub struct ClamdScanner {
+    endpoint: ClamdEndpoint,
+    /// Time limit for a whole scan, including connecting
+    timeout: Duration,
+    /// Size of each INSTREAM chunk
+    chunk_size: usize,
+}
+
+impl ClamdScanner {
+    /// Create a client for a clamd endpoint
+    pub fn new(endpoint: ClamdEndpoint) -> Self {
+        Self {
+            endpoint,
+            timeout: Duration::from_secs(30),
+            chunk_size: 64 * 1024,
+        }
+    }
+
+    /// Create a client from an address string
+    pub fn from_address(address: &str) -> Result<Self, ScanError> {
+        Ok(Self::new(ClamdEndpoint::parse(address)?))
+    }
+
+    /// Set the scan timeout
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Check that clamd answers `PING`
+    pub async fn ping(&self) -> Result<(), ScanError> {
+        let response = tokio::time::timeout(self.timeout, async {
+            match &self.endpoint {
+                ClamdEndpoint::Tcp(address) => {
+                    let stream = TcpStream::connect(address).await.map_err(unavailable)?;
+                    Self::command(stream, b"zPING\0").await
+                }
+                #[cfg(unix)]
+                ClamdEndpoint::Unix(path) => {
+                    let stream = tokio::net::UnixStream::connect(path).await.map_err(unavailable)?;
+                    Self::command(stream, b"zPING\0").await
+                }
+                #[cfg(not(unix))]
+                ClamdEndpoint::Unix(path) => Err(ScanError::InvalidAddress(path.display().to_string())),
+            }
+        })
+        .await
+        .map_err(|_| ScanError::Timeout)??;
+
+        match response.as_str() {
+            "PONG" => Ok(()),
+            other => Err(ScanError::Protocol(other.to_string())),
+        }
+    }
+
+    async fn command<S>(mut stream: S, command: &[u8]) -> Result<String, ScanError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        stream.write_all(command).await.map_err(unavailable)?;
+        Self::read_reply(&mut stream).await
+    }
+
+    /// Send the INSTREAM command followed by length-prefixed chunks
+    async fn instream<S>(
+        &self,
+        mut stream: S,
+        reader: &mut (dyn AsyncRead + Unpin + Send),
+    ) -> Result<ScanVerdict, ScanError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let sent = async {
+            stream.write_all(b"zINSTREAM\0").await?;
+
+            let mut buf = vec![0u8; self.chunk_size];
+            loop {
+                let n = reader.read(&mut buf).await?;
+                if n == 0 {
+                    break;
+                }
+                stream.write_all(&(n as u32).to_be_bytes()).await?;
+                stream.write_all(&buf[..n]).await?;
+            }
+
+            stream.write_all(&0u32.to_be_bytes()).await?;
+            stream.flush().await
+        }.await;
+
+        // clamd replies and closes early when the stream exceeds its
+        // StreamMaxLength, so prefer its answer over our write error
+        let reply = Self::read_reply(&mut stream).await;
+        match (sent, reply) {
+            (_, Ok(reply)) if !reply.is_empty() => parse_reply(&reply),
+            (Err(e), _) => Err(unavailable(e)),
+            (Ok(()), Err(e)) => Err(e),
+            (Ok(()), Ok(_)) => Err(ScanError::Protocol("empty reply".to_string())),
+        }
+    }
+
+    async fn read_reply<S: AsyncRead + Unpin>(stream: &mut S) -> Result<String, ScanError> {
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).await.map_err(unavailable)?;
+        Ok(String::from_utf8_lossy(&reply)
+            .trim_end_matches(['\0', '\n'])
+            .trim()
+            .to_string())
+    }
+}
+
+#[async_trait]
+impl VirusScanner for ClamdScanner {
+    fn name(&self) -> &str {
+        "clamd"
+    }
+
+    async fn scan_reader(&self, reader: &mut (dyn AsyncRead + Unpin + Send)) -> Result<ScanVerdict, ScanError> {
+        tokio::time::timeout(self.timeout, async {
+            match &self.endpoint {
+                ClamdEndpoint::Tcp(address) => {
+                    let stream = TcpStream::connect(address).await.map_err(unavailable)?;
+                    self.instream(stream, reader).await
+                }
+                #[cfg(unix)]
+                ClamdEndpoint::Unix(path) => {
+                    let stream = tokio::net::UnixStream::connect(path).await.map_err(unavailable)?;
+                    self.instream(stream, reader).await
+                }
+                #[cfg(not(unix))]
+                ClamdEndpoint::Unix(path) => Err(ScanError::InvalidAddress(path.display().to_string())),
+            }
+        })
+        .await
+        .map_err(|_| ScanError::Timeout)?
+    }
+}
+
+/// Stand-in for a scanner that could not be set up
+///
+/// Every scan fails, so the failure policy decides: uploads are rejected
+/// when failing closed instead of going through unscanned.
+pub struct UnavailableScanner {
+    reason: String,
+}
+
+impl UnavailableScanner {
+    /// Create a scanner failing with `reason`
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self { reason: reason.into() }
+    }
+}
+
+#[async_trait]
+impl VirusScanner for UnavailableScanner {
+    fn name(&self) -> &str {
+        "unavailable"
+    }
+
+    async fn scan_reader(&self, _reader: &mut (dyn AsyncRead + Unpin + Send)) -> Result<ScanVerdict, ScanError> {
+        Err(ScanError::Unavailable(self.reason.clone()))
+    }
+}
+
+/// Parse a clamd INSTREAM reply (`stream: OK`, `stream: <name> FOUND`)
+fn parse_reply(reply: &str) -> Result<ScanVerdict, ScanError> {
+    let body = reply.strip_prefix("stream:").map(str::trim).unwrap_or(reply);
+
+    if body == "OK" {
+        Ok(ScanVerdict::Clean)
+    } else if body.starts_with("INSTREAM size limit exceeded") {
+        Err(ScanError::TooLarge)
+    } else if let Some(signature) = body.strip_suffix("FOUND") {
+        Ok(ScanVerdict::Infected(signature.trim().to_string()))
+    } else {
+        Err(ScanError::Protocol(body.to_string()))
+    }
+}
+
+fn unavailable(error: std::io::Error) -> ScanError {
+    ScanError::Unavailable(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    const EICAR_MARKER: &[u8] = b"EICAR-STANDARD-ANTIVIRUS-TEST-FILE";
+
+    /// Answer one INSTREAM request the way clamd does
+    async fn answer<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S) {
+        let mut command = Vec::new();
+        loop {
+            let byte = stream.read_u8().await.unwrap();
+            command.push(byte);
+            if byte == 0 {
+                break;
+            }
+        }
+
+        if command == b"zPING\0" {
+            stream.write_all(b"PONG\0").await.unwrap();
+            return;
+        }
+        assert_eq!(command, b"zINSTREAM\0");
+
+        let mut received = Vec::new();
+        loop {
+            let len = stream.read_u32().await.unwrap() as usize;
+            if len == 0 {
+                break;
+            }
+            let mut chunk = vec![0u8; len];
+            stream.read_exact(&mut chunk).await.unwrap();
+            received.extend_from_slice(&chunk);
+        }
+
+        let infected = received.windows(EICAR_MARKER.len()).any(|w| w == EICAR_MARKER);
+        let reply: &[u8] = if infected {
+            b"stream: Eicar-Test-Signature FOUND\0"
+        } else {
+            b"stream: OK\0"
+        };
+        stream.write_all(reply).await.unwrap();
+    }
+
+    async fn fake_clamd() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(answer(stream));
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn test_parse_endpoint() {
+        assert_eq!(ClamdEndpoint::parse("tcp://localhost:3310").unwrap(), ClamdEndpoint::Tcp("localhost:3310".to_string()));
+        assert_eq!(ClamdEndpoint::parse("127.0.0.1:3310").unwrap(), ClamdEndpoint::Tcp("127.0.0.1:3310".to_string()));
+        assert_eq!(ClamdEndpoint::parse("unix:///run/clamd.ctl").unwrap(), ClamdEndpoint::Unix("/run/clamd.ctl".into()));
+        assert_eq!(ClamdEndpoint::parse("/run/clamd.ctl").unwrap(), ClamdEndpoint::Unix("/run/clamd.ctl".into()));
+        assert!(ClamdEndpoint::parse("localhost").is_err());
+        assert!(ClamdEndpoint::parse("unix:").is_err());
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        assert_eq!(parse_reply("stream: OK").unwrap(), ScanVerdict::Clean);
+        assert_eq!(
+            parse_reply("stream: Win.Test.EICAR_HDB-1 FOUND").unwrap(),
+            ScanVerdict::Infected("Win.Test.EICAR_HDB-1".to_string())
+        );
+        assert!(matches!(
+            parse_reply("INSTREAM size limit exceeded. ERROR"),
+            Err(ScanError::TooLarge)
+        ));
+        assert!(matches!(parse_reply("UNKNOWN COMMAND"), Err(ScanError::Protocol(_))));
+    }
+
+    #[tokio::test]
+    async fn test_instream_tcp() {
+        let address = fake_clamd().await;
+        let scanner = ClamdScanner::from_address(&address).unwrap();
+
+        scanner.ping().await.unwrap();
+        assert_eq!(scanner.scan(b"hello world").await.unwrap(), ScanVerdict::Clean);
+
+        // Marker split across INSTREAM chunks
+        let mut data = vec![b'x'; 64 * 1024 - 10];
+        data.extend_from_slice(b"X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*");
+        assert_eq!(
+            scanner.scan(&data).await.unwrap(),
+            ScanVerdict::Infected("Eicar-Test-Signature".to_string())
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_instream_unix() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clamd.sock");
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            answer(stream).await;
+        });
+
+        let scanner = ClamdScanner::new(ClamdEndpoint::Unix(path));
+        assert_eq!(scanner.scan(b"plain text").await.unwrap(), ScanVerdict::Clean);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable() {
+        // Bind and drop to get a port nothing listens on
+        let address = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().to_string();
+        let scanner = ClamdScanner::from_address(&address).unwrap();
+        assert!(matches!(scanner.scan(b"data").await, Err(ScanError::Unavailable(_))));
+    }
+}
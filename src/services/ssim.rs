@@ -0,0 +1,102 @@
+//! SSIM
+//!
+//! Structural similarity between two images of the same size, computed on
+//! luma over 8x8 windows. 1.0 means identical; values above about 0.99 are
+//! hard to tell apart from the original.
+
+use image::{DynamicImage, GrayImage};
+
+/// Window size in pixels
+const WINDOW: u32 = 8;
+/// Distance between windows (they overlap by half)
+const STEP: u32 = 4;
+
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// Mean SSIM of `a` against `b`, or `None` if their sizes differ
+///
+/// Transparency is ignored: both images are compared as opaque.
+pub fn ssim(a: &DynamicImage, b: &DynamicImage) -> Option<f64> {
+    if a.width() != b.width() || a.height() != b.height() || a.width() == 0 || a.height() == 0 {
+        return None;
+    }
+
+    // Rec. 601 luma, as JPEG uses
+    let (a, b) = (DynamicImage::ImageRgb8(a.to_rgb8()).to_luma8(), DynamicImage::ImageRgb8(b.to_rgb8()).to_luma8());
+
+    // Images smaller than a window are one window
+    let (width, height) = (WINDOW.min(a.width()), WINDOW.min(a.height()));
+    let mut total = 0.0;
+    let mut windows = 0u32;
+
+    for y in starts(a.height(), height) {
+        for x in starts(a.width(), width) {
+            total += window(&a, &b, x, y, width, height);
+            windows += 1;
+        }
+    }
+
+    Some(total / f64::from(windows))
+}
+
+/// Window origins along one axis, with the last window flush to the edge
+fn starts(length: u32, window: u32) -> impl Iterator<Item = u32> {
+    let last = length - window;
+    (0..=last).step_by(STEP as usize).chain((!last.is_multiple_of(STEP)).then_some(last))
+}
+
+fn window(a: &GrayImage, b: &GrayImage, x0: u32, y0: u32, width: u32, height: u32) -> f64 {
+    let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+
+    for y in y0..y0 + height {
+        for x in x0..x0 + width {
+            let pa = f64::from(a.get_pixel(x, y)[0]);
+            let pb = f64::from(b.get_pixel(x, y)[0]);
+            sum_a += pa;
+            sum_b += pb;
+            sum_aa += pa * pa;
+            sum_bb += pb * pb;
+            sum_ab += pa * pb;
+        }
+    }
+
+    let n = f64::from(width * height);
+    let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+    let var_a = sum_aa / n - mean_a * mean_a;
+    let var_b = sum_bb / n - mean_b * mean_b;
+    let covariance = sum_ab / n - mean_a * mean_b;
+
+    ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+        / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Luma, RgbImage};
+
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| Luma([((x * 3 + y * 5) % 256) as u8])))
+    }
+
+    #[test]
+    fn test_ssim() {
+        let image = gradient(37, 21);
+        assert!((ssim(&image, &image).unwrap() - 1.0).abs() < 1e-9);
+
+        // Small noise scores high, an unrelated image low
+        let mut noisy = image.to_luma8();
+        for (i, pixel) in noisy.pixels_mut().enumerate() {
+            pixel[0] = pixel[0].saturating_add((i % 3) as u8);
+        }
+        let noisy = ssim(&image, &DynamicImage::ImageLuma8(noisy)).unwrap();
+        let flat = ssim(&image, &DynamicImage::ImageRgb8(RgbImage::new(37, 21))).unwrap();
+        assert!(noisy > 0.9 && noisy < 1.0);
+        assert!(flat < 0.1);
+
+        // Tiny and mismatched images
+        assert!(ssim(&gradient(3, 2), &gradient(3, 2)).unwrap() > 0.999);
+        assert_eq!(ssim(&image, &gradient(36, 21)), None);
+    }
+}
@@ -4,8 +4,13 @@
 
 use std::path::{Path, PathBuf};
 use tokio::fs;
+use tokio::sync::RwLock;
 use chrono::Utc;
 use sha2::{Sha256, Digest};
+use crate::models::{CollisionStrategy, FilenamePolicy};
+
+/// Attempts before giving up on finding a free name
+const MAX_NAME_ATTEMPTS: u32 = 1000;
 
 /// Storage error
 #[derive(Debug, thiserror::Error)]
@@ -29,11 +34,13 @@ pub struct StorageService {
     /// Base URL for uploads
     base_url: String,
     /// Maximum file size in bytes
-    max_file_size: u64,
+    max_file_size: RwLock<u64>,
     /// Allowed MIME types (empty = all)
     allowed_types: Vec<String>,
     /// Organize by date
     organize_by_date: bool,
+    /// Naming of stored files
+    filename_policy: RwLock<FilenamePolicy>,
 }
 
 impl StorageService {
@@ -42,9 +49,10 @@ impl StorageService {
         Self {
             uploads_dir,
             base_url: base_url.into(),
-            max_file_size: 50 * 1024 * 1024, // 50MB default
+            max_file_size: RwLock::new(50 * 1024 * 1024), // 50MB default
             allowed_types: Vec::new(),
             organize_by_date: true,
+            filename_policy: RwLock::new(FilenamePolicy::default()),
         }
     }
 
@@ -56,7 +64,12 @@ impl StorageService {
 
     /// Set maximum file size
     pub fn set_max_size(&mut self, size: u64) {
-        self.max_file_size = size;
+        *self.max_file_size.get_mut() = size;
+    }
+
+    /// Replace the maximum file size of a running service
+    pub async fn update_max_size(&self, size: u64) {
+        *self.max_file_size.write().await = size;
     }
 
     /// Set allowed MIME types
@@ -64,6 +77,16 @@ impl StorageService {
         self.allowed_types = types;
     }
 
+    /// Set the filename policy
+    pub fn set_filename_policy(&mut self, policy: FilenamePolicy) {
+        *self.filename_policy.get_mut() = policy;
+    }
+
+    /// Replace the filename policy of a running service
+    pub async fn update_filename_policy(&self, policy: FilenamePolicy) {
+        *self.filename_policy.write().await = policy;
+    }
+
     /// Store a file
     pub async fn store(
         &self,
@@ -73,7 +96,7 @@ impl StorageService {
     ) -> Result<StoredFile, StorageError> {
         // Check file size
         let size = data.len() as u64;
-        if size > self.max_file_size {
+        if size > *self.max_file_size.read().await {
             return Err(StorageError::FileTooLarge(size));
         }
 
@@ -87,17 +110,15 @@ impl StorageService {
         hasher.update(data);
         let hash = hex::encode(hasher.finalize());
 
-        // Generate path
-        let relative_path = self.generate_path(filename);
+        // Reserve a path
+        let relative_path = self.claim_path(filename, Some(&hash)).await?;
         let full_path = self.uploads_dir.join(&relative_path);
 
-        // Create directory if needed
-        if let Some(parent) = full_path.parent() {
-            fs::create_dir_all(parent).await?;
-        }
-
         // Write file
-        fs::write(&full_path, data).await?;
+        if let Err(e) = fs::write(&full_path, data).await {
+            let _ = fs::remove_file(&full_path).await;
+            return Err(e.into());
+        }
 
         // Generate URL
         let url = format!("{}/{}", self.base_url.trim_end_matches('/'), relative_path);
@@ -110,25 +131,231 @@ impl StorageService {
         })
     }
 
+    /// Overwrite a stored file, keeping its path
+    ///
+    /// The new contents are written next to the file and renamed over it,
+    /// so readers never see a partially written file.
+    pub async fn replace(
+        &self,
+        path: &str,
+        data: &[u8],
+        mime_type: &str,
+    ) -> Result<StoredFile, StorageError> {
+        let size = data.len() as u64;
+        if size > *self.max_file_size.read().await {
+            return Err(StorageError::FileTooLarge(size));
+        }
+        if !self.allowed_types.is_empty() && !self.allowed_types.contains(&mime_type.to_string()) {
+            return Err(StorageError::InvalidType(mime_type.to_string()));
+        }
+
+        let full_path = self.uploads_dir.join(path);
+        if !full_path.exists() {
+            return Err(StorageError::NotFound(path.to_string()));
+        }
+
+        let mut temp_path = full_path.clone().into_os_string();
+        temp_path.push(".replacing");
+        let temp_path = PathBuf::from(temp_path);
+
+        fs::write(&temp_path, data).await?;
+        if let Err(e) = fs::rename(&temp_path, &full_path).await {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(e.into());
+        }
+
+        Ok(StoredFile {
+            path: path.to_string(),
+            url: self.url_for(path),
+            size,
+            hash: hex::encode(Sha256::digest(data)),
+        })
+    }
+
+    /// Move a file already in storage into its final location
+    ///
+    /// Used for files assembled in a temporary directory so they are never
+    /// loaded into memory. `hash` is the SHA-256 computed while writing.
+    pub async fn store_file(
+        &self,
+        source: &str,
+        filename: &str,
+        mime_type: &str,
+        hash: String,
+    ) -> Result<StoredFile, StorageError> {
+        let source_path = self.uploads_dir.join(source);
+        if !source_path.exists() {
+            return Err(StorageError::NotFound(source.to_string()));
+        }
+
+        // Check file size
+        let size = fs::metadata(&source_path).await?.len();
+        if size > *self.max_file_size.read().await {
+            return Err(StorageError::FileTooLarge(size));
+        }
+
+        // Check MIME type
+        if !self.allowed_types.is_empty() && !self.allowed_types.contains(&mime_type.to_string()) {
+            return Err(StorageError::InvalidType(mime_type.to_string()));
+        }
+
+        let hint = (!hash.is_empty()).then_some(hash.as_str());
+        let relative_path = self.claim_path(filename, hint).await?;
+        if let Err(e) = self.move_file(source, &relative_path).await {
+            let _ = self.delete(&relative_path).await;
+            return Err(e);
+        }
+
+        let url = format!("{}/{}", self.base_url.trim_end_matches('/'), relative_path);
+
+        Ok(StoredFile {
+            path: relative_path,
+            url,
+            size,
+            hash,
+        })
+    }
+
+    /// Concatenate files into `dest` without buffering them
+    ///
+    /// Each block written is passed to `inspect` (for hashing or sniffing).
+    /// Returns the number of bytes written.
+    pub async fn concatenate<F>(
+        &self,
+        sources: &[String],
+        dest: &str,
+        mut inspect: F,
+    ) -> Result<u64, StorageError>
+    where
+        F: FnMut(&[u8]),
+    {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+
+        let dest_path = self.uploads_dir.join(dest);
+        if let Some(parent) = dest_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        let mut writer = BufWriter::new(fs::File::create(&dest_path).await?);
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut total = 0u64;
+
+        for source in sources {
+            let source_path = self.uploads_dir.join(source);
+            if !source_path.exists() {
+                return Err(StorageError::NotFound(source.clone()));
+            }
+
+            let mut reader = fs::File::open(&source_path).await?;
+            loop {
+                let read = reader.read(&mut buffer).await?;
+                if read == 0 {
+                    break;
+                }
+                inspect(&buffer[..read]);
+                writer.write_all(&buffer[..read]).await?;
+                total += read as u64;
+            }
+        }
+
+        writer.flush().await?;
+        Ok(total)
+    }
+
     /// Store file from path (move or copy)
+    ///
+    /// The file is streamed, never read into memory as a whole. When `copy`
+    /// is false the source is renamed into place where possible.
     pub async fn store_from_path(
         &self,
         source: &Path,
         filename: &str,
         copy: bool,
     ) -> Result<StoredFile, StorageError> {
-        let data = fs::read(source).await?;
+        let size = fs::metadata(source).await?.len();
+        if size > *self.max_file_size.read().await {
+            return Err(StorageError::FileTooLarge(size));
+        }
+
         let mime_type = mime_guess::from_path(source)
             .first_or_octet_stream()
             .to_string();
+        if !self.allowed_types.is_empty() && !self.allowed_types.contains(&mime_type) {
+            return Err(StorageError::InvalidType(mime_type));
+        }
+
+        let relative_path = self.claim_path(filename, None).await?;
+        let full_path = self.uploads_dir.join(&relative_path);
+
+        let hash = if !copy && fs::rename(source, &full_path).await.is_ok() {
+            Self::hash_path(&full_path).await?
+        } else {
+            let hash = match Self::copy_hashed(source, &full_path).await {
+                Ok(hash) => hash,
+                Err(e) => {
+                    let _ = fs::remove_file(&full_path).await;
+                    return Err(e);
+                }
+            };
+            if !copy {
+                let _ = fs::remove_file(source).await;
+            }
+            hash
+        };
+
+        let url = format!("{}/{}", self.base_url.trim_end_matches('/'), relative_path);
+
+        Ok(StoredFile {
+            path: relative_path,
+            url,
+            size,
+            hash,
+        })
+    }
+
+    /// SHA-256 of a file on disk, streamed
+    pub async fn hash_file(&self, source: &Path) -> Result<String, StorageError> {
+        Self::hash_path(source).await
+    }
+
+    async fn hash_path(path: &Path) -> Result<String, StorageError> {
+        use tokio::io::AsyncReadExt;
+
+        let mut reader = fs::File::open(path).await?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 64 * 1024];
+
+        loop {
+            let read = reader.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+
+        Ok(hex::encode(hasher.finalize()))
+    }
 
-        let result = self.store(&data, filename, &mime_type).await?;
+    /// Copy a file, returning the SHA-256 of the copied bytes
+    async fn copy_hashed(source: &Path, dest: &Path) -> Result<String, StorageError> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
 
-        if !copy {
-            let _ = fs::remove_file(source).await;
+        let mut reader = fs::File::open(source).await?;
+        let mut writer = BufWriter::new(fs::File::create(dest).await?);
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 64 * 1024];
+
+        loop {
+            let read = reader.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            writer.write_all(&buffer[..read]).await?;
         }
 
-        Ok(result)
+        writer.flush().await?;
+        Ok(hex::encode(hasher.finalize()))
     }
 
     /// Read file contents
@@ -142,6 +369,56 @@ impl StorageService {
         Ok(fs::read(&full_path).await?)
     }
 
+    /// Write data to a path, replacing any existing file
+    pub async fn write(&self, path: &str, data: &[u8]) -> Result<(), StorageError> {
+        let full_path = self.uploads_dir.join(path);
+
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        fs::write(&full_path, data).await?;
+        Ok(())
+    }
+
+    /// Append data to a file, creating it if needed. Returns the new file size.
+    pub async fn append(&self, path: &str, data: &[u8]) -> Result<u64, StorageError> {
+        use tokio::io::AsyncWriteExt;
+
+        let full_path = self.uploads_dir.join(path);
+
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&full_path)
+            .await?;
+        file.write_all(data).await?;
+        file.flush().await?;
+
+        Ok(file.metadata().await?.len())
+    }
+
+    /// Create a directory (and parents)
+    pub async fn create_directory(&self, path: &str) -> Result<(), StorageError> {
+        fs::create_dir_all(self.uploads_dir.join(path)).await?;
+        Ok(())
+    }
+
+    /// Delete a directory and its contents
+    pub async fn delete_directory(&self, path: &str) -> Result<(), StorageError> {
+        let full_path = self.uploads_dir.join(path);
+
+        if full_path.exists() {
+            fs::remove_dir_all(&full_path).await?;
+        }
+
+        Ok(())
+    }
+
     /// Delete a file
     pub async fn delete(&self, path: &str) -> Result<(), StorageError> {
         let full_path = self.uploads_dir.join(path);
@@ -193,39 +470,45 @@ impl StorageService {
     }
 
     /// Generate unique filename
+    #[deprecated(note = "use `claim_path`, which applies the filename policy and reserves the name")]
     pub fn generate_unique_filename(&self, original: &str) -> String {
-        let timestamp = Utc::now().format("%Y%m%d%H%M%S");
-        let random: u32 = rand_simple();
-
-        let ext = Path::new(original)
-            .extension()
-            .and_then(|e| e.to_str())
-            .unwrap_or("");
-
-        let stem = Path::new(original)
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("file");
-
-        let sanitized = crate::models::sanitize_filename(stem);
-
-        if ext.is_empty() {
-            format!("{}-{}-{:08x}", sanitized, timestamp, random)
-        } else {
-            format!("{}-{}-{:08x}.{}", sanitized, timestamp, random, ext)
-        }
+        let policy = self.filename_policy.try_read().map(|p| p.clone()).unwrap_or_default();
+        let policy = FilenamePolicy { collision: CollisionStrategy::Random, ..policy };
+        policy.candidate(&policy.sanitize(original), 1, None)
     }
 
-    /// Generate path based on organization settings
-    fn generate_path(&self, filename: &str) -> String {
-        let unique_name = self.generate_unique_filename(filename);
-
-        if self.organize_by_date {
+    /// Pick a free path for `filename` and reserve it
+    ///
+    /// The name is sanitized by the filename policy. An empty placeholder is
+    /// created atomically so concurrent uploads never claim the same path;
+    /// callers overwrite it. `hash` feeds the hash-suffix strategy.
+    pub async fn claim_path(&self, filename: &str, hash: Option<&str>) -> Result<String, StorageError> {
+        let dir = if self.organize_by_date {
             let now = Utc::now();
-            format!("{}/{}/{}", now.format("%Y"), now.format("%m"), unique_name)
+            format!("{}/{}/", now.format("%Y"), now.format("%m"))
         } else {
-            unique_name
+            String::new()
+        };
+        fs::create_dir_all(self.uploads_dir.join(&dir)).await?;
+
+        let policy = self.filename_policy.read().await.clone();
+        let sanitized = policy.sanitize(filename);
+        for attempt in 0..MAX_NAME_ATTEMPTS {
+            let relative = format!("{}{}", dir, policy.candidate(&sanitized, attempt, hash));
+            let claimed = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(self.uploads_dir.join(&relative))
+                .await;
+
+            match claimed {
+                Ok(_) => return Ok(relative),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
         }
+
+        Err(StorageError::InvalidPath(format!("no free name for {}", sanitized)))
     }
 
     /// Get full filesystem path
@@ -324,13 +607,6 @@ pub struct FileInfo {
     pub modified: Option<std::time::SystemTime>,
 }
 
-/// Simple random number (no external crate)
-fn rand_simple() -> u32 {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let duration = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-    ((duration.as_nanos() % u32::MAX as u128) as u32).wrapping_mul(1103515245).wrapping_add(12345)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -369,4 +645,74 @@ mod tests {
 
         assert!(!storage.exists(&result.path).await);
     }
+
+    #[tokio::test]
+    async fn test_concatenate_and_store_file() {
+        let dir = tempdir().unwrap();
+        let storage = StorageService::new(dir.path().to_path_buf(), "/uploads");
+
+        storage.write("temp/a", b"Hello, ").await.unwrap();
+        storage.write("temp/b", b"World!").await.unwrap();
+
+        let mut seen = Vec::new();
+        let size = storage
+            .concatenate(&["temp/a".to_string(), "temp/b".to_string()], "temp/out", |b| seen.extend_from_slice(b))
+            .await
+            .unwrap();
+        assert_eq!(size, 13);
+        assert_eq!(seen, b"Hello, World!");
+
+        let stored = storage.store_file("temp/out", "hello.txt", "text/plain", String::new()).await.unwrap();
+        assert_eq!(stored.size, 13);
+        assert!(!storage.exists("temp/out").await);
+        assert_eq!(storage.read(&stored.path).await.unwrap(), b"Hello, World!");
+    }
+
+    #[tokio::test]
+    async fn test_collisions_follow_policy() {
+        let dir = tempdir().unwrap();
+        let mut storage = StorageService::new(dir.path().to_path_buf(), "/uploads");
+        storage.organize_by_date = false;
+
+        let first = storage.store(b"one", "Café Müller.txt", "text/plain").await.unwrap();
+        let second = storage.store(b"two", "Café Müller.txt", "text/plain").await.unwrap();
+        assert_eq!(first.path, "cafe-muller.txt");
+        assert_eq!(second.path, "cafe-muller-1.txt");
+        assert_eq!(storage.read(&first.path).await.unwrap(), b"one");
+
+        storage.set_filename_policy(FilenamePolicy {
+            collision: CollisionStrategy::HashSuffix,
+            ..FilenamePolicy::default()
+        });
+        let third = storage.store(b"three", "cafe-muller.txt", "text/plain").await.unwrap();
+        assert_eq!(third.path, format!("cafe-muller-{}.txt", &third.hash[..8]));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_generate_unique_filename() {
+        let storage = StorageService::new(PathBuf::from("/tmp"), "/uploads");
+        let first = storage.generate_unique_filename("Café Müller.JPG");
+        let second = storage.generate_unique_filename("Café Müller.JPG");
+        assert!(first.starts_with("cafe-muller-") && first.ends_with(".jpg"));
+        assert_ne!(first, second);
+    }
+
+    #[tokio::test]
+    async fn test_replace_keeps_path() {
+        let dir = tempdir().unwrap();
+        let storage = StorageService::new(dir.path().to_path_buf(), "/uploads");
+
+        let stored = storage.store(b"old", "logo.txt", "text/plain").await.unwrap();
+        let replaced = storage.replace(&stored.path, b"new contents", "text/plain").await.unwrap();
+
+        assert_eq!(replaced.path, stored.path);
+        assert_eq!(replaced.url, stored.url);
+        assert_eq!(replaced.size, 12);
+        assert_ne!(replaced.hash, stored.hash);
+        assert_eq!(storage.read(&stored.path).await.unwrap(), b"new contents");
+
+        let err = storage.replace("missing.txt", b"x", "text/plain").await.unwrap_err();
+        assert!(matches!(err, StorageError::NotFound(_)));
+    }
 }
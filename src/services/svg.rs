@@ -0,0 +1,429 @@
+//! SVG Sanitizer
+//!
+//! Rewrites uploaded SVGs keeping only allow-listed elements and
+//! attributes, so stored files cannot carry scripts, event handlers,
+//! `javascript:` links, external references or DOCTYPE entity tricks.
+
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+use serde::Serialize;
+
+/// Elements kept in sanitized SVGs
+const ALLOWED_ELEMENTS: &[&str] = &[
+    "svg", "g", "a", "defs", "title", "desc", "symbol", "use", "image", "switch", "view", "style",
+    "path", "rect", "circle", "ellipse", "line", "polyline", "polygon",
+    "text", "tspan", "textPath",
+    "linearGradient", "radialGradient", "stop", "pattern", "clipPath", "mask", "marker",
+    "filter", "feBlend", "feColorMatrix", "feComponentTransfer", "feComposite",
+    "feConvolveMatrix", "feDiffuseLighting", "feDisplacementMap", "feDistantLight",
+    "feDropShadow", "feFlood", "feFuncA", "feFuncB", "feFuncG", "feFuncR",
+    "feGaussianBlur", "feImage", "feMerge", "feMergeNode", "feMorphology", "feOffset",
+    "fePointLight", "feSpecularLighting", "feSpotLight", "feTile", "feTurbulence",
+];
+
+/// Attributes kept in sanitized SVGs
+const ALLOWED_ATTRIBUTES: &[&str] = &[
+    // Core and namespaces
+    "id", "class", "style", "lang", "xml:lang", "xml:space", "xmlns", "xmlns:xlink", "xmlns:svg",
+    "version", "baseProfile", "role", "aria-label", "aria-hidden", "focusable",
+    // Links (values checked separately)
+    "href", "xlink:href", "xlink:title",
+    // Geometry
+    "viewBox", "preserveAspectRatio", "width", "height", "x", "y", "x1", "y1", "x2", "y2",
+    "cx", "cy", "r", "rx", "ry", "fx", "fy", "fr", "d", "points", "pathLength", "transform",
+    // Presentation
+    "fill", "fill-opacity", "fill-rule", "stroke", "stroke-width", "stroke-linecap",
+    "stroke-linejoin", "stroke-miterlimit", "stroke-dasharray", "stroke-dashoffset",
+    "stroke-opacity", "opacity", "color", "display", "visibility", "overflow",
+    "clip-path", "clip-rule", "mask", "filter", "paint-order", "vector-effect",
+    "shape-rendering", "image-rendering", "color-interpolation", "color-interpolation-filters",
+    "stop-color", "stop-opacity", "flood-color", "flood-opacity", "lighting-color",
+    "marker-start", "marker-mid", "marker-end",
+    // Text
+    "font-family", "font-size", "font-weight", "font-style", "font-variant", "text-anchor",
+    "dominant-baseline", "alignment-baseline", "baseline-shift", "letter-spacing",
+    "word-spacing", "text-decoration", "writing-mode", "dx", "dy", "rotate", "textLength",
+    "lengthAdjust", "startOffset", "method", "spacing",
+    // Paint servers, clipping, markers
+    "offset", "gradientUnits", "gradientTransform", "spreadMethod", "patternUnits",
+    "patternContentUnits", "patternTransform", "clipPathUnits", "maskUnits",
+    "maskContentUnits", "markerWidth", "markerHeight", "markerUnits", "refX", "refY", "orient",
+    // Filters
+    "filterUnits", "primitiveUnits", "in", "in2", "result", "stdDeviation", "mode", "operator",
+    "k1", "k2", "k3", "k4", "values", "type", "tableValues", "slope", "intercept", "amplitude",
+    "exponent", "scale", "xChannelSelector", "yChannelSelector", "baseFrequency", "numOctaves",
+    "seed", "stitchTiles", "radius", "surfaceScale", "diffuseConstant", "specularConstant",
+    "specularExponent", "kernelMatrix", "order", "divisor", "bias", "targetX", "targetY",
+    "edgeMode", "preserveAlpha", "azimuth", "elevation", "pointsAtX", "pointsAtY", "pointsAtZ",
+    "limitingConeAngle", "z",
+];
+
+/// Embedded image types allowed in `href` on `<image>` and `<feImage>`
+const ALLOWED_DATA_URLS: &[&str] = &[
+    "data:image/png;base64,",
+    "data:image/jpeg;base64,",
+    "data:image/gif;base64,",
+    "data:image/webp;base64,",
+];
+
+/// Namespace URIs allowed on `xmlns` attributes
+const ALLOWED_NAMESPACES: &[(&str, &str)] = &[
+    ("xmlns", "http://www.w3.org/2000/svg"),
+    ("xmlns:svg", "http://www.w3.org/2000/svg"),
+    ("xmlns:xlink", "http://www.w3.org/1999/xlink"),
+];
+
+/// SVG sanitizer error
+#[derive(Debug, thiserror::Error)]
+pub enum SvgError {
+    #[error("Invalid SVG: {0}")]
+    Parse(String),
+    #[error("Root element is <{0}>, not <svg>")]
+    NotSvg(String),
+}
+
+/// What sanitizing removed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SvgReport {
+    /// Removed elements (with their content)
+    pub elements: Vec<String>,
+    /// Removed attributes as `element@attribute`
+    pub attributes: Vec<String>,
+    /// DOCTYPE declaration (and any entities it defined) removed
+    pub doctype: bool,
+    /// Removed processing instructions (e.g. `xml-stylesheet`)
+    pub processing_instructions: Vec<String>,
+    /// Text nodes dropped for referencing undefined entities
+    pub entity_references: usize,
+}
+
+impl SvgReport {
+    /// Check if nothing was removed
+    pub fn is_clean(&self) -> bool {
+        self.elements.is_empty()
+            && self.attributes.is_empty()
+            && !self.doctype
+            && self.processing_instructions.is_empty()
+            && self.entity_references == 0
+    }
+
+    /// One-line summary for logs and metadata
+    pub fn summary(&self) -> String {
+        let mut parts: Vec<String> = Vec::new();
+        if self.doctype {
+            parts.push("DOCTYPE".to_string());
+        }
+        parts.extend(self.processing_instructions.iter().map(|p| format!("<?{}?>", p)));
+        parts.extend(self.elements.iter().map(|e| format!("<{}>", e)));
+        parts.extend(self.attributes.iter().cloned());
+        if self.entity_references > 0 {
+            parts.push(format!("{} entity references", self.entity_references));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Sanitized SVG
+#[derive(Debug, Clone)]
+pub struct SanitizedSvg {
+    /// Rewritten file
+    pub data: Vec<u8>,
+    /// What was removed
+    pub report: SvgReport,
+}
+
+/// Sanitize an SVG document
+pub fn sanitize(data: &[u8]) -> Result<SanitizedSvg, SvgError> {
+    let mut reader = Reader::from_reader(data);
+    reader.config_mut().check_end_names = true;
+
+    let mut writer = Writer::new(Vec::with_capacity(data.len()));
+    let mut report = SvgReport::default();
+    let mut root_seen = false;
+    // Depth inside a removed element
+    let mut skip_depth = 0usize;
+    // Text and CDATA of the open <style> element, checked as a whole when
+    // it closes (comments could otherwise split a keyword across events)
+    let mut style: Option<String> = None;
+
+    loop {
+        let event = reader.read_event()
+            .map_err(|e| SvgError::Parse(format!("at byte {}: {}", reader.error_position(), e)))?;
+
+        match event {
+            Event::Eof => break,
+            Event::Start(_) if skip_depth > 0 => skip_depth += 1,
+            Event::End(_) if skip_depth > 0 => skip_depth -= 1,
+            _ if skip_depth > 0 => {}
+            Event::Start(ref start) | Event::Empty(ref start) => {
+                let is_empty = matches!(event, Event::Empty(_));
+                let name = element_name(start);
+
+                if !root_seen {
+                    root_seen = true;
+                    if name != "svg" {
+                        return Err(SvgError::NotSvg(name));
+                    }
+                }
+
+                // Stylesheets hold text only
+                if style.is_some() || !ALLOWED_ELEMENTS.contains(&name.as_str()) {
+                    report.elements.push(name);
+                    if !is_empty {
+                        skip_depth = 1;
+                    }
+                    continue;
+                }
+
+                let element = clean_element(start, &name, &mut report)?;
+                if is_empty {
+                    write(&mut writer, Event::Empty(element))?;
+                } else {
+                    if name == "style" {
+                        style = Some(String::new());
+                    }
+                    write(&mut writer, Event::Start(element))?;
+                }
+            }
+            Event::End(end) => {
+                let name = String::from_utf8_lossy(end.local_name().as_ref()).into_owned();
+                // Only the open <style> can close here: its children were skipped
+                if let Some(css) = style.take() {
+                    if css_is_safe(&css) {
+                        write(&mut writer, Event::Text(BytesText::new(&css)))?;
+                    } else {
+                        report.elements.push("style".to_string());
+                    }
+                }
+                write(&mut writer, Event::End(BytesEnd::new(name)))?;
+            }
+            Event::Text(text) => {
+                let Ok(unescaped) = text.unescape() else {
+                    report.entity_references += 1;
+                    continue;
+                };
+                match style.as_mut() {
+                    Some(css) => css.push_str(&unescaped),
+                    None => write(&mut writer, Event::Text(BytesText::new(&unescaped)))?,
+                }
+            }
+            Event::CData(cdata) => match style.as_mut() {
+                Some(css) => css.push_str(&String::from_utf8_lossy(&cdata)),
+                None => write(&mut writer, Event::CData(cdata))?,
+            },
+            Event::Decl(decl) => write(&mut writer, Event::Decl(decl))?,
+            Event::DocType(_) => report.doctype = true,
+            Event::PI(pi) => {
+                let content = String::from_utf8_lossy(&pi);
+                let target = content.split_whitespace().next().unwrap_or_default().to_string();
+                report.processing_instructions.push(target);
+            }
+            Event::Comment(_) => {}
+        }
+    }
+
+    if !root_seen {
+        return Err(SvgError::Parse("no root element".to_string()));
+    }
+
+    Ok(SanitizedSvg {
+        data: writer.into_inner(),
+        report,
+    })
+}
+
+/// Element name with any namespace prefix removed
+fn element_name(start: &BytesStart) -> String {
+    String::from_utf8_lossy(start.local_name().as_ref()).into_owned()
+}
+
+/// Copy an element keeping only safe attributes
+fn clean_element(
+    start: &BytesStart,
+    name: &str,
+    report: &mut SvgReport,
+) -> Result<BytesStart<'static>, SvgError> {
+    let mut element = BytesStart::new(name.to_string());
+
+    for attribute in start.attributes() {
+        let attribute = attribute.map_err(|e| SvgError::Parse(e.to_string()))?;
+        let key = String::from_utf8_lossy(attribute.key.as_ref()).into_owned();
+        let value = attribute.unescape_value()
+            .map_err(|e| SvgError::Parse(e.to_string()))?;
+
+        if attribute_is_safe(name, &key, &value) {
+            element.push_attribute((key.as_str(), value.as_ref()));
+        } else {
+            report.attributes.push(format!("{}@{}", name, key));
+        }
+    }
+
+    Ok(element)
+}
+
+fn attribute_is_safe(element: &str, key: &str, value: &str) -> bool {
+    if !ALLOWED_ATTRIBUTES.contains(&key) {
+        return false;
+    }
+
+    // Browsers ignore whitespace and control characters inside URL schemes
+    let compact: String = value
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_lowercase();
+
+    match key {
+        "xmlns" | "xmlns:svg" | "xmlns:xlink" => ALLOWED_NAMESPACES.contains(&(key, value.trim())),
+        "href" | "xlink:href" => {
+            compact.starts_with('#')
+                || (matches!(element, "image" | "feImage")
+                    && ALLOWED_DATA_URLS.iter().any(|prefix| compact.starts_with(prefix)))
+        }
+        "style" => css_is_safe(value),
+        _ => {
+            !compact.contains("javascript:")
+                && !compact.contains("vbscript:")
+                && !compact.contains("data:")
+                && urls_are_local(&compact)
+        }
+    }
+}
+
+/// CSS may only reference fragments in the same document
+fn css_is_safe(css: &str) -> bool {
+    let compact: String = css
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase();
+
+    !compact.contains('\\')
+        && !compact.contains("@import")
+        && !compact.contains("javascript:")
+        && !compact.contains("expression(")
+        && !compact.contains("-moz-binding")
+        && !compact.contains("behavior:")
+        && urls_are_local(&compact)
+}
+
+/// Every `url(...)` points at `#fragment`, and no `image-set(...)` names a
+/// file with a bare string
+fn urls_are_local(compact: &str) -> bool {
+    let urls = compact.match_indices("url(").all(|(i, _)| {
+        let target = compact[i + 4..].trim_start_matches(['"', '\'']);
+        target.starts_with('#')
+    });
+
+    // Covers the -webkit- prefixed form too
+    let image_sets = compact.match_indices("image-set(").all(|(i, _)| {
+        let args = &compact[i + 10..];
+        let args = &args[..args.find(')').unwrap_or(args.len())];
+        !args.contains(['"', '\''])
+    });
+
+    urls && image_sets
+}
+
+fn write(writer: &mut Writer<Vec<u8>>, event: Event) -> Result<(), SvgError> {
+    writer.write_event(event).map_err(|e| SvgError::Parse(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clean(svg: &str) -> (String, SvgReport) {
+        let sanitized = sanitize(svg.as_bytes()).unwrap();
+        (String::from_utf8(sanitized.data).unwrap(), sanitized.report)
+    }
+
+    #[test]
+    fn test_keeps_safe_content() {
+        let svg = r##"<?xml version="1.0"?><svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 10 10"><defs><linearGradient id="g"><stop offset="0" stop-color="red"/></linearGradient></defs><rect width="10" height="10" fill="url(#g)"/><text x="1">a &amp; b</text></svg>"##;
+        let (out, report) = clean(svg);
+        assert!(report.is_clean(), "{:?}", report);
+        assert_eq!(out, svg);
+    }
+
+    #[test]
+    fn test_strips_scripts_and_handlers() {
+        let (out, report) = clean(concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" onload="alert(1)">"#,
+            r#"<script>alert(2)</script>"#,
+            r#"<foreignObject><div><script>alert(3)</script></div></foreignObject>"#,
+            r#"<a href="javascript:alert(4)"><circle r="5" onclick="alert(5)"/></a>"#,
+            r#"<use href="java&#x09;script:alert(6)"/>"#,
+            r#"<image href="https://evil.example/x.png"/>"#,
+            r#"<rect style="fill:url(https://evil.example/track)" width="1"/>"#,
+            r#"</svg>"#,
+        ));
+
+        assert!(!out.contains("alert"));
+        assert!(!out.contains("evil"));
+        assert!(out.contains("<circle r=\"5\"/>"));
+        assert_eq!(report.elements, vec!["script", "foreignObject"]);
+        assert_eq!(
+            report.attributes,
+            vec!["svg@onload", "a@href", "circle@onclick", "use@href", "image@href", "rect@style"]
+        );
+    }
+
+    #[test]
+    fn test_removes_doctype_and_entities() {
+        let (out, report) = clean(concat!(
+            r#"<?xml version="1.0"?>"#,
+            r#"<!DOCTYPE svg [<!ENTITY xxe SYSTEM "file:///etc/passwd">]>"#,
+            r#"<?xml-stylesheet href="https://evil.example/a.css"?>"#,
+            r#"<svg xmlns="http://www.w3.org/2000/svg"><text>&xxe;</text></svg>"#,
+        ));
+
+        assert!(!out.contains("DOCTYPE"));
+        assert!(!out.contains("xxe"));
+        assert!(!out.contains("stylesheet"));
+        assert!(report.doctype);
+        assert_eq!(report.processing_instructions, vec!["xml-stylesheet"]);
+        assert_eq!(report.entity_references, 1);
+    }
+
+    #[test]
+    fn test_allows_embedded_raster_images() {
+        let (out, report) = clean(r#"<svg><image href="data:image/png;base64,AAAA"/><image href="data:image/svg+xml;base64,AAAA"/></svg>"#);
+        assert!(out.contains("data:image/png"));
+        assert_eq!(report.attributes, vec!["image@href"]);
+    }
+
+    #[test]
+    fn test_style_bypasses() {
+        // A child element used to end the stylesheet early
+        let (out, report) = clean(r#"<svg><style><g></g>@import url(https://evil.example/x.css)</style></svg>"#);
+        assert!(!out.contains("evil"));
+        assert_eq!(report.elements, vec!["g", "style"]);
+
+        // Comments used to split keywords across text events
+        let (out, report) = clean(r#"<svg><style>@imp<!-- -->ort "https://evil.example/x.css";</style></svg>"#);
+        assert!(!out.contains("evil"));
+        assert_eq!(report.elements, vec!["style"]);
+
+        let (out, _) = clean(r#"<svg><style>rect { background: image-set("https://evil.example/a.png" 1x) }</style></svg>"#);
+        assert!(!out.contains("evil"));
+
+        // Safe stylesheets survive, CDATA included
+        let (out, report) = clean(r#"<svg><style><![CDATA[rect { fill: url(#g) }]]> circle { fill: red }</style></svg>"#);
+        assert!(report.is_clean(), "{:?}", report);
+        assert!(out.contains("rect { fill: url(#g) } circle { fill: red }"));
+    }
+
+    #[test]
+    fn test_checks_namespaces() {
+        let (out, report) = clean(r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="https://evil.example/ns"/>"#);
+        assert!(!out.contains("evil"));
+        assert_eq!(report.attributes, vec!["svg@xmlns:xlink"]);
+    }
+
+    #[test]
+    fn test_rejects_non_svg() {
+        assert!(matches!(sanitize(b"<html><body/></html>"), Err(SvgError::NotSvg(_))));
+        assert!(matches!(sanitize(b"<svg><g></svg>"), Err(SvgError::Parse(_))));
+    }
+}
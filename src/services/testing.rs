@@ -0,0 +1,38 @@
+//! Test Services
+//!
+//! The storage, image, media and optimizer services most tests build on,
+//! wired with default settings.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use super::{ImageService, MediaService, OptimizerService, StorageService, UploadService};
+
+/// Services storing under one directory
+pub struct TestServices {
+    pub storage: Arc<StorageService>,
+    pub image: Arc<ImageService>,
+    pub media: Arc<MediaService>,
+    pub optimizer: Arc<OptimizerService>,
+}
+
+impl TestServices {
+    /// Create services storing under `dir`
+    pub fn new(dir: &Path) -> Self {
+        let storage = Arc::new(StorageService::new(dir.to_path_buf(), "/uploads"));
+        let image = Arc::new(ImageService::new(Arc::clone(&storage)));
+        let media = Arc::new(MediaService::new(Arc::clone(&storage), Arc::clone(&image)));
+        let optimizer = Arc::new(OptimizerService::new(Arc::clone(&image), Arc::clone(&storage)));
+        Self { storage, image, media, optimizer }
+    }
+
+    /// Create an upload service on top
+    pub fn upload_service(&self) -> UploadService {
+        UploadService::new(
+            Arc::clone(&self.storage),
+            Arc::clone(&self.image),
+            Arc::clone(&self.media),
+            Arc::clone(&self.optimizer),
+        )
+    }
+}
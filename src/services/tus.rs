@@ -0,0 +1,514 @@
+//! tus Resumable Upload Service
+//!
+//! Server side of the tus 1.0 protocol (<https://tus.io/protocols/resumable-upload>)
+//! with the creation, creation-with-upload, termination, checksum and
+//! expiration extensions. Data is appended at byte offsets to a temporary
+//! file and handed to [`UploadService`] once the upload is complete.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::models::{ChecksumAlgorithm, DuplicateStrategy, MediaItem, TusUpload, UploadOptions};
+use super::storage::StorageService;
+use super::upload::{UploadError, UploadService};
+
+/// Supported protocol version
+pub const TUS_VERSION: &str = "1.0.0";
+
+/// Supported protocol extensions
+pub const TUS_EXTENSIONS: &str = "creation,creation-with-upload,termination,checksum,expiration";
+
+/// tus service error
+#[derive(Debug, thiserror::Error)]
+pub enum TusError {
+    #[error("Upload not found: {0}")]
+    NotFound(Uuid),
+    #[error("Upload expired: {0}")]
+    Expired(Uuid),
+    #[error("Offset mismatch: expected {0}, got {1}")]
+    OffsetMismatch(u64, u64),
+    #[error("Upload exceeds its length: {0} > {1}")]
+    ExceedsLength(u64, u64),
+    #[error("Upload too large: {0} bytes (max: {1})")]
+    TooLarge(u64, u64),
+    #[error("Checksum mismatch ({0})")]
+    ChecksumMismatch(&'static str),
+    #[error("Unsupported checksum algorithm: {0}")]
+    UnsupportedChecksum(String),
+    #[error("Upload is locked by another request")]
+    Locked,
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+    #[error("Storage error: {0}")]
+    Storage(#[from] super::storage::StorageError),
+    #[error("Upload error: {0}")]
+    Upload(#[from] UploadError),
+}
+
+/// tus settings
+#[derive(Debug, Clone)]
+pub struct TusSettings {
+    /// Maximum upload length (Tus-Max-Size)
+    pub max_size: u64,
+    /// Hours before an unfinished upload expires
+    pub expiry_hours: u32,
+    /// Temporary directory (relative to the uploads root)
+    pub temp_dir: String,
+}
+
+impl Default for TusSettings {
+    fn default() -> Self {
+        Self {
+            max_size: 100 * 1024 * 1024, // 100MB
+            expiry_hours: 24,
+            temp_dir: "temp/tus".to_string(),
+        }
+    }
+}
+
+/// Checksum sent with a PATCH request (Upload-Checksum)
+#[derive(Debug, Clone)]
+pub struct TusChecksum {
+    pub algorithm: ChecksumAlgorithm,
+    pub digest: Vec<u8>,
+}
+
+/// tus upload service
+pub struct TusService {
+    /// Upload service used to finish uploads
+    upload_service: Arc<UploadService>,
+    /// Storage service
+    storage: Arc<StorageService>,
+    /// Settings
+    settings: TusSettings,
+    /// Uploads by ID
+    uploads: Arc<RwLock<HashMap<Uuid, TusUpload>>>,
+    /// Uploads with a PATCH in progress
+    locked: Arc<RwLock<HashSet<Uuid>>>,
+}
+
+impl TusService {
+    /// Create a new tus service
+    pub fn new(upload_service: Arc<UploadService>, storage: Arc<StorageService>) -> Self {
+        let settings = TusSettings {
+            max_size: upload_service.get_max_file_size(),
+            ..TusSettings::default()
+        };
+
+        Self {
+            upload_service,
+            storage,
+            settings,
+            uploads: Arc::new(RwLock::new(HashMap::new())),
+            locked: Arc::new(RwLock::new(HashSet::new())),
+        }
+    }
+
+    /// Configure settings
+    pub fn configure(&mut self, settings: TusSettings) {
+        self.settings = settings;
+    }
+
+    /// Get settings
+    pub fn settings(&self) -> &TusSettings {
+        &self.settings
+    }
+
+    /// Create a new upload (creation extension)
+    ///
+    /// With `initial_data` this also covers creation-with-upload.
+    pub async fn create(
+        &self,
+        length: u64,
+        metadata: HashMap<String, String>,
+        user_id: Option<Uuid>,
+        initial_data: Option<&[u8]>,
+    ) -> Result<TusUpload, TusError> {
+        if length > self.settings.max_size {
+            return Err(TusError::TooLarge(length, self.settings.max_size));
+        }
+
+        // Validate the declared file early so clients fail before sending data
+        if let Some(filename) = metadata.get("filename").or_else(|| metadata.get("name")) {
+            self.upload_service.validate_file(
+                filename,
+                length,
+                metadata.get("filetype").map(|s| s.as_str()),
+            )?;
+        }
+
+        // Charged up front so limited clients stop before sending data
+        self.upload_service.check_rate_limit(user_id, length).await?;
+
+        let id = Uuid::now_v7();
+        let now = Utc::now();
+        let upload = TusUpload {
+            id,
+            length,
+            offset: 0,
+            metadata,
+            user_id,
+            temp_path: format!("{}/{}", self.settings.temp_dir.trim_end_matches('/'), id),
+            created_at: now,
+            expires_at: now + Duration::hours(self.settings.expiry_hours as i64),
+            media_id: None,
+        };
+
+        // Create the (empty) file so HEAD works before the first PATCH
+        self.storage.write(&upload.temp_path, &[]).await?;
+
+        {
+            let mut uploads = self.uploads.write().await;
+            uploads.insert(id, upload.clone());
+        }
+
+        match initial_data {
+            Some(data) if !data.is_empty() => self.append(id, 0, data, None).await,
+            _ => Ok(upload),
+        }
+    }
+
+    /// Get upload state (HEAD)
+    pub async fn get(&self, id: Uuid) -> Result<TusUpload, TusError> {
+        let uploads = self.uploads.read().await;
+        let upload = uploads.get(&id).ok_or(TusError::NotFound(id))?;
+
+        if upload.is_expired() && upload.media_id.is_none() {
+            return Err(TusError::Expired(id));
+        }
+
+        Ok(upload.clone())
+    }
+
+    /// Check that `user_id` created an upload
+    ///
+    /// Other users' uploads are reported as not found.
+    pub async fn check_owner(&self, id: Uuid, user_id: Option<Uuid>) -> Result<(), TusError> {
+        match self.uploads.read().await.get(&id) {
+            Some(upload) if upload.user_id == user_id => Ok(()),
+            _ => Err(TusError::NotFound(id)),
+        }
+    }
+
+    /// Append data at an offset (PATCH)
+    ///
+    /// When the last byte arrives the upload is finished through
+    /// [`UploadService`] and `media_id` is set on the returned state. If
+    /// that fails, a PATCH without data at the final offset retries it.
+    pub async fn append(
+        &self,
+        id: Uuid,
+        offset: u64,
+        data: &[u8],
+        checksum: Option<TusChecksum>,
+    ) -> Result<TusUpload, TusError> {
+        // Only one PATCH per upload at a time
+        {
+            let mut locked = self.locked.write().await;
+            if !locked.insert(id) {
+                return Err(TusError::Locked);
+            }
+        }
+
+        let result = self.append_locked(id, offset, data, checksum).await;
+
+        self.locked.write().await.remove(&id);
+        result
+    }
+
+    async fn append_locked(
+        &self,
+        id: Uuid,
+        offset: u64,
+        data: &[u8],
+        checksum: Option<TusChecksum>,
+    ) -> Result<TusUpload, TusError> {
+        let upload = self.get(id).await?;
+
+        if upload.is_complete() && upload.media_id.is_none() && offset == upload.offset && data.is_empty() {
+            return self.complete(upload).await;
+        }
+
+        if upload.media_id.is_some() || offset != upload.offset {
+            return Err(TusError::OffsetMismatch(upload.offset, offset));
+        }
+
+        let new_offset = upload.offset + data.len() as u64;
+        if new_offset > upload.length {
+            return Err(TusError::ExceedsLength(new_offset, upload.length));
+        }
+
+        // Checksum extension: reject the whole request on mismatch
+        if let Some(checksum) = checksum {
+            if checksum.algorithm.digest(data) != checksum.digest {
+                return Err(TusError::ChecksumMismatch(checksum.algorithm.name()));
+            }
+        }
+
+        let size = self.storage.append(&upload.temp_path, data).await?;
+        if size != new_offset {
+            return Err(TusError::InvalidRequest(format!(
+                "Stored size {} does not match offset {}",
+                size, new_offset
+            )));
+        }
+
+        let upload = {
+            let mut uploads = self.uploads.write().await;
+            let upload = uploads.get_mut(&id).ok_or(TusError::NotFound(id))?;
+            upload.offset = new_offset;
+            upload.clone()
+        };
+
+        if upload.is_complete() {
+            return self.complete(upload).await;
+        }
+
+        Ok(upload)
+    }
+
+    /// Finish a complete upload and record its media item
+    ///
+    /// On failure the file is kept and the upload stays complete without a
+    /// `media_id`, so the finish can be retried.
+    async fn complete(&self, upload: TusUpload) -> Result<TusUpload, TusError> {
+        let media = self.finish(&upload).await?;
+
+        let mut uploads = self.uploads.write().await;
+        match uploads.get_mut(&upload.id) {
+            Some(upload) => {
+                upload.media_id = Some(media.id);
+                Ok(upload.clone())
+            }
+            None => Ok(TusUpload { media_id: Some(media.id), ..upload }),
+        }
+    }
+
+    /// Terminate an upload (termination extension)
+    ///
+    /// Holds the upload's lock, so no PATCH can start while it is removed.
+    pub async fn terminate(&self, id: Uuid) -> Result<(), TusError> {
+        {
+            let mut locked = self.locked.write().await;
+            if !locked.insert(id) {
+                return Err(TusError::Locked);
+            }
+        }
+
+        let result = self.terminate_locked(id).await;
+
+        self.locked.write().await.remove(&id);
+        result
+    }
+
+    async fn terminate_locked(&self, id: Uuid) -> Result<(), TusError> {
+        let upload = {
+            let mut uploads = self.uploads.write().await;
+            uploads.remove(&id).ok_or(TusError::NotFound(id))?
+        };
+
+        self.storage.delete(&upload.temp_path).await?;
+        Ok(())
+    }
+
+    /// Remove expired uploads (expiration extension)
+    pub async fn cleanup_expired(&self) -> usize {
+        let mut uploads = self.uploads.write().await;
+
+        let expired: Vec<Uuid> = uploads.values()
+            .filter(|u| u.is_expired())
+            .map(|u| u.id)
+            .collect();
+
+        for id in &expired {
+            if let Some(upload) = uploads.remove(id) {
+                let _ = self.storage.delete(&upload.temp_path).await;
+            }
+        }
+
+        expired.len()
+    }
+
+    /// Hand the completed file to the upload service
+    ///
+    /// The file is registered from temporary storage, so only images small
+    /// enough to process are read into memory.
+    async fn finish(&self, upload: &TusUpload) -> Result<MediaItem, TusError> {
+        let filename = upload.filename()
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| format!("upload-{}", upload.id));
+
+        let options = UploadOptions {
+            folder_id: upload.metadata.get("folder_id").and_then(|f| Uuid::parse_str(f).ok()),
+            title: upload.metadata.get("title").cloned(),
+            description: upload.metadata.get("description").cloned(),
+            alt_text: upload.metadata.get("alt_text").cloned(),
+            tags: upload.metadata.get("tags")
+                .map(|t| t.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+                .unwrap_or_default(),
+            on_duplicate: upload.metadata.get("on_duplicate").and_then(|s| DuplicateStrategy::from_name(s)),
+            allow_degraded: upload.metadata.get("allow_degraded").is_some_and(|s| s == "true" || s == "1"),
+            ..UploadOptions::default()
+        };
+
+        let media = self.upload_service
+            .upload_stored_without_limit(&upload.temp_path, &filename, options, upload.user_id)
+            .await?;
+
+        // Large files were moved into place
+        if self.storage.exists(&upload.temp_path).await {
+            if let Err(e) = self.storage.delete(&upload.temp_path).await {
+                tracing::warn!("Failed to remove tus temp file {}: {}", upload.temp_path, e);
+            }
+        }
+
+        Ok(media)
+    }
+}
+
+/// Parse an Upload-Metadata header (`key base64value,key2 base64value2`)
+pub fn parse_metadata(header: &str) -> Result<HashMap<String, String>, TusError> {
+    use base64::Engine;
+
+    let mut metadata = HashMap::new();
+
+    for pair in header.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+        let mut parts = pair.splitn(2, ' ');
+        let key = parts.next().unwrap_or("").trim();
+        if key.is_empty() {
+            return Err(TusError::InvalidRequest("Empty metadata key".to_string()));
+        }
+
+        let value = match parts.next().map(|v| v.trim()) {
+            Some(encoded) if !encoded.is_empty() => {
+                let bytes = base64::engine::general_purpose::STANDARD
+                    .decode(encoded)
+                    .map_err(|_| TusError::InvalidRequest(format!("Invalid metadata value for {}", key)))?;
+                String::from_utf8(bytes)
+                    .map_err(|_| TusError::InvalidRequest(format!("Metadata {} is not UTF-8", key)))?
+            }
+            _ => String::new(),
+        };
+
+        metadata.insert(key.to_string(), value);
+    }
+
+    Ok(metadata)
+}
+
+/// Parse an Upload-Checksum header (`sha1 base64digest`)
+pub fn parse_checksum(header: &str) -> Result<TusChecksum, TusError> {
+    use base64::Engine;
+
+    let mut parts = header.trim().splitn(2, ' ');
+    let name = parts.next().unwrap_or("");
+    let algorithm = ChecksumAlgorithm::from_name(name)
+        .ok_or_else(|| TusError::UnsupportedChecksum(name.to_string()))?;
+
+    let digest = base64::engine::general_purpose::STANDARD
+        .decode(parts.next().unwrap_or("").trim())
+        .map_err(|_| TusError::InvalidRequest("Invalid Upload-Checksum digest".to_string()))?;
+
+    Ok(TusChecksum { algorithm, digest })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use tempfile::tempdir;
+    use crate::services::testing::TestServices;
+
+    fn service(dir: &std::path::Path) -> TusService {
+        let services = TestServices::new(dir);
+        TusService::new(Arc::new(services.upload_service()), services.storage)
+    }
+
+    #[test]
+    fn test_parse_metadata() {
+        let metadata = parse_metadata("filename d29ybGRfZG9taW5hdGlvbl9wbGFuLnBkZg==,is_confidential").unwrap();
+        assert_eq!(metadata["filename"], "world_domination_plan.pdf");
+        assert_eq!(metadata["is_confidential"], "");
+    }
+
+    #[tokio::test]
+    async fn test_offset_based_upload() {
+        let dir = tempdir().unwrap();
+        let tus = service(dir.path());
+
+        let mut metadata = HashMap::new();
+        metadata.insert("filename".to_string(), "notes.txt".to_string());
+
+        let upload = tus.create(11, metadata, None, Some(b"hello")).await.unwrap();
+        assert_eq!(upload.offset, 5);
+
+        // Wrong offset is rejected
+        let err = tus.append(upload.id, 3, b" world", None).await.unwrap_err();
+        assert!(matches!(err, TusError::OffsetMismatch(5, 3)));
+
+        // Bad checksum is rejected without advancing the offset
+        let bad = TusChecksum { algorithm: ChecksumAlgorithm::Sha1, digest: vec![0; 20] };
+        let err = tus.append(upload.id, 5, b" world", Some(bad)).await.unwrap_err();
+        assert!(matches!(err, TusError::ChecksumMismatch("sha1")));
+        assert_eq!(tus.get(upload.id).await.unwrap().offset, 5);
+
+        let header = format!(
+            "sha1 {}",
+            base64::engine::general_purpose::STANDARD.encode(ChecksumAlgorithm::Sha1.digest(b" world"))
+        );
+        let checksum = parse_checksum(&header).unwrap();
+        let done = tus.append(upload.id, 5, b" world", Some(checksum)).await.unwrap();
+
+        assert_eq!(done.offset, 11);
+        assert!(done.media_id.is_some());
+        assert!(!dir.path().join(&upload.temp_path).exists());
+    }
+
+    #[tokio::test]
+    async fn test_retry_failed_finish() {
+        let dir = tempdir().unwrap();
+        let services = TestServices::new(dir.path());
+        let upload_service = Arc::new(services.upload_service());
+        let tus = TusService::new(Arc::clone(&upload_service), Arc::clone(&services.storage));
+
+        let existing = upload_service.upload(b"hello".to_vec(), "a.txt", UploadOptions::default(), None)
+            .await
+            .unwrap();
+
+        let mut metadata = HashMap::new();
+        metadata.insert("filename".to_string(), "b.txt".to_string());
+        metadata.insert("on_duplicate".to_string(), "reject".to_string());
+        let upload = tus.create(5, metadata, None, None).await.unwrap();
+
+        // The finish fails, but the received data is kept
+        let err = tus.append(upload.id, 0, b"hello", None).await.unwrap_err();
+        assert!(matches!(err, TusError::Upload(UploadError::Media(_))));
+        let state = tus.get(upload.id).await.unwrap();
+        assert_eq!(state.offset, 5);
+        assert!(state.media_id.is_none());
+
+        services.media.delete(existing.id, true).await.unwrap();
+        let done = tus.append(upload.id, 5, b"", None).await.unwrap();
+        assert!(done.media_id.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_terminate() {
+        let dir = tempdir().unwrap();
+        let tus = service(dir.path());
+
+        let upload = tus.create(10, HashMap::new(), None, None).await.unwrap();
+
+        // Not while a PATCH holds the lock
+        tus.locked.write().await.insert(upload.id);
+        assert!(matches!(tus.terminate(upload.id).await, Err(TusError::Locked)));
+        tus.locked.write().await.remove(&upload.id);
+
+        tus.terminate(upload.id).await.unwrap();
+
+        assert!(matches!(tus.get(upload.id).await, Err(TusError::NotFound(_))));
+    }
+}
@@ -2,17 +2,40 @@
 //!
 //! File upload handling with validation and processing.
 
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, PoisonError};
 use tokio::sync::RwLock;
 use chrono::{DateTime, Utc, Duration};
 use uuid::Uuid;
+use bytes::Bytes;
 
-use crate::models::{MediaItem, UploadOptions, ChunkedUpload, ChunkInfo, ImageFormat};
+use crate::hooks::PendingUpload;
+use crate::models::{
+    MediaItem, UploadOptions, ChunkedUpload, ChunkInfo, Checksum, ChecksumAlgorithm,
+    ChecksumHasher, BatchFileRef, FileState,
+};
 use super::storage::StorageService;
 use super::image::ImageService;
-use super::media::MediaService;
+use super::media::{MediaError, MediaService};
 use super::optimizer::OptimizerService;
+use super::url_import::{UrlImporter, UrlImportError, UrlImportSettings};
+use super::archive::{
+    self, ArchiveEntry, ArchiveError, ArchiveFormat, ArchiveImportReport, ArchiveLimits, EntryStatus,
+};
+use super::folder::{FolderError, FolderService};
+use super::svg::{self, SvgError};
+use super::validator::{self, ContentRejection, ContentValidator};
+use super::data_url::{self, DataUrlError};
+use super::idempotency::{Claim, IdempotencyError, IdempotencyStore};
+use super::rate_limit::{RateLimited, RateLimiter};
+use super::batch::{self, UploadBatches};
+use super::exif;
+use super::scanner::{ScanError, ScanFailurePolicy, ScanSettings, ScanVerdict, VirusScanner};
+
+/// Bytes kept from the start of an assembled file for MIME sniffing
+const SNIFF_LEN: usize = 8192;
+/// Size of the blocks archive entries are streamed in
+const ARCHIVE_BLOCK: usize = 64 * 1024;
 
 /// Upload service error
 #[derive(Debug, thiserror::Error)]
@@ -29,6 +52,22 @@ pub enum UploadError {
     Expired,
     #[error("Chunk missing: {0}")]
     ChunkMissing(usize),
+    #[error("Invalid chunk index: {0}")]
+    InvalidChunk(usize),
+    #[error("Invalid chunk layout: {0}")]
+    InvalidChunkLayout(String),
+    #[error("Chunk {0} is already being uploaded")]
+    ChunkInProgress(usize),
+    #[error("Upload is already being completed")]
+    Completing,
+    #[error("Chunk {0} size mismatch: expected {1} bytes, got {2}")]
+    ChunkSizeMismatch(usize, usize, usize),
+    #[error("Chunk {0} checksum mismatch: expected {1}, got {2}")]
+    ChunkChecksumMismatch(usize, String, String),
+    #[error("File size mismatch: expected {0} bytes, got {1}")]
+    SizeMismatch(u64, u64),
+    #[error("File checksum mismatch: expected {0}, got {1}")]
+    ChecksumMismatch(String, String),
     #[error("Storage error: {0}")]
     Storage(#[from] super::storage::StorageError),
     #[error("Image error: {0}")]
@@ -37,6 +76,28 @@ pub enum UploadError {
     Media(#[from] super::media::MediaError),
     #[error("Network error: {0}")]
     Network(String),
+    #[error("URL import error: {0}")]
+    UrlImport(#[from] UrlImportError),
+    #[error("Archive error: {0}")]
+    Archive(#[from] ArchiveError),
+    #[error("Folder error: {0}")]
+    Folder(#[from] FolderError),
+    #[error("Content rejected: {0}")]
+    ContentRejected(#[from] ContentRejection),
+    #[error("SVG error: {0}")]
+    Svg(#[from] SvgError),
+    #[error("Encoding error: {0}")]
+    Encoding(#[from] DataUrlError),
+    #[error("Declared type {0} does not match content ({1})")]
+    DeclaredTypeMismatch(String, String),
+    #[error("Idempotency error: {0}")]
+    Idempotency(#[from] IdempotencyError),
+    #[error("Rate limited: {0}")]
+    RateLimited(#[from] RateLimited),
+    #[error("Malware detected: {0}")]
+    Infected(String),
+    #[error("Virus scan failed: {0}")]
+    ScanFailed(#[from] ScanError),
 }
 
 /// Upload settings
@@ -56,6 +117,16 @@ pub struct UploadSettings {
     pub auto_optimize: bool,
     /// Auto-generate thumbnails
     pub auto_thumbnails: bool,
+    /// Largest assembled image loaded into memory for optimization and thumbnails
+    pub max_image_process_size: u64,
+    /// Limits for archive imports
+    pub archive_limits: ArchiveLimits,
+    /// Check that file contents match their extension
+    pub validate_contents: bool,
+    /// Strip scripts and external references from SVGs
+    pub sanitize_svg: bool,
+    /// How long results are replayed for retries with the same idempotency key
+    pub idempotency_window_minutes: u32,
 }
 
 impl Default for UploadSettings {
@@ -121,10 +192,76 @@ impl Default for UploadSettings {
             chunk_expiry_hours: 24,
             auto_optimize: true,
             auto_thumbnails: true,
+            max_image_process_size: 50 * 1024 * 1024, // 50MB
+            archive_limits: ArchiveLimits::default(),
+            validate_contents: true,
+            sanitize_svg: true,
+            idempotency_window_minutes: 24 * 60,
         }
     }
 }
 
+/// Part of an archive streamed from the thread reading it
+enum ArchivePart {
+    /// Next entry; a file is followed by its contents and `End`
+    Entry(ArchiveEntry),
+    /// Block of the current file
+    Block(Vec<u8>),
+    /// End of the current file
+    End,
+}
+
+/// Archive file being received
+struct ArchiveFile {
+    /// Path inside the archive
+    path: String,
+    /// Filename
+    name: String,
+    /// Staged contents and target folder, or why the file cannot be imported
+    staged: Result<(StagedFile, Option<Uuid>), UploadError>,
+}
+
+/// Infected file to quarantine
+enum Quarantine<'a> {
+    /// Contents held in memory
+    Data(&'a [u8]),
+    /// File already in storage (moved into quarantine)
+    Stored(&'a str),
+}
+
+/// Upload being streamed into temporary storage
+///
+/// Created by [`UploadService::stage`], fed with [`UploadService::write_staged`]
+/// and finished with [`UploadService::upload_staged`] or
+/// [`UploadService::discard_staged`].
+pub struct StagedFile {
+    /// Temporary path in storage
+    path: String,
+    /// Bytes written so far
+    size: u64,
+    /// Largest size accepted
+    max_size: u64,
+    /// Start of the file, for MIME sniffing
+    head: Vec<u8>,
+    /// Content hash of everything written
+    hasher: ChecksumHasher,
+}
+
+impl StagedFile {
+    /// Bytes written so far
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// Complete file in temporary storage, ready to be registered
+struct AssembledFile<'a> {
+    path: &'a str,
+    head: &'a [u8],
+    size: u64,
+    content_hash: String,
+}
+
 /// Upload service
 pub struct UploadService {
     /// Storage service
@@ -135,10 +272,30 @@ pub struct UploadService {
     media_service: Arc<MediaService>,
     /// Optimizer service
     optimizer: Arc<OptimizerService>,
-    /// Settings
-    settings: UploadSettings,
+    /// Settings (read by synchronous checks, so behind a std lock)
+    settings: std::sync::RwLock<Arc<UploadSettings>>,
     /// Chunked uploads in progress
     chunked_uploads: Arc<RwLock<HashMap<Uuid, ChunkedUpload>>>,
+    /// Chunks currently being written (upload ID, chunk index)
+    chunks_in_flight: Arc<RwLock<HashSet<(Uuid, usize)>>>,
+    /// Chunked uploads currently being assembled
+    completing: Arc<RwLock<HashSet<Uuid>>>,
+    /// Client for URL imports
+    url_importer: UrlImporter,
+    /// Folder service for archive imports
+    folder_service: Option<Arc<FolderService>>,
+    /// Malware scanner (uploads are not scanned without one)
+    scanner: RwLock<Option<Arc<dyn VirusScanner>>>,
+    /// Scan failure and quarantine policy
+    scan_settings: RwLock<ScanSettings>,
+    /// Content validator
+    validator: ContentValidator,
+    /// Results of requests sent with an idempotency key
+    idempotency: IdempotencyStore,
+    /// Per-user upload limits (uploads are not limited without one)
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// Upload batches and their progress events
+    batches: UploadBatches,
 }
 
 impl UploadService {
@@ -154,14 +311,77 @@ impl UploadService {
             image_service,
             media_service,
             optimizer,
-            settings: UploadSettings::default(),
+            settings: std::sync::RwLock::new(Arc::new(UploadSettings::default())),
             chunked_uploads: Arc::new(RwLock::new(HashMap::new())),
+            chunks_in_flight: Arc::new(RwLock::new(HashSet::new())),
+            completing: Arc::new(RwLock::new(HashSet::new())),
+            url_importer: UrlImporter::new(UrlImportSettings::default())
+                .expect("default URL import client"),
+            folder_service: None,
+            scanner: RwLock::new(None),
+            scan_settings: RwLock::new(ScanSettings::default()),
+            validator: ContentValidator::new(),
+            idempotency: IdempotencyStore::new(),
+            rate_limiter: None,
+            batches: UploadBatches::new(),
+        }
+    }
+
+    /// Set folder service (required for archive imports)
+    pub fn set_folder_service(&mut self, folder_service: Arc<FolderService>) {
+        self.folder_service = Some(folder_service);
+    }
+
+    /// Scan every upload before it is stored
+    pub fn set_scanner(&mut self, scanner: Arc<dyn VirusScanner>, settings: ScanSettings) {
+        *self.scanner.get_mut() = Some(scanner);
+        *self.scan_settings.get_mut() = settings;
+    }
+
+    /// Replace the scanner of a running service (`None` stops scanning)
+    pub async fn update_scanner(&self, scanner: Option<Arc<dyn VirusScanner>>, settings: ScanSettings) {
+        let mut current = self.scanner.write().await;
+        *self.scan_settings.write().await = settings;
+        *current = scanner;
+    }
+
+    /// Limit uploads per user
+    pub fn set_rate_limiter(&mut self, rate_limiter: Arc<RateLimiter>) {
+        self.rate_limiter = Some(rate_limiter);
+    }
+
+    /// Charge one upload of `size` bytes against the user's rate limits
+    pub async fn check_rate_limit(&self, user_id: Option<Uuid>, size: u64) -> Result<(), UploadError> {
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.acquire(user_id, size).await?;
         }
+        Ok(())
+    }
+
+    /// Upload batches and their progress events
+    pub fn batches(&self) -> &UploadBatches {
+        &self.batches
     }
 
     /// Configure settings
     pub fn configure(&mut self, settings: UploadSettings) {
-        self.settings = settings;
+        *self.settings.get_mut().unwrap_or_else(PoisonError::into_inner) = Arc::new(settings);
+    }
+
+    /// Replace the settings of a running service
+    pub fn update_settings(&self, settings: UploadSettings) {
+        *self.settings.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(settings);
+    }
+
+    /// Current settings
+    pub fn settings(&self) -> Arc<UploadSettings> {
+        Arc::clone(&self.settings.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Configure URL import (redirects, timeouts, allowed internal addresses)
+    pub fn configure_url_import(&mut self, settings: UrlImportSettings) -> Result<(), UploadError> {
+        self.url_importer = UrlImporter::new(settings)?;
+        Ok(())
     }
 
     /// Upload a file
@@ -172,32 +392,404 @@ impl UploadService {
         options: UploadOptions,
         user_id: Option<Uuid>,
     ) -> Result<MediaItem, UploadError> {
+        self.check_rate_limit(user_id, data.len() as u64).await?;
+        self.upload_without_limit(data, filename, options, user_id).await
+    }
+
+    /// Upload a file already charged against the rate limits
+    pub(crate) async fn upload_without_limit(
+        &self,
+        data: Vec<u8>,
+        filename: &str,
+        options: UploadOptions,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItem, UploadError> {
+        batch::report(FileState::Scanning).await;
+
         // Validate file
         let mime_type = self.detect_mime_type(&data, filename);
         self.validate_file(filename, data.len() as u64, Some(&mime_type))?;
+        if self.settings().validate_contents {
+            self.validator.validate(&data, filename)?;
+        }
+
+        if let Some(scanner) = self.scanner.read().await.clone() {
+            let verdict = scanner.scan(&data).await;
+            self.check_verdict(verdict, filename, Quarantine::Data(&data)).await?;
+        }
+
+        batch::report(FileState::Processing).await;
+        self.store_upload(data, filename, &mime_type, options, user_id).await
+    }
+
+    /// Upload a file at most once per idempotency key
+    ///
+    /// A retry with the same key and payload returns the first result; the
+    /// same key with a different payload is rejected.
+    pub async fn upload_idempotent(
+        &self,
+        key: &str,
+        data: Vec<u8>,
+        filename: &str,
+        options: UploadOptions,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItem, UploadError> {
+        let content_hash = Checksum::compute(ChecksumAlgorithm::Sha256, &data).value;
+        let fingerprint = upload_fingerprint(filename, &content_hash, &options, user_id);
+
+        self.idempotent(key, user_id, &fingerprint, self.upload(data, filename, options, user_id)).await
+    }
+
+    /// Run an operation at most once per idempotency key of a user
+    async fn idempotent(
+        &self,
+        key: &str,
+        user_id: Option<Uuid>,
+        fingerprint: &str,
+        operation: impl std::future::Future<Output = Result<MediaItem, UploadError>>,
+    ) -> Result<MediaItem, UploadError> {
+        let window = Duration::minutes(self.settings().idempotency_window_minutes as i64);
+
+        let guard = match self.idempotency.claim(user_id, key, fingerprint, window).await? {
+            Claim::Run(guard) => guard,
+            // Replay the item's current state where it still exists
+            Claim::Replay(media) => return Ok(self.media_service.get(media.id).await.unwrap_or(*media)),
+        };
+
+        match operation.await {
+            Ok(media) => {
+                guard.complete(&media, window).await;
+                Ok(media)
+            }
+            Err(e) => {
+                guard.release().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Upload a `data:` URL or raw base64 string
+    ///
+    /// Decoding stops at the maximum file size. A type declared by the data
+    /// URL must match the content; it also supplies the extension when
+    /// `filename` has none (or is empty).
+    pub async fn upload_encoded(
+        &self,
+        encoded: &str,
+        filename: &str,
+        options: UploadOptions,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItem, UploadError> {
+        let decoded = data_url::decode(encoded, self.settings().max_file_size).map_err(|e| match e {
+            DataUrlError::TooLarge(max) => UploadError::FileTooLarge(max + 1, max),
+            e => e.into(),
+        })?;
+
+        let declared = decoded.declared_type.filter(|t| t != "application/octet-stream");
+        let filename = match (std::path::Path::new(filename).extension(), &declared) {
+            (None, Some(declared)) => {
+                let extension = validator::extension_for(declared)
+                    .ok_or_else(|| UploadError::TypeNotAllowed(declared.clone()))?;
+                let stem = if filename.trim().is_empty() { "pasted" } else { filename.trim() };
+                format!("{}.{}", stem, extension)
+            }
+            (None, None) if filename.trim().is_empty() => {
+                return Err(UploadError::InvalidFile("A filename is required for base64 uploads".to_string()));
+            }
+            _ => filename.to_string(),
+        };
+
+        if let Some(declared) = declared {
+            let detected = self.detect_mime_type(&decoded.data, &filename);
+            if !same_type(&declared, &detected) {
+                return Err(UploadError::DeclaredTypeMismatch(declared, detected));
+            }
+        }
+
+        self.upload(decoded.data, &filename, options, user_id).await
+    }
+
+    /// Replace a media item's file, keeping its ID and URL
+    ///
+    /// The new file is scanned here; validation and rendition handling are
+    /// done by [`MediaService::replace`].
+    pub async fn replace(&self, id: Uuid, data: Vec<u8>, user_id: Option<Uuid>) -> Result<MediaItem, UploadError> {
+        let media = self.media_service.get(id).await
+            .ok_or_else(|| UploadError::NotFound(id.to_string()))?;
+
+        let max_file_size = self.settings().max_file_size;
+        if data.len() as u64 > max_file_size {
+            return Err(UploadError::FileTooLarge(data.len() as u64, max_file_size));
+        }
+
+        self.check_rate_limit(user_id, data.len() as u64).await?;
+
+        if let Some(scanner) = self.scanner.read().await.clone() {
+            let verdict = scanner.scan(&data).await;
+            self.check_verdict(verdict, &media.filename, Quarantine::Data(&data)).await?;
+        }
+
+        Ok(self.media_service.replace(id, &data, user_id).await?)
+    }
+
+    /// Start streaming an upload into temporary storage
+    ///
+    /// `max_size` is capped at the maximum file size.
+    pub fn stage(&self, max_size: u64) -> StagedFile {
+        StagedFile {
+            path: format!("temp/staged/{}", Uuid::now_v7()),
+            size: 0,
+            max_size: max_size.min(self.settings().max_file_size),
+            head: Vec::with_capacity(SNIFF_LEN),
+            hasher: ChecksumHasher::new(ChecksumAlgorithm::Sha256),
+        }
+    }
+
+    /// Append a block to a staged upload
+    ///
+    /// Fails without writing once the file would exceed its size limit.
+    pub async fn write_staged(&self, file: &mut StagedFile, bytes: &[u8]) -> Result<(), UploadError> {
+        let size = file.size + bytes.len() as u64;
+        if size > file.max_size {
+            return Err(UploadError::FileTooLarge(size, file.max_size));
+        }
+
+        file.hasher.update(bytes);
+        if file.head.len() < SNIFF_LEN {
+            let take = (SNIFF_LEN - file.head.len()).min(bytes.len());
+            file.head.extend_from_slice(&bytes[..take]);
+        }
+        self.storage.append(&file.path, bytes).await?;
+        file.size = size;
+
+        Ok(())
+    }
+
+    /// Validate, scan and store a staged upload
+    ///
+    /// The temporary file is removed whatever the outcome.
+    pub async fn upload_staged(
+        &self,
+        file: StagedFile,
+        filename: &str,
+        options: UploadOptions,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItem, UploadError> {
+        if file.size > 0 {
+            if let Err(e) = self.check_rate_limit(user_id, file.size).await {
+                self.discard_staged(file).await;
+                return Err(e);
+            }
+        }
+
+        self.upload_staged_without_limit(file, filename, options, user_id).await
+    }
+
+    /// Validate, scan and store a staged upload at most once per
+    /// idempotency key
+    ///
+    /// Retries are matched against [`UploadService::upload_idempotent`]
+    /// requests too. The temporary file is removed whatever the outcome.
+    pub async fn upload_staged_idempotent(
+        &self,
+        key: &str,
+        file: StagedFile,
+        filename: &str,
+        options: UploadOptions,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItem, UploadError> {
+        let content_hash = file.hasher.clone().finish().value;
+        let fingerprint = upload_fingerprint(filename, &content_hash, &options, user_id);
+        let path = file.path.clone();
+
+        let result = self.idempotent(key, user_id, &fingerprint, self.upload_staged(file, filename, options, user_id)).await;
+
+        // Replays and rejected keys never ran the upload
+        if self.storage.exists(&path).await {
+            let _ = self.storage.delete(&path).await;
+        }
+
+        result
+    }
+
+    /// Validate, scan and store a staged upload whose rate limits were
+    /// already charged
+    async fn upload_staged_without_limit(
+        &self,
+        file: StagedFile,
+        filename: &str,
+        options: UploadOptions,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItem, UploadError> {
+        let assembled = AssembledFile {
+            path: &file.path,
+            head: &file.head,
+            size: file.size,
+            content_hash: file.hasher.finish().value,
+        };
+
+        let result = if file.size == 0 {
+            Err(UploadError::InvalidFile(format!("{} is empty", filename)))
+        } else {
+            self.register_assembled(assembled, filename, options, user_id).await
+        };
+
+        if self.storage.exists(&file.path).await {
+            let _ = self.storage.delete(&file.path).await;
+        }
+
+        result
+    }
+
+    /// Validate, scan and store a complete file already in temporary
+    /// storage, whose rate limits were already charged
+    ///
+    /// The file is streamed for hashing and moved into place unless it is
+    /// an image small enough to process. It is left where it is on failure.
+    pub(crate) async fn upload_stored_without_limit(
+        &self,
+        path: &str,
+        filename: &str,
+        options: UploadOptions,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItem, UploadError> {
+        use tokio::io::AsyncReadExt;
+
+        let full_path = self.storage.full_path(path);
+        let mut head = Vec::with_capacity(SNIFF_LEN);
+        tokio::fs::File::open(&full_path)
+            .await
+            .map_err(super::storage::StorageError::from)?
+            .take(SNIFF_LEN as u64)
+            .read_to_end(&mut head)
+            .await
+            .map_err(super::storage::StorageError::from)?;
+
+        let assembled = AssembledFile {
+            path,
+            head: &head,
+            size: self.storage.size(path).await?,
+            content_hash: self.storage.hash_file(&full_path).await?,
+        };
+
+        if assembled.size == 0 {
+            return Err(UploadError::InvalidFile(format!("{} is empty", filename)));
+        }
+        self.register_assembled(assembled, filename, options, user_id).await
+    }
+
+    /// Drop a staged upload
+    pub async fn discard_staged(&self, file: StagedFile) {
+        if self.storage.exists(&file.path).await {
+            let _ = self.storage.delete(&file.path).await;
+        }
+    }
+
+    /// Optimize and store a validated (and scanned) upload
+    async fn store_upload(
+        &self,
+        data: Vec<u8>,
+        filename: &str,
+        mime_type: &str,
+        options: UploadOptions,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItem, UploadError> {
+        let mut pending = PendingUpload {
+            filename: filename.to_string(),
+            mime_type: mime_type.to_string(),
+            size: data.len() as u64,
+            data: Some(Bytes::from(data)),
+            options,
+            user_id,
+        };
+        self.approve(&mut pending, &[]).await?;
+        let PendingUpload { filename, mime_type, data, options, user_id, .. } = pending;
+        let data = data.map(Vec::from).unwrap_or_default();
+
+        // Rewrite SVGs before anything is stored
+        let mut svg_report = None;
+        let data = if mime_type == "image/svg+xml" && self.settings().sanitize_svg {
+            let sanitized = svg::sanitize(&data)?;
+            if !sanitized.report.is_clean() {
+                tracing::info!("Sanitized {}: removed {}", filename, sanitized.report.summary());
+                svg_report = Some(sanitized.report);
+            }
+            sanitized.data
+        } else {
+            data
+        };
+
+        // Optimization may strip EXIF, so read it from the original
+        let original_exif = if self.is_image(&mime_type) && options.optimize {
+            exif::read(&data).ok()
+        } else {
+            None
+        };
 
         // Process image if applicable
-        let processed_data = if self.is_image(&mime_type) && options.optimize {
-            self.optimizer.resize_and_optimize(&data, 2048, 2048)
-                .await
-                .map(|o| o.data)
-                .unwrap_or(data)
+        let mut optimization = None;
+        let mut filename = filename;
+        let mut mime_type = mime_type.as_str();
+        let processed_data = if self.is_image(mime_type) && options.optimize {
+            match self.optimizer.optimize_within_limits(&data).await {
+                Ok(optimized) => {
+                    // The optimizer may pick another format (WebP)
+                    if optimized.format.mime_type() != mime_type {
+                        mime_type = optimized.format.mime_type();
+                        filename = std::path::Path::new(&filename)
+                            .with_extension(optimized.format.extension())
+                            .to_string_lossy()
+                            .into_owned();
+                    }
+                    optimization = Some(optimized.result());
+                    optimized.data
+                }
+                Err(_) => data,
+            }
         } else {
             data
         };
 
         // Upload via media service
-        let media = self.media_service.upload(
-            processed_data,
-            filename,
-            options,
+        let media = self.media_service.upload_approved(
+            &processed_data,
+            &filename,
+            mime_type,
+            &options,
             user_id,
         ).await?;
 
+        // Content resolved to an existing item: nothing new was stored
+        if media.duplicate_of.is_some() {
+            return Ok(media);
+        }
+
+        let media = match svg_report {
+            Some(report) => self.media_service
+                .set_custom_metadata(media.id, "svg_sanitized", report.summary())
+                .await?,
+            None => media,
+        };
+
+        // Stripped files are stored upright
+        let media = match original_exif {
+            Some(mut metadata) if media.metadata.exif.is_none() => {
+                metadata.exif.orientation = metadata.exif.orientation.map(|_| 1);
+                self.media_service.set_exif(media.id, metadata).await?
+            }
+            _ => media,
+        };
+
+        if let Some(result) = optimization {
+            self.media_service.hooks().after_optimize(&media, &result).await;
+        }
+
         Ok(media)
     }
 
     /// Initialize chunked upload
+    ///
+    /// `checksum` is the expected checksum of the whole file, verified on completion.
     pub async fn init_chunked_upload(
         &self,
         filename: &str,
@@ -205,12 +797,26 @@ impl UploadService {
         chunk_size: usize,
         total_chunks: usize,
         mime_type: Option<String>,
+        checksum: Option<Checksum>,
         folder_id: Option<Uuid>,
         user_id: Option<Uuid>,
     ) -> Result<ChunkedUpload, UploadError> {
         // Validate
-        if total_size > self.settings.max_file_size {
-            return Err(UploadError::FileTooLarge(total_size, self.settings.max_file_size));
+        let max_file_size = self.settings().max_file_size;
+        if total_size > max_file_size {
+            return Err(UploadError::FileTooLarge(total_size, max_file_size));
+        }
+
+        if chunk_size == 0 {
+            return Err(UploadError::InvalidChunkLayout("Chunk size must be greater than zero".to_string()));
+        }
+
+        let expected_chunks = total_size.div_ceil(chunk_size as u64).max(1) as usize;
+        if total_chunks != expected_chunks {
+            return Err(UploadError::InvalidChunkLayout(format!(
+                "{} bytes in {} byte chunks needs {} chunks, got {}",
+                total_size, chunk_size, expected_chunks, total_chunks
+            )));
         }
 
         let ext = std::path::Path::new(filename)
@@ -219,10 +825,19 @@ impl UploadService {
             .unwrap_or("")
             .to_lowercase();
 
-        if !self.settings.allowed_extensions.contains(&ext) {
+        if !self.settings().allowed_extensions.contains(&ext) {
             return Err(UploadError::TypeNotAllowed(ext));
         }
 
+        let upload_id = Uuid::now_v7();
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.open_session(user_id, upload_id).await?;
+            if let Err(e) = limiter.acquire(user_id, total_size).await {
+                limiter.close_session(upload_id).await;
+                return Err(e.into());
+            }
+        }
+
         // Create chunks info
         let chunks: Vec<ChunkInfo> = (0..total_chunks)
             .map(|i| {
@@ -240,120 +855,412 @@ impl UploadService {
             .collect();
 
         let upload = ChunkedUpload {
-            id: Uuid::now_v7(),
+            id: upload_id,
             filename: filename.to_string(),
             total_size,
             chunk_size,
             total_chunks,
             chunks,
             mime_type,
+            checksum,
             folder_id,
             user_id,
             temp_path: format!("temp/chunks/{}", Uuid::now_v7()),
             started_at: Utc::now(),
-            expires_at: Utc::now() + Duration::hours(self.settings.chunk_expiry_hours as i64),
+            expires_at: Utc::now() + Duration::hours(self.settings().chunk_expiry_hours as i64),
+            batch: None,
         };
 
-        // Store
-        let mut uploads = self.chunked_uploads.write().await;
-        uploads.insert(upload.id, upload.clone());
-
         // Create temp directory
-        self.storage.create_directory(&upload.temp_path).await?;
+        if let Err(e) = self.storage.create_directory(&upload.temp_path).await {
+            self.close_session(upload_id).await;
+            return Err(e.into());
+        }
+
+        // Store
+        self.chunked_uploads.write().await.insert(upload.id, upload.clone());
 
         Ok(upload)
     }
 
     /// Upload a chunk
+    ///
+    /// Chunks may arrive in any order and in parallel. The chunk length must
+    /// match its slot in the layout and, when `checksum` is given, the data
+    /// must match it. A chunk that was already received is only rewritten
+    /// when its content differs.
     pub async fn upload_chunk(
         &self,
         upload_id: Uuid,
         chunk_index: usize,
         data: Vec<u8>,
+        checksum: Option<Checksum>,
     ) -> Result<ChunkedUpload, UploadError> {
-        let mut uploads = self.chunked_uploads.write().await;
+        let (expected, temp_path) = {
+            let uploads = self.chunked_uploads.read().await;
+            let upload = uploads.get(&upload_id)
+                .ok_or_else(|| UploadError::NotFound(upload_id.to_string()))?;
 
-        let upload = uploads.get_mut(&upload_id)
-            .ok_or_else(|| UploadError::NotFound(upload_id.to_string()))?;
+            if Utc::now() > upload.expires_at {
+                drop(uploads);
+                self.chunked_uploads.write().await.remove(&upload_id);
+                self.close_session(upload_id).await;
+                return Err(UploadError::Expired);
+            }
+
+            let chunk = upload.chunks.get(chunk_index)
+                .ok_or(UploadError::InvalidChunk(chunk_index))?;
+
+            (chunk.clone(), upload.temp_path.clone())
+        };
+
+        // Verify before touching storage
+        if data.len() != expected.size {
+            return Err(UploadError::ChunkSizeMismatch(chunk_index, expected.size, data.len()));
+        }
 
-        // Check expiry
-        if Utc::now() > upload.expires_at {
-            uploads.remove(&upload_id);
-            return Err(UploadError::Expired);
+        let received = match &checksum {
+            Some(checksum) => {
+                let actual = Checksum::compute(checksum.algorithm, &data);
+                if actual.value != checksum.value {
+                    return Err(UploadError::ChunkChecksumMismatch(
+                        chunk_index,
+                        checksum.to_string(),
+                        actual.to_string(),
+                    ));
+                }
+                actual
+            }
+            None => Checksum::compute(ChecksumAlgorithm::Md5, &data),
+        };
+
+        // Resending an identical chunk is a no-op
+        if expected.received {
+            if let Some(existing) = &expected.checksum {
+                if existing.matches(&data) {
+                    return self.get_chunked_upload(upload_id)
+                        .await
+                        .ok_or_else(|| UploadError::NotFound(upload_id.to_string()));
+                }
+            }
+        }
+
+        if !self.chunks_in_flight.write().await.insert((upload_id, chunk_index)) {
+            return Err(UploadError::ChunkInProgress(chunk_index));
         }
+        // Chunks must not change while the file is assembled
+        if self.completing.read().await.contains(&upload_id) {
+            self.chunks_in_flight.write().await.remove(&(upload_id, chunk_index));
+            return Err(UploadError::Completing);
+        }
+
+        let result = self.write_chunk(upload_id, chunk_index, &temp_path, &data, received).await;
+
+        self.chunks_in_flight.write().await.remove(&(upload_id, chunk_index));
+
+        result
+    }
 
-        // Validate chunk index
-        if chunk_index >= upload.total_chunks {
-            return Err(UploadError::InvalidFile(format!("Invalid chunk index: {}", chunk_index)));
+    /// Write a verified chunk and mark it received
+    ///
+    /// The upload map is not locked while writing, so other chunks can be
+    /// stored concurrently.
+    async fn write_chunk(
+        &self,
+        upload_id: Uuid,
+        chunk_index: usize,
+        temp_path: &str,
+        data: &[u8],
+        checksum: Checksum,
+    ) -> Result<ChunkedUpload, UploadError> {
+        // A replaced chunk is not usable until the new data is on disk
+        if let Some(chunk) = self.chunked_uploads.write().await
+            .get_mut(&upload_id)
+            .and_then(|u| u.chunks.get_mut(chunk_index))
+        {
+            chunk.received = false;
         }
 
-        // Save chunk to temp storage
-        let chunk_path = format!("{}/chunk_{}", upload.temp_path, chunk_index);
-        self.storage.write(&chunk_path, &data).await?;
+        let chunk_path = format!("{}/chunk_{}", temp_path, chunk_index);
+        self.storage.write(&chunk_path, data).await?;
+
+        let mut uploads = self.chunked_uploads.write().await;
+        let upload = uploads.get_mut(&upload_id)
+            .ok_or_else(|| UploadError::NotFound(upload_id.to_string()))?;
 
-        // Update chunk info
         if let Some(chunk) = upload.chunks.get_mut(chunk_index) {
             chunk.received = true;
-            chunk.checksum = Some(format!("{:x}", md5::compute(&data)));
+            chunk.checksum = Some(checksum);
         }
 
         Ok(upload.clone())
     }
 
     /// Complete chunked upload
+    ///
+    /// Only one caller can complete an upload at a time; others get
+    /// [`UploadError::Completing`] until it is done.
     pub async fn complete_chunked_upload(&self, upload_id: Uuid) -> Result<MediaItem, UploadError> {
         let upload = {
-            let uploads = self.chunked_uploads.read().await;
-            uploads.get(&upload_id)
+            let uploads = self.chunked_uploads.write().await;
+            let upload = uploads.get(&upload_id)
                 .cloned()
-                .ok_or_else(|| UploadError::NotFound(upload_id.to_string()))?
-        };
+                .ok_or_else(|| UploadError::NotFound(upload_id.to_string()))?;
 
-        // Verify all chunks received
-        for (i, chunk) in upload.chunks.iter().enumerate() {
-            if !chunk.received {
-                return Err(UploadError::ChunkMissing(i));
+            // Verify all chunks received
+            for (i, chunk) in upload.chunks.iter().enumerate() {
+                if !chunk.received {
+                    return Err(UploadError::ChunkMissing(i));
+                }
             }
-        }
-
-        // Assemble file
-        let mut data = Vec::with_capacity(upload.total_size as usize);
-        for i in 0..upload.total_chunks {
-            let chunk_path = format!("{}/chunk_{}", upload.temp_path, i);
-            let chunk_data = self.storage.read(&chunk_path).await?;
-            data.extend(chunk_data);
-        }
 
-        // Upload assembled file
-        let options = UploadOptions {
-            folder_id: upload.folder_id,
-            title: None,
-            description: None,
-            alt_text: None,
-            tags: vec![],
-            optimize: self.settings.auto_optimize,
-            generate_thumbnails: self.settings.auto_thumbnails,
+            if !self.completing.write().await.insert(upload_id) {
+                return Err(UploadError::Completing);
+            }
+            upload
         };
 
-        let media = self.upload(&data, &upload.filename, options, upload.user_id).await?;
+        let result = self.finish_chunked_upload(&upload).await;
+        self.completing.write().await.remove(&upload_id);
+        result
+    }
+
+    /// Assemble a chunked upload marked as completing, then stop tracking it
+    async fn finish_chunked_upload(&self, upload: &ChunkedUpload) -> Result<MediaItem, UploadError> {
+        let upload_id = upload.id;
+        if let Some(&(_, index)) = self.chunks_in_flight.read().await
+            .iter()
+            .find(|(id, _)| *id == upload_id)
+        {
+            return Err(UploadError::ChunkInProgress(index));
+        }
+
+        let assembled_path = format!("{}/assembled", upload.temp_path);
+        let result = self.assemble_chunked_upload(upload, &assembled_path).await;
+        if result.is_err() {
+            let _ = self.storage.delete(&assembled_path).await;
+        }
+        let media = result?;
 
         // Cleanup temp files
         self.storage.delete_directory(&upload.temp_path).await?;
 
         // Remove from tracking
-        let mut uploads = self.chunked_uploads.write().await;
-        uploads.remove(&upload_id);
+        self.chunked_uploads.write().await.remove(&upload_id);
+        self.close_session(upload_id).await;
 
         Ok(media)
     }
 
-    /// Cancel chunked upload
-    pub async fn cancel_chunked_upload(&self, upload_id: Uuid) -> Result<(), UploadError> {
-        let upload = {
-            let mut uploads = self.chunked_uploads.write().await;
-            uploads.remove(&upload_id)
-                .ok_or_else(|| UploadError::NotFound(upload_id.to_string()))?
-        };
+    /// Complete a chunked upload at most once per idempotency key
+    ///
+    /// Retries after a successful completion get the stored item back
+    /// instead of [`UploadError::NotFound`].
+    pub async fn complete_chunked_upload_idempotent(
+        &self,
+        key: &str,
+        upload_id: Uuid,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItem, UploadError> {
+        let fingerprint = IdempotencyStore::fingerprint(&[
+            b"chunked",
+            id_bytes(user_id).as_slice(),
+            upload_id.as_bytes(),
+        ]);
+        self.idempotent(key, user_id, &fingerprint, self.complete_chunked_upload(upload_id)).await
+    }
+
+    /// Concatenate chunks into `assembled_path` and register the result
+    ///
+    /// Chunks are streamed through storage in index order while the content
+    /// hash, the expected checksum and the MIME sniffing buffer are fed, so
+    /// the file is never held in memory. Only images small enough to
+    /// process are read back for optimization and thumbnails.
+    async fn assemble_chunked_upload(
+        &self,
+        upload: &ChunkedUpload,
+        assembled_path: &str,
+    ) -> Result<MediaItem, UploadError> {
+        let sources: Vec<String> = (0..upload.total_chunks)
+            .map(|i| format!("{}/chunk_{}", upload.temp_path, i))
+            .collect();
+
+        let mut content_hasher = ChecksumHasher::new(ChecksumAlgorithm::Sha256);
+        let mut checksum_hasher = upload.checksum
+            .as_ref()
+            .map(|c| ChecksumHasher::new(c.algorithm));
+        let mut head = Vec::with_capacity(SNIFF_LEN);
+
+        let size = self.storage.concatenate(&sources, assembled_path, |bytes| {
+            content_hasher.update(bytes);
+            if let Some(hasher) = checksum_hasher.as_mut() {
+                hasher.update(bytes);
+            }
+            if head.len() < SNIFF_LEN {
+                let take = (SNIFF_LEN - head.len()).min(bytes.len());
+                head.extend_from_slice(&bytes[..take]);
+            }
+        }).await?;
+
+        if size != upload.total_size {
+            return Err(UploadError::SizeMismatch(upload.total_size, size));
+        }
+
+        if let (Some(expected), Some(hasher)) = (&upload.checksum, checksum_hasher) {
+            let actual = hasher.finish();
+            if actual != *expected {
+                return Err(UploadError::ChecksumMismatch(expected.to_string(), actual.to_string()));
+            }
+        }
+
+        let settings = self.settings();
+        let options = UploadOptions {
+            folder_id: upload.folder_id,
+            title: None,
+            description: None,
+            alt_text: None,
+            tags: vec![],
+            optimize: settings.auto_optimize,
+            generate_thumbnails: settings.auto_thumbnails,
+            on_duplicate: None,
+            allow_degraded: false,
+        };
+
+        let assembled = AssembledFile {
+            path: assembled_path,
+            head: &head,
+            size,
+            content_hash: content_hasher.finish().value,
+        };
+
+        self.register_assembled(assembled, &upload.filename, options, upload.user_id).await
+    }
+
+    /// Validate, scan and store a complete file from temporary storage
+    ///
+    /// Only images small enough to process are read back into memory;
+    /// everything else is moved into place.
+    async fn register_assembled(
+        &self,
+        file: AssembledFile<'_>,
+        filename: &str,
+        options: UploadOptions,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItem, UploadError> {
+        batch::report(FileState::Scanning).await;
+
+        let mime_type = self.detect_mime_type(file.head, filename);
+        self.validate_file(filename, file.size, Some(&mime_type))?;
+
+        // Files streamed through storage only get their type checked here;
+        // images loaded below are validated in full
+        let settings = self.settings();
+        let is_svg = mime_type == "image/svg+xml";
+        let processable = (self.is_image(&mime_type) || is_svg) && file.size <= settings.max_image_process_size;
+        if is_svg && settings.sanitize_svg && !processable {
+            return Err(UploadError::InvalidFile(format!("SVG too large to sanitize: {} bytes", file.size)));
+        }
+        if settings.validate_contents && !processable {
+            self.validator.check_type(file.head, filename)?;
+        }
+
+        if let Some(scanner) = self.scanner.read().await.clone() {
+            let mut reader = tokio::fs::File::open(self.storage.full_path(file.path))
+                .await
+                .map_err(super::storage::StorageError::from)?;
+            let verdict = scanner.scan_reader(&mut reader).await;
+            self.check_verdict(verdict, filename, Quarantine::Stored(file.path)).await?;
+        }
+
+        batch::report(FileState::Processing).await;
+        if processable {
+            let data = self.storage.read(file.path).await?;
+            if settings.validate_contents {
+                self.validator.validate(&data, filename)?;
+            }
+            return self.store_upload(data, filename, &mime_type, options, user_id).await;
+        }
+
+        let mut pending = PendingUpload {
+            filename: filename.to_string(),
+            mime_type,
+            size: file.size,
+            data: None,
+            options,
+            user_id,
+        };
+        self.approve(&mut pending, file.head).await?;
+        if pending.mime_type == "image/svg+xml" && settings.sanitize_svg {
+            return Err(UploadError::InvalidFile(format!("SVG too large to sanitize: {} bytes", file.size)));
+        }
+
+        let media = self.media_service.upload_from_storage_approved(
+            file.path,
+            &pending.filename,
+            &pending.mime_type,
+            file.content_hash,
+            &pending.options,
+            pending.user_id,
+        ).await?;
+
+        Ok(media)
+    }
+
+    /// Run the `before_upload` hooks and check whatever they changed
+    ///
+    /// Hooks run once the upload passed validation and scanning, so a new
+    /// name, type or contents goes through both again (`head` stands in
+    /// for contents the upload does not hold). Rewritten SVGs are sanitized
+    /// afterwards like any other.
+    async fn approve(&self, pending: &mut PendingUpload, head: &[u8]) -> Result<(), UploadError> {
+        let (filename, mime_type, data) = (pending.filename.clone(), pending.mime_type.clone(), pending.data.clone());
+        self.media_service.hooks().before_upload(pending).await.map_err(MediaError::from)?;
+
+        // Contents can be replaced, but not taken away or added to
+        // streamed files
+        if pending.data.is_none() != data.is_none() {
+            pending.data = data.clone();
+        }
+        let rewritten = pending.data != data;
+        if !rewritten && pending.filename == filename && pending.mime_type == mime_type {
+            return Ok(());
+        }
+
+        let contents = pending.data.clone();
+        let contents = contents.as_deref().unwrap_or(head);
+        if rewritten {
+            pending.size = contents.len() as u64;
+        }
+        pending.mime_type = self.detect_mime_type(contents, &pending.filename);
+        self.validate_file(&pending.filename, pending.size, Some(&pending.mime_type))?;
+        if self.settings().validate_contents {
+            match pending.data {
+                Some(_) => self.validator.validate(contents, &pending.filename)?,
+                None => self.validator.check_type(contents, &pending.filename)?,
+            };
+        }
+
+        if rewritten {
+            if let Some(scanner) = self.scanner.read().await.clone() {
+                let verdict = scanner.scan(contents).await;
+                self.check_verdict(verdict, &pending.filename, Quarantine::Data(contents)).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Cancel chunked upload
+    pub async fn cancel_chunked_upload(&self, upload_id: Uuid) -> Result<(), UploadError> {
+        let upload = {
+            let mut uploads = self.chunked_uploads.write().await;
+            if self.completing.read().await.contains(&upload_id) {
+                return Err(UploadError::Completing);
+            }
+            uploads.remove(&upload_id)
+                .ok_or_else(|| UploadError::NotFound(upload_id.to_string()))?
+        };
+        self.close_session(upload_id).await;
 
         // Cleanup temp files
         self.storage.delete_directory(&upload.temp_path).await?;
@@ -361,6 +1268,21 @@ impl UploadService {
         Ok(())
     }
 
+    /// Report the progress of a chunked upload to a batch file
+    pub async fn attach_batch(&self, upload_id: Uuid, file: BatchFileRef) -> Result<ChunkedUpload, UploadError> {
+        let mut uploads = self.chunked_uploads.write().await;
+        let upload = uploads.get_mut(&upload_id)
+            .ok_or_else(|| UploadError::NotFound(upload_id.to_string()))?;
+        upload.batch = Some(file);
+        Ok(upload.clone())
+    }
+
+    async fn close_session(&self, upload_id: Uuid) {
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.close_session(upload_id).await;
+        }
+    }
+
     /// Get chunked upload
     pub async fn get_chunked_upload(&self, upload_id: Uuid) -> Option<ChunkedUpload> {
         let uploads = self.chunked_uploads.read().await;
@@ -368,6 +1290,9 @@ impl UploadService {
     }
 
     /// Upload from URL
+    ///
+    /// Internal addresses are refused (including after DNS resolution and
+    /// redirects) and the download is capped at the maximum file size.
     pub async fn upload_from_url(
         &self,
         url: &str,
@@ -375,55 +1300,257 @@ impl UploadService {
         folder_id: Option<Uuid>,
         user_id: Option<Uuid>,
     ) -> Result<MediaItem, UploadError> {
-        // Download file
-        let response = reqwest::get(url).await
-            .map_err(|e| UploadError::Network(e.to_string()))?;
-
-        if !response.status().is_success() {
-            return Err(UploadError::Network(format!("HTTP {}", response.status())));
-        }
+        let settings = self.settings();
+        let fetched = self.url_importer
+            .fetch(url, settings.max_file_size, &settings.allowed_types)
+            .await?;
 
-        // Get filename from URL or header
         let final_filename = filename
             .map(|s| s.to_string())
-            .or_else(|| {
-                response.headers()
-                    .get("content-disposition")
-                    .and_then(|v| v.to_str().ok())
-                    .and_then(|v| {
-                        v.split("filename=")
-                            .nth(1)
-                            .map(|s| s.trim_matches('"').to_string())
-                    })
-            })
-            .or_else(|| {
-                url.split('/').last()
-                    .map(|s| s.split('?').next().unwrap_or(s).to_string())
-            })
+            .or(fetched.filename)
             .unwrap_or_else(|| format!("download_{}", Uuid::now_v7()));
 
-        let data = response.bytes().await
-            .map_err(|e| UploadError::Network(e.to_string()))?
-            .to_vec();
-
         let options = UploadOptions {
             folder_id,
             title: None,
             description: None,
             alt_text: None,
             tags: vec![],
-            optimize: self.settings.auto_optimize,
-            generate_thumbnails: self.settings.auto_thumbnails,
+            optimize: settings.auto_optimize,
+            generate_thumbnails: settings.auto_thumbnails,
+            on_duplicate: None,
+            allow_degraded: false,
         };
 
-        self.upload(data, &final_filename, options, user_id).await
+        self.upload(fetched.data, &final_filename, options, user_id).await
+    }
+
+    /// Import from a URL at most once per idempotency key
+    pub async fn upload_from_url_idempotent(
+        &self,
+        key: &str,
+        url: &str,
+        filename: Option<&str>,
+        folder_id: Option<Uuid>,
+        user_id: Option<Uuid>,
+    ) -> Result<MediaItem, UploadError> {
+        let fingerprint = IdempotencyStore::fingerprint(&[
+            b"url",
+            id_bytes(user_id).as_slice(),
+            url.as_bytes(),
+            filename.unwrap_or_default().as_bytes(),
+            id_bytes(folder_id).as_slice(),
+        ]);
+
+        self.idempotent(key, user_id, &fingerprint, self.upload_from_url(url, filename, folder_id, user_id)).await
+    }
+
+    /// Import a `.zip`, `.tar` or `.tar.gz` archive
+    ///
+    /// Directories become folders under `folder_id` and every file goes
+    /// through the same validation as [`UploadService::upload`]. Problems
+    /// with single entries are recorded in the report. A limit violation or
+    /// corrupt data stops the import; entries imported before it are kept
+    /// and the error is recorded as [`ArchiveImportReport::error`].
+    ///
+    /// Entries are streamed into staged files one at a time, so only
+    /// images small enough to process are ever loaded into memory.
+    pub async fn import_archive(
+        &self,
+        data: Vec<u8>,
+        filename: &str,
+        folder_id: Option<Uuid>,
+        user_id: Option<Uuid>,
+    ) -> Result<ArchiveImportReport, UploadError> {
+        let folder_service = self.folder_service.as_ref()
+            .ok_or_else(|| UploadError::InvalidFile("Archive import requires a folder service".to_string()))?;
+
+        let format = ArchiveFormat::detect(&data, filename)
+            .ok_or_else(|| ArchiveError::Unsupported(filename.to_string()))?;
+
+        // The archive counts as one upload of its compressed size
+        self.check_rate_limit(user_id, data.len() as u64).await?;
+
+        let settings = self.settings();
+        let mut limits = settings.archive_limits.clone();
+        limits.max_entry_size.get_or_insert(settings.max_file_size);
+
+        let (sender, mut parts) = tokio::sync::mpsc::channel(4);
+        let reader = tokio::task::spawn_blocking(move || {
+            archive::read_archive(&data, format, &limits, |entry, contents| {
+                let send = |part| sender.blocking_send(part).map_err(|_| ArchiveError::Stopped);
+                let is_file = matches!(entry, ArchiveEntry::File { .. });
+                send(ArchivePart::Entry(entry))?;
+                if !is_file {
+                    return Ok(());
+                }
+
+                let mut buffer = vec![0; ARCHIVE_BLOCK];
+                loop {
+                    let read = contents.read(&mut buffer).map_err(|e| ArchiveError::Corrupt(e.to_string()))?;
+                    if read == 0 {
+                        return send(ArchivePart::End);
+                    }
+                    send(ArchivePart::Block(buffer[..read].to_vec()))?;
+                }
+            })
+        });
+
+        let mut report = ArchiveImportReport::default();
+        let mut folders: HashMap<Vec<String>, Uuid> = HashMap::new();
+        let mut current: Option<ArchiveFile> = None;
+
+        while let Some(part) = parts.recv().await {
+            match part {
+                ArchivePart::Entry(ArchiveEntry::Directory { path, dirs }) => {
+                    if let Err(e) = self.archive_folder(folder_service, &dirs, folder_id, user_id, &mut folders, &mut report).await {
+                        report.push(path, EntryStatus::Failed { error: e.to_string() });
+                    }
+                }
+                ArchivePart::Entry(ArchiveEntry::File { path, dirs, name }) => {
+                    let staged = self.archive_folder(folder_service, &dirs, folder_id, user_id, &mut folders, &mut report)
+                        .await
+                        .map(|target| (self.stage(u64::MAX), target));
+                    current = Some(ArchiveFile { path, name, staged });
+                }
+                ArchivePart::Entry(ArchiveEntry::Skipped { path, reason }) => {
+                    report.push(path, EntryStatus::Skipped { reason });
+                }
+                ArchivePart::Block(bytes) => {
+                    if let Some(ArchiveFile { staged, .. }) = current.as_mut() {
+                        if let Ok((file, _)) = staged {
+                            if let Err(e) = self.write_staged(file, &bytes).await {
+                                if let Ok((file, _)) = std::mem::replace(staged, Err(e)) {
+                                    self.discard_staged(file).await;
+                                }
+                            }
+                        }
+                    }
+                }
+                ArchivePart::End => {
+                    let Some(ArchiveFile { path, name, staged }) = current.take() else { continue };
+                    let result = match staged {
+                        Ok((file, target)) => {
+                            let options = UploadOptions {
+                                folder_id: target,
+                                optimize: settings.auto_optimize,
+                                generate_thumbnails: settings.auto_thumbnails,
+                                ..Default::default()
+                            };
+                            self.upload_staged_without_limit(file, &name, options, user_id).await
+                        }
+                        Err(e) => Err(e),
+                    };
+
+                    let status = match result {
+                        Ok(media) => EntryStatus::Imported { media_id: media.id },
+                        Err(e) => EntryStatus::Failed { error: e.to_string() },
+                    };
+                    report.push(path, status);
+                }
+            }
+        }
+
+        // A file cut short by a limit
+        if let Some(ArchiveFile { staged: Ok((file, _)), .. }) = current {
+            self.discard_staged(file).await;
+        }
+
+        if let Err(e) = reader.await.map_err(|e| UploadError::InvalidFile(e.to_string()))? {
+            report.error = Some(e.to_string());
+        }
+
+        Ok(report)
+    }
+
+    /// Resolve (creating as needed) the folder for an archive directory path
+    async fn archive_folder(
+        &self,
+        folder_service: &FolderService,
+        dirs: &[String],
+        root: Option<Uuid>,
+        user_id: Option<Uuid>,
+        folders: &mut HashMap<Vec<String>, Uuid>,
+        report: &mut ArchiveImportReport,
+    ) -> Result<Option<Uuid>, UploadError> {
+        let mut parent = root;
+
+        for depth in 1..=dirs.len() {
+            let key = dirs[..depth].to_vec();
+            if let Some(&id) = folders.get(&key) {
+                parent = Some(id);
+                continue;
+            }
+
+            let (folder, created) = folder_service.ensure_child(&dirs[depth - 1], parent, user_id).await?;
+            if created {
+                report.folders_created.push(folder.id);
+            }
+            let id = folder.id;
+
+            folders.insert(key, id);
+            parent = Some(id);
+        }
+
+        Ok(parent)
+    }
+
+    /// Apply a scan verdict: reject (and optionally quarantine) infected
+    /// files, and apply the failure policy when there is no verdict
+    async fn check_verdict(
+        &self,
+        verdict: Result<ScanVerdict, ScanError>,
+        filename: &str,
+        file: Quarantine<'_>,
+    ) -> Result<(), UploadError> {
+        let settings = self.scan_settings.read().await.clone();
+        match verdict {
+            Ok(ScanVerdict::Clean) => Ok(()),
+            Ok(ScanVerdict::Infected(signature)) => {
+                tracing::warn!("Rejected {}: {} detected", filename, signature);
+                if settings.quarantine {
+                    if let Err(e) = self.quarantine(filename, file).await {
+                        tracing::warn!("Failed to quarantine {}: {}", filename, e);
+                    }
+                }
+                Err(UploadError::Infected(signature))
+            }
+            // Failing open would let anything past by making it big enough
+            Err(e @ ScanError::TooLarge) => Err(e.into()),
+            Err(e) => match settings.on_failure {
+                ScanFailurePolicy::FailOpen => {
+                    tracing::warn!("Accepting {} unscanned: {}", filename, e);
+                    Ok(())
+                }
+                ScanFailurePolicy::FailClosed => Err(e.into()),
+            },
+        }
+    }
+
+    /// Keep an infected file under the quarantine directory
+    async fn quarantine(&self, filename: &str, file: Quarantine<'_>) -> Result<String, UploadError> {
+        let name = std::path::Path::new(filename)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("upload");
+        let dir = self.scan_settings.read().await.quarantine_dir.clone();
+        let path = format!("{}/{}-{}", dir, Uuid::now_v7(), name);
+
+        match file {
+            Quarantine::Data(data) => self.storage.write(&path, data).await?,
+            Quarantine::Stored(source) => self.storage.move_file(source, &path).await?,
+        }
+
+        Ok(path)
     }
 
     /// Validate file
     pub fn validate_file(&self, filename: &str, size: u64, mime_type: Option<&str>) -> Result<(), UploadError> {
+        let settings = self.settings();
+
         // Check size
-        if size > self.settings.max_file_size {
-            return Err(UploadError::FileTooLarge(size, self.settings.max_file_size));
+        if size > settings.max_file_size {
+            return Err(UploadError::FileTooLarge(size, settings.max_file_size));
         }
 
         // Check extension
@@ -433,13 +1560,13 @@ impl UploadService {
             .unwrap_or("")
             .to_lowercase();
 
-        if !self.settings.allowed_extensions.contains(&ext) {
+        if !settings.allowed_extensions.contains(&ext) {
             return Err(UploadError::TypeNotAllowed(ext));
         }
 
         // Check MIME type
         if let Some(mime) = mime_type {
-            if !self.settings.allowed_types.iter().any(|t| t == mime || t.starts_with(&format!("{}/*", mime.split('/').next().unwrap_or("")))) {
+            if !settings.allowed_types.iter().any(|t| t == mime || t.starts_with(&format!("{}/*", mime.split('/').next().unwrap_or("")))) {
                 return Err(UploadError::TypeNotAllowed(mime.to_string()));
             }
         }
@@ -449,32 +1576,37 @@ impl UploadService {
 
     /// Get allowed file types
     pub fn get_allowed_types(&self) -> Vec<String> {
-        self.settings.allowed_types.clone()
+        self.settings().allowed_types.clone()
     }
 
     /// Get allowed extensions
     pub fn get_allowed_extensions(&self) -> Vec<String> {
-        self.settings.allowed_extensions.clone()
+        self.settings().allowed_extensions.clone()
     }
 
     /// Get max file size
     pub fn get_max_file_size(&self) -> u64 {
-        self.settings.max_file_size
+        self.settings().max_file_size
     }
 
     /// Detect MIME type
     fn detect_mime_type(&self, data: &[u8], filename: &str) -> String {
+        let ext = std::path::Path::new(filename)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+
         // Try to detect from content
         if let Some(kind) = infer::get(data) {
+            // SVGs with an XML declaration are detected as generic XML
+            if kind.mime_type() == "text/xml" && ext == "svg" {
+                return "image/svg+xml".to_string();
+            }
             return kind.mime_type().to_string();
         }
 
         // Fall back to extension
-        let ext = std::path::Path::new(filename)
-            .extension()
-            .and_then(|e| e.to_str())
-            .unwrap_or("")
-            .to_lowercase();
 
         match ext.as_str() {
             "jpg" | "jpeg" => "image/jpeg",
@@ -506,10 +1638,11 @@ impl UploadService {
     /// Cleanup expired uploads
     pub async fn cleanup_expired(&self) -> usize {
         let mut uploads = self.chunked_uploads.write().await;
+        let completing = self.completing.read().await;
         let now = Utc::now();
 
         let expired: Vec<Uuid> = uploads.iter()
-            .filter(|(_, u)| u.expires_at < now)
+            .filter(|(id, u)| u.expires_at < now && !completing.contains(id))
             .map(|(id, _)| *id)
             .collect();
 
@@ -519,8 +1652,572 @@ impl UploadService {
             if let Some(upload) = uploads.remove(&id) {
                 let _ = self.storage.delete_directory(&upload.temp_path).await;
             }
+            self.close_session(id).await;
         }
 
+        // Batches go quiet once done, or when the client gave up
+        self.batches.cleanup(Duration::hours(self.settings().chunk_expiry_hours as i64)).await;
+
         count
     }
 }
+
+/// Optional ID as fingerprint input (empty for `None`)
+fn id_bytes(id: Option<Uuid>) -> Vec<u8> {
+    id.map(|id| id.as_bytes().to_vec()).unwrap_or_default()
+}
+
+/// Fingerprint of a file upload, for idempotency keys
+fn upload_fingerprint(filename: &str, content_hash: &str, options: &UploadOptions, user_id: Option<Uuid>) -> String {
+    let options_json = serde_json::to_vec(options).unwrap_or_default();
+    IdempotencyStore::fingerprint(&[
+        b"upload",
+        id_bytes(user_id).as_slice(),
+        filename.as_bytes(),
+        content_hash.as_bytes(),
+        &options_json,
+    ])
+}
+
+/// Whether two MIME types name the same format
+fn same_type(a: &str, b: &str) -> bool {
+    fn canonical(mime: &str) -> &str {
+        match mime {
+            "image/jpg" | "image/pjpeg" => "image/jpeg",
+            "image/x-png" => "image/png",
+            "image/x-icon" => "image/vnd.microsoft.icon",
+            "audio/wav" | "audio/wave" => "audio/x-wav",
+            "audio/flac" => "audio/x-flac",
+            "text/xml" => "image/svg+xml",
+            other => other,
+        }
+    }
+    canonical(a) == canonical(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use crate::services::testing::TestServices;
+
+    fn service(dir: &std::path::Path) -> UploadService {
+        TestServices::new(dir).upload_service()
+    }
+
+    #[tokio::test]
+    async fn test_chunk_verification() {
+        let dir = tempdir().unwrap();
+        let service = service(dir.path());
+
+        let upload = service.init_chunked_upload("notes.txt", 10, 4, 3, None, None, None, None)
+            .await
+            .unwrap();
+
+        // Last chunk is 2 bytes
+        let err = service.upload_chunk(upload.id, 2, b"xyz".to_vec(), None).await.unwrap_err();
+        assert!(matches!(err, UploadError::ChunkSizeMismatch(2, 2, 3)));
+
+        let wrong = Checksum::compute(ChecksumAlgorithm::Md5, b"nope");
+        let err = service.upload_chunk(upload.id, 0, b"abcd".to_vec(), Some(wrong)).await.unwrap_err();
+        assert!(matches!(err, UploadError::ChunkChecksumMismatch(0, _, _)));
+
+        let err = service.upload_chunk(upload.id, 3, b"ab".to_vec(), None).await.unwrap_err();
+        assert!(matches!(err, UploadError::InvalidChunk(3)));
+
+        let state = service.get_chunked_upload(upload.id).await.unwrap();
+        assert!(state.chunks.iter().all(|c| !c.received));
+
+        // Layout must cover the declared size
+        let err = service.init_chunked_upload("notes.txt", 10, 4, 2, None, None, None, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UploadError::InvalidChunkLayout(_)));
+    }
+
+    #[tokio::test]
+    async fn test_parallel_out_of_order_chunks() {
+        let dir = tempdir().unwrap();
+        let service = service(dir.path());
+
+        let content = b"hello world";
+        let checksum = Checksum::compute(ChecksumAlgorithm::Sha256, content);
+        let upload = service.init_chunked_upload("notes.txt", 11, 4, 3, None, Some(checksum), None, None)
+            .await
+            .unwrap();
+
+        let chunk = |i: usize| {
+            let data = content[i * 4..(i * 4 + 4).min(11)].to_vec();
+            let checksum = Checksum::compute(ChecksumAlgorithm::Sha256, &data);
+            service.upload_chunk(upload.id, i, data, Some(checksum))
+        };
+        let (c, b, a) = tokio::join!(chunk(2), chunk(1), chunk(0));
+        a.unwrap();
+        b.unwrap();
+        c.unwrap();
+
+        let media = service.complete_chunked_upload(upload.id).await.unwrap();
+        assert_eq!(media.size, 11);
+        assert!(service.get_chunked_upload(upload.id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_completion() {
+        let dir = tempdir().unwrap();
+        let service = service(dir.path());
+
+        let upload = service.init_chunked_upload("notes.txt", 5, 5, 1, None, None, None, None)
+            .await
+            .unwrap();
+        service.upload_chunk(upload.id, 0, b"hello".to_vec(), None).await.unwrap();
+
+        let (first, second) = tokio::join!(
+            service.complete_chunked_upload(upload.id),
+            service.complete_chunked_upload(upload.id),
+        );
+        assert_eq!(first.unwrap().size, 5);
+        assert!(matches!(second.unwrap_err(), UploadError::Completing));
+        assert_eq!(service.media_service.get_stats().await.total_items, 1);
+    }
+
+    #[tokio::test]
+    async fn test_idempotent_retries() {
+        let dir = tempdir().unwrap();
+        let service = service(dir.path());
+        let options = UploadOptions { optimize: false, ..UploadOptions::default() };
+
+        let first = service.upload_idempotent("k1", b"hello".to_vec(), "notes.txt", options.clone(), None)
+            .await
+            .unwrap();
+        let retry = service.upload_idempotent("k1", b"hello".to_vec(), "notes.txt", options.clone(), None)
+            .await
+            .unwrap();
+        assert_eq!(retry.id, first.id);
+        assert_eq!(service.media_service.get_stats().await.total_items, 1);
+
+        let err = service.upload_idempotent("k1", b"other".to_vec(), "notes.txt", options.clone(), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UploadError::Idempotency(IdempotencyError::KeyReused(_))));
+
+        // Failures are not cached
+        for _ in 0..2 {
+            let err = service.upload_idempotent("k2", b"MZ".to_vec(), "setup.exe", options.clone(), None)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, UploadError::TypeNotAllowed(_)));
+        }
+
+        let upload = service.init_chunked_upload("log.txt", 3, 4, 1, None, None, None, None).await.unwrap();
+        service.upload_chunk(upload.id, 0, b"abc".to_vec(), None).await.unwrap();
+        let media = service.complete_chunked_upload_idempotent("k3", upload.id, None).await.unwrap();
+        let retry = service.complete_chunked_upload_idempotent("k3", upload.id, None).await.unwrap();
+        assert_eq!(retry.id, media.id);
+        // Another user's key of the same name gets no replay
+        let err = service.complete_chunked_upload_idempotent("k3", upload.id, Some(Uuid::new_v4()))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UploadError::NotFound(_)));
+        assert!(matches!(
+            service.complete_chunked_upload(upload.id).await,
+            Err(UploadError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits() {
+        use super::super::rate_limit::{RateLimitSettings, RateLimits};
+
+        let dir = tempdir().unwrap();
+        let mut service = service(dir.path());
+        service.set_rate_limiter(Arc::new(RateLimiter::new(RateLimitSettings {
+            default: RateLimits { files_per_minute: 2, bytes_per_hour: 0, concurrent_sessions: 1 },
+            ..RateLimitSettings::default()
+        })));
+        let user = Some(Uuid::new_v4());
+
+        let upload = service.init_chunked_upload("a.txt", 3, 4, 1, None, None, None, user).await.unwrap();
+        let err = service.init_chunked_upload("b.txt", 3, 4, 1, None, None, None, user).await.unwrap_err();
+        assert!(matches!(err, UploadError::RateLimited(ref r) if r.limit == "sessions"));
+
+        // Cancelling frees the session; the cancelled file still counted
+        service.cancel_chunked_upload(upload.id).await.unwrap();
+        service.init_chunked_upload("b.txt", 3, 4, 1, None, None, None, user).await.unwrap();
+
+        let options = UploadOptions { optimize: false, ..UploadOptions::default() };
+        let err = service.upload(b"hi".to_vec(), "c.txt", options, user).await.unwrap_err();
+        assert!(matches!(err, UploadError::RateLimited(ref r) if r.limit == "files" && r.retry_after > 0));
+    }
+
+    #[tokio::test]
+    async fn test_failed_init_leaves_nothing_behind() {
+        use super::super::rate_limit::{RateLimitSettings, RateLimits};
+
+        // Storage root is a file, so the temp directory cannot be created
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("uploads");
+        std::fs::write(&root, b"").unwrap();
+        let mut service = service(&root);
+        service.set_rate_limiter(Arc::new(RateLimiter::new(RateLimitSettings {
+            default: RateLimits { files_per_minute: 0, bytes_per_hour: 0, concurrent_sessions: 1 },
+            ..RateLimitSettings::default()
+        })));
+        let user = Some(Uuid::new_v4());
+
+        let err = service.init_chunked_upload("a.txt", 3, 4, 1, None, None, None, user).await.unwrap_err();
+        assert!(matches!(err, UploadError::Storage(_)));
+        assert!(service.chunked_uploads.read().await.is_empty());
+
+        // The session was given back
+        std::fs::remove_file(&root).unwrap();
+        std::fs::create_dir(&root).unwrap();
+        service.init_chunked_upload("a.txt", 3, 4, 1, None, None, None, user).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_import_archive() {
+        use std::io::Write;
+
+        let dir = tempdir().unwrap();
+        let mut service = service(dir.path());
+        let folders = Arc::new(FolderService::new());
+        service.set_folder_service(Arc::clone(&folders));
+
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        for (name, data) in [
+            ("Trip/Day 1/notes.txt", &b"first day"[..]),
+            ("Trip/Day 2/notes.txt", b"second day"),
+            ("Trip/script.exe", b"MZ"),
+            ("../escape.txt", b"nope"),
+        ] {
+            writer.start_file(name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        let data = writer.finish().unwrap().into_inner();
+
+        let report = service.import_archive(data, "trip.zip", None, None).await.unwrap();
+
+        assert_eq!(report.imported(), 2);
+        assert_eq!(report.failed(), 1);
+        assert_eq!(report.skipped(), 1);
+        assert_eq!(report.folders_created.len(), 3);
+
+        let day2 = folders.get_by_path("trip/day-2").await.unwrap();
+        let media_id = match &report.entries[1].status {
+            EntryStatus::Imported { media_id } => *media_id,
+            other => panic!("unexpected status: {:?}", other),
+        };
+        let media = service.media_service.get(media_id).await.unwrap();
+        assert_eq!(media.folder_id, Some(day2.id));
+    }
+
+    #[tokio::test]
+    async fn test_archive_entries_limited_to_file_size() {
+        use std::io::Write;
+
+        let dir = tempdir().unwrap();
+        let mut service = service(dir.path());
+        service.set_folder_service(Arc::new(FolderService::new()));
+        service.configure(UploadSettings { max_file_size: 1024, ..UploadSettings::default() });
+
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        writer.start_file("small.txt", options).unwrap();
+        writer.write_all(b"small").unwrap();
+        writer.start_file("large.txt", options).unwrap();
+        writer.write_all(&[b'x'; 2048]).unwrap();
+        let data = writer.finish().unwrap().into_inner();
+
+        let report = service.import_archive(data, "files.zip", None, None).await.unwrap();
+        assert_eq!(report.error, Some(ArchiveError::TooLarge(1024).to_string()));
+
+        // Entries before the limit are kept; nothing is left staged
+        assert_eq!(report.imported(), 1);
+        assert_eq!(service.media_service.get_stats().await.total_items, 1);
+        let staged = std::fs::read_dir(dir.path().join("temp/staged")).unwrap();
+        assert_eq!(staged.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_file_checksum_mismatch() {
+        let dir = tempdir().unwrap();
+        let service = service(dir.path());
+
+        let checksum = Checksum::compute(ChecksumAlgorithm::Md5, b"something else");
+        let upload = service.init_chunked_upload("notes.txt", 4, 4, 1, None, Some(checksum), None, None)
+            .await
+            .unwrap();
+        service.upload_chunk(upload.id, 0, b"abcd".to_vec(), None).await.unwrap();
+
+        let err = service.complete_chunked_upload(upload.id).await.unwrap_err();
+        assert!(matches!(err, UploadError::ChecksumMismatch(_, _)));
+    }
+
+    #[tokio::test]
+    async fn test_content_validation() {
+        let dir = tempdir().unwrap();
+        let mut service = service(dir.path());
+
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::RgbImage::new(2, 2).write_to(&mut png, image::ImageFormat::Png).unwrap();
+        let png = png.into_inner();
+
+        let err = service.upload(png.clone(), "notes.txt", UploadOptions::default(), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UploadError::ContentRejected(ContentRejection::ExtensionMismatch { .. })));
+
+        service.configure(UploadSettings {
+            validate_contents: false,
+            ..UploadSettings::default()
+        });
+        let options = UploadOptions { optimize: false, ..UploadOptions::default() };
+        assert!(service.upload(png, "notes.txt", options, None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_upload_encoded() {
+        use base64::Engine;
+
+        let dir = tempdir().unwrap();
+        let service = service(dir.path());
+
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::RgbImage::new(2, 2).write_to(&mut png, image::ImageFormat::Png).unwrap();
+        let encoded = base64::engine::general_purpose::STANDARD.encode(png.into_inner());
+        let options = UploadOptions { optimize: false, alt_text: Some("Screenshot".to_string()), ..UploadOptions::default() };
+
+        let media = service.upload_encoded(&format!("data:image/png;base64,{}", encoded), "", options.clone(), None)
+            .await
+            .unwrap();
+        assert_eq!(media.filename, "pasted.png");
+        assert_eq!(media.mime_type, "image/png");
+        assert_eq!(media.alt_text.as_deref(), Some("Screenshot"));
+
+        let err = service.upload_encoded(&format!("data:image/gif;base64,{}", encoded), "shot", options.clone(), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UploadError::DeclaredTypeMismatch(ref declared, _) if declared == "image/gif"));
+
+        let err = service.upload_encoded(&encoded, "", options, None).await.unwrap_err();
+        assert!(matches!(err, UploadError::InvalidFile(_)));
+    }
+
+    #[tokio::test]
+    async fn test_exif_kept_when_optimized() {
+        use ::exif::{experimental::Writer, Field, In, Tag, Value};
+
+        let dir = tempdir().unwrap();
+        let service = service(dir.path());
+
+        let fields = [
+            Field { tag: Tag::Make, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"Canon".to_vec()]) },
+            Field { tag: Tag::Orientation, ifd_num: In::PRIMARY, value: Value::Short(vec![6]) },
+        ];
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut tiff = std::io::Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        let tiff = tiff.into_inner();
+
+        // 40x20 JPEG, to be shown rotated a quarter turn
+        let mut plain = std::io::Cursor::new(Vec::new());
+        image::RgbImage::from_pixel(40, 20, image::Rgb([90, 120, 200]))
+            .write_to(&mut plain, image::ImageFormat::Jpeg)
+            .unwrap();
+        let plain = plain.into_inner();
+        let mut jpeg = plain[..2].to_vec();
+        jpeg.extend_from_slice(&[0xFF, 0xE1]);
+        jpeg.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(&tiff);
+        jpeg.extend_from_slice(&plain[2..]);
+
+        let media = service.upload(jpeg, "photo.jpg", UploadOptions::default(), None).await.unwrap();
+        let exif = media.metadata.exif.as_ref().unwrap();
+        assert_eq!(exif.camera_make.as_deref(), Some("Canon"));
+        assert_eq!(exif.orientation, Some(1));
+
+        // The stored file is upright and carries no EXIF
+        let dimensions = media.dimensions.as_ref().unwrap();
+        assert_eq!((dimensions.width, dimensions.height), (20, 40));
+        let stored = std::fs::read(dir.path().join(&media.path)).unwrap();
+        assert!(!stored.windows(5).any(|w| w == b"Canon"));
+    }
+
+    #[tokio::test]
+    async fn test_svg_sanitized_before_storage() {
+        let dir = tempdir().unwrap();
+        let service = service(dir.path());
+
+        let svg = br#"<?xml version="1.0"?><svg xmlns="http://www.w3.org/2000/svg" onload="alert(1)"><script>alert(2)</script><rect width="1" height="1"/></svg>"#;
+        let media = service.upload(svg.to_vec(), "logo.svg", UploadOptions::default(), None).await.unwrap();
+
+        let stored = String::from_utf8(std::fs::read(dir.path().join(&media.path)).unwrap()).unwrap();
+        assert!(!stored.contains("alert"));
+        assert!(stored.contains("<rect"));
+        assert_eq!(media.metadata.custom.get("svg_sanitized").unwrap(), "<script>, svg@onload");
+
+        let err = service.upload(b"<svg><g></svg>".to_vec(), "broken.svg", UploadOptions::default(), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UploadError::Svg(_)));
+    }
+
+    /// Flags files containing "VIRUS" and refuses ones starting with
+    /// "HUGE"; fails when `down`
+    struct StubScanner {
+        down: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl VirusScanner for StubScanner {
+        fn name(&self) -> &str {
+            "stub"
+        }
+
+        async fn scan_reader(
+            &self,
+            reader: &mut (dyn tokio::io::AsyncRead + Unpin + Send),
+        ) -> Result<ScanVerdict, ScanError> {
+            use tokio::io::AsyncReadExt;
+
+            if self.down {
+                return Err(ScanError::Unavailable("connection refused".to_string()));
+            }
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data).await.map_err(|e| ScanError::Unavailable(e.to_string()))?;
+            if data.starts_with(b"HUGE") {
+                return Err(ScanError::TooLarge);
+            }
+            if data.windows(5).any(|w| w == b"VIRUS") {
+                Ok(ScanVerdict::Infected("Test.Virus".to_string()))
+            } else {
+                Ok(ScanVerdict::Clean)
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scan_rejects_and_quarantines() {
+        let dir = tempdir().unwrap();
+        let mut service = service(dir.path());
+        service.set_scanner(Arc::new(StubScanner { down: false }), ScanSettings::default());
+
+        service.upload(b"clean".to_vec(), "ok.txt", UploadOptions::default(), None).await.unwrap();
+
+        let err = service.upload(b"a VIRUS b".to_vec(), "bad.txt", UploadOptions::default(), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UploadError::Infected(ref s) if s == "Test.Virus"));
+
+        // Chunked uploads are scanned after assembly and moved to quarantine
+        let upload = service.init_chunked_upload("big.txt", 8, 4, 2, None, None, None, None).await.unwrap();
+        service.upload_chunk(upload.id, 0, b"xVIR".to_vec(), None).await.unwrap();
+        service.upload_chunk(upload.id, 1, b"USxx".to_vec(), None).await.unwrap();
+        let err = service.complete_chunked_upload(upload.id).await.unwrap_err();
+        assert!(matches!(err, UploadError::Infected(_)));
+
+        let quarantined: Vec<_> = std::fs::read_dir(dir.path().join("quarantine"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(quarantined.len(), 2);
+        assert!(quarantined.iter().any(|n| n.ends_with("-bad.txt")));
+        assert!(quarantined.iter().any(|n| n.ends_with("-big.txt")));
+    }
+
+    #[tokio::test]
+    async fn test_scan_failure_policy() {
+        let dir = tempdir().unwrap();
+        let mut service = service(dir.path());
+        service.set_scanner(Arc::new(StubScanner { down: true }), ScanSettings::default());
+
+        let err = service.upload(b"data".to_vec(), "a.txt", UploadOptions::default(), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UploadError::ScanFailed(_)));
+
+        service.set_scanner(Arc::new(StubScanner { down: true }), ScanSettings {
+            on_failure: ScanFailurePolicy::FailOpen,
+            ..ScanSettings::default()
+        });
+        service.upload(b"data".to_vec(), "a.txt", UploadOptions::default(), None).await.unwrap();
+
+        // Files too large to scan are rejected even when failing open
+        service.set_scanner(Arc::new(StubScanner { down: false }), ScanSettings {
+            on_failure: ScanFailurePolicy::FailOpen,
+            ..ScanSettings::default()
+        });
+        let err = service.upload(b"HUGE data".to_vec(), "b.txt", UploadOptions::default(), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UploadError::ScanFailed(ScanError::TooLarge)));
+    }
+
+    /// Renames every upload and replaces its contents with `data`
+    struct Rewriter {
+        filename: &'static str,
+        data: Option<&'static [u8]>,
+    }
+
+    #[async_trait::async_trait]
+    impl crate::hooks::MediaHook for Rewriter {
+        fn name(&self) -> &str {
+            "rewriter"
+        }
+
+        async fn before_upload(&self, upload: &mut PendingUpload) -> Result<(), crate::hooks::HookError> {
+            upload.filename = self.filename.to_string();
+            if let Some(data) = self.data {
+                upload.data = Some(Bytes::from_static(data));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hook_changes_checked() {
+        let dir = tempdir().unwrap();
+        let mut service = service(dir.path());
+        service.set_scanner(Arc::new(StubScanner { down: false }), ScanSettings::default());
+        let hooks = Arc::clone(service.media_service.hooks());
+        let rewrite = |filename, data| {
+            let hooks = Arc::clone(&hooks);
+            async move {
+                hooks.unregister("rewriter").await;
+                hooks.register(Arc::new(Rewriter { filename, data })).await;
+            }
+        };
+
+        // Rewritten SVGs are sanitized
+        let svg: &[u8] = br#"<svg xmlns="http://www.w3.org/2000/svg"><script>alert(1)</script><rect width="1" height="1"/></svg>"#;
+        rewrite("logo.svg", Some(svg)).await;
+        let media = service.upload(b"plain".to_vec(), "a.txt", UploadOptions::default(), None).await.unwrap();
+        assert_eq!(media.mime_type, "image/svg+xml");
+        let stored = std::fs::read_to_string(dir.path().join(&media.path)).unwrap();
+        assert!(!stored.contains("alert"));
+
+        // Rewritten contents are scanned again
+        rewrite("b.txt", Some(b"a VIRUS b")).await;
+        let err = service.upload(b"plain".to_vec(), "b.txt", UploadOptions::default(), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UploadError::Infected(_)));
+
+        // New names are checked, also for streamed files
+        rewrite("run.exe", None).await;
+        let err = service.upload(b"plain".to_vec(), "c.txt", UploadOptions::default(), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UploadError::TypeNotAllowed(_)));
+
+        let upload = service.init_chunked_upload("d.txt", 4, 4, 1, None, None, None, None).await.unwrap();
+        service.upload_chunk(upload.id, 0, b"text".to_vec(), None).await.unwrap();
+        let err = service.complete_chunked_upload(upload.id).await.unwrap_err();
+        assert!(matches!(err, UploadError::TypeNotAllowed(_)));
+    }
+}
@@ -0,0 +1,639 @@
+//! URL Import Service
+//!
+//! Fetches remote files for import with SSRF protection and size limits.
+
+use std::error::Error as StdError;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect;
+use url::Url;
+
+/// URL import error
+#[derive(Debug, thiserror::Error)]
+pub enum UrlImportError {
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(String),
+    #[error("Blocked address: {0}")]
+    Blocked(String),
+    #[error("Too many redirects (max: {0})")]
+    TooManyRedirects(usize),
+    #[error("Timed out")]
+    Timeout,
+    #[error("File too large: more than {0} bytes")]
+    TooLarge(u64),
+    #[error("Content type not allowed: {0}")]
+    TypeNotAllowed(String),
+    #[error("HTTP {0}")]
+    Status(u16),
+    #[error("Network error: {0}")]
+    Network(String),
+}
+
+/// Rejection raised from inside the resolver or redirect policy
+#[derive(Debug)]
+struct Rejected(UrlImportError);
+
+impl std::fmt::Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl StdError for Rejected {}
+
+/// URL import settings
+#[derive(Debug, Clone)]
+pub struct UrlImportSettings {
+    /// Maximum number of redirects to follow
+    pub max_redirects: usize,
+    /// Total time allowed for the request, including the body
+    pub timeout: Duration,
+    /// Time allowed to establish a connection
+    pub connect_timeout: Duration,
+    /// Addresses exempt from the private network check (e.g. an internal CDN)
+    pub allowed_addresses: Vec<IpAddr>,
+    /// User agent sent with requests
+    pub user_agent: String,
+}
+
+impl Default for UrlImportSettings {
+    fn default() -> Self {
+        Self {
+            max_redirects: 5,
+            timeout: Duration::from_secs(60),
+            connect_timeout: Duration::from_secs(10),
+            allowed_addresses: Vec::new(),
+            user_agent: format!("RustMedia/{}", env!("CARGO_PKG_VERSION")),
+        }
+    }
+}
+
+/// Downloaded file
+#[derive(Debug, Clone)]
+pub struct FetchedFile {
+    /// File contents
+    pub data: Vec<u8>,
+    /// Filename from Content-Disposition or the final URL
+    pub filename: Option<String>,
+    /// Content-Type essence (`image/png`), if sent
+    pub content_type: Option<String>,
+    /// URL after redirects
+    pub final_url: String,
+}
+
+/// URL importer
+pub struct UrlImporter {
+    client: reqwest::Client,
+    settings: UrlImportSettings,
+}
+
+impl UrlImporter {
+    /// Create a new importer
+    pub fn new(settings: UrlImportSettings) -> Result<Self, UrlImportError> {
+        let allowed = Arc::new(settings.allowed_addresses.clone());
+        let max_redirects = settings.max_redirects;
+
+        let policy_allowed = Arc::clone(&allowed);
+        let policy = redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > max_redirects {
+                return attempt.error(Rejected(UrlImportError::TooManyRedirects(max_redirects)));
+            }
+            match check_url(attempt.url(), &policy_allowed) {
+                Ok(()) => attempt.follow(),
+                Err(e) => attempt.error(Rejected(e)),
+            }
+        });
+
+        let client = reqwest::Client::builder()
+            .dns_resolver(Arc::new(GuardedResolver { allowed }))
+            .redirect(policy)
+            .timeout(settings.timeout)
+            .connect_timeout(settings.connect_timeout)
+            .user_agent(settings.user_agent.clone())
+            // A proxy would resolve hosts itself and bypass the address check
+            .no_proxy()
+            .build()
+            .map_err(|e| UrlImportError::Network(e.to_string()))?;
+
+        Ok(Self { client, settings })
+    }
+
+    /// Get settings
+    pub fn settings(&self) -> &UrlImportSettings {
+        &self.settings
+    }
+
+    /// Fetch a URL, reading at most `max_size` bytes
+    ///
+    /// When the response declares a Content-Type other than
+    /// `application/octet-stream`, it must be in `allowed_types`.
+    pub async fn fetch(
+        &self,
+        url: &str,
+        max_size: u64,
+        allowed_types: &[String],
+    ) -> Result<FetchedFile, UrlImportError> {
+        let parsed = Url::parse(url).map_err(|e| UrlImportError::InvalidUrl(e.to_string()))?;
+        check_url(&parsed, &self.settings.allowed_addresses)?;
+
+        let mut response = self.client.get(parsed).send().await.map_err(classify)?;
+
+        if !response.status().is_success() {
+            return Err(UrlImportError::Status(response.status().as_u16()));
+        }
+
+        let headers = response.headers();
+
+        let content_type = headers
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .map(|v| v.trim().to_lowercase())
+            .filter(|v| !v.is_empty());
+
+        if let Some(mime) = &content_type {
+            if mime != "application/octet-stream" && !allowed_types.iter().any(|t| t == mime) {
+                return Err(UrlImportError::TypeNotAllowed(mime.clone()));
+            }
+        }
+
+        if let Some(length) = response.content_length() {
+            if length > max_size {
+                return Err(UrlImportError::TooLarge(max_size));
+            }
+        }
+
+        let filename = headers
+            .get(reqwest::header::CONTENT_DISPOSITION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_content_disposition)
+            .or_else(|| filename_from_url(response.url()));
+
+        let final_url = response.url().to_string();
+
+        // Stream the body, enforcing the limit regardless of Content-Length
+        let mut data = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(classify)? {
+            if data.len() as u64 + chunk.len() as u64 > max_size {
+                return Err(UrlImportError::TooLarge(max_size));
+            }
+            data.extend_from_slice(&chunk);
+        }
+
+        Ok(FetchedFile {
+            data,
+            filename,
+            content_type,
+            final_url,
+        })
+    }
+}
+
+/// Resolver that refuses hosts resolving to internal addresses
+///
+/// Connections use the addresses returned here, so a host cannot pass the
+/// check and then be re-resolved to a different address.
+struct GuardedResolver {
+    allowed: Arc<Vec<IpAddr>>,
+}
+
+impl Resolve for GuardedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let allowed = Arc::clone(&self.allowed);
+        let host = name.as_str().to_string();
+
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .collect();
+
+            if let Some(addr) = addrs.iter().find(|a| !is_allowed_ip(a.ip(), &allowed)) {
+                let error = UrlImportError::Blocked(format!("{} resolves to {}", host, addr.ip()));
+                return Err(Box::new(Rejected(error)) as Box<dyn StdError + Send + Sync>);
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Check scheme and literal IP hosts (hostnames are checked on resolution)
+fn check_url(url: &Url, allowed: &[IpAddr]) -> Result<(), UrlImportError> {
+    match url.scheme() {
+        "http" | "https" => {}
+        scheme => return Err(UrlImportError::InvalidUrl(format!("Unsupported scheme: {}", scheme))),
+    }
+
+    let ip = match url.host() {
+        Some(url::Host::Ipv4(ip)) => IpAddr::V4(ip),
+        Some(url::Host::Ipv6(ip)) => IpAddr::V6(ip),
+        Some(url::Host::Domain(_)) => return Ok(()),
+        None => return Err(UrlImportError::InvalidUrl("Missing host".to_string())),
+    };
+
+    if is_allowed_ip(ip, allowed) {
+        Ok(())
+    } else {
+        Err(UrlImportError::Blocked(ip.to_string()))
+    }
+}
+
+fn is_allowed_ip(ip: IpAddr, allowed: &[IpAddr]) -> bool {
+    allowed.contains(&ip) || !is_internal_ip(ip)
+}
+
+/// Check if an address is loopback, private, link-local or otherwise not public
+pub fn is_internal_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_internal_ipv4(ip),
+        IpAddr::V6(ip) => {
+            if let Some(embedded) = embedded_ipv4(ip) {
+                return is_internal_ipv4(embedded);
+            }
+            let [first, second, third, ..] = ip.segments();
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || (first & 0xfe00) == 0xfc00 // unique local fc00::/7
+                || (first & 0xffc0) == 0xfe80 // link-local fe80::/10
+                || (first & 0xffc0) == 0xfec0 // site-local fec0::/10
+                || is_ipv4_compatible(ip)
+                || (first == 0x2001 && second == 0) // Teredo 2001::/32
+                || (first == 0x64 && second == 0xff9b && third == 1) // local-use NAT64 64:ff9b:1::/48
+        }
+    }
+}
+
+fn is_internal_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        || a == 0 // "this network" 0.0.0.0/8
+        || (a == 100 && (64..128).contains(&b)) // carrier-grade NAT 100.64.0.0/10
+        || (a == 198 && (b == 18 || b == 19)) // benchmarking 198.18.0.0/15
+        || a >= 240 // reserved 240.0.0.0/4
+}
+
+/// IPv4 address reached through an IPv6 one: mapped (`::ffff:a.b.c.d`),
+/// NAT64 (`64:ff9b::/96`) or 6to4 (`2002::/16`)
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = ip.segments();
+    let ipv4 = |high: u16, low: u16| Ipv4Addr::from((u32::from(high) << 16) | u32::from(low));
+
+    if let Some(mapped) = ip.to_ipv4_mapped() {
+        Some(mapped)
+    } else if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        Some(ipv4(segments[6], segments[7]))
+    } else if segments[0] == 0x2002 {
+        Some(ipv4(segments[1], segments[2]))
+    } else {
+        None
+    }
+}
+
+/// Deprecated `::a.b.c.d` form
+fn is_ipv4_compatible(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    segments[..6].iter().all(|&s| s == 0) && !ip.is_loopback() && !ip.is_unspecified()
+}
+
+/// Map a reqwest error, surfacing rejections from the resolver or redirect policy
+fn classify(error: reqwest::Error) -> UrlImportError {
+    let mut source: Option<&(dyn StdError + 'static)> = Some(&error);
+    while let Some(err) = source {
+        if let Some(Rejected(inner)) = err.downcast_ref::<Rejected>() {
+            return match inner {
+                UrlImportError::Blocked(reason) => UrlImportError::Blocked(reason.clone()),
+                UrlImportError::TooManyRedirects(max) => UrlImportError::TooManyRedirects(*max),
+                UrlImportError::InvalidUrl(reason) => UrlImportError::InvalidUrl(reason.clone()),
+                other => UrlImportError::Network(other.to_string()),
+            };
+        }
+        source = err.source();
+    }
+
+    if error.is_timeout() {
+        UrlImportError::Timeout
+    } else {
+        UrlImportError::Network(error.to_string())
+    }
+}
+
+/// Extract the filename from a Content-Disposition header (RFC 6266)
+///
+/// `filename*` (RFC 5987 encoded) takes precedence over `filename`. Any
+/// directory components are stripped.
+pub fn parse_content_disposition(header: &str) -> Option<String> {
+    let mut filename = None;
+    let mut filename_ext = None;
+
+    for (name, value) in disposition_params(header) {
+        match name.as_str() {
+            "filename*" => filename_ext = decode_ext_value(&value),
+            "filename" => filename = Some(value),
+            _ => {}
+        }
+    }
+
+    filename_ext.or(filename).and_then(|f| clean_filename(&f))
+}
+
+/// Split `type; name=value; name="quoted"` into lowercase names and unquoted values
+fn disposition_params(header: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut chars = header.chars().peekable();
+
+    // Skip the disposition type
+    for c in chars.by_ref() {
+        if c == ';' {
+            break;
+        }
+    }
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace() || *c == ';').is_some() {}
+
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|c| *c != '=' && *c != ';') {
+            name.push(c);
+        }
+        if name.is_empty() {
+            break;
+        }
+        if chars.next_if_eq(&'=').is_none() {
+            continue;
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some(escaped) = chars.next() {
+                            value.push(escaped);
+                        }
+                    }
+                    '"' => break,
+                    c => value.push(c),
+                }
+            }
+            // Ignore anything between the closing quote and the next parameter
+            while chars.next_if(|c| *c != ';').is_some() {}
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ';') {
+                value.push(c);
+            }
+            value = value.trim().to_string();
+        }
+
+        params.push((name.trim().to_lowercase(), value));
+    }
+
+    params
+}
+
+/// Decode an RFC 5987 ext-value (`UTF-8''%e2%82%ac%20rates`)
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?.to_lowercase();
+    let _language = parts.next()?;
+    let encoded = parts.next()?;
+
+    let bytes = percent_decode(encoded)?;
+
+    match charset.as_str() {
+        "utf-8" => String::from_utf8(bytes).ok(),
+        "iso-8859-1" => Some(bytes.into_iter().map(char::from).collect()),
+        _ => None,
+    }
+}
+
+fn percent_decode(value: &str) -> Option<Vec<u8>> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = value.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    Some(decoded)
+}
+
+/// Last path segment of a URL
+fn filename_from_url(url: &Url) -> Option<String> {
+    let segment = url.path_segments()?.next_back()?;
+    let decoded = percent_decode(segment)
+        .and_then(|b| String::from_utf8(b).ok())
+        .unwrap_or_else(|| segment.to_string());
+    clean_filename(&decoded)
+}
+
+/// Strip directory components and reject empty or dot names
+fn clean_filename(name: &str) -> Option<String> {
+    let base = name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or("")
+        .trim()
+        .trim_matches(char::from(0));
+
+    if base.is_empty() || base == "." || base == ".." {
+        None
+    } else {
+        Some(base.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Minimal HTTP server answering each request with `respond(path, port)`
+    async fn serve<F>(respond: F) -> u16
+    where
+        F: Fn(&str, u16) -> Vec<u8> + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let respond = Arc::new(respond);
+
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let respond = Arc::clone(&respond);
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match socket.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let request = String::from_utf8_lossy(&request);
+                    let path = request.split_whitespace().nth(1).unwrap_or("/").to_string();
+                    let _ = socket.write_all(&respond(&path, port)).await;
+                    let _ = socket.shutdown().await;
+                });
+            }
+        });
+
+        port
+    }
+
+    fn response(status: &str, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+        let mut out = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+        for (name, value) in headers {
+            out.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        out.push_str("\r\n");
+        let mut out = out.into_bytes();
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn local_importer() -> UrlImporter {
+        UrlImporter::new(UrlImportSettings {
+            allowed_addresses: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            timeout: Duration::from_secs(5),
+            ..Default::default()
+        }).unwrap()
+    }
+
+    fn allowed() -> Vec<String> {
+        vec!["text/plain".to_string(), "image/png".to_string()]
+    }
+
+    #[test]
+    fn test_internal_addresses() {
+        for ip in [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254",
+            "0.0.0.0", "100.64.0.1", "::1", "fe80::1", "fd00::1", "::ffff:127.0.0.1",
+            "64:ff9b::7f00:1", "64:ff9b::a9fe:a9fe", "64:ff9b:1::5db8:d822", "2002:7f00:1::1",
+            "2002:c0a8:101::", "2001:0:4136:e378:8000:63bf:3fff:fdd2",
+        ] {
+            assert!(is_internal_ip(ip.parse().unwrap()), "{} should be internal", ip);
+        }
+        for ip in ["93.184.216.34", "2606:2800:220:1::1", "64:ff9b::5db8:d822", "2002:5db8:d822::1"] {
+            assert!(!is_internal_ip(ip.parse().unwrap()), "{} should be public", ip);
+        }
+    }
+
+    #[test]
+    fn test_parse_content_disposition() {
+        assert_eq!(
+            parse_content_disposition("attachment; filename=\"report \\\"final\\\".pdf\""),
+            Some("report \"final\".pdf".to_string())
+        );
+        assert_eq!(
+            parse_content_disposition("attachment; filename=plain.txt; size=12"),
+            Some("plain.txt".to_string())
+        );
+        assert_eq!(
+            parse_content_disposition("attachment; filename=\"fallback.txt\"; filename*=UTF-8''%e2%82%ac%20rates.txt"),
+            Some("€ rates.txt".to_string())
+        );
+        assert_eq!(
+            parse_content_disposition("attachment; filename=\"../../etc/passwd\""),
+            Some("passwd".to_string())
+        );
+        assert_eq!(parse_content_disposition("inline"), None);
+    }
+
+    #[tokio::test]
+    async fn test_blocks_internal_addresses() {
+        let port = serve(|_, _| response("200 OK", &[], b"secret")).await;
+        let importer = UrlImporter::new(UrlImportSettings::default()).unwrap();
+
+        let err = importer.fetch(&format!("http://127.0.0.1:{}/", port), 1024, &allowed()).await.unwrap_err();
+        assert!(matches!(err, UrlImportError::Blocked(_)));
+
+        let err = importer.fetch(&format!("http://localhost:{}/", port), 1024, &allowed()).await.unwrap_err();
+        assert!(matches!(err, UrlImportError::Blocked(_)));
+
+        let err = importer.fetch("file:///etc/passwd", 1024, &allowed()).await.unwrap_err();
+        assert!(matches!(err, UrlImportError::InvalidUrl(_)));
+    }
+
+    #[tokio::test]
+    async fn test_fetch() {
+        let port = serve(|_, _| response(
+            "200 OK",
+            &[
+                ("Content-Type", "text/plain; charset=utf-8"),
+                ("Content-Length", "5"),
+                ("Content-Disposition", "attachment; filename*=UTF-8''notes%20v2.txt"),
+            ],
+            b"hello",
+        )).await;
+
+        let file = local_importer()
+            .fetch(&format!("http://127.0.0.1:{}/download?id=1", port), 1024, &allowed())
+            .await
+            .unwrap();
+
+        assert_eq!(file.data, b"hello");
+        assert_eq!(file.filename.as_deref(), Some("notes v2.txt"));
+        assert_eq!(file.content_type.as_deref(), Some("text/plain"));
+    }
+
+    #[tokio::test]
+    async fn test_redirects() {
+        let port = serve(|path, port| match path {
+            "/internal" => response("302 Found", &[("Location", "http://169.254.169.254/latest/meta-data")], b""),
+            "/start" => response("302 Found", &[("Location", "/file.txt")], b""),
+            "/file.txt" => response("200 OK", &[("Content-Type", "text/plain")], b"moved"),
+            _ => response("302 Found", &[("Location", &format!("http://127.0.0.1:{}/loop", port))], b""),
+        }).await;
+        let importer = local_importer();
+        let base = format!("http://127.0.0.1:{}", port);
+
+        let file = importer.fetch(&format!("{}/start", base), 1024, &allowed()).await.unwrap();
+        assert_eq!(file.data, b"moved");
+        assert_eq!(file.filename.as_deref(), Some("file.txt"));
+
+        let err = importer.fetch(&format!("{}/internal", base), 1024, &allowed()).await.unwrap_err();
+        assert!(matches!(err, UrlImportError::Blocked(_)));
+
+        let err = importer.fetch(&format!("{}/loop", base), 1024, &allowed()).await.unwrap_err();
+        assert!(matches!(err, UrlImportError::TooManyRedirects(5)));
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let port = serve(|path, _| match path {
+            // No Content-Length: the limit must hold while streaming
+            "/big" => response("200 OK", &[("Content-Type", "text/plain")], &[b'x'; 4096]),
+            "/declared" => response("200 OK", &[("Content-Length", "999999")], b""),
+            _ => response("200 OK", &[("Content-Type", "text/html")], b"<html>"),
+        }).await;
+        let importer = local_importer();
+        let base = format!("http://127.0.0.1:{}", port);
+
+        let err = importer.fetch(&format!("{}/big", base), 1024, &allowed()).await.unwrap_err();
+        assert!(matches!(err, UrlImportError::TooLarge(1024)));
+
+        let err = importer.fetch(&format!("{}/declared", base), 1024, &allowed()).await.unwrap_err();
+        assert!(matches!(err, UrlImportError::TooLarge(1024)));
+
+        let err = importer.fetch(&format!("{}/page", base), 1024, &allowed()).await.unwrap_err();
+        assert!(matches!(err, UrlImportError::TypeNotAllowed(_)));
+    }
+}
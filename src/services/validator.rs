@@ -0,0 +1,396 @@
+//! Content Validation
+//!
+//! Checks that uploaded bytes are what their extension claims: no
+//! executables, no files whose content contradicts the extension, no
+//! polyglots hiding markup or archives, and images that actually decode.
+
+use std::io::Cursor;
+use serde::Serialize;
+
+/// Bytes examined for type and text detection
+const SNIFF_LEN: usize = 8192;
+
+/// Zip end-of-central-directory record can start this far from the end
+const EOCD_SEARCH: usize = 22 + 65535;
+
+/// Markup that must not appear inside binary files
+const ACTIVE_MARKERS: &[&str] = &["<script", "<?php", "<html", "<!doctype html", "<iframe", "<object", "<embed"];
+
+/// Why content was rejected
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, thiserror::Error)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum ContentRejection {
+    #[error("File is empty")]
+    Empty,
+    #[error("Executable content ({format})")]
+    Executable { format: String },
+    #[error("Content is {detected}, which does not match .{extension}")]
+    ExtensionMismatch { extension: String, detected: String },
+    #[error("{detected} file contains embedded {embedded}")]
+    Polyglot { detected: String, embedded: String },
+    #[error("Malformed {detected}: {error}")]
+    Malformed { detected: String, error: String },
+}
+
+/// Content validator
+#[derive(Debug, Clone)]
+pub struct ContentValidator {
+    /// Fully decode raster images
+    decode_images: bool,
+}
+
+impl Default for ContentValidator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ContentValidator {
+    /// Create a validator that decodes images
+    pub fn new() -> Self {
+        Self { decode_images: true }
+    }
+
+    /// Enable or disable full image decoding
+    pub fn set_decode_images(&mut self, decode: bool) {
+        self.decode_images = decode;
+    }
+
+    /// Validate a complete file
+    ///
+    /// Returns the MIME type the content was identified as.
+    pub fn validate(&self, data: &[u8], filename: &str) -> Result<String, ContentRejection> {
+        let detected = self.check_type(data, filename)?;
+
+        if !is_text_type(&detected) {
+            if let Some(embedded) = find_embedded(data, &detected) {
+                return Err(ContentRejection::Polyglot { detected, embedded });
+            }
+        }
+
+        if self.decode_images {
+            decode_image(data, &detected)?;
+        }
+
+        Ok(detected)
+    }
+
+    /// Check the start of a file against its extension
+    ///
+    /// Only the first few kilobytes are needed, so this also works for
+    /// files too large to hold in memory. Returns the detected MIME type.
+    pub fn check_type(&self, head: &[u8], filename: &str) -> Result<String, ContentRejection> {
+        if head.is_empty() {
+            return Err(ContentRejection::Empty);
+        }
+        let head = &head[..head.len().min(SNIFF_LEN)];
+
+        if let Some(format) = executable_format(head) {
+            return Err(ContentRejection::Executable { format: format.to_string() });
+        }
+
+        let detected = detect(head);
+        let extension = std::path::Path::new(filename)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+
+        if let Some(expected) = expected_types(&extension) {
+            if !expected.contains(&detected.as_str()) {
+                return Err(ContentRejection::ExtensionMismatch { extension, detected });
+            }
+            if extension == "svg" && !contains_ignore_case(head, "<svg") {
+                return Err(ContentRejection::ExtensionMismatch { extension, detected });
+            }
+        }
+
+        Ok(detected)
+    }
+}
+
+/// Preferred extension for a detected MIME type
+pub fn extension_for(mime_type: &str) -> Option<&'static str> {
+    let extension = match mime_type {
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/bmp" => "bmp",
+        "image/tiff" => "tif",
+        "image/vnd.microsoft.icon" => "ico",
+        "image/svg+xml" => "svg",
+        "video/mp4" => "mp4",
+        "video/quicktime" => "mov",
+        "video/webm" => "webm",
+        "video/x-matroska" => "mkv",
+        "video/x-msvideo" => "avi",
+        "audio/mpeg" => "mp3",
+        "audio/x-wav" => "wav",
+        "audio/x-flac" => "flac",
+        "audio/ogg" => "ogg",
+        "application/pdf" => "pdf",
+        "application/zip" => "zip",
+        "text/plain" => "txt",
+        _ => return None,
+    };
+    Some(extension)
+}
+
+/// MIME types acceptable for an extension (`None` for unknown extensions)
+fn expected_types(extension: &str) -> Option<&'static [&'static str]> {
+    let types: &[&str] = match extension {
+        "jpg" | "jpeg" => &["image/jpeg"],
+        "png" => &["image/png"],
+        "gif" => &["image/gif"],
+        "webp" => &["image/webp"],
+        "bmp" => &["image/bmp"],
+        "tif" | "tiff" => &["image/tiff"],
+        "ico" => &["image/vnd.microsoft.icon"],
+        "svg" => &["image/svg+xml", "text/xml", "text/plain"],
+        "mp4" | "m4v" => &["video/mp4", "video/x-m4v", "video/quicktime"],
+        "mov" => &["video/quicktime", "video/mp4"],
+        "m4a" => &["audio/m4a", "video/mp4"],
+        "webm" | "weba" => &["video/webm", "video/x-matroska"],
+        "mkv" => &["video/x-matroska", "video/webm"],
+        "ogg" | "ogv" => &["audio/ogg", "audio/opus", "video/ogg"],
+        "avi" => &["video/x-msvideo"],
+        "mp3" => &["audio/mpeg"],
+        "wav" => &["audio/x-wav"],
+        "flac" => &["audio/x-flac"],
+        "pdf" => &["application/pdf"],
+        "doc" | "xls" | "ppt" => &[
+            "application/msword",
+            "application/vnd.ms-excel",
+            "application/vnd.ms-powerpoint",
+            "application/x-ole-storage",
+        ],
+        "docx" | "xlsx" | "pptx" => &[
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+            "application/zip",
+        ],
+        "zip" => &["application/zip"],
+        "rar" => &["application/vnd.rar"],
+        "7z" => &["application/x-7z-compressed"],
+        "tar" => &["application/x-tar"],
+        "gz" => &["application/gzip"],
+        "txt" | "csv" => &["text/plain"],
+        _ => return None,
+    };
+    Some(types)
+}
+
+/// Identify content from its first bytes
+fn detect(head: &[u8]) -> String {
+    if looks_like_html(head) {
+        return "text/html".to_string();
+    }
+
+    match infer::get(head) {
+        Some(kind) if kind.mime_type() == "text/xml" && contains_ignore_case(head, "<svg") => {
+            "image/svg+xml".to_string()
+        }
+        // infer's executable matchers only look at a couple of bytes
+        Some(kind) if kind.matcher_type() == infer::MatcherType::App && is_text(head) => {
+            "text/plain".to_string()
+        }
+        Some(kind) => kind.mime_type().to_string(),
+        None if contains_ignore_case(head, "<svg") && is_text(head) => "image/svg+xml".to_string(),
+        None if is_text(head) => "text/plain".to_string(),
+        None => "application/octet-stream".to_string(),
+    }
+}
+
+fn is_text_type(mime: &str) -> bool {
+    mime.starts_with("text/") || mime == "image/svg+xml"
+}
+
+/// Text without NUL bytes that is valid UTF-8 (allowing a cut-off final character)
+fn is_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none() && head.len() - e.valid_up_to() < 4,
+    }
+}
+
+/// HTML document start, after an optional BOM and whitespace
+fn looks_like_html(head: &[u8]) -> bool {
+    let head = head.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(head);
+    let start = head.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(head.len());
+    let head = &head[start..];
+
+    ["<!doctype html", "<html", "<head", "<body", "<script", "<iframe"]
+        .iter()
+        .any(|tag| starts_with_ignore_case(head, tag))
+}
+
+/// Native executable or script formats
+fn executable_format(head: &[u8]) -> Option<&'static str> {
+    if head.starts_with(b"\x7FELF") {
+        return Some("ELF");
+    }
+    if head.starts_with(b"#!") {
+        return Some("script");
+    }
+    if matches!(head.get(..4), Some([0xFE, 0xED, 0xFA, 0xCE | 0xCF]) | Some([0xCE | 0xCF, 0xFA, 0xED, 0xFE])) {
+        return Some("Mach-O");
+    }
+    if head.starts_with(b"MZ") {
+        // PE header offset lives at 0x3C
+        let pe_offset = head.get(0x3C..0x40)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+        if let Some(b"PE\0\0") = pe_offset.and_then(|o| head.get(o..o + 4)) {
+            return Some("PE");
+        }
+    }
+    None
+}
+
+/// Look for markup or an appended archive inside a binary file
+fn find_embedded(data: &[u8], detected: &str) -> Option<String> {
+    let mut from = 0;
+    while let Some(pos) = data[from..].iter().position(|&b| b == b'<') {
+        let at = &data[from + pos..];
+        if let Some(marker) = ACTIVE_MARKERS.iter().find(|m| starts_with_ignore_case(at, m)) {
+            return Some(format!("{}>", marker));
+        }
+        from += pos + 1;
+    }
+
+    let zip_based = detected == "application/zip" || detected.starts_with("application/vnd.openxmlformats");
+    if !zip_based {
+        let tail = &data[data.len().saturating_sub(EOCD_SEARCH)..];
+        if tail.windows(4).any(|w| w == b"PK\x05\x06") {
+            return Some("zip archive".to_string());
+        }
+    }
+
+    None
+}
+
+/// Fully decode raster images the image crate can read
+fn decode_image(data: &[u8], detected: &str) -> Result<(), ContentRejection> {
+    let Some(format) = image::ImageFormat::from_mime_type(detected) else {
+        return Ok(());
+    };
+    if !format.reading_enabled() {
+        return Ok(());
+    }
+
+    let malformed = |error: String| ContentRejection::Malformed {
+        detected: detected.to_string(),
+        error,
+    };
+
+    image::ImageReader::with_format(Cursor::new(data), format)
+        .decode()
+        .map(|_| ())
+        .map_err(|e| malformed(e.to_string()))
+}
+
+fn starts_with_ignore_case(data: &[u8], prefix: &str) -> bool {
+    data.len() >= prefix.len() && data[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+}
+
+fn contains_ignore_case(data: &[u8], needle: &str) -> bool {
+    data.windows(needle.len()).any(|w| w.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png() -> Vec<u8> {
+        let img = image::RgbImage::from_pixel(4, 4, image::Rgb([200, 10, 10]));
+        let mut out = Cursor::new(Vec::new());
+        img.write_to(&mut out, image::ImageFormat::Png).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn test_accepts_matching_content() {
+        let validator = ContentValidator::new();
+        assert_eq!(validator.validate(&png(), "red.png").unwrap(), "image/png");
+        assert_eq!(validator.validate(b"a,b\n1,2\n", "data.csv").unwrap(), "text/plain");
+        assert_eq!(
+            validator.validate(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>", "icon.svg").unwrap(),
+            "image/svg+xml"
+        );
+        // Unknown extensions are not type-checked
+        assert!(validator.validate(b"anything", "notes.md").is_ok());
+    }
+
+    #[test]
+    fn test_rejects_mismatch() {
+        let validator = ContentValidator::new();
+
+        let err = validator.validate(b"<!DOCTYPE html><html><body>hi</body></html>", "photo.jpg").unwrap_err();
+        assert_eq!(err, ContentRejection::ExtensionMismatch {
+            extension: "jpg".to_string(),
+            detected: "text/html".to_string(),
+        });
+
+        let err = validator.validate(&png(), "notes.txt").unwrap_err();
+        assert!(matches!(err, ContentRejection::ExtensionMismatch { .. }));
+
+        assert_eq!(validator.validate(b"", "a.txt").unwrap_err(), ContentRejection::Empty);
+    }
+
+    #[test]
+    fn test_rejects_executables() {
+        let validator = ContentValidator::new();
+
+        let mut pe = vec![0u8; 0x80];
+        pe[..2].copy_from_slice(b"MZ");
+        pe[0x3C] = 0x40;
+        pe[0x40..0x44].copy_from_slice(b"PE\0\0");
+        let err = validator.validate(&pe, "photo.jpg").unwrap_err();
+        assert_eq!(err, ContentRejection::Executable { format: "PE".to_string() });
+
+        let err = validator.validate(b"\x7FELF\x02\x01\x01", "song.mp3").unwrap_err();
+        assert_eq!(err, ContentRejection::Executable { format: "ELF".to_string() });
+
+        // Plain text starting with "MZ" is fine
+        assert!(validator.validate(b"MZ is a postcode area", "notes.txt").is_ok());
+    }
+
+    #[test]
+    fn test_rejects_polyglots() {
+        let validator = ContentValidator::new();
+
+        let mut with_script = png();
+        with_script.extend_from_slice(b"<SCRIPT>alert(1)</SCRIPT>");
+        let err = validator.validate(&with_script, "red.png").unwrap_err();
+        assert!(matches!(err, ContentRejection::Polyglot { ref embedded, .. } if embedded == "<script>"));
+
+        let mut with_zip = png();
+        with_zip.extend_from_slice(b"PK\x05\x06\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0");
+        let err = validator.validate(&with_zip, "red.png").unwrap_err();
+        assert!(matches!(err, ContentRejection::Polyglot { ref embedded, .. } if embedded == "zip archive"));
+    }
+
+    #[test]
+    fn test_rejects_malformed_images() {
+        let validator = ContentValidator::new();
+
+        let mut truncated = png();
+        truncated.truncate(40);
+        let err = validator.validate(&truncated, "red.png").unwrap_err();
+        assert!(matches!(err, ContentRejection::Malformed { .. }));
+
+        let mut lenient = ContentValidator::new();
+        lenient.set_decode_images(false);
+        assert!(lenient.validate(&truncated, "red.png").is_ok());
+    }
+
+    #[test]
+    fn test_rejection_serializes_reason() {
+        let json = serde_json::to_value(ContentRejection::Executable { format: "PE".to_string() }).unwrap();
+        assert_eq!(json, serde_json::json!({ "reason": "executable", "format": "PE" }));
+    }
+}
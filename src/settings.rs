@@ -1,10 +1,23 @@
 //! RustMedia Settings
 
 use serde::{Deserialize, Serialize};
-use crate::models::{ImageSize, ResizeMode};
+use crate::config::Secret;
+use crate::models::{
+    CollisionStrategy, DuplicateStrategy, FilenamePolicy, ImageFormat, ImageSize, ResizeMode, RevisionPolicy,
+};
+use crate::services::scanner::ClamdEndpoint;
+use crate::services::rate_limit::RateLimitSettings;
+use crate::services::optimizer::OptimizationSettings;
+use crate::services::upload::UploadSettings;
+use crate::services::image::ImageSettings;
+use crate::services::archive::ArchiveLimits;
 
 /// Media plugin settings
+///
+/// Missing fields take their default, so settings saved by older versions
+/// still load.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct MediaSettings {
     // Storage
     /// Storage backend (local, s3)
@@ -21,6 +34,8 @@ pub struct MediaSettings {
     pub allowed_extensions: Vec<String>,
     /// Allowed MIME types
     pub allowed_mime_types: Vec<String>,
+    /// Limits for archive imports
+    pub archive_limits: ArchiveLimits,
 
     // Image processing
     /// JPEG quality (1-100)
@@ -41,6 +56,24 @@ pub struct MediaSettings {
     pub convert_to_webp: bool,
     /// Enable progressive JPEG
     pub progressive_jpeg: bool,
+    /// AVIF quality (1-100)
+    pub avif_quality: u8,
+    /// AVIF encoder speed (1 = smallest files, 10 = fastest)
+    pub avif_speed: u8,
+    /// Thumbnail format (JPEG, or WebP with `convert_to_webp`, when unset);
+    /// AVIF needs the `avif` feature
+    pub thumbnail_format: Option<ImageFormat>,
+    /// Target SSIM (0-1) for optimized JPEGs; replaces the fixed JPEG
+    /// quality with a search between the quality bounds
+    pub target_ssim: Option<f64>,
+    /// Lowest quality the SSIM search may pick (1-100)
+    pub min_quality: u8,
+    /// Highest quality the SSIM search may pick (1-100)
+    pub max_quality: u8,
+    /// Most encodes the SSIM search may try per image
+    pub max_quality_iterations: u8,
+    /// Largest image loaded into memory for optimization and thumbnails, in bytes
+    pub max_image_process_size: u64,
 
     // Thumbnails
     /// Generate thumbnails
@@ -57,14 +90,30 @@ pub struct MediaSettings {
     pub slugify_filenames: bool,
     /// Deduplicate files by hash
     pub deduplicate: bool,
+    /// Handling of duplicate uploads (`reject`, `return_existing`, `link`, `replace`)
+    pub duplicate_strategy: DuplicateStrategy,
+    /// Replaced binaries kept per item
+    pub revision_keep_files: usize,
+    /// Replaced binaries older than this many days are removed (0 = no limit)
+    pub revision_max_age_days: u32,
 
     // Security
     /// Scan uploads for malware
     pub scan_uploads: bool,
+    /// clamd address (`tcp://host:port` or `unix:///path`)
+    pub clamd_address: String,
+    /// Accept uploads when the scanner is unavailable
+    pub scan_fail_open: bool,
+    /// Keep infected uploads in the quarantine directory
+    pub quarantine_infected: bool,
     /// Validate file contents
     pub validate_contents: bool,
+    /// Strip scripts and external references from SVGs
+    pub sanitize_svg: bool,
     /// Maximum filename length
     pub max_filename_length: usize,
+    /// Naming of uploads whose filename is taken
+    pub filename_collision: CollisionStrategy,
 
     // Chunked uploads
     /// Enable chunked uploads
@@ -73,6 +122,14 @@ pub struct MediaSettings {
     pub chunk_size: usize,
     /// Chunk upload expiry in hours
     pub chunk_expiry_hours: u32,
+    /// How long retries with the same idempotency key replay the first result, in minutes
+    pub idempotency_window_minutes: u32,
+
+    // Rate limiting
+    /// Limit uploads per user
+    pub rate_limit_uploads: bool,
+    /// Default and per-role upload limits
+    pub upload_rate_limits: RateLimitSettings,
 
     // CDN
     /// CDN enabled
@@ -95,10 +152,12 @@ pub struct MediaSettings {
     pub s3_bucket: String,
     /// S3 region
     pub s3_region: String,
-    /// S3 access key
-    pub s3_access_key: String,
-    /// S3 secret key
-    pub s3_secret_key: String,
+    /// S3 access key (resolved by a secret provider, never (de)serialized)
+    #[serde(skip)]
+    pub s3_access_key: Secret,
+    /// S3 secret key (resolved by a secret provider, never (de)serialized)
+    #[serde(skip)]
+    pub s3_secret_key: Secret,
     /// S3 endpoint (for compatible services)
     pub s3_endpoint: String,
     /// S3 path prefix
@@ -172,6 +231,7 @@ impl Default for MediaSettings {
                 "application/x-tar".to_string(),
                 "application/gzip".to_string(),
             ],
+            archive_limits: ArchiveLimits::default(),
 
             // Image processing
             jpeg_quality: 85,
@@ -183,6 +243,14 @@ impl Default for MediaSettings {
             strip_metadata: true,
             convert_to_webp: false,
             progressive_jpeg: true,
+            avif_quality: 70,
+            avif_speed: 6,
+            thumbnail_format: None,
+            target_ssim: None,
+            min_quality: 40,
+            max_quality: 95,
+            max_quality_iterations: 6,
+            max_image_process_size: 50 * 1024 * 1024, // 50MB
 
             // Thumbnails
             generate_thumbnails: true,
@@ -226,16 +294,29 @@ impl Default for MediaSettings {
             date_format: "%Y/%m".to_string(),
             slugify_filenames: true,
             deduplicate: true,
+            duplicate_strategy: DuplicateStrategy::Reject,
+            revision_keep_files: 10,
+            revision_max_age_days: 0,
 
             // Security
             scan_uploads: false,
+            clamd_address: "tcp://127.0.0.1:3310".to_string(),
+            scan_fail_open: false,
+            quarantine_infected: true,
             validate_contents: true,
+            sanitize_svg: true,
             max_filename_length: 255,
+            filename_collision: CollisionStrategy::NumericSuffix,
 
             // Chunked uploads
             chunked_uploads: true,
             chunk_size: 5 * 1024 * 1024, // 5MB
             chunk_expiry_hours: 24,
+            idempotency_window_minutes: 24 * 60,
+
+            // Rate limiting
+            rate_limit_uploads: false,
+            upload_rate_limits: RateLimitSettings::default(),
 
             // CDN
             cdn_enabled: false,
@@ -250,8 +331,8 @@ impl Default for MediaSettings {
             // S3
             s3_bucket: String::new(),
             s3_region: "us-east-1".to_string(),
-            s3_access_key: String::new(),
-            s3_secret_key: String::new(),
+            s3_access_key: Secret::default(),
+            s3_secret_key: Secret::default(),
             s3_endpoint: String::new(),
             s3_prefix: String::new(),
         }
@@ -266,7 +347,7 @@ impl MediaSettings {
         Ok(settings)
     }
 
-    /// Save settings to file
+    /// Save settings to file (secrets are not written)
     pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
         let content = serde_json::to_string_pretty(self)?;
         std::fs::write(path, content)?;
@@ -292,6 +373,73 @@ impl MediaSettings {
         self.allowed_mime_types.iter().any(|m| m == mime)
     }
 
+    /// Filename policy for stored uploads
+    pub fn filename_policy(&self) -> FilenamePolicy {
+        FilenamePolicy {
+            transliterate: true,
+            lowercase: self.slugify_filenames,
+            max_length: self.max_filename_length,
+            collision: self.filename_collision,
+        }
+    }
+
+    /// Retention policy for replaced binaries
+    pub fn revision_policy(&self) -> RevisionPolicy {
+        RevisionPolicy {
+            keep_files: self.revision_keep_files,
+            max_file_age_days: self.revision_max_age_days,
+        }
+    }
+
+    /// Encoder settings for the optimizer
+    pub fn optimization_settings(&self) -> OptimizationSettings {
+        OptimizationSettings {
+            jpeg_quality: self.jpeg_quality,
+            png_compression: self.png_compression,
+            webp_quality: self.webp_quality,
+            max_width: Some(self.max_image_width),
+            max_height: Some(self.max_image_height),
+            strip_metadata: self.strip_metadata,
+            convert_to_webp: self.convert_to_webp,
+            progressive_jpeg: self.progressive_jpeg,
+            avif_quality: self.avif_quality,
+            avif_speed: self.avif_speed,
+            target_ssim: self.target_ssim,
+            min_quality: self.min_quality,
+            max_quality: self.max_quality,
+            max_iterations: self.max_quality_iterations,
+        }
+    }
+
+    /// Image processing settings (thumbnails and edits)
+    pub fn image_settings(&self) -> ImageSettings {
+        ImageSettings {
+            sizes: self.image_sizes.clone(),
+            default_quality: self.jpeg_quality,
+            convert_to_webp: self.convert_to_webp,
+            avif_speed: self.avif_speed,
+            thumbnail_format: self.thumbnail_format,
+        }
+    }
+
+    /// Upload handling settings
+    pub fn upload_settings(&self) -> UploadSettings {
+        UploadSettings {
+            max_file_size: self.max_file_size,
+            allowed_types: self.allowed_mime_types.clone(),
+            allowed_extensions: self.allowed_extensions.clone(),
+            chunk_size: self.chunk_size,
+            chunk_expiry_hours: self.chunk_expiry_hours,
+            auto_optimize: self.auto_optimize,
+            auto_thumbnails: self.generate_thumbnails,
+            max_image_process_size: self.max_image_process_size,
+            archive_limits: self.archive_limits.clone(),
+            validate_contents: self.validate_contents,
+            sanitize_svg: self.sanitize_svg,
+            idempotency_window_minutes: self.idempotency_window_minutes,
+        }
+    }
+
     /// Get enabled image sizes
     pub fn get_enabled_sizes(&self) -> Vec<&ImageSize> {
         self.image_sizes.iter().filter(|s| s.enabled).collect()
@@ -321,6 +469,30 @@ impl MediaSettings {
             errors.push("WebP quality must be between 1 and 100".to_string());
         }
 
+        if self.avif_quality == 0 || self.avif_quality > 100 {
+            errors.push("AVIF quality must be between 1 and 100".to_string());
+        }
+
+        if !(1..=10).contains(&self.avif_speed) {
+            errors.push("AVIF speed must be between 1 and 10".to_string());
+        }
+
+        if self.target_ssim.is_some_and(|t| !(t > 0.0 && t <= 1.0)) {
+            errors.push("Target SSIM must be above 0 and at most 1".to_string());
+        }
+
+        if self.min_quality == 0 || self.max_quality > 100 || self.min_quality > self.max_quality {
+            errors.push("Quality bounds must satisfy 1 <= min <= max <= 100".to_string());
+        }
+
+        if self.max_quality_iterations == 0 {
+            errors.push("Quality search needs at least one iteration".to_string());
+        }
+
+        if self.thumbnail_format == Some(ImageFormat::Avif) && !cfg!(feature = "avif") {
+            errors.push("AVIF thumbnails need the `avif` feature".to_string());
+        }
+
         if self.storage_backend == "s3" {
             if self.s3_bucket.is_empty() {
                 errors.push("S3 bucket name is required".to_string());
@@ -333,6 +505,14 @@ impl MediaSettings {
             }
         }
 
+        if !(16..=255).contains(&self.max_filename_length) {
+            errors.push("Max filename length must be between 16 and 255".to_string());
+        }
+
+        if self.scan_uploads && ClamdEndpoint::parse(&self.clamd_address).is_err() {
+            errors.push(format!("Invalid clamd address: {}", self.clamd_address));
+        }
+
         if errors.is_empty() {
             Ok(())
         } else {
@@ -340,3 +520,95 @@ impl MediaSettings {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fields of `MediaSettings` before any were added
+    const ORIGINAL_FIELDS: &[&str] = &[
+        "storage_backend", "storage_path", "base_url", "max_file_size", "allowed_extensions",
+        "allowed_mime_types", "jpeg_quality", "png_compression", "webp_quality", "max_image_width",
+        "max_image_height", "auto_optimize", "strip_metadata", "convert_to_webp", "progressive_jpeg",
+        "generate_thumbnails", "image_sizes", "organize_by_date", "date_format", "slugify_filenames",
+        "deduplicate", "scan_uploads", "validate_contents", "max_filename_length", "chunked_uploads",
+        "chunk_size", "chunk_expiry_hours", "cdn_enabled", "cdn_url", "watermark_enabled",
+        "watermark_path", "watermark_position", "watermark_opacity", "s3_bucket", "s3_region",
+        "s3_endpoint", "s3_prefix",
+    ];
+
+    /// Settings file as written before the newer fields existed
+    fn load_original() -> MediaSettings {
+        let mut json = serde_json::to_value(MediaSettings::default()).unwrap();
+        let fields = json.as_object_mut().unwrap();
+        fields.retain(|key, _| ORIGINAL_FIELDS.contains(&key.as_str()));
+        fields.insert("jpeg_quality".to_string(), 70.into());
+        // Secrets were saved back then
+        fields.insert("s3_access_key".to_string(), "".into());
+        fields.insert("s3_secret_key".to_string(), "saved".into());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("media.json");
+        std::fs::write(&path, serde_json::to_string(&json).unwrap()).unwrap();
+        MediaSettings::load(path.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_load_original_format() {
+        let settings = load_original();
+        let defaults = MediaSettings::default();
+        assert_eq!(settings.jpeg_quality, 70);
+        assert!(settings.validate().is_ok());
+        // Secrets only come from secret providers
+        assert!(settings.s3_secret_key.is_empty());
+
+        assert_eq!(settings.clamd_address, defaults.clamd_address);
+        assert_eq!(settings.scan_fail_open, defaults.scan_fail_open);
+        assert_eq!(settings.quarantine_infected, defaults.quarantine_infected);
+
+        assert_eq!(settings.filename_collision, defaults.filename_collision);
+
+        assert_eq!(settings.duplicate_strategy, defaults.duplicate_strategy);
+
+        assert_eq!(settings.revision_keep_files, defaults.revision_keep_files);
+        assert_eq!(settings.revision_max_age_days, defaults.revision_max_age_days);
+
+        assert_eq!(settings.idempotency_window_minutes, defaults.idempotency_window_minutes);
+
+        assert_eq!(settings.rate_limit_uploads, defaults.rate_limit_uploads);
+        assert_eq!(
+            serde_json::to_value(&settings.upload_rate_limits).unwrap(),
+            serde_json::to_value(&defaults.upload_rate_limits).unwrap()
+        );
+
+        assert_eq!(settings.avif_quality, defaults.avif_quality);
+        assert_eq!(settings.avif_speed, defaults.avif_speed);
+        assert_eq!(settings.thumbnail_format, defaults.thumbnail_format);
+
+        assert_eq!(settings.target_ssim, defaults.target_ssim);
+        assert_eq!(settings.min_quality, defaults.min_quality);
+        assert_eq!(settings.max_quality, defaults.max_quality);
+        assert_eq!(settings.max_quality_iterations, defaults.max_quality_iterations);
+
+        assert_eq!(settings.max_image_process_size, defaults.max_image_process_size);
+        assert_eq!(settings.sanitize_svg, defaults.sanitize_svg);
+        assert_eq!(settings.archive_limits.max_entries, defaults.archive_limits.max_entries);
+    }
+
+    #[test]
+    fn test_upload_settings() {
+        let settings = MediaSettings {
+            max_file_size: 1024,
+            allowed_extensions: vec!["png".to_string()],
+            generate_thumbnails: false,
+            sanitize_svg: false,
+            ..MediaSettings::default()
+        };
+        let upload = settings.upload_settings();
+        assert_eq!(upload.max_file_size, 1024);
+        assert_eq!(upload.allowed_extensions, ["png"]);
+        assert_eq!(upload.allowed_types, settings.allowed_mime_types);
+        assert!(!upload.auto_thumbnails);
+        assert!(!upload.sanitize_svg);
+    }
+}